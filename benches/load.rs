@@ -0,0 +1,77 @@
+//! Benchmarks for loading moldfiles, resolving dependencies, and expanding commands
+//!
+//! Run with `cargo bench`. The fixtures are generated into temporary directories so that these
+//! measure the same code paths as a real project.
+
+#[path = "../tests/common/fixture.rs"]
+mod fixture;
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use fixture::Fixture;
+use mold::Mold;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// Write a fixture into a fresh project directory
+fn project(fixture: &Fixture) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("moldfile");
+    fs::write(&path, fixture.render()).unwrap();
+    (dir, path)
+}
+
+fn load(path: &Path) -> Mold {
+    Mold::init(path, vec!["unix".into()], false, false, true, true).unwrap()
+}
+
+fn bench_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    for (name, fixture) in &[
+        ("small", Fixture::small()),
+        ("medium", Fixture::medium()),
+        ("large", Fixture::large()),
+    ] {
+        let (_dir, path) = project(fixture);
+        group.bench_with_input(BenchmarkId::from_parameter(name), &path, |b, path| {
+            b.iter(|| load(path))
+        });
+    }
+    group.finish();
+}
+
+fn bench_dependencies(c: &mut Criterion) {
+    let fixture = Fixture {
+        recipes: 1000,
+        requires: 5,
+        commands: 0,
+        vars: 0,
+    };
+    let (_dir, path) = project(&fixture);
+    let mold = load(&path);
+    let targets = std::iter::once("r999".to_string()).collect();
+
+    c.bench_function("dependencies", |b| {
+        b.iter(|| mold.find_all_dependencies(&targets).unwrap())
+    });
+}
+
+fn bench_expansion(c: &mut Criterion) {
+    let fixture = Fixture {
+        recipes: 1,
+        requires: 0,
+        commands: 200,
+        vars: 50,
+    };
+    let (_dir, path) = project(&fixture);
+    let mold = load(&path);
+
+    c.bench_function("expansion", |b| b.iter(|| mold.commands("r0").unwrap()));
+}
+
+criterion_group!(benches, bench_load, bench_dependencies, bench_expansion);
+criterion_main!(benches);
@@ -200,13 +200,13 @@ where
     }
 
     if res.is_ok() || !any_attempts {
-        return res.map_err(From::from);
+        return res;
     }
 
     // In the case of an authentication failure (where we tried something) then
     // we try to give a more helpful error message about precisely what we
     // tried.
-    let res = res.map_err(failure::Error::from).with_context(|_| {
+    let res = res.with_context(|_| {
         let mut msg = "failed to authenticate when downloading \
                         repository"
             .to_string();
@@ -0,0 +1,125 @@
+//! Shell completion scripts for `--completion`
+//!
+//! Options come straight from clap. Recipe names can't, since they depend on whichever moldfile
+//! is around when completing, so each script also asks `mold --list-names` for them.
+
+use colored::*;
+use failure::Error;
+use structopt::clap::App;
+use structopt::clap::Shell;
+
+const BASH_SETUP: &str = r#"# Bash completion for mold
+#
+# Load it in every new shell by adding this to ~/.bashrc:
+#   source <(mold --completion bash)
+# or install it for bash-completion to find:
+#   mold --completion bash > ~/.local/share/bash-completion/completions/mold
+"#;
+
+const BASH_RECIPES: &str = r#"
+# anything that isn't an option or an option's value is a recipe
+_mold_recipes() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    if [[ ${cur} != -* && ( ${COMP_CWORD} -eq 1 || ${prev} != -* ) ]]; then
+        COMPREPLY=( $(compgen -W "$(mold --offline --list-names 2>/dev/null)" -- "${cur}") )
+        return 0
+    fi
+    _mold "$@"
+}
+
+complete -F _mold_recipes -o bashdefault -o default mold
+"#;
+
+const ZSH_SETUP: &str = r#"# Zsh completion for mold
+#
+# Install it somewhere on $fpath, then start a new shell:
+#   mold --completion zsh > "${fpath[1]}/_mold"
+"#;
+
+const ZSH_RECIPES: &str = r#"_mold_recipes() {
+    local -a recipes
+    recipes=(${(f)"$(mold --offline --list-names 2>/dev/null)"})
+    compadd -a recipes
+}
+
+"#;
+
+const FISH_SETUP: &str = r#"# Fish completion for mold
+#
+# Install it where fish looks for completions:
+#   mold --completion fish > ~/.config/fish/completions/mold.fish
+"#;
+
+const FISH_RECIPES: &str = r#"
+# recipes from the moldfile in the current directory
+complete -c mold -n "__fish_use_subcommand" -f -a "(mold --offline --list-names 2>/dev/null)"
+"#;
+
+const POWERSHELL_SETUP: &str = r#"# PowerShell completion for mold
+#
+# Load it in every new session by adding this to $PROFILE:
+#   mold --completion powershell | Out-String | Invoke-Expression
+"#;
+
+const POWERSHELL_RECIPES: &str = r#"    if (-not $wordToComplete.StartsWith('-')) {
+        $completions += @(mold --offline --list-names 2>$null | ForEach-Object {
+            [CompletionResult]::new($_, $_, [CompletionResultType]::ParameterValue, $_)
+        })
+    }
+
+"#;
+
+/// Render the completion script for `app` in one of bash, zsh, fish, or powershell
+pub fn script(mut app: App, shell: &str) -> Result<String, Error> {
+    let kind = match shell {
+        "bash" => Shell::Bash,
+        "zsh" => Shell::Zsh,
+        "fish" => Shell::Fish,
+        "powershell" | "pwsh" => Shell::PowerShell,
+        _ => {
+            return Err(failure::format_err!(
+                "Unknown shell {}; expected bash, zsh, fish, or powershell",
+                shell.red()
+            ))
+        }
+    };
+
+    let mut generated = vec![];
+    app.gen_completions_to("mold", kind, &mut generated);
+    let generated = String::from_utf8(generated)?;
+
+    let script = match kind {
+        Shell::Bash => format!("{}\n{}{}", BASH_SETUP, generated, BASH_RECIPES),
+        Shell::Fish => format!("{}\n{}{}", FISH_SETUP, generated, FISH_RECIPES),
+
+        // zsh needs #compdef first, and the recipes function defined before _mold runs
+        Shell::Zsh => {
+            let generated = generated.replacen("#compdef mold\n", "", 1).replacen(
+                "Which recipe(s) to run:_files'",
+                "Which recipe(s) to run:_mold_recipes'",
+                1,
+            );
+            let (body, call) = generated
+                .rsplit_once("_mold \"$@\"")
+                .unwrap_or((&generated, ""));
+            format!(
+                "#compdef mold\n{}\n{}{}_mold \"$@\"{}\n",
+                ZSH_SETUP,
+                body,
+                ZSH_RECIPES,
+                call.trim_end()
+            )
+        }
+
+        // recipes join the options just before they're filtered by what's been typed
+        _ => {
+            let filter = "    $completions.Where";
+            let generated =
+                generated.replacen(filter, &format!("{}{}", POWERSHELL_RECIPES, filter), 1);
+            format!("{}{}", POWERSHELL_SETUP, generated)
+        }
+    };
+
+    Ok(script)
+}
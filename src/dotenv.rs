@@ -0,0 +1,99 @@
+//! Variables from a `.env` file next to the root moldfile
+//!
+//! Values from it act like `var NAME := "..."` defaults: anything already set in the environment
+//! wins, and the moldfile's own `var` statements replace them. A moldfile can name a different
+//! file with `env_file "path"`, and `--no-dotenv` skips it altogether.
+//!
+//! Each line is `KEY=VALUE`, optionally starting with `export`. Values can be bare, in single
+//! quotes to be taken literally, or in double quotes with `\n`, `\t`, `\"`, and `\\` escapes.
+//! Blank lines and lines starting with `#` are ignored, as is a `#` comment after a value.
+
+use colored::*;
+use failure::Error;
+use std::fs;
+use std::path::Path;
+
+/// Name of the file that's loaded when the moldfile doesn't name one
+pub const DEFAULT: &str = ".env";
+
+/// Read and parse an env file
+pub fn load(path: &Path) -> Result<Vec<(String, String)>, Error> {
+    let code = fs::read_to_string(path).map_err(|err| {
+        failure::format_err!(
+            "Couldn't read {}: {}",
+            path.display().to_string().red(),
+            err
+        )
+    })?;
+
+    parse(&code).map_err(|err| {
+        failure::format_err!(
+            "Couldn't parse {}: {}",
+            path.display().to_string().red(),
+            err
+        )
+    })
+}
+
+/// Parse the contents of an env file into variables, in file order
+pub fn parse(code: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut vars = vec![];
+    for (idx, line) in code.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| failure::format_err!("line {} has no '='", idx + 1))?;
+
+        let key = key.trim();
+        let valid = !key.is_empty()
+            && !key.starts_with(|x: char| x.is_ascii_digit())
+            && key.chars().all(|x| x.is_ascii_alphanumeric() || x == '_');
+        if !valid {
+            return Err(failure::format_err!(
+                "line {} has an invalid name {}",
+                idx + 1,
+                key.red()
+            ));
+        }
+
+        let value = parse_value(value.trim())
+            .ok_or_else(|| failure::format_err!("line {} has an unterminated quote", idx + 1))?;
+        vars.push((key.to_string(), value));
+    }
+
+    Ok(vars)
+}
+
+/// Parse a single value, which is `None` when a quote is never closed
+fn parse_value(value: &str) -> Option<String> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        return rest.find('\'').map(|end| rest[..end].to_string());
+    }
+
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(out),
+                '\\' => match chars.next()? {
+                    'n' => out.push('\n'),
+                    't' => out.push('\t'),
+                    'r' => out.push('\r'),
+                    other => out.push(other),
+                },
+                _ => out.push(c),
+            }
+        }
+        return None;
+    }
+
+    // a comment after a bare value needs whitespace before it, like in a shell
+    let end = value.find(" #").or_else(|| value.find("\t#"));
+    Some(value[..end.unwrap_or(value.len())].trim_end().to_string())
+}
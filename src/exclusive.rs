@@ -0,0 +1,156 @@
+//! An advisory lock in `.mold/lock` that keeps `exclusive` runs from overlapping
+//!
+//! The lock file holds the PID of the invocation that owns it. Another invocation waits for it to
+//! go away, unless that PID is no longer running, in which case the lock is stale and gets broken.
+//! Within a single invocation the lock is shared, so an `exclusive` recipe inside an exclusive run
+//! doesn't wait on itself.
+
+use super::util;
+use colored::*;
+use failure::Error;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Name of the lock file inside `.mold`
+pub const LOCK: &str = "lock";
+
+/// How many guards in this process currently share the lock file
+static HOLDERS: Mutex<usize> = Mutex::new(0);
+
+/// Holds the project lock until it's dropped
+pub struct Guard(PathBuf);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut holders = HOLDERS.lock().unwrap_or_else(|x| x.into_inner());
+        *holders -= 1;
+        if *holders == 0 {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+}
+
+/// Take the lock for the project whose `.mold` is `mold_dir`, waiting for other invocations
+///
+/// Waiting gives up after `timeout`, if there is one.
+pub fn acquire(mold_dir: &Path, timeout: Option<Duration>) -> Result<Guard, Error> {
+    let path = mold_dir.join(LOCK);
+    let mut holders = HOLDERS.lock().unwrap_or_else(|x| x.into_inner());
+    if *holders > 0 {
+        *holders += 1;
+        return Ok(Guard(path));
+    }
+
+    fs::create_dir_all(mold_dir)?;
+    let started = Instant::now();
+    let mut waiting_on = None;
+    while !try_create(&path)? {
+        let owner = read_owner(&path);
+        match owner {
+            Some(pid) if !is_running(pid) => {
+                if break_stale(&path, pid) {
+                    util::warn(&format!(
+                        "Breaking stale lock {} left by pid {}",
+                        path.display(),
+                        pid
+                    ));
+                }
+                continue;
+            }
+            Some(pid) if waiting_on != Some(pid) => {
+                println!(
+                    "{:>12} for another mold invocation (pid {})...",
+                    "Waiting".yellow(),
+                    pid
+                );
+                waiting_on = Some(pid);
+            }
+            _ => {}
+        }
+
+        if let Some(timeout) = timeout {
+            if started.elapsed() >= timeout {
+                let owner = owner.map(|x| format!(" held by pid {}", x));
+                return Err(failure::format_err!(
+                    "Timed out after {} waiting for {}{}",
+                    util::format_duration(timeout),
+                    path.display().to_string().red(),
+                    owner.unwrap_or_default()
+                ));
+            }
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    *holders += 1;
+    Ok(Guard(path))
+}
+
+/// Read the PID that a lock file holds
+fn read_owner(path: &Path) -> Option<u32> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|x| x.trim().parse::<u32>().ok())
+}
+
+/// Remove a lock file left behind by `pid`, which isn't running anymore
+///
+/// Other waiters can see the same dead PID, so the lock is renamed aside first, which only one of
+/// them can do, and is only deleted if it still holds that PID. Otherwise another waiter already
+/// broke it and took the lock in the meantime, so it's put back.
+fn break_stale(path: &Path, pid: u32) -> bool {
+    let aside = path.with_extension(format!("stale.{}", std::process::id()));
+    if fs::rename(path, &aside).is_err() {
+        return false;
+    }
+
+    let broken = read_owner(&aside) == Some(pid);
+    if !broken {
+        // linking doesn't replace a lock that somebody created since
+        let _ = fs::hard_link(&aside, path);
+    }
+    let _ = fs::remove_file(&aside);
+    broken
+}
+
+/// Create the lock file holding this process's PID, unless it already exists
+///
+/// The PID is written to a file of its own first and then linked into place, so that nobody ever
+/// sees a lock file without an owner in it.
+fn try_create(path: &Path) -> Result<bool, Error> {
+    let pid = std::process::id();
+    let staged = path.with_extension(pid.to_string());
+    fs::write(&staged, format!("{}\n", pid))?;
+    let linked = fs::hard_link(&staged, path);
+    let _ = fs::remove_file(&staged);
+
+    match linked {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(err) => Err(failure::format_err!(
+            "Couldn't create lock {}: {}",
+            path.display().to_string().red(),
+            err
+        )),
+    }
+}
+
+/// Whether a process is still running
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // signal 0 only checks whether the process exists; EPERM means it does, just not as ours
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether a process is still running; without a way to ask, it's assumed to be
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
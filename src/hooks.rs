@@ -0,0 +1,192 @@
+//! Git hooks that run mold recipes
+//!
+//! Installed hooks are small shell scripts that find mold on PATH when they run, so they keep
+//! working across mold upgrades. Any arguments git gives a hook are passed on to its recipes. Each one carries a marker line so that mold never overwrites or
+//! removes a hook that somebody else wrote unless it's forced to.
+
+use super::util;
+use colored::*;
+use failure::Error;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Line that identifies a hook as one that mold wrote
+const MARKER: &str = "# generated by mold";
+
+/// Every hook that git runs from a work tree
+const HOOKS: &[&str] = &[
+    "applypatch-msg",
+    "commit-msg",
+    "post-applypatch",
+    "post-checkout",
+    "post-commit",
+    "post-merge",
+    "post-rewrite",
+    "pre-applypatch",
+    "pre-auto-gc",
+    "pre-commit",
+    "pre-merge-commit",
+    "pre-push",
+    "pre-rebase",
+    "prepare-commit-msg",
+];
+
+/// Make sure that git knows about a hook
+pub fn check(hook: &str) -> Result<(), Error> {
+    if HOOKS.contains(&hook) {
+        return Ok(());
+    }
+
+    let hint = match util::suggest(hook, HOOKS.iter().copied()) {
+        Some(x) => format!("; did you mean {}?", x.green()),
+        None => String::new(),
+    };
+    Err(failure::format_err!(
+        "Unknown git hook {}{}",
+        hook.red(),
+        hint
+    ))
+}
+
+/// Find the work tree containing `dir` and the directory that its hooks live in
+///
+/// `core.hooksPath` is respected, relative to the work tree like git does.
+fn locate(dir: &Path) -> Result<(PathBuf, PathBuf), Error> {
+    let repo = git2::Repository::discover(dir).map_err(|err| {
+        failure::format_err!(
+            "Couldn't find a git repository for {}: {}",
+            dir.display().to_string().red(),
+            err.message()
+        )
+    })?;
+
+    let work_dir = repo
+        .workdir()
+        .ok_or_else(|| failure::format_err!("Hooks can't be installed in a bare repository"))?
+        .to_path_buf();
+
+    let hooks_dir = match repo.config()?.get_path("core.hooksPath") {
+        Ok(path) => work_dir.join(path),
+        Err(_) => repo.path().join("hooks"),
+    };
+
+    Ok((work_dir, hooks_dir))
+}
+
+/// Write the script for a hook that runs `targets` from the moldfile at `moldfile`
+///
+/// `moldfile` is relative to the top of the work tree, which is where the script runs mold from.
+/// Whatever arguments git passes the hook are forwarded after `--`, so each target sees them as
+/// `$1`, `$2`, and `$@`, or bound to its `param`s in order, like the commit-msg hook's message file.
+fn render(hook: &str, moldfile: &Path, targets: &[String]) -> String {
+    let mut args = vec![
+        "--non-interactive".to_string(),
+        "--file".to_string(),
+        moldfile.to_string_lossy().to_string(),
+    ];
+    args.extend(targets.iter().cloned());
+
+    format!(
+        r#"#!/bin/sh
+{marker}; remove it with `mold --uninstall-hook {hook}`
+if ! command -v mold >/dev/null 2>&1; then
+  echo "The {hook} hook runs mold, but mold isn't on PATH." >&2
+  echo "Install mold, or skip the hook just this once with --no-verify." >&2
+  exit 1
+fi
+cd "$(git rev-parse --show-toplevel)" || exit 1
+exec mold {args} -- "$@"
+"#,
+        marker = MARKER,
+        hook = hook,
+        args = shell_words::join(args),
+    )
+}
+
+/// Install a hook that runs `targets` from the moldfile at `moldfile`
+///
+/// An existing hook is only replaced if mold wrote it, or if `force` is set.
+pub fn install(moldfile: &Path, hook: &str, targets: &[String], force: bool) -> Result<(), Error> {
+    check(hook)?;
+
+    let root_dir = moldfile.parent().unwrap_or_else(|| Path::new("/"));
+    let (work_dir, hooks_dir) = locate(root_dir)?;
+    let relative = match (fs::canonicalize(moldfile), fs::canonicalize(&work_dir)) {
+        (Ok(file), Ok(top)) => file.strip_prefix(&top).map(Path::to_path_buf).ok(),
+        _ => None,
+    };
+    let relative = relative.ok_or_else(|| {
+        failure::format_err!(
+            "Moldfile {} isn't inside the git repository at {}",
+            moldfile.display().to_string().red(),
+            work_dir.display()
+        )
+    })?;
+
+    let path = hooks_dir.join(hook);
+    let contents = render(hook, &relative, targets);
+    let existing = fs::read_to_string(&path).ok();
+
+    let status = match &existing {
+        Some(old) if *old == contents => {
+            println!("{:>12} {}", "Unchanged".green(), path.display());
+            return Ok(());
+        }
+        Some(old) if !old.contains(MARKER) && !force => {
+            return Err(failure::format_err!(
+                "Hook {} already exists and wasn't written by mold; use --force to replace it",
+                path.display().to_string().red()
+            ));
+        }
+        Some(_) => "Replaced",
+        None => "Installed",
+    };
+
+    fs::create_dir_all(&hooks_dir)?;
+    util::write_atomic(&path, contents.as_bytes())?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    println!(
+        "{:>12} {} {}",
+        status.green(),
+        path.display(),
+        format!("({})", targets.join(" ")).white()
+    );
+    Ok(())
+}
+
+/// Remove a hook that mold installed
+///
+/// Hooks that mold didn't write are left alone unless `force` is set.
+pub fn uninstall(moldfile: &Path, hook: &str, force: bool) -> Result<(), Error> {
+    check(hook)?;
+
+    let root_dir = moldfile.parent().unwrap_or_else(|| Path::new("/"));
+    let (_, hooks_dir) = locate(root_dir)?;
+    let path = hooks_dir.join(hook);
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => {
+            println!("{:>12} {} isn't installed", "Skipped".yellow(), hook);
+            return Ok(());
+        }
+    };
+
+    if !contents.contains(MARKER) && !force {
+        return Err(failure::format_err!(
+            "Hook {} wasn't written by mold; use --force to remove it anyway",
+            path.display().to_string().red()
+        ));
+    }
+
+    fs::remove_file(&path)?;
+    println!("{:>12} {}", "Removed".green(), path.display());
+    Ok(())
+}
@@ -2,12 +2,58 @@ use super::remote;
 use failure::err_msg;
 use failure::format_err;
 use failure::Error;
+use indexmap::IndexMap;
 use pest::iterators::Pair;
 use pest::iterators::Pairs;
 use pest::Parser;
 use pest_derive::Parser;
+use std::collections::VecDeque;
+use std::fmt;
 use std::str::FromStr;
 
+/// Language features understood by this version of mold, for use with `requires_features`
+pub const FEATURES: &[&str] = &[
+    "append",
+    "cache",
+    "check",
+    "compare",
+    "compat",
+    "env_file",
+    "environments",
+    "exclusive",
+    "expected",
+    "hook",
+    "inputs",
+    "interpreter",
+    "named_remotes",
+    "outputs",
+    "param",
+    "positional",
+    "prepend",
+    "private",
+    "require_completed",
+    "require_file",
+    "require_var",
+    "require_with",
+    "requires_clean_tree",
+    "retry",
+    "run_any",
+    "run_list",
+    "run_raw",
+    "scoped",
+    "script",
+    "settings",
+    "shell",
+    "shell_functions",
+    "shell_var",
+    "short_recipe",
+    "timeout",
+    "umask",
+    "unset",
+    "watch",
+    "weight",
+];
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Expr {
     And(Box<Expr>, Box<Expr>),
@@ -15,21 +61,45 @@ pub enum Expr {
     Not(Box<Expr>),
     Group(Box<Expr>),
     Atom(String),
+    Eq(String, String),
+    Ne(String, String),
     Wild,
 }
 
 impl Expr {
-    pub fn apply(&self, to: &super::EnvSet) -> bool {
+    /// Evaluate this expression against a set of active environments and expanded variables
+    ///
+    /// Undefined variables compare unequal to everything, including the empty string.
+    pub fn apply(&self, to: &super::EnvSet, vars: &super::VarMap) -> bool {
+        let lookup = |name: &str| vars.get(name).cloned().or_else(|| std::env::var(name).ok());
+
         match self {
-            Expr::And(x, y) => x.apply(to) && y.apply(to),
-            Expr::Or(x, y) => x.apply(to) || y.apply(to),
-            Expr::Not(x) => !x.apply(to),
-            Expr::Group(x) => x.apply(to),
+            Expr::And(x, y) => x.apply(to, vars) && y.apply(to, vars),
+            Expr::Or(x, y) => x.apply(to, vars) || y.apply(to, vars),
+            Expr::Not(x) => !x.apply(to, vars),
+            Expr::Group(x) => x.apply(to, vars),
             Expr::Atom(x) => to.contains(x),
+            Expr::Eq(name, value) => lookup(name).as_ref() == Some(value),
+            Expr::Ne(name, value) => lookup(name).as_ref() != Some(value),
             Expr::Wild => true,
         }
     }
 
+    /// Collect the names of every environment this expression refers to
+    pub fn atoms(&self, into: &mut super::EnvSet) {
+        match self {
+            Expr::And(x, y) | Expr::Or(x, y) => {
+                x.atoms(into);
+                y.atoms(into);
+            }
+            Expr::Not(x) | Expr::Group(x) => x.atoms(into),
+            Expr::Atom(x) => {
+                into.insert(x.clone());
+            }
+            Expr::Eq(..) | Expr::Ne(..) | Expr::Wild => {}
+        }
+    }
+
     /// Given a Pair, convert it into an Expr
     pub fn from(pair: Pair<Rule>) -> Self {
         use Expr::*;
@@ -50,6 +120,18 @@ impl Expr {
                 And(lhs.into(), rhs.into())
             }
 
+            compare => {
+                let mut inner = pair.into_inner();
+                let var_name = single_name(inner.next().unwrap());
+                let op = inner.next().unwrap().as_rule();
+                let value = consume_string(&mut inner).unwrap();
+                match op {
+                    eq => Eq(var_name, value),
+                    ne => Ne(var_name, value),
+                    _ => unreachable!(),
+                }
+            }
+
             not_expr => Not(single_expr(pair).into()),
             atom | group => single_expr(pair),
             name => Atom(pair.as_str().into()),
@@ -59,21 +141,72 @@ impl Expr {
     }
 }
 
-// FIXME inline scripts?
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::And(x, y) => write!(f, "{} + {}", x, y),
+            Expr::Or(x, y) => write!(f, "{} | {}", x, y),
+            Expr::Not(x) => write!(f, "~{}", x),
+            Expr::Group(x) => write!(f, "({})", x),
+            Expr::Atom(x) => write!(f, "{}", x),
+            Expr::Eq(name, value) => write!(f, "${} == {}", name, quote(value)),
+            Expr::Ne(name, value) => write!(f, "${} != {}", name, quote(value)),
+            Expr::Wild => write!(f, "*"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Statement {
+    Check(String, Vec<Statement>),
+    CleanTree,
+    Cache,
+    Exclusive,
+    Private,
+    Compat(String),
+    Completed(String, Option<String>),
     Dir(String),
+    Shell(String),
+    Environments(Vec<String>),
+    Expected(String),
+    Features(Vec<String>),
     Help(String),
+    Interpreter(String),
+    Hook(String, String),
     IfBlock(Vec<Statement>),
     If(Expr, Vec<Statement>),
     Else(Vec<Statement>),
-    Import(String, Option<String>),
-    Recipe(String, Vec<Statement>),
-    Require(String),
+    Import(String, Option<String>, bool),
+    ImportRemote(String, String, Option<String>, bool),
+    DeclareRemote(String, String),
+    Param(String, Option<String>, bool),
+    Positional(Vec<String>),
+    Unset(Vec<String>),
+    Timeout(String),
+    TimeoutBlock(String, Vec<Statement>),
+    Watch(Vec<String>),
+    Inputs(Vec<String>),
+    Outputs(Vec<String>),
+    Recipe(String, Option<usize>, Vec<Statement>),
+    Settings(Vec<(String, String)>),
+    ShellFunctions(Vec<String>),
+    EnvFile(String),
+    Require(String, Vec<(String, String)>),
+    RequireVar(String, Option<String>),
     Run(String),
+    RunList(Vec<String>),
+    RunAny(Vec<String>),
+    RunRaw(String),
+    Script(String),
     Var(String, String),
+    ShellVar(String, String),
+    Append(String, String),
+    Prepend(String, String),
     Default(String, String),
+    Umask(String),
     Version(String),
+    Weight(String),
+    Retry(String),
 }
 
 impl Statement {
@@ -114,17 +247,52 @@ impl Statement {
             }
 
             import_stmt => {
+                let mut source = String::new();
+                let mut via = None;
+                let mut dep_name = None;
+                let mut is_scoped = false;
+                for part in pair.into_inner() {
+                    match part.as_rule() {
+                        remote_name => via = Some(part.as_str().into()),
+                        string => source = single_string_inner(part),
+                        scoped => is_scoped = true,
+                        _ => dep_name = Some(part.as_str().into()),
+                    }
+                }
+
+                match via {
+                    Some(via) => ImportRemote(via, source, dep_name, is_scoped),
+                    None => Import(source, dep_name, is_scoped),
+                }
+            }
+
+            remote_stmt => {
                 let mut inner = pair.into_inner();
-                let source = consume_string(&mut inner).unwrap();
-                let dep_name = consume_name(&mut inner);
-                Import(source, dep_name)
+                let decl_name = consume_name(&mut inner).unwrap();
+                let url = consume_string(&mut inner).unwrap();
+                DeclareRemote(decl_name, url)
             }
 
             recipe_stmt => {
+                let line = pair.as_span().start_pos().line_col().0;
                 let mut inner = pair.into_inner();
                 let rec_name = consume_name(&mut inner).unwrap();
                 let stmts = consume_statements(&mut inner);
-                Recipe(rec_name, stmts)
+                Recipe(rec_name, Some(line), stmts)
+            }
+
+            timeout_block => {
+                let mut inner = pair.into_inner();
+                let limit = consume_string(&mut inner).unwrap();
+                let stmts = consume_statements(&mut inner);
+                TimeoutBlock(limit, stmts)
+            }
+
+            check_stmt => {
+                let mut inner = pair.into_inner();
+                let check_name = consume_string(&mut inner).unwrap();
+                let stmts = consume_statements(&mut inner);
+                Check(check_name, stmts)
             }
 
             var_stmt => {
@@ -134,6 +302,27 @@ impl Statement {
                 Var(var_name, value)
             }
 
+            shell_var_stmt => {
+                let mut inner = pair.into_inner();
+                let var_name = consume_name(&mut inner).unwrap();
+                let command = consume_string(&mut inner).unwrap();
+                ShellVar(var_name, command)
+            }
+
+            append_stmt => {
+                let mut inner = pair.into_inner();
+                let var_name = consume_name(&mut inner).unwrap();
+                let value = consume_string(&mut inner).unwrap();
+                Append(var_name, value)
+            }
+
+            prepend_stmt => {
+                let mut inner = pair.into_inner();
+                let var_name = consume_name(&mut inner).unwrap();
+                let value = consume_string(&mut inner).unwrap();
+                Prepend(var_name, value)
+            }
+
             default_stmt => {
                 let mut inner = pair.into_inner();
                 let var_name = consume_name(&mut inner).unwrap();
@@ -141,11 +330,114 @@ impl Statement {
                 Default(var_name, value)
             }
 
+            clean_tree_stmt => CleanTree,
+            cache_stmt => Cache,
+            exclusive_stmt => Exclusive,
+            private_stmt => Private,
+
+            require_var_stmt => {
+                let mut inner = pair.into_inner();
+                let var_name = consume_name(&mut inner).unwrap();
+                let help = consume_string(&mut inner);
+                RequireVar(var_name, help)
+            }
+            compat_stmt => Compat(single_name(pair)),
+            environments_stmt => {
+                Environments(pair.into_inner().map(|x| x.as_str().into()).collect())
+            }
             dir_stmt => Dir(single_string(pair)),
+            shell_stmt => Shell(single_string(pair)),
+            interpreter_stmt => Interpreter(single_string(pair)),
+            completed_stmt => {
+                let mut inner = pair.into_inner();
+                let target = consume_name(&mut inner).unwrap();
+                Completed(target, consume_string(&mut inner))
+            }
+            expected_stmt => Expected(single_string(pair)),
+            features_stmt => Features(pair.into_inner().map(|x| x.as_str().into()).collect()),
             help_stmt => Help(single_string(pair)),
-            require_stmt => Require(single_name(pair)),
-            run_stmt => Run(single_string(pair)),
+            hook_stmt => {
+                let mut inner = pair.into_inner();
+                let hook = consume_name(&mut inner).unwrap();
+                let targets = consume_string(&mut inner).unwrap();
+                Hook(hook, targets)
+            }
+            require_stmt => {
+                // either a bare recipe name or a quoted "path#recipe" reference
+                let mut inner = pair.into_inner();
+                let target = inner.next().unwrap();
+                let target = match target.as_rule() {
+                    string => single_string_inner(target),
+                    _ => target.as_str().into(),
+                };
+
+                let bindings = inner
+                    .map(|pair| {
+                        let mut inner = pair.into_inner();
+                        let var_name = consume_name(&mut inner).unwrap();
+                        let value = consume_string(&mut inner).unwrap();
+                        (var_name, value)
+                    })
+                    .collect();
+
+                Require(target, bindings)
+            }
+            param_stmt => {
+                // `secret` and the default value are both optional
+                let mut param = String::new();
+                let mut value = None;
+                let mut is_secret = false;
+                for part in pair.into_inner() {
+                    match part.as_rule() {
+                        secret => is_secret = true,
+                        name => param = part.as_str().into(),
+                        _ => value = Some(single_string_inner(part)),
+                    }
+                }
+
+                Param(param, value, is_secret)
+            }
+            positional_stmt => Positional(pair.into_inner().map(|x| x.as_str().into()).collect()),
+            unset_stmt => Unset(pair.into_inner().map(|x| x.as_str().into()).collect()),
+            settings_stmt => Settings(
+                pair.into_inner()
+                    .map(|pair| {
+                        // values are either bare words like `4` and `true` or quoted strings
+                        let mut inner = pair.into_inner();
+                        let key = consume_name(&mut inner).unwrap();
+                        let value = inner.next().unwrap();
+                        let value = match value.as_rule() {
+                            string => single_string_inner(value),
+                            _ => value.as_str().into(),
+                        };
+                        (key, value)
+                    })
+                    .collect(),
+            ),
+            run_stmt | short_run_stmt => Run(single_string(pair)),
+            run_list_stmt => RunList(single_list(pair)),
+            run_any_stmt => RunAny(single_list(pair)),
+            run_raw_stmt => RunRaw(single_string(pair)),
+            script_stmt => {
+                let body = pair
+                    .into_inner()
+                    .next()
+                    .unwrap()
+                    .into_inner()
+                    .next()
+                    .unwrap();
+                Script(dedent(body.as_str()))
+            }
+            umask_stmt => Umask(single_string(pair)),
+            timeout_stmt => Timeout(single_string(pair)),
+            watch_stmt => Watch(single_list(pair)),
+            shell_functions_stmt => ShellFunctions(single_list(pair)),
+            env_file_stmt => EnvFile(single_string(pair)),
+            inputs_stmt => Inputs(single_string_or_list(pair)),
+            outputs_stmt => Outputs(single_string_or_list(pair)),
             version_stmt => Version(single_string(pair)),
+            weight_stmt => Weight(single_name(pair)),
+            retry_stmt => Retry(single_name(pair)),
             _ => unreachable!(),
         }
     }
@@ -189,6 +481,74 @@ fn unescape(source: &str) -> String {
     new
 }
 
+/// Given a &str, wrap it in quotes and escape it so that `unescape` reverses it
+fn quote(source: &str) -> String {
+    let mut new = String::with_capacity(source.len() + 2);
+    new.push('"');
+
+    for ch in source.chars() {
+        match ch {
+            '"' => new.push_str("\\\""),
+            '\\' => new.push_str("\\\\"),
+            '\n' => new.push_str("\\n"),
+            '\r' => new.push_str("\\r"),
+            '\t' => new.push_str("\\t"),
+            x => new.push(x),
+        }
+    }
+
+    new.push('"');
+    new
+}
+
+/// Given the contents of a triple-quoted string, remove the indentation shared by its lines
+///
+/// The line breaks right after the opening quotes and right before the closing quotes are
+/// dropped, so that the body can be indented along with the rest of the recipe. The result always
+/// ends with a newline.
+fn dedent(source: &str) -> String {
+    let mut lines: Vec<&str> = source.lines().collect();
+    if lines.first().is_some_and(|x| x.trim().is_empty()) {
+        lines.remove(0);
+    }
+    if lines.last().is_some_and(|x| x.trim().is_empty()) {
+        lines.pop();
+    }
+
+    let indent = lines
+        .iter()
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| x.len() - x.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    let mut new = String::with_capacity(source.len());
+    for line in lines {
+        new.push_str(line.get(indent..).unwrap_or("").trim_end());
+        new.push('\n');
+    }
+
+    new
+}
+
+/// Check whether a &str can be written as a bare `name`
+fn is_name(source: &str) -> bool {
+    !source.is_empty()
+        && source
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || "_-/:".contains(x))
+}
+
+/// Given a Pairs iterator, try to yank a `list` of strings out of it
+fn consume_list(pairs: &mut Pairs<Rule>) -> Option<Vec<String>> {
+    pairs.next().map(|x| {
+        x.into_inner()
+            .filter_map(|x| x.into_inner().next())
+            .map(|x| unescape(x.as_str()))
+            .collect()
+    })
+}
+
 /// Given a Pairs iterator, try to yank a `name` out of it
 fn consume_name(pairs: &mut Pairs<Rule>) -> Option<String> {
     pairs.next().map(|x| x.as_str().to_string())
@@ -212,6 +572,28 @@ fn single_string(pair: Pair<Rule>) -> String {
     consume_string(&mut pair.into_inner()).unwrap()
 }
 
+/// Given a `string` Pair, unescape its contents
+fn single_string_inner(pair: Pair<Rule>) -> String {
+    pair.into_inner()
+        .next()
+        .map(|x| unescape(x.as_str()))
+        .unwrap()
+}
+
+/// Given a Pair, consume a single `list` from it
+fn single_list(pair: Pair<Rule>) -> Vec<String> {
+    consume_list(&mut pair.into_inner()).unwrap()
+}
+
+/// Given a Pair, consume either a single `string` or a `list` of them from it
+fn single_string_or_list(pair: Pair<Rule>) -> Vec<String> {
+    let inner = pair.into_inner().next().unwrap();
+    match inner.as_rule() {
+        Rule::list => inner.into_inner().map(single_string_inner).collect(),
+        _ => vec![single_string_inner(inner)],
+    }
+}
+
 /// Given a Pair, consume a single `name` from it
 fn single_name(pair: Pair<Rule>) -> String {
     consume_name(&mut pair.into_inner()).unwrap()
@@ -223,25 +605,366 @@ fn single_expr(pair: Pair<Rule>) -> Expr {
 }
 
 /// Given a &str of mold lang code, convert it into a pest parse tree
+///
+/// A moldfile written for a newer mold might not parse at all, so when parsing fails, any
+/// `requires_features` at the top of the file are checked before reporting the parse error.
 fn parse(code: &str) -> Result<Vec<Statement>, Error> {
-    let mut main = MoldParser::parse(Rule::main, code)?;
-    let stmts = consume_statements(&mut main);
-    Ok(stmts)
+    match MoldParser::parse(Rule::main, code) {
+        Ok(mut main) => {
+            let stmts = consume_statements(&mut main);
+            check_features(&stmts)?;
+            Ok(stmts)
+        }
+        Err(err) => {
+            if let Ok(mut prelude) = MoldParser::parse(Rule::prelude, code) {
+                check_features(&consume_statements(&mut prelude))?;
+            }
+            Err(err.into())
+        }
+    }
+}
+
+/// Find the env file that a moldfile names with `env_file`, if it parses and names one
+///
+/// Only top-level statements count, since the file is loaded before any conditions are evaluated.
+pub fn env_file(code: &str) -> Option<String> {
+    parse(code).ok()?.into_iter().find_map(|stmt| match stmt {
+        Statement::EnvFile(path) => Some(path),
+        _ => None,
+    })
+}
+
+/// Given a &str of user config, collect the values of all of its `settings` blocks
+pub fn parse_settings(code: &str) -> Result<IndexMap<String, String>, Error> {
+    let mut values = IndexMap::new();
+    for stmt in consume_statements(&mut MoldParser::parse(Rule::settings_file, code)?) {
+        if let Statement::Settings(pairs) = stmt {
+            values.extend(pairs);
+        }
+    }
+
+    Ok(values)
+}
+
+/// Make sure that every feature required by `requires_features` is supported
+fn check_features(stmts: &[Statement]) -> Result<(), Error> {
+    for stmt in stmts {
+        if let Statement::Features(names) = stmt {
+            if let Some(name) = names.iter().find(|x| !FEATURES.contains(&x.as_str())) {
+                return Err(format_err!(
+                    "this moldfile needs the '{}' feature, which mold {} doesn't support",
+                    name,
+                    super::VERSION
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Given a list of Statements, convert them back into mold lang code
+///
+/// The output always parses back into the same Statements, but comments and formatting from any
+/// original source are lost.
+pub fn emit(statements: &[Statement]) -> String {
+    let mut out = String::new();
+    emit_body(statements, 0, &mut out);
+    out
+}
+
+/// Find the command and help of a recipe body that can be written as `recipe name = "command"`
+fn short_recipe(body: &[Statement]) -> Option<(&String, Option<&String>)> {
+    match body {
+        [Statement::Run(command)] => Some((command, None)),
+        [Statement::Run(command), Statement::Help(help)]
+        | [Statement::Help(help), Statement::Run(command)] => Some((command, Some(help))),
+        _ => None,
+    }
+}
+
+/// Write a list of Statements at a given indentation level
+fn emit_body(statements: &[Statement], depth: usize, out: &mut String) {
+    use Statement::*;
+
+    let indent = "  ".repeat(depth);
+    for (idx, stmt) in statements.iter().enumerate() {
+        // give blocks some room to breathe at the top level
+        if depth == 0 && idx > 0 && matches!(stmt, Recipe(..) | Check(..) | IfBlock(..)) {
+            out.push('\n');
+        }
+
+        out.push_str(&indent);
+        match stmt {
+            IfBlock(cases) => {
+                for (idx, case) in cases.iter().enumerate() {
+                    let (head, body) = match case {
+                        If(expr, body) if idx == 0 => (format!("if {} {{", expr), body),
+                        If(expr, body) => (format!(" elif {} {{", expr), body),
+                        Else(body) => (" else {".to_string(), body),
+                        _ => unreachable!(),
+                    };
+                    out.push_str(&head);
+                    out.push('\n');
+                    emit_body(body, depth + 1, out);
+                    out.push_str(&indent);
+                    out.push('}');
+                }
+            }
+
+            // recipes that only run one command keep the short form
+            Recipe(name, _, body) => match short_recipe(body) {
+                Some((command, help)) => {
+                    out.push_str(&format!("recipe {} = {}", name, quote(command)));
+                    if let Some(help) = help {
+                        out.push_str(&format!(" help {}", quote(help)));
+                    }
+                }
+                None => {
+                    out.push_str(&format!("recipe {} {{\n", name));
+                    emit_body(body, depth + 1, out);
+                    out.push_str(&indent);
+                    out.push('}');
+                }
+            },
+
+            Check(name, body) => {
+                out.push_str(&format!("check {} {{\n", quote(name)));
+                emit_body(body, depth + 1, out);
+                out.push_str(&indent);
+                out.push('}');
+            }
+
+            Settings(pairs) => {
+                out.push_str("settings {\n");
+                for (key, value) in pairs {
+                    let value = if is_name(value) {
+                        value.clone()
+                    } else {
+                        quote(value)
+                    };
+                    out.push_str(&format!("{}  {} {}\n", indent, key, value));
+                }
+                out.push_str(&indent);
+                out.push('}');
+            }
+
+            CleanTree => out.push_str("requires_clean_tree"),
+            Cache => out.push_str("cache"),
+            Exclusive => out.push_str("exclusive"),
+            Private => out.push_str("private"),
+            Compat(name) => out.push_str(&format!("compat {}", name)),
+            Completed(s, None) => out.push_str(&format!("require completed:{}", s)),
+            Completed(s, Some(age)) => {
+                out.push_str(&format!("require completed:{} within {}", s, quote(age)))
+            }
+            Dir(s) => out.push_str(&format!("dir {}", quote(s))),
+            Shell(s) => out.push_str(&format!("shell {}", quote(s))),
+            Interpreter(s) => out.push_str(&format!("interpreter {}", quote(s))),
+            RequireVar(name, help) => {
+                out.push_str(&format!("require_var {}", name));
+                if let Some(help) = help {
+                    out.push_str(&format!(" {}", quote(help)));
+                }
+            }
+            Expected(s) => out.push_str(&format!("expected {}", quote(s))),
+            Timeout(s) => out.push_str(&format!("timeout {}", quote(s))),
+            TimeoutBlock(s, body) => {
+                out.push_str(&format!("timeout {} {{\n", quote(s)));
+                emit_body(body, depth + 1, out);
+                out.push_str(&indent);
+                out.push('}');
+            }
+            Features(names) => out.push_str(&format!("requires_features {}", names.join(" "))),
+            Environments(names) => out.push_str(&format!("environments {}", names.join(", "))),
+            Positional(names) => out.push_str(&format!("positional {}", names.join(" "))),
+            Help(s) => out.push_str(&format!("help {}", quote(s))),
+            Hook(hook, targets) => out.push_str(&format!("hook {} = {}", hook, quote(targets))),
+            Import(s, prefix, is_scoped) | ImportRemote(_, s, prefix, is_scoped) => {
+                out.push_str("import ");
+                if let ImportRemote(via, ..) = stmt {
+                    out.push_str(&format!("{} ", via));
+                }
+                out.push_str(&quote(s));
+                if let Some(prefix) = prefix {
+                    out.push_str(&format!(" as {}", prefix));
+                }
+                if *is_scoped {
+                    out.push_str(" scoped");
+                }
+            }
+            DeclareRemote(name, url) => out.push_str(&format!("remote {} = {}", name, quote(url))),
+            Require(s, bindings) => {
+                if is_name(s) {
+                    out.push_str(&format!("require {}", s));
+                } else {
+                    out.push_str(&format!("require {}", quote(s)));
+                }
+
+                let bindings: Vec<_> = bindings
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, quote(value)))
+                    .collect();
+                if !bindings.is_empty() {
+                    out.push_str(&format!(" with {}", bindings.join(", ")));
+                }
+            }
+            Run(s) => out.push_str(&format!("$ {}", quote(s))),
+            RunList(args) => {
+                let args: Vec<_> = args.iter().map(|x| quote(x)).collect();
+                out.push_str(&format!("$ [{}]", args.join(", ")));
+            }
+            RunAny(alternatives) => {
+                let alternatives: Vec<_> = alternatives.iter().map(|x| quote(x)).collect();
+                out.push_str(&format!("$ any [{}]", alternatives.join(", ")));
+            }
+            RunRaw(s) => out.push_str(&format!("run raw {}", quote(s))),
+            Watch(patterns) => {
+                let patterns: Vec<_> = patterns.iter().map(|x| quote(x)).collect();
+                out.push_str(&format!("watch [{}]", patterns.join(", ")));
+            }
+            ShellFunctions(names) => {
+                let names: Vec<_> = names.iter().map(|x| quote(x)).collect();
+                out.push_str(&format!("shell_functions [{}]", names.join(", ")));
+            }
+            EnvFile(path) => out.push_str(&format!("env_file {}", quote(path))),
+            Inputs(patterns) | Outputs(patterns) => {
+                let keyword = if let Inputs(_) = stmt {
+                    "inputs"
+                } else {
+                    "outputs"
+                };
+                let patterns: Vec<_> = patterns.iter().map(|x| quote(x)).collect();
+                match patterns.as_slice() {
+                    [pattern] => out.push_str(&format!("{} {}", keyword, pattern)),
+                    _ => out.push_str(&format!("{} [{}]", keyword, patterns.join(", "))),
+                }
+            }
+            Script(s) => {
+                out.push_str("script \"\"\"\n");
+                for line in s.lines() {
+                    if !line.is_empty() {
+                        out.push_str(&indent);
+                        out.push_str("  ");
+                        out.push_str(line);
+                    }
+                    out.push('\n');
+                }
+                out.push_str(&indent);
+                out.push_str("\"\"\"");
+            }
+            Var(name, value) => out.push_str(&format!("var {} = {}", name, quote(value))),
+            ShellVar(name, command) => {
+                out.push_str(&format!("var {} = shell {}", name, quote(command)))
+            }
+            Append(name, value) => out.push_str(&format!("var append {} = {}", name, quote(value))),
+            Prepend(name, value) => {
+                out.push_str(&format!("var prepend {} = {}", name, quote(value)))
+            }
+            Default(name, value) => out.push_str(&format!("var {} := {}", name, quote(value))),
+            Unset(names) => out.push_str(&format!("unset {}", names.join(" "))),
+            Umask(s) => out.push_str(&format!("umask {}", quote(s))),
+            Version(s) => out.push_str(&format!("version {}", quote(s))),
+            Weight(s) => out.push_str(&format!("weight {}", s)),
+            Retry(s) => out.push_str(&format!("retry {}", s)),
+            Param(name, default, is_secret) => {
+                out.push_str("param ");
+                if *is_secret {
+                    out.push_str("secret ");
+                }
+                out.push_str(name);
+                if let Some(default) = default {
+                    out.push_str(&format!(" = {}", quote(default)));
+                }
+            }
+            If(..) | Else(..) => unreachable!(),
+        }
+        out.push('\n');
+    }
 }
 
 /// Given a &str of code and an EnvSet, compile it into a Moldfile
+///
+/// Conditions are evaluated in file order as the file is compiled, so variable comparisons see
+/// the variables from every previously loaded moldfile, plus any assignments that appear earlier
+/// in this file. Variables from this file's own imports aren't visible, since those are loaded
+/// afterwards.
 pub fn compile(code: &str, mold: &mut super::Mold) -> Result<super::Moldfile, Error> {
+    let statements = parse(code)?;
+    check_recipes(&statements)?;
+    compile_statements(statements, mold)
+}
+
+/// Reject recipes that can't do anything on any platform, since they're always a mistake
+///
+/// Legacy YAML files skip this; recipes that they can't translate are already warned about.
+fn check_recipes(statements: &[Statement]) -> Result<(), Error> {
+    use Statement::*;
+
+    for stmt in statements {
+        match stmt {
+            Recipe(name, _, body) if !has_effect(body) => {
+                return Err(format_err!(
+                    "Recipe {} has no commands and no requirements",
+                    name
+                ));
+            }
+            IfBlock(body) | If(_, body) | Else(body) => check_recipes(body)?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Given a Vec<Statement> from any source, compile it into a Moldfile
+pub fn compile_statements(
+    statements: Vec<Statement>,
+    mold: &mut super::Mold,
+) -> Result<super::Moldfile, Error> {
     use Statement::*;
-    let statements = flatten(parse(code)?, &mold.envs)?;
+
+    // every environment referenced by any condition, whether or not it ends up applying
+    let mut conditions = super::EnvSet::new();
+    collect_atoms(&statements, &mut conditions);
+
+    // variables as they stand at the current point in the file, for evaluating conditions.
+    // overrides from the command line are visible from the start, like an earlier `var`
+    let mut scope = mold.vars.clone();
+    scope.extend(mold.overrides.clone());
 
     let mut version = None;
     let mut dir = None;
+    let mut shell = None;
+    let mut windows_vars = false;
+    let mut environments = super::EnvSet::new();
     let mut includes = super::IncludeVec::new();
+    let mut remotes: IndexMap<String, remote::Remote> = IndexMap::new();
     let mut recipes = super::RecipeMap::new();
-    let mut vars = super::VarMap::new();
+    let mut checks = super::RecipeMap::new();
+    let mut vars = super::VarOpVec::new();
+    let mut settings = IndexMap::new();
+    let mut hooks = IndexMap::new();
+    let mut exclusive = false;
+    let mut shell_functions: Option<Vec<String>> = None;
+    let mut required_vars = vec![];
 
-    for stmt in statements {
+    // each statement is paired with the conditions that had to hold for it to be reached
+    let mut statements: VecDeque<(Statement, Vec<String>)> =
+        statements.into_iter().map(|x| (x, vec![])).collect();
+
+    while let Some((stmt, guards)) = statements.pop_front() {
         match stmt {
+            IfBlock(cases) => {
+                let expanded = mold.expand_vars(&scope);
+                let (body, guard) = select_case(cases, &mold.envs, &expanded);
+                for stmt in body.into_iter().rev() {
+                    let mut guards = guards.clone();
+                    guards.extend(guard.clone());
+                    statements.push_front((stmt, guards));
+                }
+            }
+
             Version(s) => {
                 if version.is_none() {
                     version = Some(s);
@@ -250,65 +973,281 @@ pub fn compile(code: &str, mold: &mut super::Mold) -> Result<super::Moldfile, Er
                 }
             }
 
-            Help(_) => {}
+            // env files are loaded before the moldfile is compiled, by `env_file` below
+            Help(_) | Features(_) | EnvFile(_) => {}
 
-            Import(url, prefix) => includes.push(super::Include {
-                remote: remote::Remote::from_str(&url)?,
-                prefix: prefix.unwrap_or_else(|| "".to_string()),
-            }),
+            Compat(option) => match option.as_str() {
+                "windows_vars" => windows_vars = true,
+                _ => return Err(format_err!("Unknown compat option: {}", option)),
+            },
+
+            Environments(names) => environments.extend(names),
+
+            Import(url, prefix, scoped) => {
+                let prefix = prefix.unwrap_or_else(|| "".to_string());
+                if scoped && prefix.is_empty() {
+                    return Err(format_err!("Scoped import {} needs a prefix", url));
+                }
+
+                includes.push(super::Include {
+                    remote: remote::Remote::from_str(&url)?,
+                    prefix,
+                    scoped,
+                });
+            }
+
+            DeclareRemote(name, url) => {
+                let remote = remote::Remote::from_str(&url)?;
+                if remote.file.is_some() {
+                    return Err(format_err!(
+                        "Remote {} names a file; import files from it instead, like: import {} \"file\"",
+                        name,
+                        name
+                    ));
+                }
+                if remotes.insert(name.clone(), remote).is_some() {
+                    return Err(format_err!("Duplicate remote specified: {}", name));
+                }
+            }
+
+            ImportRemote(via, file, prefix, scoped) => {
+                let prefix = prefix.unwrap_or_else(|| "".to_string());
+                if scoped && prefix.is_empty() {
+                    return Err(format_err!("Scoped import {} {} needs a prefix", via, file));
+                }
+
+                let mut remote = remotes.get(&via).cloned().ok_or_else(|| {
+                    format_err!(
+                        "Unknown remote {}; declare it first, like: remote {} = \"url#ref\"",
+                        via,
+                        via
+                    )
+                })?;
+                remote.file = Some(file.into());
+                includes.push(super::Include {
+                    remote,
+                    prefix,
+                    scoped,
+                });
+            }
 
             Var(name, value) => {
                 if mold.use_vars {
-                    vars.insert(name, value);
+                    add_var(&mut vars, &mut scope, name, super::VarOp::Set(value));
+                }
+            }
+
+            ShellVar(name, command) => {
+                if mold.use_vars {
+                    // the output is taken literally, so a $ in it isn't expanded again later
+                    let value = mold.capture_var(&name, &command, &scope)?;
+                    let value = super::VarOp::Set(value.replace('$', "$$"));
+                    add_var(&mut vars, &mut scope, name, value);
+                }
+            }
+
+            Append(name, value) => {
+                if mold.use_vars {
+                    add_var(&mut vars, &mut scope, name, super::VarOp::Append(value));
+                }
+            }
+
+            Prepend(name, value) => {
+                if mold.use_vars {
+                    add_var(&mut vars, &mut scope, name, super::VarOp::Prepend(value));
                 }
             }
 
             Default(name, value) => {
-                if mold.use_vars
-                    && !vars.contains_key(&name)
-                    && !mold.vars.contains_key(&name)
-                    && std::env::var(&name).is_err()
-                {
-                    vars.insert(name, value);
+                if mold.use_vars && !scope.contains_key(&name) && std::env::var(&name).is_err() {
+                    add_var(&mut vars, &mut scope, name, super::VarOp::Set(value));
                 }
             }
 
-            Recipe(name, body) => {
-                recipes.insert(name, compile_recipe(body, mold)?);
+            Unset(names) => {
+                if mold.use_vars {
+                    for name in names {
+                        add_var(&mut vars, &mut scope, name, super::VarOp::Unset);
+                    }
+                }
+            }
+
+            Recipe(name, line, body) => {
+                let expanded = mold.expand_vars(&scope);
+                let mut recipe = compile_recipe(body, mold, &expanded)?;
+                recipe.line = line;
+                recipe.guards = guards;
+                recipes.insert(name, recipe);
+            }
+
+            Check(name, body) => {
+                let expanded = mold.expand_vars(&scope);
+                checks.insert(name, compile_recipe(body, mold, &expanded)?);
             }
 
             Dir(path) => {
                 dir = Some(path);
             }
 
+            Shell(command) => {
+                shell = Some(command);
+            }
+
+            Settings(pairs) => {
+                for (key, value) in pairs {
+                    super::settings::check(&key, &value)?;
+                    settings.insert(key, value);
+                }
+            }
+
+            Hook(hook, targets) => {
+                super::hooks::check(&hook)?;
+                hooks.insert(hook, targets);
+            }
+
+            Exclusive => {
+                exclusive = true;
+            }
+
+            ShellFunctions(names) => {
+                shell_functions.get_or_insert_with(Vec::new).extend(names);
+            }
+
+            RequireVar(name, help) => {
+                required_vars.push((name, help));
+            }
+
             _ => unreachable!(),
         }
     }
 
     let version = version.ok_or_else(|| err_msg("File version must be specified"))?;
 
+    // recipes without a shell of their own use their moldfile's, wherever it was declared
+    // the same goes for variables that the whole moldfile requires
+    for recipe in recipes.values_mut().chain(checks.values_mut()) {
+        if recipe.shell.is_none() {
+            recipe.shell = shell.clone();
+        }
+        recipe.required_vars.extend(required_vars.iter().cloned());
+    }
+
+    // %VAR% references are only rewritten in the moldfile that asked for it, so that imports and
+    // the files importing them don't change each other's meaning
+    if windows_vars {
+        for recipe in recipes.values_mut().chain(checks.values_mut()) {
+            recipe.convert_windows_vars();
+        }
+        for (_, op) in vars.iter_mut() {
+            op.convert_windows_vars();
+        }
+        dir = dir.map(|x| super::util::convert_windows_vars(&x));
+    }
+
     Ok(super::Moldfile {
         version,
         includes,
         recipes,
+        checks,
         vars,
         dir,
+        environments,
+        conditions,
+        settings,
+        hooks,
+        exclusive,
+        shell_functions,
+    })
+}
+
+/// Collect every environment name used in a condition anywhere in a statement tree
+fn collect_atoms(body: &[Statement], into: &mut super::EnvSet) {
+    use Statement::*;
+
+    for stmt in body {
+        match stmt {
+            If(expr, body) => {
+                expr.atoms(into);
+                collect_atoms(body, into);
+            }
+            IfBlock(body) | Else(body) | Recipe(_, _, body) | Check(_, body) => {
+                collect_atoms(body, into)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Check whether a recipe body runs or requires anything in any of its conditional branches
+fn has_effect(body: &[Statement]) -> bool {
+    use Statement::*;
+
+    body.iter().any(|stmt| match stmt {
+        Run(_)
+        | RunList(_)
+        | RunAny(_)
+        | RunRaw(_)
+        | Script(_)
+        | Require(_, _)
+        | Completed(_, _) => true,
+        IfBlock(body) | If(_, body) | Else(body) | TimeoutBlock(_, body) => has_effect(body),
+        _ => false,
     })
 }
 
+/// Record a variable assignment and update the in-progress scope
+///
+/// Unset variables stay in the scope as empty strings so that they don't expand to their value
+/// from the environment.
+fn add_var(vars: &mut super::VarOpVec, scope: &mut super::VarMap, name: String, op: super::VarOp) {
+    let value = op.clone().resolve(&name, scope).unwrap_or_default();
+    scope.insert(name.clone(), value);
+    vars.push((name, op));
+}
+
+/// Parse a timeout, which is a duration like `5m` or a plain number of seconds
+fn parse_timeout(source: &str) -> Result<std::time::Duration, Error> {
+    match source.trim().parse() {
+        Ok(secs) => Ok(std::time::Duration::from_secs(secs)),
+        Err(_) => super::util::parse_duration(source),
+    }
+}
+
 /// Given a Vec<Statement> and an EnvSet, compile it into a Recipe
 pub fn compile_recipe(
     body: Vec<Statement>,
     mold: &mut super::Mold,
+    vars: &super::VarMap,
 ) -> Result<super::Recipe, Error> {
     use Statement::*;
 
     let mut help = None;
     let mut dir = None;
     let mut commands = vec![];
+    let mut script = None;
     let mut requires = super::TargetSet::new();
+    let mut bound = super::BindingMap::new();
+    let mut clean_tree = false;
+    let mut cache = false;
+    let mut exclusive = false;
+    let mut private = false;
+    let mut weight = 1;
+    let mut retries = 0;
+    let mut shell = None;
+    let mut required_vars = vec![];
+    let mut interpreter = None;
+    let mut umask = None;
+    let mut expected = None;
+    let mut completed = vec![];
+    let mut params = vec![];
+    let mut positionals = vec![];
+    let mut unset = vec![];
+    let mut timeout = None;
+    let mut watch = vec![];
+    let mut inputs = vec![];
+    let mut outputs = vec![];
 
-    let body = flatten(body, &mold.envs)?;
+    let body = flatten(body, &mold.envs, vars)?;
 
     for stmt in body {
         match stmt {
@@ -320,12 +1259,149 @@ pub fn compile_recipe(
                 dir = Some(s);
             }
 
+            Shell(s) => {
+                shell = Some(s);
+            }
+
+            Interpreter(s) => {
+                interpreter = Some(s);
+            }
+
+            RequireVar(name, help) => {
+                required_vars.push((name, help));
+            }
+
             Run(cmd) => {
-                commands.push(cmd);
+                commands.push(super::Command::Shell(cmd));
+            }
+
+            RunList(args) => {
+                commands.push(super::Command::Args(args));
+            }
+
+            RunRaw(cmd) => {
+                commands.push(super::Command::Raw(cmd));
+            }
+
+            RunAny(alternatives) => {
+                if alternatives.is_empty() {
+                    return Err(err_msg("`run any` needs at least one alternative"));
+                }
+                commands.push(super::Command::Any(alternatives));
+            }
+
+            TimeoutBlock(limit, body) => {
+                let limit = parse_timeout(&limit)?;
+                for stmt in body {
+                    let command = match stmt {
+                        Run(cmd) => super::Command::Shell(cmd),
+                        RunList(args) => super::Command::Args(args),
+                        RunRaw(cmd) => super::Command::Raw(cmd),
+                        RunAny(alternatives) => super::Command::Any(alternatives),
+                        _ => unreachable!(),
+                    };
+                    commands.push(super::Command::Timed(Box::new(command), limit));
+                }
+            }
+
+            Script(body) => {
+                if script.is_some() {
+                    return Err(err_msg("Recipes can only have one script"));
+                }
+                script = Some(body);
+            }
+
+            Require(recipe, bindings) => {
+                if bindings.is_empty() {
+                    requires.insert(recipe);
+                } else {
+                    let bindings: super::VarMap = bindings.into_iter().collect();
+                    let key = super::instance_key(&recipe, &bindings);
+                    requires.insert(key.clone());
+                    bound.insert(key, (recipe, bindings));
+                }
+            }
+
+            CleanTree => {
+                clean_tree = true;
+            }
+
+            Cache => {
+                cache = true;
             }
 
-            Require(recipe) => {
-                requires.insert(recipe);
+            Exclusive => {
+                exclusive = true;
+            }
+
+            Private => {
+                private = true;
+            }
+
+            Completed(recipe, max_age) => {
+                let max_age = match max_age {
+                    Some(s) => Some(super::util::parse_duration(&s)?),
+                    None => None,
+                };
+                completed.push((recipe, max_age));
+            }
+
+            Expected(s) => {
+                expected = Some(super::util::parse_duration(&s)?);
+            }
+
+            Timeout(s) => {
+                timeout = Some(parse_timeout(&s)?);
+            }
+
+            Umask(s) => {
+                umask = match u32::from_str_radix(&s, 8) {
+                    Ok(mask) if mask <= 0o777 => Some(mask),
+                    _ => return Err(format_err!("Invalid umask: {}", s)),
+                };
+            }
+
+            Param(name, default, secret) => {
+                params.push(super::Param {
+                    name,
+                    default,
+                    secret,
+                });
+            }
+
+            Positional(names) => {
+                if !positionals.is_empty() {
+                    return Err(err_msg("Recipes can only have one positional statement"));
+                }
+                positionals = names;
+            }
+
+            Unset(names) => {
+                unset.extend(names);
+            }
+
+            Watch(patterns) => {
+                watch.extend(patterns);
+            }
+
+            Inputs(patterns) => {
+                inputs.extend(patterns);
+            }
+
+            Outputs(patterns) => {
+                outputs.extend(patterns);
+            }
+
+            Retry(s) => {
+                retries = s
+                    .parse()
+                    .map_err(|_| format_err!("Invalid retry count: {}", s))?;
+            }
+
+            Weight(s) => {
+                weight = s
+                    .parse()
+                    .map_err(|_| format_err!("Invalid weight: {}", s))?;
             }
 
             _ => unreachable!(),
@@ -335,8 +1411,31 @@ pub fn compile_recipe(
     Ok(super::Recipe {
         help,
         commands,
+        script,
         dir,
+        shell,
+        interpreter,
+        required_vars,
         requires,
+        clean_tree,
+        cache,
+        exclusive,
+        private,
+        completed,
+        params,
+        positionals,
+        unset,
+        timeout,
+        watch,
+        inputs,
+        outputs,
+        bindings: bound,
+        weight,
+        retries,
+        umask,
+        expected,
+        line: None,
+        guards: vec![],
     })
 }
 
@@ -348,7 +1447,11 @@ pub fn compile_recipe(
 /// flattened is an IfBlock, which will only contain a sequence of If statements followed by an
 /// optional Else statement. If these assumptions are ever violated, this function will simply not
 /// work as expected.
-pub fn flatten(body: Vec<Statement>, envs: &super::EnvSet) -> Result<Vec<Statement>, Error> {
+pub fn flatten(
+    body: Vec<Statement>,
+    envs: &super::EnvSet,
+    vars: &super::VarMap,
+) -> Result<Vec<Statement>, Error> {
     let mut ret = vec![];
 
     for stmt in body {
@@ -356,26 +1459,7 @@ pub fn flatten(body: Vec<Statement>, envs: &super::EnvSet) -> Result<Vec<Stateme
             // IfBlock is the only conditional structure we flatten, and it should only ever contain a
             // series of If statements followed by an optional Else. Anything else will break this.
             Statement::IfBlock(cases) => {
-                for case in cases {
-                    match case {
-                        // If should check if its condition applies, and if so, push its contents and then
-                        // break the loop. This gives us the if..elif behavior.
-                        Statement::If(expr, body) => {
-                            if expr.apply(envs) {
-                                ret.extend(flatten(body, envs)?);
-                                break;
-                            }
-                        }
-                        // Else has no condition to check, so it unconditionally applies and breaks. Because of
-                        // the grammar constraints, this should only ever appear as the last case.
-                        Statement::Else(body) => {
-                            ret.extend(flatten(body, envs)?);
-                            break;
-                        }
-                        // Nothing else should ever appear in an IfBlock.
-                        _ => unreachable!(),
-                    }
-                }
+                ret.extend(flatten(select_case(cases, envs, vars).0, envs, vars)?);
             }
 
             // All non-IfBlock statemnts are pushed through transparently.
@@ -385,3 +1469,37 @@ pub fn flatten(body: Vec<Statement>, envs: &super::EnvSet) -> Result<Vec<Stateme
 
     Ok(ret)
 }
+
+/// Given the cases of an IfBlock, return the body of the first one that applies
+///
+/// The returned body is not flattened, so any nested IfBlocks are left for the caller. The
+/// condition that selected the body is returned alongside it, including the negations of every
+/// earlier case.
+fn select_case(
+    cases: Vec<Statement>,
+    envs: &super::EnvSet,
+    vars: &super::VarMap,
+) -> (Vec<Statement>, Option<String>) {
+    let mut skipped = vec![];
+
+    for case in cases {
+        match case {
+            // If should check if its condition applies, and if so, return its contents. This gives
+            // us the if..elif behavior.
+            Statement::If(expr, body) => {
+                if expr.apply(envs, vars) {
+                    skipped.push(expr.to_string());
+                    return (body, Some(skipped.join(" + ")));
+                }
+                skipped.push(format!("~({})", expr));
+            }
+            // Else has no condition to check, so it unconditionally applies. Because of the grammar
+            // constraints, this should only ever appear as the last case.
+            Statement::Else(body) => return (body, Some(skipped.join(" + "))),
+            // Nothing else should ever appear in an IfBlock.
+            _ => unreachable!(),
+        }
+    }
+
+    (vec![], None)
+}
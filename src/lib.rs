@@ -1,30 +1,94 @@
 mod cargo;
+pub mod dotenv;
+pub mod exclusive;
+pub mod hooks;
 pub mod lang;
+pub mod lockfile;
+pub mod manifest;
+pub mod order;
+pub mod output;
 pub mod remote;
+pub mod script;
+pub mod settings;
 pub mod util;
+pub mod watch;
+#[cfg(feature = "yaml")]
+pub mod yaml;
 
 use colored::*;
 use failure::Error;
 use indexmap::indexmap;
 use indexmap::IndexMap;
 use indexmap::IndexSet;
+use lockfile::Lockfile;
+use lockfile::LOCKFILE;
+use output::Event;
 use remote::Remote;
 use semver::Version;
 use semver::VersionReq;
 use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::fmt;
 use std::fs;
 use std::io::prelude::*;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process;
 use std::string::ToString;
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 // sorted by insertion order
 pub type IncludeVec = Vec<Include>;
 pub type TargetSet = IndexSet<String>;
 pub type EnvSet = IndexSet<String>;
-pub type VarMap = IndexMap<String, String>; // TODO maybe down the line this should allow nulls to `unset` a variable
+pub type VarMap = IndexMap<String, String>;
 pub type SourceMap = IndexMap<String, PathBuf>;
+pub type VarOpVec = Vec<(String, VarOp)>;
+pub type LocalFileMap = IndexMap<PathBuf, String>;
+pub type BindingMap = IndexMap<String, (String, VarMap)>;
+pub type ImportMap = IndexMap<PathBuf, Vec<(String, Remote, PathBuf)>>;
+
+/// Version of the `--dump json` document format, bumped on incompatible changes
+pub const DUMP_SCHEMA: u32 = 1;
+
+/// Version of mold, used to check moldfile version requirements
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Variables holding paths that mold sets itself, which are never split into multiple arguments
+const PATH_VARS: &[&str] = &[
+    "MOLD_ROOT",
+    "MOLD_DIR",
+    "MOLD_SOURCE",
+    "MOLD_SCRIPT",
+    "MOLD_STATE",
+];
+
+/// How many remotes are cloned at once when several imports are missing
+const CLONE_JOBS: usize = 4;
+
+/// Environments that mold activates automatically on some platform
+pub const PLATFORM_ENVS: &[&str] = &[
+    "unix",
+    "windows",
+    "wasm",
+    "linux",
+    "macos",
+    "ios",
+    "android",
+    "freebsd",
+    "dragonfly",
+    "netbsd",
+    "openbsd",
+    "solaris",
+];
 
 // sorted alphabetically
 pub type RecipeMap = BTreeMap<String, Recipe>;
@@ -40,12 +104,69 @@ pub struct Mold {
     /// A map of recipe sources
     pub sources: SourceMap,
 
+    /// A map of `--doctor` checks
+    pub checks: RecipeMap,
+
+    /// A map of check sources
+    pub check_sources: SourceMap,
+
+    /// A map of recipes to the moldfile that defined them
+    pub recipe_files: SourceMap,
+
     /// A map of environment variables
     pub vars: VarMap,
 
+    /// A map of variable sources
+    pub var_sources: SourceMap,
+
+    /// Variables that moldfiles have unset, which are removed from the environment of commands
+    pub unset: IndexSet<String>,
+
+    /// Variables from scoped imports, keyed by the import's prefix
+    pub scopes: IndexMap<String, VarMap>,
+
+    /// Moldfiles loaded through a scoped import, mapped to the prefix of that import
+    pub scoped_files: IndexMap<PathBuf, String>,
+
+    /// Custom environments declared by any moldfile
+    pub declared_envs: EnvSet,
+
+    /// Environments referenced by conditions mapped to the first moldfile using them
+    pub condition_sources: SourceMap,
+
+    /// Recipes required with variable bindings, mapped to the base recipe and its bindings
+    pub instances: BindingMap,
+
+    /// A map of moldfiles to their imports as (prefix, remote, imported moldfile)
+    pub imports: ImportMap,
+
+    /// The version requirement that each loaded moldfile declared
+    pub versions: IndexMap<PathBuf, String>,
+
+    /// A map of moldfiles loaded through `require "path#recipe"` to their internal prefix
+    pub local_files: LocalFileMap,
+
+    /// Cross-moldfile references waiting to be verified once everything is loaded
+    references: Vec<Reference>,
+
+    /// Recipes that a later moldfile defined again, which were ignored, and that moldfile
+    pub shadowed: Vec<(String, PathBuf)>,
+
+    /// Where inline scripts are written, once something has needed one
+    script_dir: std::sync::OnceLock<PathBuf>,
+
     /// List of Remotes that have been imported
     pub remotes: Vec<Remote>,
 
+    /// Commits that imports are pinned to by mold.lock, if the project has one
+    pub lockfile: Option<Lockfile>,
+
+    /// Whether mold.lock must already pin every import, rather than being written as needed
+    pub frozen: bool,
+
+    /// Path to the origin moldfile
+    pub root_file: PathBuf,
+
     /// Root of the origin moldfile
     pub root_dir: PathBuf,
 
@@ -60,8 +181,72 @@ pub struct Mold {
     /// Use external git binary rather than libgit2
     pub use_git: bool,
 
+    /// Show output from git operations
+    pub git_verbose: bool,
+
+    /// Never clone missing remotes
+    pub offline: bool,
+
     /// Skip variables when compiling moldfiles
     pub use_vars: bool,
+
+    /// Whether to run recipes that require a clean working tree anyway
+    pub allow_dirty: bool,
+
+    /// Whether to keep running unrelated recipes after one fails
+    pub keep_going: bool,
+
+    /// Label printed alongside each command, eg: to tell matrix runs apart
+    pub label: Option<String>,
+
+    /// Whether to describe the recipe, command, and environment when a command fails
+    pub failure_context: bool,
+
+    /// Whether missing parameters may be prompted for, if `util::is_interactive` also allows it
+    pub interactive: bool,
+
+    /// Whether an import that fails to load is skipped instead of failing the whole load
+    pub skip_broken_imports: bool,
+
+    /// Imports that were skipped, mapped from their prefix to the error that broke them
+    pub broken_imports: IndexMap<String, String>,
+
+    /// Whether targets may name an imported recipe without its prefix
+    pub suffix_match: bool,
+
+    /// Whether recipes run even when their outputs are up to date
+    pub force: bool,
+
+    /// Whether recipes marked `cache` may be skipped when nothing they depend on changed
+    pub use_cache: bool,
+
+    /// Arguments given after `--`, keyed by the recipes that see them as $1, $2, ... and $@
+    pub args: IndexMap<String, Vec<String>>,
+
+    /// Parameter values given on the command line, keyed by the recipes they're for
+    pub param_args: IndexMap<String, VarMap>,
+
+    /// Variables set on the command line, which replace the values from every moldfile
+    pub overrides: VarMap,
+
+    /// Values from the root moldfile's `settings` blocks
+    pub settings: IndexMap<String, String>,
+
+    /// Git hooks declared by the root moldfile, mapped to the targets they run
+    pub hooks: IndexMap<String, String>,
+
+    /// Whether the root moldfile asked for whole runs to hold the project lock
+    pub exclusive: bool,
+
+    /// Recipes that the root moldfile allows `--shell-init` to define functions for, if it limits
+    /// them at all
+    pub shell_functions: Option<Vec<String>>,
+
+    /// How long to wait for another invocation's project lock before giving up
+    pub lock_timeout: Option<Duration>,
+
+    /// Whether variables are loaded from `.env`, or whichever file the root moldfile names
+    pub use_dotenv: bool,
 }
 
 /// An external module included for reuse
@@ -71,6 +256,201 @@ pub struct Include {
 
     /// Prefix to prepend
     pub prefix: String,
+
+    /// Whether the variables of the imported moldfile are kept under its prefix
+    pub scoped: bool,
+}
+
+/// A `require "path#recipe"` reference to a recipe in another moldfile
+struct Reference {
+    /// Registered key of the required recipe
+    key: String,
+
+    /// Recipe name as written in the referenced moldfile
+    recipe: String,
+
+    /// Referenced moldfile
+    target: PathBuf,
+
+    /// Moldfile containing the `require`
+    from: PathBuf,
+}
+
+/// How `help` and `explain` print what they describe
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Colored text for people to read
+    Plain,
+
+    /// A single JSON document on stdout for tools to read
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(OutputFormat::Plain),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(failure::format_err!(
+                "Unknown output format {}; expected plain or json",
+                s.red()
+            )),
+        }
+    }
+}
+
+/// How serious a problem found by `--validate` is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A single problem found by `--validate`
+#[derive(Clone, Debug)]
+pub struct ValidationDiagnostic {
+    pub severity: Severity,
+    pub message: String,
+
+    /// Recipe that the problem is in, if it's in one
+    pub recipe: Option<String>,
+
+    /// Where the problem is, as `file:line` or just `file`
+    pub location: Option<String>,
+}
+
+impl fmt::Display for ValidationDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+        };
+        write!(f, "{}: {}", label, self.message)?;
+        if let Some(location) = &self.location {
+            write!(f, "\n  {} {}", "-->".blue(), location)?;
+        }
+        if let Some(recipe) = &self.recipe {
+            write!(f, "\n   {} in recipe {}", "=".blue(), recipe)?;
+        }
+        Ok(())
+    }
+}
+
+/// A single variable assignment
+#[derive(Clone, Debug)]
+pub enum VarOp {
+    /// Replace any existing value
+    Set(String),
+
+    /// Concatenate onto the end of any existing value
+    Append(String),
+
+    /// Concatenate onto the start of any existing value
+    Prepend(String),
+
+    /// Remove the variable, including any value inherited from the environment
+    Unset,
+}
+
+impl VarOp {
+    /// Rewrite Windows-style %VAR% references in the value being assigned
+    pub fn convert_windows_vars(&mut self) {
+        match self {
+            VarOp::Set(value) | VarOp::Append(value) | VarOp::Prepend(value) => {
+                *value = util::convert_windows_vars(value);
+            }
+            VarOp::Unset => {}
+        }
+    }
+
+    /// Combine this assignment with the current value of `name` in `vars`
+    ///
+    /// Appending or prepending to a variable that isn't in `vars` composes with the value from
+    /// the environment instead. Unsetting has no value at all.
+    pub fn resolve(self, name: &str, vars: &VarMap) -> Option<String> {
+        let existing = || {
+            vars.get(name)
+                .cloned()
+                .or_else(|| std::env::var(name).ok())
+                .unwrap_or_default()
+        };
+
+        match self {
+            VarOp::Set(value) => Some(value),
+            VarOp::Append(value) => Some(format!("{}{}", existing(), value)),
+            VarOp::Prepend(value) => Some(format!("{}{}", value, existing())),
+            VarOp::Unset => None,
+        }
+    }
+}
+
+/// A single command line in a recipe
+#[derive(Clone, Debug)]
+pub enum Command {
+    /// A string that is split into arguments after variable expansion
+    Shell(String),
+
+    /// A pre-split list of arguments that are each expanded independently
+    Args(Vec<String>),
+
+    /// A string that is split into arguments without any variable expansion
+    Raw(String),
+
+    /// Strings like `Shell` where only the first whose program can be found is run
+    Any(Vec<String>),
+
+    /// Another command that's killed if it runs for longer than the duration
+    Timed(Box<Command>, Duration),
+}
+
+impl Command {
+    /// Rewrite Windows-style %VAR% references in everything that gets expanded
+    fn convert_windows_vars(&mut self) {
+        match self {
+            Command::Shell(command) => *command = util::convert_windows_vars(command),
+            Command::Args(args) | Command::Any(args) => {
+                for arg in args {
+                    *arg = util::convert_windows_vars(arg);
+                }
+            }
+            Command::Raw(_) => {}
+            Command::Timed(command, _) => command.convert_windows_vars(),
+        }
+    }
+
+    /// How long the command may run before it's killed, if it has a limit of its own
+    fn timeout(&self) -> Option<Duration> {
+        match self {
+            Command::Timed(_, limit) => Some(*limit),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Shell(command) | Command::Raw(command) => write!(f, "{}", command),
+            Command::Args(args) => write!(f, "{}", shell_words::join(args)),
+            Command::Any(alternatives) => write!(f, "any {}", alternatives.join(" | ")),
+            Command::Timed(command, _) => write!(f, "{}", command),
+        }
+    }
+}
+
+/// A value that a recipe needs before it can run
+#[derive(Clone)]
+pub struct Param {
+    /// Variable that the value is bound to
+    pub name: String,
+
+    /// Value used when nothing else provides one
+    pub default: Option<String>,
+
+    /// Whether the value should be hidden while it's typed
+    pub secret: bool,
 }
 
 /// A single task to execute
@@ -82,11 +462,123 @@ pub struct Recipe {
     /// Working directory relative to $MOLD_ROOT
     pub dir: Option<String>,
 
+    /// Shell that command strings are handed to, like `bash -c`, instead of being split into
+    /// arguments; a recipe without one of its own gets its moldfile's
+    pub shell: Option<String>,
+
     /// The command to execute
-    pub commands: Vec<String>,
+    pub commands: Vec<Command>,
+
+    /// Body of an inline script, written to $MOLD_SCRIPT before the commands run
+    pub script: Option<String>,
+
+    /// Program that runs the script, like `python3`, when the recipe has no commands of its own
+    pub interpreter: Option<String>,
 
     /// A list of prerequisite recipes
     pub requires: TargetSet,
+
+    /// Variables that have to be set before the recipe runs, each with an optional description
+    /// for whoever has to set it; this includes the ones its whole moldfile requires
+    pub required_vars: Vec<(String, Option<String>)>,
+
+    /// Whether the recipe refuses to run with uncommitted changes in the working tree
+    pub clean_tree: bool,
+
+    /// Whether the recipe is skipped when its inputs and commands hash the same as its last
+    /// successful run
+    pub cache: bool,
+
+    /// Whether the recipe holds the project lock while it runs, so that other invocations of
+    /// mold wait for it
+    pub exclusive: bool,
+
+    /// Whether the recipe is left out of listings, like one whose name starts with `_`
+    pub private: bool,
+
+    /// Recipes that must have succeeded in an earlier invocation, with an optional maximum age
+    pub completed: Vec<(String, Option<Duration>)>,
+
+    /// Values that must be bound before the recipe can run
+    pub params: Vec<Param>,
+
+    /// Names bound to the arguments that follow the recipe on the command line
+    pub positionals: Vec<String>,
+
+    /// Variables removed from the environment of this recipe's commands
+    pub unset: Vec<String>,
+
+    /// Requirements with variable bindings, mapped to the required recipe and its bindings
+    pub bindings: BindingMap,
+
+    /// How many job slots the recipe occupies while running; 0 means it doesn't count at all
+    pub weight: usize,
+
+    /// How many more times the recipe's commands are run after one of them exits non-zero
+    pub retries: u32,
+
+    /// File mode creation mask for the recipe's commands (Unix only)
+    pub umask: Option<u32>,
+
+    /// How long the recipe is expected to take, for progress estimates
+    pub expected: Option<Duration>,
+
+    /// How long all of the recipe's commands together may run before it's killed
+    pub timeout: Option<Duration>,
+
+    /// Glob patterns for the files that `--watch` re-runs the recipe after changes to
+    pub watch: Vec<String>,
+
+    /// Glob patterns for the files that the recipe reads, relative to its source directory
+    pub inputs: Vec<String>,
+
+    /// Glob patterns for the files that the recipe writes; once they're all newer than its
+    /// inputs, the recipe is skipped
+    pub outputs: Vec<String>,
+
+    /// Line of its moldfile that the recipe was defined on, if it came from one
+    pub line: Option<usize>,
+
+    /// Conditions that had to hold for the recipe to be defined
+    pub guards: Vec<String>,
+}
+
+impl Recipe {
+    /// Whether the recipe only exists to group its requirements together
+    pub fn is_aggregate(&self) -> bool {
+        self.commands.is_empty() && self.script.is_none() && !self.requires.is_empty()
+    }
+
+    /// Whether the recipe is left out of help and completions, though it still runs as usual
+    ///
+    /// That's either because it says it's `private`, or because `name`, without any import
+    /// prefix, starts with an underscore.
+    pub fn is_private(&self, name: &str) -> bool {
+        self.private || name.rsplit('/').next().unwrap_or(name).starts_with('_')
+    }
+
+    /// Rewrite Windows-style %VAR% references in everything about the recipe that gets expanded
+    pub fn convert_windows_vars(&mut self) {
+        let convert = |x: &mut String| *x = util::convert_windows_vars(x);
+        self.commands
+            .iter_mut()
+            .for_each(Command::convert_windows_vars);
+        self.dir.iter_mut().for_each(convert);
+        self.interpreter.iter_mut().for_each(convert);
+        self.params
+            .iter_mut()
+            .flat_map(|x| x.default.iter_mut())
+            .for_each(convert);
+        self.bindings
+            .values_mut()
+            .flat_map(|(_, vars)| vars.values_mut())
+            .for_each(convert);
+        self.watch
+            .iter_mut()
+            .chain(&mut self.inputs)
+            .chain(&mut self.outputs)
+            .for_each(convert);
+    }
 }
 
 /// Data straight from a file
@@ -100,13 +592,34 @@ pub struct Moldfile {
     /// A list of recipes
     pub recipes: RecipeMap,
 
-    /// A list of environment variables
-    pub vars: VarMap,
+    /// A list of `--doctor` checks
+    pub checks: RecipeMap,
+
+    /// A list of environment variable assignments in file order
+    pub vars: VarOpVec,
 
     /// Working directory relative to $MOLD_ROOT
     ///
     /// This is overridden by a recipe's `dir`
     pub dir: Option<String>,
+
+    /// Custom environments declared with `environments`
+    pub environments: EnvSet,
+
+    /// Every environment referenced by a condition in this file
+    pub conditions: EnvSet,
+
+    /// Values from `settings` blocks, which only apply in the root moldfile
+    pub settings: IndexMap<String, String>,
+
+    /// Git hooks declared with `hook`, which only apply in the root moldfile
+    pub hooks: IndexMap<String, String>,
+
+    /// Whether runs hold the project lock, which only applies in the root moldfile
+    pub exclusive: bool,
+
+    /// Recipes that `--shell-init` defines functions for, which only applies in the root moldfile
+    pub shell_functions: Option<Vec<String>>,
 }
 
 impl Mold {
@@ -115,9 +628,57 @@ impl Mold {
         path: &Path,
         envs: Vec<String>,
         use_git: bool,
+        git_verbose: bool,
+        offline: bool,
+        use_vars: bool,
+    ) -> Result<Mold, Error> {
+        let mut mold = Mold::new(path, envs, use_git, git_verbose, offline, use_vars)?;
+        mold.load()?;
+        Ok(mold)
+    }
+
+    /// Load the root moldfile and everything that it imports
+    ///
+    /// This is for applications that need to change settings between `new` and loading;
+    /// otherwise, `init` does both.
+    pub fn load(&mut self) -> Result<(), Error> {
+        let path = self.root_file.clone();
+
+        self.open(&path, "")?;
+        self.lock()?;
+        self.check_references()?;
+        self.check_conditions();
+
+        for (prefix, err) in &self.broken_imports {
+            let recipes = if prefix.is_empty() {
+                "some recipes may be missing".to_string()
+            } else {
+                format!("recipes under {} are unavailable", prefix.yellow())
+            };
+            util::warn(&format!(
+                "skipped a broken import; {}\n{:>12} {}",
+                recipes, "", err
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Create a new, empty application rooted at the given path without loading anything
+    pub fn new(
+        path: &Path,
+        envs: Vec<String>,
+        use_git: bool,
+        git_verbose: bool,
+        offline: bool,
         use_vars: bool,
     ) -> Result<Mold, Error> {
-        let root_dir = path.parent().unwrap_or(&Path::new("/")).to_path_buf();
+        // resolve symlinks up front so that $MOLD_ROOT points at the real project directory
+        let path = canonicalize(path)?;
+        let root_dir = path
+            .parent()
+            .unwrap_or_else(|| Path::new("/"))
+            .to_path_buf();
         let mold_dir = root_dir.join(".mold");
 
         if !mold_dir.is_dir() {
@@ -128,52 +689,89 @@ impl Mold {
                     err
                 )
             })?;
+
+            // a self-ignoring directory keeps .mold out of commits without touching .gitignore
+            if util::in_git_repo(&root_dir) {
+                fs::write(mold_dir.join(".gitignore"), "*\n").map_err(|err| {
+                    failure::format_err!(
+                        "Could not write {}: {}",
+                        mold_dir.join(".gitignore").display().to_string().red(),
+                        err
+                    )
+                })?;
+            }
         }
 
+        let mold_dir = canonicalize(&mold_dir)?;
+
         let vars = indexmap! {
           "MOLD_ROOT".into() => root_dir.to_string_lossy().into(),
           "MOLD_DIR".into() => mold_dir.to_string_lossy().into(),
         };
 
         let envs = envs.into_iter().collect();
+        let lockfile = Lockfile::load(&root_dir)?;
 
-        let root_dir = fs::canonicalize(&root_dir).map_err(|err| {
-            failure::format_err!(
-                "Couldn't canonicalize directory {}: {}",
-                root_dir.display().to_string().red(),
-                err
-            )
-        })?;
-
-        let mold_dir = fs::canonicalize(&mold_dir).map_err(|err| {
-            failure::format_err!(
-                "Couldn't canonicalize directory {}: {}",
-                mold_dir.display().to_string().red(),
-                err
-            )
-        })?;
-
-        let mut mold = Mold {
+        Ok(Mold {
+            root_file: path.clone(),
             root_dir,
             mold_dir,
             recipes: RecipeMap::new(),
             sources: SourceMap::new(),
+            recipe_files: SourceMap::new(),
+            checks: RecipeMap::new(),
+            check_sources: SourceMap::new(),
+            var_sources: SourceMap::new(),
+            unset: IndexSet::new(),
+            scopes: IndexMap::new(),
+            scoped_files: IndexMap::new(),
+            declared_envs: EnvSet::new(),
+            condition_sources: SourceMap::new(),
+            instances: BindingMap::new(),
+            imports: ImportMap::new(),
+            versions: IndexMap::new(),
+            local_files: indexmap! { path.clone() => String::new() },
+            references: vec![],
+            shadowed: vec![],
+            script_dir: std::sync::OnceLock::new(),
             remotes: vec![],
+            lockfile,
+            frozen: false,
             work_dir: None,
             envs,
             vars,
             use_git,
+            git_verbose,
+            offline,
             use_vars,
-        };
-
-        mold.open(path, "")?;
-
-        Ok(mold)
+            allow_dirty: false,
+            keep_going: false,
+            label: None,
+            failure_context: true,
+            interactive: true,
+            skip_broken_imports: false,
+            broken_imports: IndexMap::new(),
+            suffix_match: true,
+            force: false,
+            use_cache: true,
+            args: IndexMap::new(),
+            param_args: IndexMap::new(),
+            overrides: VarMap::new(),
+            settings: IndexMap::new(),
+            hooks: IndexMap::new(),
+            exclusive: false,
+            shell_functions: None,
+            lock_timeout: None,
+            use_dotenv: true,
+        })
     }
 
     /// Delete all cloned top-level targets
     pub fn clean_all(path: &Path) -> Result<(), Error> {
-        let root_dir = path.parent().unwrap_or(&Path::new("/")).to_path_buf();
+        let root_dir = path
+            .parent()
+            .unwrap_or_else(|| Path::new("/"))
+            .to_path_buf();
         let mold_dir = root_dir.join(".mold");
 
         if mold_dir.is_dir() {
@@ -190,304 +788,3252 @@ impl Mold {
             println!("{:>12}", "Clean!".green());
         }
 
+        // scripts from a project on a noexec mount were written outside of .mold
+        let fallback = fs::canonicalize(&root_dir).map(|x| fallback_script_dir(&x));
+        if let Some(fallback) = fallback.ok().filter(|x| x.is_dir()) {
+            fs::remove_dir_all(&fallback).map_err(|err| {
+                failure::format_err!(
+                    "Couldn't remove directory {}: {}",
+                    fallback.display().to_string().red(),
+                    err
+                )
+            })?;
+
+            println!("{:>12} {}", "Deleted".red(), fallback.display());
+        }
+
         Ok(())
     }
 
-    /// Given a path, load the file into the current application
-    fn open(&mut self, path: &Path, prefix: &str) -> Result<(), Error> {
-        let mut file = fs::File::open(path).map_err(|err| {
-            failure::format_err!(
-                "Couldn't open {}: {}",
-                path.display().to_string().red(),
-                err
-            )
-        })?;
-
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).map_err(|err| {
-            failure::format_err!(
-                "Couldn't read {}: {}",
-                path.display().to_string().red(),
-                err
-            )
-        })?;
+    /// Check every file that mold generated in .mold against the manifest
+    pub fn verify_all(path: &Path) -> Result<(), Error> {
+        let mold_dir = path
+            .parent()
+            .unwrap_or_else(|| Path::new("/"))
+            .join(".mold");
 
-        let data = self::lang::compile(&contents, self).map_err(|err| {
-            failure::format_err!(
-                "Couldn't compile {}: {}",
-                path.display().to_string().red(),
-                err
-            )
-        })?;
+        let problems = manifest::verify(&mold_dir)?;
+        for problem in &problems {
+            match problem {
+                manifest::Discrepancy::Missing(file) => {
+                    println!("{:>12} {}", "Missing".red(), mold_dir.join(file).display())
+                }
+                manifest::Discrepancy::Modified(file) => {
+                    println!("{:>12} {}", "Modified".red(), mold_dir.join(file).display())
+                }
+            }
+        }
 
-        let root_dir = path.parent().unwrap_or(&Path::new("/")).to_path_buf();
+        match problems.len() {
+            0 => {
+                let count = manifest::load(&mold_dir)?.len();
+                println!("{:>12} {} files", "Verified".green(), count);
+                Ok(())
+            }
+            n => Err(failure::format_err!(
+                "{} files in {} don't match the manifest",
+                n,
+                mold_dir.display().to_string().red()
+            )),
+        }
+    }
 
-        // check version requirements
-        let self_version = Version::parse(clap::crate_version!())?;
-        let target_version = VersionReq::parse(&data.version).map_err(|err| {
-            failure::format_err!(
-                "Couldn't parse version requirement {} from {}: {}",
-                data.version.red(),
-                path.display().to_string().red(),
-                err
-            )
-        })?;
+    /// Add `.mold/` to the project's .gitignore unless it's already ignored there
+    pub fn update_gitignore(path: &Path) -> Result<(), Error> {
+        let root_dir = path.parent().unwrap_or_else(|| Path::new("/"));
+        let gitignore = root_dir.join(".gitignore");
 
-        if !target_version.matches(&self_version) {
-            return Err(failure::format_err!(
-                "{} requires version {}, but mold version is {}",
-                path.to_str().unwrap().blue(),
-                target_version.to_string().green(),
-                self_version.to_string().red()
-            ));
+        if !util::in_git_repo(root_dir) {
+            println!(
+                "{:>12} {} is not in a git repository",
+                "Skipped".yellow(),
+                root_dir.display()
+            );
+            return Ok(());
         }
 
-        for (name, recipe) in data.recipes {
-            let new_key = format!("{}{}", prefix, name);
+        let contents = if gitignore.is_file() {
+            fs::read_to_string(&gitignore)?
+        } else {
+            String::new()
+        };
 
-            // clone this recipe and prefix all of its dependencies
-            let mut new_recipe = recipe.clone();
-            new_recipe.requires = new_recipe
-                .requires
-                .iter()
-                .map(|x| format!("{}{}", prefix, x))
-                .collect();
+        let ignored = contents
+            .lines()
+            .map(str::trim)
+            .any(|x| [".mold", ".mold/", "/.mold", "/.mold/"].contains(&x));
+        if ignored {
+            println!(
+                "{:>12} {} already ignores .mold",
+                "Unchanged".green(),
+                gitignore.display()
+            );
+            return Ok(());
+        }
 
+        let mut contents = contents;
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str(".mold/\n");
+        util::write_atomic(&gitignore, contents.as_bytes())?;
+
+        println!("{:>12} {}", "Updated".green(), gitignore.display());
+        Ok(())
+    }
+
+    /// Remove a git hook that was installed with `install_hook`
+    pub fn uninstall_hook(path: &Path, hook: &str, force: bool) -> Result<(), Error> {
+        hooks::uninstall(path, hook, force)
+    }
+
+    /// Install a git hook that runs the given targets, or the ones the moldfile declares for it
+    pub fn install_hook(&self, hook: &str, targets: &[String], force: bool) -> Result<(), Error> {
+        let targets: Vec<String> = if targets.is_empty() {
+            let declared = self.hooks.get(hook).ok_or_else(|| {
+                failure::format_err!(
+                    "No targets given for hook {}, and the moldfile doesn't declare any",
+                    hook.red()
+                )
+            })?;
+            declared.split_whitespace().map(Into::into).collect()
+        } else {
+            targets
+                .iter()
+                .flat_map(|x| x.split_whitespace())
+                .map(Into::into)
+                .collect()
+        };
+
+        // catch typos now rather than on the next commit
+        let resolved = targets
+            .iter()
+            .map(|x| self.resolve_target(x))
+            .collect::<Result<TargetSet, Error>>()?;
+        self.check_targets(&resolved)?;
+
+        hooks::install(&self.root_file, hook, &targets, force)
+    }
+
+    /// Install every git hook that the root moldfile declares with `hook`
+    pub fn install_hooks(&self, force: bool) -> Result<(), Error> {
+        if self.hooks.is_empty() {
+            println!(
+                "{:>12} the moldfile doesn't declare any hooks",
+                "Skipped".yellow()
+            );
+        }
+
+        for hook in self.hooks.keys() {
+            self.install_hook(hook, &[], force)?;
+        }
+
+        Ok(())
+    }
+
+    /// Clone every remote reachable from the given moldfile and check that each one loads
+    ///
+    /// All remotes are attempted even if some of them fail, so that a single run reports every
+    /// problem at once.
+    pub fn clone_all(
+        path: &Path,
+        envs: Vec<String>,
+        use_git: bool,
+        git_verbose: bool,
+        use_vars: bool,
+        frozen: bool,
+    ) -> Result<(), Error> {
+        let mut mold = Mold::new(path, envs, use_git, git_verbose, false, use_vars)?;
+        mold.frozen = frozen;
+        let path = mold.root_file.clone();
+        let contents = Self::read(&path)?;
+        let data = mold.compile(&path, &contents).map_err(|err| {
+            failure::format_err!(
+                "Couldn't compile {}: {}",
+                path.display().to_string().red(),
+                err
+            )
+        })?;
+        mold.check_version(&path, &data, None)?;
+
+        let mut queue: VecDeque<Include> = data.includes.into_iter().collect();
+        let mut seen = IndexSet::new();
+        let mut failed = 0;
+
+        while let Some(include) = queue.pop_front() {
+            let name = include.remote.to_string();
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+
+            match mold.verify(&include.remote) {
+                Ok(includes) => {
+                    println!("{:>12} {}", "Ok".green(), name);
+                    queue.extend(includes);
+                }
+                Err(err) => {
+                    println!("{:>12} {}: {}", "Failed".red(), name, err);
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed > 0 {
+            return Err(failure::format_err!(
+                "{} of {} remotes failed verification",
+                failed.to_string().red(),
+                seen.len()
+            ));
+        }
+
+        mold.lock()?;
+        Ok(())
+    }
+
+    /// Clone a remote if necessary and make sure its moldfile can be loaded
+    ///
+    /// Returns the remote's own includes so that they can be verified as well.
+    fn verify(&mut self, remote: &Remote) -> Result<IncludeVec, Error> {
+        self.sync(remote)?;
+        self.remotes.push(remote.clone());
+
+        let dir = remote.path(&self.mold_dir);
+        let path = Self::discover(&dir, remote.file.clone()).map_err(|_| {
+            let expected = match &remote.file {
+                Some(file) => dir.join(file),
+                None => dir.join("moldfile"),
+            };
+
+            failure::format_err!(
+                "moldfile missing at {}",
+                expected.display().to_string().red()
+            )
+        })?;
+
+        let contents = Self::read(&path)?;
+        let data = self
+            .compile(&path, &contents)
+            .map_err(|err| failure::format_err!("parse error: {}", err))?;
+        self.check_version(&path, &data, Some(remote))?;
+
+        Ok(data.includes)
+    }
+
+    /// Find the commit that mold.lock pins a remote to
+    ///
+    /// When frozen, every remote has to be pinned already.
+    fn pinned(&self, remote: &Remote) -> Result<Option<String>, Error> {
+        let commit = self.lockfile.as_ref().and_then(|x| x.get(remote));
+        match (commit, &self.lockfile) {
+            (Some(commit), _) => Ok(Some(commit.to_string())),
+            (None, _) if !self.frozen => Ok(None),
+            (None, Some(_)) => Err(failure::format_err!(
+                "Import {} isn't pinned in {}; run {} to add it",
+                remote.to_string().red(),
+                LOCKFILE,
+                "mold --update".green()
+            )),
+            (None, None) => Err(failure::format_err!(
+                "{} doesn't exist; run {} to create it",
+                Lockfile::path(&self.root_dir).display().to_string().red(),
+                "mold --update".green()
+            )),
+        }
+    }
+
+    /// Make sure that a remote is cloned and checked out at the commit it's pinned to, if any
+    fn sync(&self, remote: &Remote) -> Result<(), Error> {
+        // a clone that another import shares has already been synced during this load
+        if self.remotes.iter().any(|x| x.same_clone(remote)) {
+            return Ok(());
+        }
+
+        let pinned = self.pinned(remote)?;
+        if !remote.exists(&self.mold_dir) {
+            return self.fetch(remote, pinned.as_deref());
+        }
+
+        let commit = match pinned {
+            Some(commit) if remote.commit(&self.mold_dir).as_ref() != Some(&commit) => commit,
+            _ => return Ok(()),
+        };
+
+        if self.offline {
+            return Err(failure::format_err!(
+                "Import {} is pinned to {} by {}, but can't be checked out while offline",
+                remote.to_string().red(),
+                commit.red(),
+                LOCKFILE
+            ));
+        }
+
+        self.checkout(remote, Some(&commit))
+    }
+
+    /// Record the commit that every loaded remote is at in mold.lock
+    ///
+    /// Returns whether the lockfile changed. When frozen, any change is an error instead.
+    fn lock(&mut self) -> Result<bool, Error> {
+        let mut lockfile = Lockfile::default();
+        for remote in &self.remotes {
+            if let Some(commit) = remote.commit(&self.mold_dir) {
+                lockfile.insert(remote, commit);
+            }
+        }
+
+        let unchanged = match &self.lockfile {
+            Some(existing) => *existing == lockfile,
+            None => lockfile.remotes.is_empty(),
+        };
+        if unchanged {
+            return Ok(false);
+        }
+
+        if self.frozen {
+            return Err(failure::format_err!(
+                "{} is out of date; run {} to update it",
+                Lockfile::path(&self.root_dir).display().to_string().red(),
+                "mold --update".green()
+            ));
+        }
+
+        lockfile.save(&self.root_dir)?;
+        self.lockfile = Some(lockfile);
+        Ok(true)
+    }
+
+    /// Clone a remote and check out its ref, or the commit that it's pinned to
+    fn fetch(&self, remote: &Remote, commit: Option<&str>) -> Result<(), Error> {
+        if self.offline {
+            return Err(failure::format_err!(
+                "Couldn't clone {} while offline",
+                remote.url.red()
+            ));
+        }
+
+        remote
+            .pull(&self.mold_dir, self.use_git, self.git_verbose)
+            .map_err(|err| {
+                failure::format_err!(
+                    "Couldn't clone {}: {}",
+                    remote.url.red(),
+                    util::error_chain(&err)
+                )
+            })?;
+
+        self.checkout(remote, commit)
+    }
+
+    /// Clone the remotes that any of several imports need, if more than one of them is missing
+    ///
+    /// A single missing remote is left to `sync`, which reports on it in more detail.
+    fn fetch_missing(&self, includes: &[Include]) -> Result<(), Error> {
+        if self.offline {
+            return Ok(());
+        }
+
+        let mut missing: Vec<(&Remote, Option<String>)> = vec![];
+        for include in includes {
+            let remote = &include.remote;
+            let seen = self
+                .remotes
+                .iter()
+                .chain(missing.iter().map(|(x, _)| *x))
+                .any(|x| x.same_clone(remote));
+            if !seen && !remote.exists(&self.mold_dir) {
+                missing.push((remote, self.pinned(remote)?));
+            }
+        }
+
+        if missing.len() < 2 {
+            return Ok(());
+        }
+
+        let results = Remote::clone_many(
+            &missing,
+            &self.mold_dir,
+            self.use_git,
+            self.git_verbose,
+            CLONE_JOBS,
+        );
+        results.into_iter().collect()
+    }
+
+    /// Check out a remote's ref, or the given commit
+    fn checkout(&self, remote: &Remote, commit: Option<&str>) -> Result<(), Error> {
+        remote
+            .checkout(&self.mold_dir, commit, self.use_git, self.git_verbose)
+            .map_err(|err| {
+                failure::format_err!(
+                    "Couldn't checkout {}: {}",
+                    commit.unwrap_or(&remote.ref_).red(),
+                    util::error_chain(&err)
+                )
+            })
+    }
+
+    /// Compile the contents of a moldfile, picking the language from its extension
+    fn compile(&mut self, path: &Path, contents: &str) -> Result<Moldfile, Error> {
+        let ext = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+        if ext != "yaml" && ext != "yml" {
+            return self::lang::compile(contents, self);
+        }
+
+        #[cfg(feature = "yaml")]
+        {
+            util::warn(&format!(
+                "{} uses the deprecated YAML format; convert it with {}",
+                path.display(),
+                "mold --migrate".green()
+            ));
+
+            let translation = self::yaml::translate(contents)?;
+            for skipped in &translation.skipped {
+                util::warn(&format!("ignoring unsupported {}", skipped.yellow()));
+            }
+
+            self::lang::compile_statements(translation.statements, self)
+        }
+
+        #[cfg(not(feature = "yaml"))]
+        Err(failure::format_err!(
+            "YAML moldfiles aren't supported without the {} feature",
+            "yaml".red()
+        ))
+    }
+
+    /// Read the contents of a moldfile
+    fn read(path: &Path) -> Result<String, Error> {
+        let mut file = fs::File::open(path).map_err(|err| {
+            failure::format_err!(
+                "Couldn't open {}: {}",
+                path.display().to_string().red(),
+                err
+            )
+        })?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).map_err(|err| {
+            failure::format_err!(
+                "Couldn't read {}: {}",
+                path.display().to_string().red(),
+                err
+            )
+        })?;
+
+        Ok(contents)
+    }
+
+    /// Make sure this version of mold satisfies a moldfile's version requirement
+    ///
+    /// When the moldfile came from a remote, the error says which commit of which ref needs a
+    /// newer mold, since pinning an older ref is just as good a fix as upgrading.
+    fn check_version(
+        &self,
+        path: &Path,
+        data: &Moldfile,
+        remote: Option<&Remote>,
+    ) -> Result<(), Error> {
+        let self_version = Version::parse(VERSION)?;
+        let target_version = VersionReq::parse(&data.version).map_err(|err| {
+            failure::format_err!(
+                "Couldn't parse version requirement {} from {}: {}",
+                data.version.red(),
+                path.display().to_string().red(),
+                err
+            )
+        })?;
+
+        if !target_version.matches(&self_version) {
+            if let Some(remote) = remote {
+                let commit = remote
+                    .commit(&self.mold_dir)
+                    .map(|x| x.chars().take(10).collect())
+                    .unwrap_or_else(|| "unknown".to_string());
+                let upgrade = match minimum_version(&data.version) {
+                    Some(version) => format!("upgrade mold to {}", version.green()),
+                    None => format!(
+                        "upgrade mold to a version matching {}",
+                        data.version.green()
+                    ),
+                };
+
+                return Err(failure::format_err!(
+                    "{} at ref {} (commit {}) requires version {}, but mold version is {}\n{:>12} {}, or pin the import to an older ref that worked",
+                    remote.url.blue(),
+                    remote.ref_.yellow(),
+                    commit.yellow(),
+                    target_version.to_string().green(),
+                    self_version.to_string().red(),
+                    "",
+                    upgrade
+                ));
+            }
+
+            return Err(failure::format_err!(
+                "{} requires version {}, but mold version is {}",
+                path.to_str().unwrap().blue(),
+                target_version.to_string().green(),
+                self_version.to_string().red()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Convert a legacy YAML moldfile into a new moldfile in the same directory
+    ///
+    /// The original file is left untouched, and anything that couldn't be translated is listed
+    /// afterwards so that it can be fixed by hand.
+    pub fn migrate(path: &Path) -> Result<(), Error> {
+        let target = path.with_file_name("moldfile");
+        if target.exists() {
+            return Err(failure::format_err!(
+                "Couldn't migrate {}: {} already exists",
+                path.display().to_string().red(),
+                target.display().to_string().red()
+            ));
+        }
+
+        #[cfg(feature = "yaml")]
+        {
+            let contents = Self::read(path)?;
+            let translation = self::yaml::translate(&contents).map_err(|err| {
+                failure::format_err!(
+                    "Couldn't translate {}: {}",
+                    path.display().to_string().red(),
+                    err
+                )
+            })?;
+
+            fs::write(&target, self::lang::emit(&translation.statements)).map_err(|err| {
+                failure::format_err!(
+                    "Couldn't write {}: {}",
+                    target.display().to_string().red(),
+                    err
+                )
+            })?;
+
+            println!("{:>12} {}", "Migrated".green(), target.display());
+            for skipped in &translation.skipped {
+                println!("{:>12} unsupported {}", "Skipped".yellow(), skipped);
+            }
+
+            Ok(())
+        }
+
+        #[cfg(not(feature = "yaml"))]
+        Err(failure::format_err!(
+            "YAML moldfiles aren't supported without the {} feature",
+            "yaml".red()
+        ))
+    }
+
+    /// Given a path, load the file into the current application
+    fn open(&mut self, path: &Path, prefix: &str) -> Result<(), Error> {
+        let contents = Self::read(path)?;
+        if path == self.root_file && self.use_vars && self.use_dotenv {
+            self.load_dotenv(&contents)?;
+        }
+
+        let data = self.compile(path, &contents).map_err(|err| {
+            failure::format_err!(
+                "Couldn't compile {}: {}",
+                path.display().to_string().red(),
+                err
+            )
+        })?;
+
+        // a symlinked moldfile should use the directory it really lives in
+        let real_path = canonicalize(path)?;
+        let root_dir = real_path
+            .parent()
+            .unwrap_or_else(|| Path::new("/"))
+            .to_path_buf();
+
+        // imported moldfiles have their remote on record by now, which helps explain a mismatch
+        let remote = self
+            .imports
+            .values()
+            .flatten()
+            .find(|(_, _, file)| file == path)
+            .map(|(_, remote, _)| remote.clone());
+        self.versions
+            .insert(path.to_path_buf(), data.version.clone());
+        self.check_version(path, &data, remote.as_ref())?;
+
+        for (name, recipe) in data.recipes {
+            let new_key = format!("{}{}", prefix, name);
+
+            // clone this recipe and prefix all of its dependencies
+            let mut new_recipe = recipe.clone();
+            let mut requires = TargetSet::new();
+            for x in &recipe.requires {
+                let key = match recipe.bindings.get(x) {
+                    Some((target, bindings)) => {
+                        // bound requirements are renamed after their resolved target
+                        let target = self.resolve_require(&root_dir, path, prefix, target)?;
+                        let key = instance_key(&target, bindings);
+                        self.instances
+                            .entry(key.clone())
+                            .or_insert_with(|| (target, bindings.clone()));
+                        key
+                    }
+                    None => self.resolve_require(&root_dir, path, prefix, x)?,
+                };
+                requires.insert(key);
+            }
+            new_recipe.requires = requires;
+            new_recipe.bindings = BindingMap::new();
+
+            let mut completed = vec![];
+            for (target, max_age) in &recipe.completed {
+                let key = self.resolve_require(&root_dir, path, prefix, target)?;
+                completed.push((key, *max_age));
+            }
+            new_recipe.completed = completed;
+
+            // the first definition wins, but a different moldfile redefining it is worth knowing
+            if self.recipe_files.get(&new_key).is_some_and(|x| x != path) {
+                self.shadowed.push((new_key.clone(), path.to_path_buf()));
+            }
             self.recipes.entry(new_key.clone()).or_insert(new_recipe);
 
-            // keep track of where this recipe came from so it can use things from its repo
-            self.sources.entry(new_key).or_insert(root_dir.clone());
+            // keep track of where this recipe came from so it can use things from its repo
+            self.recipe_files
+                .entry(new_key.clone())
+                .or_insert_with(|| path.to_path_buf());
+            self.sources.entry(new_key).or_insert(root_dir.clone());
+        }
+
+        for (name, check) in data.checks {
+            let new_key = format!("{}{}", prefix, name);
+            self.checks.entry(new_key.clone()).or_insert(check);
+            self.check_sources
+                .entry(new_key)
+                .or_insert_with(|| root_dir.clone());
+        }
+
+        // missing imports are cloned all at once before any of them is loaded; whatever they import
+        // in turn is cloned when they are
+        self.fetch_missing(&data.includes)?;
+
+        for include in data.includes {
+            // failing to fetch is always fatal; only a broken moldfile in a remote can be skipped
+            self.sync(&include.remote)?;
+
+            let prefix = include.prefix.clone();
+            let url = include.remote.to_string();
+            if let Err(err) = self.open_include(path, include) {
+                if !self.skip_broken_imports {
+                    return Err(err);
+                }
+
+                // remember why so that targeting one of its recipes can explain what happened
+                let err = format!("Couldn't load import {}: {}", url.red(), err);
+                self.broken_imports.entry(prefix).or_insert(err);
+            }
+        }
+
+        for (name, op) in data.vars {
+            self.apply_var(path, name, op);
+        }
+
+        // the root moldfile is applied last, so overrides go right after its own variables
+        if path == self.root_file {
+            for (name, value) in self.overrides.clone() {
+                self.apply_var(path, name, VarOp::Set(value));
+            }
+        }
+
+        self.declared_envs.extend(data.environments);
+        for name in data.conditions {
+            self.condition_sources
+                .entry(name)
+                .or_insert_with(|| path.to_path_buf());
+        }
+
+        // settings are per project, so imported moldfiles don't get a say
+        if path == self.root_file {
+            self.settings.extend(data.settings);
+            self.hooks.extend(data.hooks);
+            self.exclusive |= data.exclusive;
+            if let Some(names) = data.shell_functions {
+                self.shell_functions
+                    .get_or_insert_with(Vec::new)
+                    .extend(names);
+            }
+        }
+
+        // if this file has a `dir` stmt, it overrides any other dir that was set
+        if let Some(rel_path) = data.dir {
+            self.work_dir = Some(rel_path);
+        }
+
+        Ok(())
+    }
+
+    /// Load a single, already fetched import of the moldfile at `path`
+    fn open_include(&mut self, path: &Path, include: Include) -> Result<(), Error> {
+        let dir = include.remote.path(&self.mold_dir);
+        self.remotes.push(include.remote.clone());
+        let filepath = Self::discover(&dir, include.remote.file.clone())?;
+
+        // anything imported by a scoped import shares its scope, unless it has one of its own
+        let scope = match include.scoped {
+            true => Some(include.prefix.clone()),
+            false => self.scoped_files.get(path).cloned(),
+        };
+        if let Some(scope) = scope {
+            self.scopes.entry(scope.clone()).or_default();
+            self.scoped_files.insert(filepath.clone(), scope);
+        }
+
+        self.imports.entry(path.to_path_buf()).or_default().push((
+            include.prefix.clone(),
+            include.remote,
+            filepath.clone(),
+        ));
+        self.open(&filepath, &include.prefix)
+    }
+
+    /// Resolve a required recipe name from a moldfile into a registered recipe key
+    fn resolve_require(
+        &mut self,
+        root_dir: &Path,
+        path: &Path,
+        prefix: &str,
+        name: &str,
+    ) -> Result<String, Error> {
+        match name.find('#') {
+            Some(idx) => self.require_file(root_dir, path, &name[..idx], &name[idx + 1..]),
+            None => Ok(format!("{}{}", prefix, name)),
+        }
+    }
+
+    /// Resolve a `require "path#recipe"` reference into a registered recipe key
+    ///
+    /// The referenced moldfile is loaded under an internal prefix the first time it's seen, and
+    /// reused afterwards. The path is relative to the directory of the requiring moldfile and may
+    /// name either a moldfile or a directory containing one.
+    fn require_file(
+        &mut self,
+        base_dir: &Path,
+        from: &Path,
+        rel_path: &str,
+        recipe: &str,
+    ) -> Result<String, Error> {
+        let target = base_dir.join(rel_path);
+        let target = if target.is_dir() {
+            target.join("moldfile")
+        } else {
+            target
+        };
+
+        if !target.is_file() {
+            return Err(failure::format_err!(
+                "Couldn't find moldfile {} required from {}",
+                target.display().to_string().red(),
+                from.display().to_string().red()
+            ));
+        }
+
+        let target = canonicalize(&target)?;
+        let prefix = match self.local_files.get(&target) {
+            Some(prefix) => prefix.clone(),
+            None => {
+                let name = target.strip_prefix(&self.root_dir).unwrap_or(&target);
+                let prefix = format!("{}#", name.display());
+
+                // register before opening so that files requiring each other don't recurse forever
+                self.local_files.insert(target.clone(), prefix.clone());
+                self.open(&target, &prefix)?;
+                prefix
+            }
+        };
+
+        let key = format!("{}{}", prefix, recipe);
+        self.references.push(Reference {
+            key: key.clone(),
+            recipe: recipe.into(),
+            target,
+            from: from.to_path_buf(),
+        });
+
+        Ok(key)
+    }
+
+    /// Make sure every cross-moldfile reference points at a recipe that exists
+    fn check_references(&self) -> Result<(), Error> {
+        for reference in &self.references {
+            if !self.recipes.contains_key(&reference.key) {
+                return Err(failure::format_err!(
+                    "Couldn't find recipe {} in {} required from {}",
+                    reference.recipe.red(),
+                    reference.target.display().to_string().red(),
+                    reference.from.display().to_string().red()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Warn about conditions that refer to environments that can never be active
+    ///
+    /// An environment is considered possible if it's one of the OS or OS family names that mold
+    /// activates automatically, if it was declared with `environments`, or if it's currently
+    /// active.
+    fn check_conditions(&self) {
+        let known: EnvSet = PLATFORM_ENVS
+            .iter()
+            .map(ToString::to_string)
+            .chain(self.declared_envs.iter().cloned())
+            .chain(self.envs.iter().cloned())
+            .collect();
+
+        for (name, path) in &self.condition_sources {
+            if known.contains(name) {
+                continue;
+            }
+
+            let hint = match util::suggest(name, known.iter().map(String::as_str)) {
+                Some(x) => format!("; did you mean {}?", x.green()),
+                None => "; declare it with `environments` if it's intentional".into(),
+            };
+
+            util::warn(&format!(
+                "environment {} in {} is never activated{}",
+                name.yellow(),
+                path.display(),
+                hint
+            ));
+        }
+    }
+
+    /// Load variables from the root moldfile's env file before compiling it
+    ///
+    /// They're defaults, so anything set in the environment wins, and since they're loaded first,
+    /// the moldfile's own assignments replace them. Only a file the moldfile names has to exist.
+    fn load_dotenv(&mut self, contents: &str) -> Result<(), Error> {
+        let named = lang::env_file(contents);
+        let path = self
+            .root_dir
+            .join(named.as_deref().unwrap_or(dotenv::DEFAULT));
+        if named.is_none() && !path.is_file() {
+            return Ok(());
+        }
+
+        for (name, value) in dotenv::load(&path)? {
+            if std::env::var_os(&name).is_none() && !self.vars.contains_key(&name) {
+                // values are literal, so a $ in one isn't expanded later
+                self.apply_var(&path, name, VarOp::Set(value.replace('$', "$$")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply a single variable assignment from the moldfile at `path`
+    ///
+    /// Assignments are applied in file load order, so includes are applied before the file that
+    /// imports them. Appending or prepending to a variable that no moldfile has defined composes
+    /// with the value from the environment instead.
+    fn apply_var(&mut self, path: &Path, name: String, op: VarOp) {
+        // scoped imports keep their variables to themselves, on top of the global ones
+        if let Some(scope) = self.scoped_files.get(path) {
+            let vars = self.scopes.entry(scope.clone()).or_default();
+            let mut visible = self.vars.clone();
+            visible.extend(vars.clone());
+            match op.resolve(&name, &visible) {
+                Some(value) => vars.insert(name, value),
+                None => vars.shift_remove(&name),
+            };
+            return;
+        }
+
+        // the origin moldfile is expected to override its includes, but two includes fighting
+        // over the same variable is most likely an accident
+        if let VarOp::Set(_) = op {
+            if let Some(prev) = self.var_sources.get(&name) {
+                if prev != path && path != self.root_file {
+                    util::warn(&format!(
+                        "{} from {} replaces value set by {}",
+                        format!("${}", name).yellow(),
+                        path.display(),
+                        prev.display(),
+                    ));
+                }
+            }
+        }
+
+        // setting a variable again brings it back, starting from nothing rather than the environment
+        if self.unset.shift_remove(&name) {
+            self.vars.insert(name.clone(), String::new());
+        }
+
+        match op.resolve(&name, &self.vars) {
+            Some(value) => {
+                self.vars.insert(name.clone(), value);
+            }
+            None => {
+                self.vars.shift_remove(&name);
+                self.unset.insert(name.clone());
+            }
+        }
+        self.var_sources.insert(name, path.to_path_buf());
+    }
+
+    /// Try to find a file by walking up the tree
+    ///
+    /// Absolute paths will either be located or fail instantly. Relative paths
+    /// will walk the entire file tree up to root, looking for a file with the
+    /// given name.
+    fn discover_file(name: &Path) -> Result<PathBuf, Error> {
+        log::debug!("Discovering file {}", name.display());
+
+        // if it's an absolute path, we don't need to walk up the tree.
+        if name.is_absolute() {
+            if name.is_file() {
+                return Ok(name.to_path_buf());
+            } else if name.exists() {
+                let name = format!("{}", name.display());
+                return Err(failure::format_err!(
+                    "{} exists, but is not a file",
+                    name.red()
+                ));
+            } else {
+                let name = format!("{}", name.display());
+                return Err(failure::format_err!("{} does not exist", name.red()));
+            }
+        }
+
+        // walk up the tree until we find the file or hit the root
+        let mut path = std::env::current_dir()
+            .map_err(|err| failure::format_err!("Couldn't identify working dir: {}", err))?;
+
+        log::debug!("Checking {}", path.join(name).display());
+        while !path.join(name).is_file() {
+            path.pop();
+            if path.parent().is_none() {
+                break;
+            }
+            log::debug!("Checking {}", path.join(name).display());
+        }
+
+        path.push(name);
+
+        if path.is_file() {
+            Ok(path)
+        } else {
+            let name = format!("{}", name.display());
+            Err(failure::format_err!("Couldn't discover {}", name.red()))
+        }
+    }
+
+    /// Search a directory for default moldfile
+    ///
+    /// Legacy `mold.yaml` and `mold.yml` files are used as a fallback when there's no moldfile.
+    fn discover_dir(name: &Path) -> Result<PathBuf, Error> {
+        log::debug!("Discovering directory {}", name.display());
+        let path = name.join("moldfile");
+        Self::discover_file(&path).or_else(|err| {
+            if cfg!(feature = "yaml") {
+                Self::discover_file(&name.join("mold.yaml"))
+                    .or_else(|_| Self::discover_file(&name.join("mold.yml")))
+                    .map_err(|_| err)
+            } else {
+                Err(err)
+            }
+        })
+    }
+
+    /// Try to locate a file or a directory, opening it if found
+    pub fn discover(dir: &Path, file: Option<PathBuf>) -> Result<PathBuf, Error> {
+        // I think this should take Option<&Path> but I couldn't figure out how to
+        // please the compiler when I have an existing Option<PathBuf>, so... I'm
+        // just using .clone() on it.
+        match file {
+            Some(file) => Self::discover_file(&dir.join(file)),
+            None => Self::discover_dir(dir),
+        }
+    }
+
+    /// Look up a recipe by name
+    fn recipe(&self, name: &str) -> Result<&Recipe, Error> {
+        let base = self.base_name(name);
+        self.recipes
+            .get(base)
+            .ok_or_else(|| self.missing_recipe(base))
+    }
+
+    /// Resolve a target from the command line into the name of a recipe
+    ///
+    /// A name that doesn't match any recipe exactly may match a single imported recipe once its
+    /// import prefix is left off, eg: `docker-build` for `ci/docker-build`. Names that don't match
+    /// anything are returned as they are, so that the usual errors report them.
+    pub fn resolve_target(&self, name: &str) -> Result<String, Error> {
+        if !self.suffix_match
+            || self.recipes.contains_key(name)
+            || self.instances.contains_key(name)
+        {
+            return Ok(name.to_string());
+        }
+
+        let prefixes: IndexSet<&str> = self
+            .imports
+            .values()
+            .flatten()
+            .map(|(prefix, _, _)| prefix.as_str())
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+
+        let candidates: Vec<_> = self
+            .recipe_names()
+            .into_iter()
+            .filter(|key| {
+                prefixes
+                    .iter()
+                    .any(|prefix| key.strip_prefix(prefix) == Some(name))
+            })
+            .collect();
+
+        match candidates.as_slice() {
+            [] => Ok(name.to_string()),
+            [found] => {
+                eprintln!("{:>12} {} to {}", "Resolved".green(), name, found.cyan());
+                Ok(found.to_string())
+            }
+            _ => Err(failure::format_err!(
+                "Recipe name {} is ambiguous; it could be any of {}",
+                name.red(),
+                candidates.join(", ")
+            )),
+        }
+    }
+
+    /// Explain why a recipe couldn't be found
+    ///
+    /// Recipes that would have come from a broken import report the import's error instead.
+    fn missing_recipe(&self, name: &str) -> Error {
+        match self.broken_import(name) {
+            Some(err) => failure::format_err!("Couldn't find recipe {}; {}", name.red(), err),
+            None => failure::format_err!("Couldn't find recipe {}", name.red()),
+        }
+    }
+
+    /// Find the error of the broken import that a recipe would have come from
+    fn broken_import(&self, name: &str) -> Option<&String> {
+        self.broken_imports
+            .iter()
+            .find(|(prefix, _)| !prefix.is_empty() && name.starts_with(prefix.as_str()))
+            .map(|(_, err)| err)
+    }
+
+    /// Find the recipe that a name refers to, looking through bound instances
+    fn base_name<'a>(&'a self, name: &'a str) -> &'a str {
+        match self.instances.get(name) {
+            Some((base, _)) => base,
+            None => name,
+        }
+    }
+
+    /// Split the targets from the command line into recipes and `NAME=value` parameter values
+    ///
+    /// A recipe that declares `positional` arguments takes that many of the tokens after it as
+    /// their values, whatever they look like. Running out of tokens, or following them with
+    /// something that isn't a recipe, is an error that shows how the recipe is called.
+    pub fn parse_targets(&mut self, tokens: &[String]) -> Result<(TargetSet, VarMap), Error> {
+        let mut targets = TargetSet::new();
+        let mut named = VarMap::new();
+        let mut tokens = tokens.iter().peekable();
+
+        while let Some(token) = tokens.next() {
+            if let Some((name, value)) = util::split_assignment(token) {
+                named.insert(name.into(), value.into());
+                continue;
+            }
+
+            let target = self.resolve_target(token)?;
+            let positionals = match self.recipe(&target) {
+                Ok(recipe) if !recipe.positionals.is_empty() => recipe.positionals.clone(),
+                _ => {
+                    targets.insert(target);
+                    continue;
+                }
+            };
+
+            let usage = || {
+                format!(
+                    "usage: {}",
+                    format!("mold {} {}", target, positionals.join(" ")).green()
+                )
+            };
+
+            let mut values = VarMap::new();
+            for name in &positionals {
+                match tokens.next() {
+                    Some(value) => values.insert(name.clone(), value.clone()),
+                    None => {
+                        return Err(failure::format_err!(
+                            "Recipe {} takes {} arguments, but got {}; {}",
+                            target.red(),
+                            positionals.len(),
+                            values.len(),
+                            usage()
+                        ))
+                    }
+                };
+            }
+
+            if let Some(extra) = tokens.peek() {
+                let known = util::split_assignment(extra).is_some()
+                    || self.recipe(&self.resolve_target(extra)?).is_ok();
+                if !known {
+                    return Err(failure::format_err!(
+                        "Recipe {} takes {} arguments, but {} was given too; {}",
+                        target.red(),
+                        positionals.len(),
+                        extra.red(),
+                        usage()
+                    ));
+                }
+            }
+
+            self.param_args
+                .entry(target.clone())
+                .or_default()
+                .extend(values);
+            targets.insert(target);
+        }
+
+        Ok((targets, named))
+    }
+
+    /// Hand the arguments from the command line to the target recipes
+    ///
+    /// Every target sees the positional arguments as $1, $2, ... and $@, and they also bind the
+    /// target's parameters in the order that they're declared. `NAME=value` arguments bind the
+    /// parameter of that name, and it's an error if no target declares one.
+    pub fn bind_args(
+        &mut self,
+        targets: &TargetSet,
+        positional: &[String],
+        named: &VarMap,
+    ) -> Result<(), Error> {
+        let mut used = TargetSet::new();
+
+        for target in targets {
+            // missing targets are reported along with everything else that's wrong with them
+            let recipe = match self.recipe(target) {
+                Ok(recipe) => recipe,
+                Err(_) => continue,
+            };
+
+            let mut values: VarMap = recipe
+                .params
+                .iter()
+                .zip(positional)
+                .map(|(param, value)| (param.name.clone(), value.clone()))
+                .collect();
+            for param in &recipe.params {
+                if let Some(value) = named.get(&param.name) {
+                    values.insert(param.name.clone(), value.clone());
+                    used.insert(param.name.clone());
+                }
+            }
+
+            self.args.insert(target.clone(), positional.to_vec());
+            if !values.is_empty() {
+                self.param_args
+                    .entry(target.clone())
+                    .or_default()
+                    .extend(values);
+            }
+        }
+
+        if let Some(name) = named.keys().find(|x| !used.contains(*x)) {
+            return Err(failure::format_err!(
+                "None of the targets have a parameter named {}",
+                name.red()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Find every value bound to a recipe's parameters
+    ///
+    /// Values come from `require ... with` bindings and from the command line, which wins.
+    fn bindings(&self, name: &str) -> Option<VarMap> {
+        let instance = self.instances.get(name).map(|(_, bindings)| bindings);
+        let given = self.param_args.get(name);
+        if instance.is_none() && given.is_none() {
+            return None;
+        }
+
+        let mut bindings = instance.cloned().unwrap_or_default();
+        bindings.extend(given.cloned().unwrap_or_default());
+        Some(bindings)
+    }
+
+    /// Find the scoped import that a recipe came from, if any
+    fn scope_of(&self, name: &str) -> Option<&String> {
+        let file = self.recipe_files.get(self.base_name(name))?;
+        self.scoped_files.get(file)
+    }
+
+    /// Find the variables that a recipe sees, including those from its scoped import
+    ///
+    /// Variables set on the command line still win over the scope's own values.
+    fn visible_vars(&self, name: &str) -> VarMap {
+        let mut vars = self.vars.clone();
+        if let Some(scope) = self.scope_of(name) {
+            for (key, value) in &self.scopes[scope] {
+                if !self.overrides.contains_key(key) {
+                    vars.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        vars
+    }
+
+    /// Construct a Task instance from a recipe name
+    fn build_task(&self, name: &str) -> Result<Task, Error> {
+        let recipe = self.recipe(name)?;
+        let source = self.sources.get(self.base_name(name)).ok_or_else(|| {
+            failure::format_err!("Couldn't find source repository for {}", name.red())
+        })?;
+
+        self.make_task(name, recipe, source, self.bindings(name).as_ref())
+    }
+
+    /// Ask for the parameters of a recipe that nothing else provides
+    ///
+    /// Parameters are provided by `require ... with` bindings, the command line, or the
+    /// environment. Without a TTY, parameters with a default use it and the others are an error.
+    fn ask_params(&self, name: &str) -> Result<VarMap, Error> {
+        let recipe = self.recipe(name)?;
+        let bindings = self.bindings(name);
+        let interactive = self.interactive && util::is_interactive(util::Stream::Stdin);
+
+        let mut answers = VarMap::new();
+        for param in &recipe.params {
+            let bound = bindings
+                .as_ref()
+                .is_some_and(|x| x.contains_key(&param.name));
+            if bound || std::env::var_os(&param.name).is_some() {
+                continue;
+            }
+
+            if interactive {
+                let default = match &param.default {
+                    Some(value) => format!(" [default: {}]", self.expand(value, &self.vars)),
+                    None => String::new(),
+                };
+                let message = format!("Enter value for {}{}: ", param.name.cyan(), default);
+                let value = util::prompt(&message, param.secret)?;
+
+                // an empty answer leaves the default in place
+                if !value.is_empty() || param.default.is_none() {
+                    answers.insert(param.name.clone(), value);
+                }
+            } else if param.default.is_none() {
+                return Err(failure::format_err!(
+                    "Recipe {} needs a value for parameter {}; pass {}=... on the command line, bind it with `require {} with {}=\"...\"`, or set it in the environment",
+                    name.red(),
+                    param.name.red(),
+                    param.name,
+                    name,
+                    param.name
+                ));
+            }
+        }
+
+        Ok(answers)
+    }
+
+    /// Make sure that every variable a recipe requires is set by a moldfile or the environment
+    ///
+    /// Parameters are left out, since missing ones can still be asked for.
+    fn check_required_vars(&self, name: &str, recipe: &Recipe, vars: &VarMap) -> Result<(), Error> {
+        for (var, help) in &recipe.required_vars {
+            let is_param = recipe.params.iter().any(|x| &x.name == var);
+            let from_env = !self.unset.contains(var) && std::env::var_os(var).is_some();
+            if is_param || vars.contains_key(var) || from_env {
+                continue;
+            }
+
+            let help = help.as_ref().map(|x| format!(" ({})", x));
+            return Err(failure::format_err!(
+                "Recipe {} requires variable {}{}; set it in the environment or pass --set {}=...",
+                name.red(),
+                var.red(),
+                help.unwrap_or_default(),
+                var
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Make sure that every recipe about to run has the variables it requires, before any of
+    /// them starts
+    pub fn check_all_required_vars(&self, targets: &TargetSet) -> Result<(), Error> {
+        for name in targets {
+            if !self.recipe(name)?.required_vars.is_empty() {
+                self.build_task(name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Construct a Task for a recipe that lives in the given source directory
+    fn make_task(
+        &self,
+        name: &str,
+        recipe: &Recipe,
+        source: &Path,
+        bindings: Option<&VarMap>,
+    ) -> Result<Task, Error> {
+        let mut vars = self.expand_vars(&self.visible_vars(name));
+
+        // bound instances get their own values layered on top
+        for (key, value) in bindings.into_iter().flatten() {
+            let value = self.expand(value, &vars).to_string();
+            vars.insert(key.clone(), value);
+        }
+
+        // parameters that weren't bound come from the environment, or else their default
+        for param in &recipe.params {
+            if bindings.is_some_and(|x| x.contains_key(&param.name)) {
+                continue;
+            }
+
+            let value = match (std::env::var(&param.name), &param.default) {
+                (Ok(value), _) => value,
+                (Err(_), Some(default)) => self.expand(default, &vars).to_string(),
+                (Err(_), None) => continue,
+            };
+            vars.insert(param.name.clone(), value);
+        }
+
+        self.check_required_vars(name, recipe, &vars)?;
+
+        // insert var for where this recipe's moldfile lives
+        vars.insert("MOLD_SOURCE".into(), source.to_string_lossy().into());
+
+        // select the recipe's working dir if it's defined, otherwise select the Mold's working dir. in
+        // both cases, we want to expand the variables afterwards and join it with $MOLD_ROOT. if
+        // neither dir is defined, the command will default to the current working dir.
+        let work_dir = self.recipe_dir(recipe, &vars);
+
+        // give every recipe its own scratch directory. it's created before anything runs, since
+        // scripts that the recipe calls can use it without the recipe ever mentioning it
+        let state_dir = self.state_dir(name);
+        vars.insert("MOLD_STATE".into(), state_dir.to_string_lossy().into());
+
+        // scripts are named by their contents, so an unchanged script is written once and reused
+        let script = recipe.script.as_ref().map(|body| {
+            let file = format!("{}.sh", util::hash_string(body));
+            (self.script_dir().join(file), body.clone())
+        });
+        if let Some((path, _)) = &script {
+            vars.insert("MOLD_SCRIPT".into(), path.to_string_lossy().into());
+        }
+
+        // remember what we injected or overrode so that failures can explain themselves
+        let context = if self.failure_context {
+            Some(injected_vars(&vars))
+        } else {
+            None
+        };
+
+        // unset variables are removed from the environment, and expand to nothing in commands
+        let mut unset: Vec<String> = self.unset.iter().cloned().collect();
+        for name in &recipe.unset {
+            vars.shift_remove(name);
+            if !unset.contains(name) {
+                unset.push(name.clone());
+            }
+        }
+
+        // positional arguments are only for expanding commands, not for their environment
+        let mut expansion = vars.clone();
+        for name in &unset {
+            expansion.entry(name.clone()).or_default();
+        }
+        if let Some(args) = self.args.get(name) {
+            for (idx, arg) in args.iter().enumerate() {
+                expansion.insert((idx + 1).to_string(), arg.clone());
+            }
+            expansion.insert("@".into(), shell_words::join(args));
+        }
+
+        // build the command strings to execute
+        let shell = match &recipe.shell {
+            Some(shell) => Some(shell_words::split(shell).map_err(|err| {
+                failure::format_err!("Couldn't shell split string {}: {}", shell.red(), err)
+            })?),
+            None => None,
+        };
+
+        let mut commands = vec![];
+        let mut timeouts = vec![];
+        for command in &recipe.commands {
+            let args = match &shell {
+                Some(shell) => {
+                    self.build_shell_args(command, shell, &expansion, work_dir.as_deref())?
+                }
+                None => self.build_args(command, &expansion, work_dir.as_deref())?,
+            };
+            if args.is_empty() {
+                continue;
+            }
+            commands.push(args);
+            timeouts.push(command.timeout());
+        }
+
+        // a script on its own is run by its interpreter, or otherwise directly, with sh unless it
+        // picks an interpreter itself
+        let mut interpreter = None;
+        if let (true, Some((path, body))) = (recipe.commands.is_empty(), &script) {
+            let path = path.to_string_lossy().to_string();
+            if let Some(program) = &recipe.interpreter {
+                let program = self.expand(program, &expansion);
+                let mut args = shell_words::split(&program).map_err(|err| {
+                    failure::format_err!("Couldn't shell split string {}: {}", program.red(), err)
+                })?;
+                if args.is_empty() {
+                    return Err(failure::format_err!(
+                        "Recipe {} has an empty interpreter",
+                        name.red()
+                    ));
+                }
+                interpreter = Some(args[0].clone());
+                args.push(path);
+                commands.push(args);
+            } else if body.starts_with("#!") {
+                commands.push(vec![path]);
+            } else {
+                commands.push(vec!["sh".into(), path]);
+            }
+            timeouts.push(None);
+        }
+
+        // the variables above hold paths as strings, which can't hold every path's bytes
+        let lossy_paths = util::lossy_paths(
+            [self.root_dir.as_path(), &self.mold_dir, source, &state_dir]
+                .iter()
+                .copied()
+                .chain(script.as_ref().map(|(path, _)| path.as_path())),
+        );
+
+        Ok(Task {
+            name: name.into(),
+            lossy_paths,
+            commands,
+            script,
+            interpreter,
+            vars,
+            secrets: recipe
+                .params
+                .iter()
+                .filter(|x| x.secret)
+                .map(|x| x.name.clone())
+                .collect(),
+            unset,
+            work_dir,
+            timeout: recipe.timeout,
+            timeouts,
+            umask: recipe.umask,
+            retries: recipe.retries,
+            exclusive: match recipe.exclusive {
+                true => Some((self.mold_dir.clone(), self.lock_timeout)),
+                false => None,
+            },
+            label: self.label.clone(),
+            quiet: false,
+            context,
+            state_dir,
+        })
+    }
+
+    /// Find the working directory that a recipe's commands run in, if it isn't the current one
+    fn recipe_dir(&self, recipe: &Recipe, vars: &VarMap) -> Option<PathBuf> {
+        let raw_path = recipe.dir.as_ref().or(self.work_dir.as_ref())?;
+        Some(self.root_dir.join(self.expand(raw_path, vars).to_string()))
+    }
+
+    /// Find the directory that inline scripts are written to
+    ///
+    /// Scripts normally go in `.mold/scripts`, but a project on a `noexec` mount couldn't run them
+    /// from there, so they're moved to the system's temp directory instead. That's only checked
+    /// once, the first time a script is needed.
+    fn script_dir(&self) -> &Path {
+        self.script_dir.get_or_init(|| {
+            let dir = self.mold_dir.join("scripts");
+            if util::can_exec(&dir) {
+                return dir;
+            }
+
+            let fallback = fallback_script_dir(&self.root_dir);
+            if let Err(err) = util::private_dir(&fallback) {
+                util::warn(&err.to_string());
+                return dir;
+            }
+            if !util::can_exec(&fallback) {
+                return dir;
+            }
+
+            util::warn(&format!(
+                "{} is on a filesystem that can't run scripts, so they're written to {} instead",
+                dir.display(),
+                fallback.display()
+            ));
+            fallback
+        })
+    }
+
+    /// Find the directory that $MOLD_STATE points to for a recipe
+    fn state_dir(&self, name: &str) -> PathBuf {
+        self.mold_dir.join("state").join(util::hash_string(name))
+    }
+
+    /// Delete the $MOLD_STATE directory of a single recipe
+    pub fn clean_state(&self, name: &str) -> Result<(), Error> {
+        self.recipe(name)?;
+
+        let dir = self.state_dir(name);
+        if dir.is_dir() {
+            fs::remove_dir_all(&dir).map_err(|err| {
+                failure::format_err!(
+                    "Couldn't remove directory {}: {}",
+                    dir.display().to_string().red(),
+                    err
+                )
+            })?;
+
+            println!("{:>12} {}", "Deleted".red(), dir.display());
+        } else {
+            println!("{:>12}", "Clean!".green());
+        }
+
+        Ok(())
+    }
+
+    /// Expand a recipe into the lists of arguments it would execute
+    pub fn commands(&self, name: &str) -> Result<Vec<Vec<String>>, Error> {
+        Ok(self.build_task(name)?.commands)
+    }
+
+    /// Construct and execute a Task from a recipe name
+    ///
+    /// A dry run prints what would be executed without checking guards, prompting, or running
+    /// anything.
+    pub fn execute(&self, name: &str, dry_run: bool) -> Result<(), Error> {
+        if self.skip_up_to_date(name)? {
+            return Ok(());
+        }
+
+        if dry_run {
+            return self.build_task(name)?.dry_run();
+        }
+
+        let task = self.prepare(name)?;
+        let key = self.cache_key(name, &task)?;
+        if self.is_cached(name, key.as_deref())? {
+            return Ok(());
+        }
+
+        task.execute()?;
+        self.mark_completed(name)?;
+        self.store_cache(name, key.as_deref())
+    }
+
+    /// Skip a recipe whose `outputs` are all newer than its `inputs`, unless forced
+    ///
+    /// Recipes without inputs or outputs always run, as do those with an input pattern that
+    /// matches nothing or an output pattern that matches nothing.
+    fn skip_up_to_date(&self, name: &str) -> Result<bool, Error> {
+        let recipe = self.recipe(name)?;
+        if self.force || recipe.inputs.is_empty() || recipe.outputs.is_empty() {
+            return Ok(false);
+        }
+
+        let times = |pattern: &str| {
+            let snapshot = self.matching_files(name, pattern);
+            snapshot.values().map(|(time, _)| *time).collect::<Vec<_>>()
+        };
+
+        let mut newest_input = None;
+        for pattern in &recipe.inputs {
+            let found = times(pattern);
+            if found.is_empty() || found.contains(&None) {
+                return Ok(false);
+            }
+            newest_input = newest_input.max(found.into_iter().max().flatten());
+        }
+
+        for pattern in &recipe.outputs {
+            let found = times(pattern);
+            if found.is_empty() || found.iter().any(|x| *x < newest_input) {
+                return Ok(false);
+            }
+        }
+
+        println!("{:>12} {} (up to date)", "Skipped".yellow(), name);
+        Ok(true)
+    }
+
+    /// Find the files that one of a recipe's `inputs` or `outputs` patterns matches
+    fn matching_files(&self, name: &str, pattern: &str) -> watch::Snapshot {
+        let vars = self.expand_vars(&self.visible_vars(name));
+        let base = self
+            .sources
+            .get(self.base_name(name))
+            .unwrap_or(&self.root_dir);
+
+        let mut watcher = watch::Watcher::default();
+        watcher.add(base, &self.expand(pattern, &vars));
+        watcher.snapshot()
+    }
+
+    /// Hash everything that a `cache` recipe's result depends on
+    ///
+    /// That's the contents of every input file along with the expanded commands and script. Recipes
+    /// that don't `cache` have no key, and neither does anything under `--no-cache`.
+    fn cache_key(&self, name: &str, task: &Task) -> Result<Option<String>, Error> {
+        let recipe = self.recipe(name)?;
+        if !recipe.cache || !self.use_cache {
+            return Ok(None);
+        }
+
+        let mut parts = vec![];
+        for pattern in &recipe.inputs {
+            parts.push(format!("inputs {}", pattern));
+            for path in self.matching_files(name, pattern).keys() {
+                let contents = fs::read(path).unwrap_or_default();
+                parts.push(format!(
+                    "{} {}",
+                    path.display(),
+                    manifest::sha256(&contents)
+                ));
+            }
+        }
+        for args in &task.commands {
+            parts.push(format!("$ {}", shell_words::join(args)));
+        }
+        if let Some((_, body)) = &task.script {
+            parts.push(format!("script {}", body));
+        }
+
+        Ok(Some(util::hash_string(&parts.join("\n"))))
+    }
+
+    /// Find the file that the cache key of a recipe's last successful run is kept in
+    ///
+    /// The path is relative to .mold, since it's tracked by its manifest.
+    fn cache_file(&self, name: &str) -> String {
+        format!("cache/{}.json", util::hash_string(name))
+    }
+
+    /// Skip a recipe whose cache key matches its last successful run
+    fn is_cached(&self, name: &str, key: Option<&str>) -> Result<bool, Error> {
+        let key = match key {
+            Some(key) => key,
+            None => return Ok(false),
+        };
+
+        let stored = manifest::read(&self.mold_dir, &self.cache_file(name), name)?;
+        let stored: serde_json::Value = stored
+            .and_then(|x| serde_json::from_slice(&x).ok())
+            .unwrap_or_default();
+        if stored["key"].as_str() != Some(key) {
+            return Ok(false);
+        }
+
+        println!("{:>12} {}", "Cached".green(), name);
+        Ok(true)
+    }
+
+    /// Remember the cache key of a recipe that just succeeded
+    fn store_cache(&self, name: &str, key: Option<&str>) -> Result<(), Error> {
+        let key = match key {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        let contents = serde_json::json!({ "recipe": name, "key": key });
+        let contents = serde_json::to_string_pretty(&contents)?;
+        manifest::write(
+            &self.mold_dir,
+            &self.cache_file(name),
+            contents.as_bytes(),
+            name,
+        )
+    }
+
+    /// Check a recipe's guards, ask for its parameters, and construct its Task
+    fn prepare(&self, name: &str) -> Result<Task, Error> {
+        if self.recipe(name)?.clean_tree && !self.allow_dirty {
+            self.check_clean_tree(name)?;
+        }
+
+        for (target, max_age) in &self.recipe(name)?.completed {
+            self.check_completed(name, target, *max_age)?;
+        }
+
+        // answers are bound just like `require ... with` bindings
+        let answers = self.ask_params(name)?;
+        let task = if answers.is_empty() {
+            self.build_task(name)?
+        } else {
+            let recipe = self.recipe(name)?;
+            let source = &self.sources[self.base_name(name)];
+            let mut bindings = self.bindings(name).unwrap_or_default();
+            bindings.extend(answers);
+            self.make_task(name, recipe, source, Some(&bindings))?
+        };
+
+        Ok(task)
+    }
+
+    /// Find the success marker that a recipe leaves in .mold/completed/
+    ///
+    /// The path is relative to .mold, since markers are tracked by its manifest.
+    fn completed_marker(&self, name: &str) -> String {
+        format!("completed/{}", util::hash_string(name))
+    }
+
+    /// Hash the contents of the moldfile that defines a recipe
+    ///
+    /// Markers left by an older version of the moldfile are stale.
+    fn source_hash(&self, name: &str) -> Result<String, Error> {
+        let path = &self.recipe_files[self.base_name(name)];
+        let contents = Self::read(path)?;
+        Ok(util::hash_string(&contents))
+    }
+
+    /// Record that a recipe just succeeded
+    fn mark_completed(&self, name: &str) -> Result<(), Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let contents = format!("{}\n{}\n{}\n", name, self.source_hash(name)?, now);
+
+        let rel = self.completed_marker(name);
+        manifest::write(&self.mold_dir, &rel, contents.as_bytes(), name)
+    }
+
+    /// Make sure that a recipe succeeded in an earlier invocation, and recently enough
+    fn check_completed(
+        &self,
+        name: &str,
+        target: &str,
+        max_age: Option<Duration>,
+    ) -> Result<(), Error> {
+        self.recipe(target)?;
+
+        // a marker that doesn't match the manifest is as good as no marker at all
+        let rel = self.completed_marker(target);
+        let marker = manifest::read(&self.mold_dir, &rel, target)?.unwrap_or_default();
+        let marker = String::from_utf8_lossy(&marker);
+        let mut lines = marker.lines();
+        let recorded = lines.next();
+        let hash = lines.next();
+        let time = lines.next().and_then(|x| x.parse::<u64>().ok());
+
+        let problem = match (recorded, hash, time) {
+            (Some(recorded), Some(hash), Some(time)) if recorded == target => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let age = Duration::from_secs(now.saturating_sub(time));
+
+                if hash != self.source_hash(target)? {
+                    Some(format!(
+                        "its moldfile has changed since it last succeeded {} ago",
+                        util::format_duration(age)
+                    ))
+                } else {
+                    match max_age {
+                        Some(max_age) if age > max_age => Some(format!(
+                            "it last succeeded {} ago, more than {}",
+                            util::format_duration(age),
+                            util::format_duration(max_age)
+                        )),
+                        _ => None,
+                    }
+                }
+            }
+            _ => Some("it hasn't succeeded yet".to_string()),
+        };
+
+        match problem {
+            None => Ok(()),
+            Some(problem) => Err(failure::format_err!(
+                "Recipe {} requires a completed {}, but {}; run '{}' first",
+                name.red(),
+                target.red(),
+                problem,
+                format!("mold {}", target).green()
+            )),
+        }
+    }
+
+    /// Execute a list of recipes in order, showing progress when there's more than one
+    ///
+    /// Estimates of the remaining time come from the `expected` durations of the recipes that
+    /// haven't finished yet. They're purely informational.
+    ///
+    /// With `keep_going`, a failure only skips the recipes that require it, and a summary is
+    /// printed at the end.
+    pub fn execute_all(&self, targets: &TargetSet, dry_run: bool) -> Result<(), Error> {
+        let expected = targets
+            .iter()
+            .map(|name| self.recipe(name).map(|x| x.expected))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut summary = Summary::default();
+
+        for (idx, name) in targets.iter().enumerate() {
+            if self.keep_going && summary.blocks(self.recipe(name)?) {
+                summary.skipped.push(name.clone());
+                continue;
+            }
+
+            if targets.len() > 1 {
+                let remaining: Vec<_> = expected[idx..].iter().flatten().collect();
+                let progress = if remaining.is_empty() {
+                    format!("[{}/{}]", idx + 1, targets.len())
+                } else {
+                    let total = remaining.into_iter().sum();
+                    format!(
+                        "[{}/{}, ~{} remaining]",
+                        idx + 1,
+                        targets.len(),
+                        util::format_duration(total)
+                    )
+                };
+                println!("{} {} {}", "mold".white(), progress.white(), name.cyan());
+            }
+
+            match self.execute(name, dry_run) {
+                Ok(()) => summary.succeeded.push(name.clone()),
+                Err(err) if self.keep_going => summary.fail(name, err),
+                Err(err) => return Err(err),
+            }
+        }
+
+        if self.keep_going {
+            summary.finish()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Execute a list of recipes with up to `jobs` of them running at once
+    ///
+    /// Recipes run in levels: each level holds every recipe whose requirements all ran in earlier
+    /// levels. A recipe's `weight` is the number of job slots it occupies, so heavy recipes leave
+    /// room for fewer siblings, and ones with a weight of 0 start right away. Output is forwarded
+    /// fairly, with each line labeled by its recipe. After the first failure no new recipes are
+    /// started, and that failure is returned once the running ones finish. With `keep_going`, only
+    /// the recipes that require a failed one are skipped, and a summary is printed at the end.
+    pub fn execute_parallel(&self, targets: &TargetSet, jobs: usize) -> Result<(), Error> {
+        let jobs = jobs.max(1);
+        let levels = order::levels(targets, |name| self.recipe(name).map(|x| &x.requires))?;
+        let labels: Vec<String> = targets.iter().cloned().collect();
+
+        let (tx, rx) = mpsc::channel();
+        let forwarder =
+            thread::spawn(move || output::Interleaver::new(std::io::stdout(), &labels, 4).run(rx));
+
+        let mut result = Ok(());
+        let mut summary = Summary::default();
+        let mut keys = IndexMap::new();
+        for level in levels {
+            // guards and prompts happen up front, one recipe at a time
+            let mut tasks = vec![];
+            for name in &level {
+                if self.keep_going && summary.blocks(self.recipe(name)?) {
+                    summary.skipped.push(name.clone());
+                    continue;
+                }
+
+                match self.skip_up_to_date(name) {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                }
+
+                let prepared = self.prepare(name).and_then(|task| {
+                    let key = self.cache_key(name, &task)?;
+                    Ok((self.is_cached(name, key.as_deref())?, task, key))
+                });
+                match prepared {
+                    Ok((true, _, _)) => {}
+                    Ok((false, task, key)) => {
+                        keys.insert(name.clone(), key);
+                        tasks.push((targets.get_index_of(name.as_str()).unwrap_or(0), task))
+                    }
+                    Err(err) if self.keep_going => summary.fail(name, err),
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                }
+            }
+            if result.is_err() {
+                break;
+            }
+
+            let (succeeded, failed) = self.run_level(tasks, jobs, &tx);
+            for name in &succeeded {
+                self.mark_completed(name)?;
+                self.store_cache(name, keys.get(name).cloned().flatten().as_deref())?;
+            }
+            summary.succeeded.extend(succeeded);
+
+            if self.keep_going {
+                for (name, err) in failed {
+                    summary.fail(&name, err);
+                }
+            } else if let Some((_, err)) = failed.into_iter().next() {
+                result = Err(err);
+                break;
+            }
+        }
+
+        drop(tx);
+        forwarder
+            .join()
+            .map_err(|_| failure::format_err!("Output forwarding thread panicked"))??;
+        result?;
+
+        if self.keep_going {
+            summary.finish()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Run the tasks of one level on up to `jobs` threads
+    ///
+    /// Returns the names of the tasks that succeeded and the failures, in the order they happened.
+    /// Unless `keep_going` is set, nothing new starts after the first failure.
+    fn run_level(
+        &self,
+        tasks: Vec<(usize, Task)>,
+        jobs: usize,
+        tx: &Sender<Event>,
+    ) -> (Vec<String>, Vec<(String, Error)>) {
+        struct State {
+            queue: VecDeque<(usize, Task)>,
+            free: usize,
+            succeeded: Vec<String>,
+            failed: Vec<(String, Error)>,
+        }
+
+        // recipes that don't occupy a slot don't wait for a worker either
+        let (weightless, tasks): (Vec<_>, Vec<_>) = tasks
+            .into_iter()
+            .partition(|(_, task)| self.recipe(&task.name).is_ok_and(|x| x.weight == 0));
+
+        let workers = jobs.min(tasks.len());
+        let state = Mutex::new(State {
+            queue: tasks.into(),
+            free: jobs,
+            succeeded: vec![],
+            failed: vec![],
+        });
+        let freed = Condvar::new();
+        let stopped = |state: &State| !self.keep_going && !state.failed.is_empty();
+
+        thread::scope(|scope| {
+            for (idx, task) in weightless {
+                let tx = tx.clone();
+                let (state, freed) = (&state, &freed);
+                scope.spawn(move || {
+                    if stopped(&state.lock().unwrap()) {
+                        return;
+                    }
+
+                    let res = task.run(Some((idx, &tx)));
+                    let _ = tx.send(Event::Done(idx));
+
+                    let mut guard = state.lock().unwrap();
+                    match res {
+                        Ok(()) => guard.succeeded.push(task.name),
+                        Err(err) => guard.failed.push((task.name, err)),
+                    }
+                    freed.notify_all();
+                });
+            }
+
+            for _ in 0..workers {
+                let tx = tx.clone();
+                scope.spawn(|| {
+                    let tx = tx;
+                    loop {
+                        let mut guard = state.lock().unwrap();
+                        let (idx, task) = match guard.queue.pop_front() {
+                            Some(next) if !stopped(&guard) => next,
+                            _ => return,
+                        };
+
+                        // a recipe heavier than the whole pool runs once everything else is done
+                        let weight = self.recipe(&task.name).map_or(1, |x| x.weight).min(jobs);
+                        while guard.free < weight && !stopped(&guard) {
+                            guard = freed.wait(guard).unwrap();
+                        }
+                        if stopped(&guard) {
+                            return;
+                        }
+                        guard.free -= weight;
+                        drop(guard);
+
+                        let res = task.run(Some((idx, &tx)));
+                        let _ = tx.send(Event::Done(idx));
+
+                        let mut guard = state.lock().unwrap();
+                        guard.free += weight;
+                        match res {
+                            Ok(()) => guard.succeeded.push(task.name),
+                            Err(err) => guard.failed.push((task.name, err)),
+                        }
+                        freed.notify_all();
+                    }
+                });
+            }
+        });
+
+        let state = state.into_inner().unwrap();
+        (state.succeeded, state.failed)
+    }
+
+    /// Run a one-off command with the same variables and working directory that a recipe gets
+    ///
+    /// The command is treated like the only command of an anonymous recipe defined in the root
+    /// moldfile, so it's expanded, echoed, and checked just like any other.
+    pub fn exec(&self, command: &str) -> Result<(), Error> {
+        let recipe = Recipe {
+            help: None,
+            dir: None,
+            shell: None,
+            commands: vec![Command::Shell(command.into())],
+            script: None,
+            interpreter: None,
+            requires: TargetSet::new(),
+            required_vars: vec![],
+            clean_tree: false,
+            cache: false,
+            exclusive: false,
+            private: false,
+            completed: vec![],
+            params: vec![],
+            positionals: vec![],
+            unset: vec![],
+            bindings: BindingMap::new(),
+            weight: 1,
+            retries: 0,
+            umask: None,
+            expected: None,
+            timeout: None,
+            watch: vec![],
+            inputs: vec![],
+            outputs: vec![],
+            line: None,
+            guards: vec![],
+        };
+
+        self.make_task("exec", &recipe, &self.root_dir, None)?
+            .execute()
+    }
+
+    /// Take the project lock, waiting for any other invocation of mold that holds it
+    pub fn exclusive_lock(&self) -> Result<exclusive::Guard, Error> {
+        exclusive::acquire(&self.mold_dir, self.lock_timeout)
+    }
+
+    /// Collect the files that the targets and their dependencies `watch`
+    ///
+    /// Patterns are relative to the recipe's working directory when it has one, and otherwise to
+    /// the directory of the moldfile it came from. When none of the recipes say what to watch,
+    /// everything in their source directories that git doesn't ignore is watched instead. Every
+    /// local moldfile is always watched too.
+    pub fn watcher(&self, targets: &TargetSet) -> Result<watch::Watcher, Error> {
+        let mut watcher = watch::Watcher::default();
+        let all_targets = self.find_all_dependencies(targets)?;
+        for name in &all_targets {
+            let recipe = self.recipe(name)?;
+            if recipe.watch.is_empty() {
+                continue;
+            }
+
+            let vars = self.expand_vars(&self.visible_vars(name));
+            let base = match self.recipe_dir(recipe, &vars) {
+                Some(dir) => dir,
+                None => self
+                    .sources
+                    .get(self.base_name(name))
+                    .cloned()
+                    .unwrap_or_else(|| self.root_dir.clone()),
+            };
+            for pattern in &recipe.watch {
+                watcher.add(&base, &self.expand(pattern, &vars));
+            }
+        }
+
+        if watcher.is_empty() {
+            for name in &all_targets {
+                let source = self.sources.get(self.base_name(name));
+                watcher.add(source.unwrap_or(&self.root_dir), "**/*");
+            }
+            watcher.skip_ignored();
+        }
+
+        for file in self.local_files.keys() {
+            let dir = file.parent().unwrap_or(&self.root_dir);
+            let file_name = file.file_name().unwrap_or_default();
+            watcher.add(dir, &file_name.to_string_lossy());
+        }
+        Ok(watcher)
+    }
+
+    /// Hash the contents of every local moldfile, to tell when one of them really changed
+    fn moldfile_hashes(&self) -> Vec<(PathBuf, Option<String>)> {
+        self.local_files
+            .keys()
+            .map(|file| {
+                let contents = fs::read_to_string(file).ok();
+                (file.clone(), contents.map(|x| util::hash_string(&x)))
+            })
+            .collect()
+    }
+
+    /// Print which recipes were added, removed, or changed in a newer load of the same project
+    fn print_recipe_changes(&self, newer: &Mold) {
+        // recipes that only moved around in their file haven't changed
+        let describe = |mold: &Mold, name: &str| {
+            let mut description = mold.describe_recipe(name);
+            if let Some(fields) = description.as_object_mut() {
+                fields.remove("line");
+            }
+            description
+        };
+
+        for name in newer.recipe_names() {
+            if !self.recipes.contains_key(name) {
+                println!("{:>12} {}", "Added".green(), name);
+            } else if describe(self, name) != describe(newer, name) {
+                println!("{:>12} {}", "Changed".yellow(), name);
+            }
+        }
+
+        for name in self.recipe_names() {
+            if !newer.recipes.contains_key(name) {
+                println!("{:>12} {}", "Removed".red(), name);
+            }
+        }
+    }
+
+    /// Run the targets, then run them again every time a file that they watch changes
+    ///
+    /// Each run is a fresh `command`, so that changes to the moldfile itself are picked up too. A
+    /// change waits until nothing has changed for `debounce`, and then kills a run that's still
+    /// going before starting the next one. Watching stops on Ctrl-C.
+    ///
+    /// When the contents of a local moldfile change, `reload` loads the project again the same way
+    /// it was loaded at first. The recipes that were added, removed, or changed are listed, and the
+    /// new recipes decide what's watched from then on. A moldfile that no longer loads is reported
+    /// and nothing is re-run; the last good one stays in effect until it's fixed.
+    pub fn watch<R, F>(
+        &self,
+        targets: &TargetSet,
+        debounce: Duration,
+        mut reload: R,
+        mut command: F,
+    ) -> Result<(), Error>
+    where
+        R: FnMut() -> Result<Mold, Error>,
+        F: FnMut() -> process::Command,
+    {
+        let mut watcher = self.watcher(targets)?;
+        let names: Vec<_> = targets.iter().cloned().collect();
+        let names = names.join(" ");
+        watch::catch_interrupts();
+        self.print_watching(&watcher);
+
+        let mut reloaded: Option<Mold> = None;
+        let mut hashes = self.moldfile_hashes();
+        let mut snapshot = watcher.snapshot();
+        let mut child = watch::spawn(command())?;
+        loop {
+            thread::sleep(Duration::from_millis(100));
+            if watch::interrupted() {
+                watch::stop(&mut child);
+                println!("{:>12} watching {}", "Stopped".yellow(), names);
+                return Ok(());
+            }
+
+            let mut next = watcher.snapshot();
+            let changed = watch::changes(&snapshot, &next);
+            if changed.is_empty() {
+                continue;
+            }
+
+            // editors tend to write a few times in a row, so wait for things to settle
+            loop {
+                thread::sleep(debounce);
+                let again = watcher.snapshot();
+                if again == next {
+                    break;
+                }
+                next = again;
+            }
+            snapshot = next;
+
+            // a moldfile that was only touched doesn't need to be loaded again
+            let current = reloaded.as_ref().unwrap_or(self);
+            let current_hashes = current.moldfile_hashes();
+            if current_hashes != hashes {
+                hashes = current_hashes;
+                let loaded = reload().and_then(|mold| Ok((mold.watcher(targets)?, mold)));
+                match loaded {
+                    Ok((new_watcher, mold)) => {
+                        current.print_recipe_changes(&mold);
+                        if new_watcher.patterns() != watcher.patterns() {
+                            mold.print_watching(&new_watcher);
+                        }
+                        hashes = mold.moldfile_hashes();
+                        watcher = new_watcher;
+                        snapshot = watcher.snapshot();
+                        reloaded = Some(mold);
+                    }
+                    Err(err) => {
+                        eprintln!("{:>12} {}", "Error".red(), util::error_chain(&err));
+                        println!(
+                            "{:>12} the last moldfile that loaded until this one is fixed",
+                            "Keeping".yellow()
+                        );
+                        continue;
+                    }
+                }
+            }
+
+            watch::stop(&mut child);
+            let path = changed[0]
+                .strip_prefix(&self.root_dir)
+                .unwrap_or(&changed[0]);
+            println!(
+                "{}",
+                format!("── {} changed, re-running {} ──", path.display(), names).white()
+            );
+            child = watch::spawn(command())?;
+        }
+    }
+
+    /// List what a watcher is watching, relative to $MOLD_ROOT where possible
+    fn print_watching(&self, watcher: &watch::Watcher) {
+        for (base, pattern) in watcher.patterns() {
+            let base = base.strip_prefix(&self.root_dir).unwrap_or(base);
+            println!("{:>12} {}", "Watching".cyan(), base.join(pattern).display());
+        }
+    }
+
+    /// Make sure the repository containing $MOLD_ROOT has no uncommitted changes
+    fn check_clean_tree(&self, name: &str) -> Result<(), Error> {
+        let dirty = util::dirty_paths(&self.root_dir).map_err(|err| {
+            failure::format_err!(
+                "Recipe {} requires a clean working tree, but {}",
+                name.red(),
+                err
+            )
+        })?;
+
+        if dirty.is_empty() {
+            return Ok(());
+        }
+
+        let listing: Vec<_> = dirty.iter().map(|x| format!("  {}", x.red())).collect();
+        Err(failure::format_err!(
+            "Recipe {} requires a clean working tree (use --allow-dirty to override), but these paths have uncommitted changes:\n{}",
+            name.red(),
+            listing.join("\n")
+        ))
+    }
+
+    /// Perform variable expansion on a string
+    ///
+    /// A doubled `$$` is an escaped literal dollar sign.
+    fn expand<'a>(&self, val: &'a str, vars: &VarMap) -> std::borrow::Cow<'a, str> {
+        if val.contains("$$") {
+            let escaped = val.replace("$$", "\u{e001}");
+            return self.expand(&escaped, vars).replace('\u{e001}', "$").into();
+        }
+
+        // $@ isn't a valid name on its own, but the braced form is
+        if val.contains("$@") {
+            return self
+                .expand(&val.replace("$@", "${@}"), vars)
+                .into_owned()
+                .into();
+        }
+
+        let context = |name: &str| {
+            vars.get(name)
+                .map(std::string::ToString::to_string)
+                .or_else(|| match self.unset.contains(name) {
+                    true => None,
+                    false => std::env::var(name).ok(),
+                })
+                .or_else(|| Some("".into()))
+        };
+
+        shellexpand::env_with_context_no_errors(val, context)
+    }
+
+    /// Expand every variable in a map, in order
+    ///
+    /// Expanded values are stored in the new map so they can be used in later expansions.
+    fn expand_vars(&self, vars: &VarMap) -> VarMap {
+        let mut expanded = VarMap::new();
+        for (name, value) in vars {
+            let value = self.expand(value, &expanded).into_owned();
+            expanded.insert(name.clone(), value);
+        }
+        expanded
+    }
+
+    /// Run the command of a `var NAME = shell "..."` statement and capture what it prints
+    ///
+    /// The command is expanded with the variables known so far and runs from the project root.
+    /// Its output is trimmed, and its stderr goes straight through.
+    fn capture_var(&self, name: &str, command: &str, vars: &VarMap) -> Result<String, Error> {
+        let vars = self.expand_vars(vars);
+        let args = self.build_args(&Command::Shell(command.into()), &vars, None)?;
+        let (program, args) = args
+            .split_first()
+            .ok_or_else(|| failure::format_err!("Variable {} has an empty command", name.red()))?;
+
+        let output = process::Command::new(program)
+            .args(args)
+            .envs(&vars)
+            .current_dir(&self.root_dir)
+            .stderr(process::Stdio::inherit())
+            .output()
+            .map_err(|err| {
+                failure::format_err!(
+                    "Couldn't run {} for variable {}: {}",
+                    program.red(),
+                    name.red(),
+                    err
+                )
+            })?;
+
+        if !output.status.success() {
+            return Err(failure::format_err!(
+                "Command {} for variable {} exited with {}",
+                command.red(),
+                name.red(),
+                output.status
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Perform variable expansion on a command and return a list of arguments to
+    /// pass to std::process::Command
+    ///
+    /// Strings are shell split after expansion, but lists are used as-is so that each element
+    /// becomes exactly one argument.
+    fn build_args(
+        &self,
+        command: &Command,
+        vars: &VarMap,
+        work_dir: Option<&Path>,
+    ) -> Result<Vec<String>, Error> {
+        match command {
+            Command::Shell(command) => {
+                // built-in paths are swapped out for placeholders while splitting so that a path
+                // containing spaces always stays part of a single argument
+                let mut protected = vars.clone();
+                for name in vars.keys().filter(|x| is_protected(x)) {
+                    protected.insert(name.to_string(), format!("\u{e000}{}\u{e000}", name));
+                }
+
+                let expanded = self.expand(command, &protected);
+                let args = shell_words::split(&expanded).map_err(|err| {
+                    failure::format_err!(
+                        "Couldn't shell split string {}: {}",
+                        self.expand(command, vars).red(),
+                        err
+                    )
+                })?;
+
+                Ok(args
+                    .into_iter()
+                    .map(|mut arg| {
+                        for (name, value) in vars.iter().filter(|(x, _)| is_protected(x)) {
+                            arg = arg.replace(&format!("\u{e000}{}\u{e000}", name), value);
+                        }
+                        arg
+                    })
+                    .collect())
+            }
+            Command::Args(args) => Ok(args
+                .iter()
+                .flat_map(|arg| match vars.get("@") {
+                    // a lone $@ becomes one element per argument, like "$@" in a shell
+                    Some(all) if arg == "$@" => shell_words::split(all).unwrap_or_default(),
+                    _ => vec![self.expand(arg, vars).into_owned()],
+                })
+                .collect()),
+            Command::Raw(command) => shell_words::split(command).map_err(|err| {
+                failure::format_err!("Couldn't shell split string {}: {}", command.red(), err)
+            }),
+            Command::Any(alternatives) => match self.choose(alternatives, vars, work_dir)? {
+                Some((_, args)) => Ok(args),
+                None => {
+                    let programs: Vec<_> = alternatives
+                        .iter()
+                        .filter_map(|x| shell_words::split(x).ok()?.into_iter().next())
+                        .collect();
+                    Err(failure::format_err!(
+                        "None of the alternatives for `run any` could be found: {}",
+                        programs.join(", ").red()
+                    ))
+                }
+            },
+            Command::Timed(command, _) => self.build_args(command, vars, work_dir),
         }
+    }
 
-        for include in data.includes {
-            if !include.remote.exists(&self.mold_dir) {
-                include
-                    .remote
-                    .pull(&self.mold_dir, self.use_git)
-                    .map_err(|err| {
-                        failure::format_err!("Couldn't clone {}: {}", include.remote.url.red(), err)
-                    })?;
-
-                include
-                    .remote
-                    .checkout(&self.mold_dir, self.use_git)
-                    .map_err(|err| {
-                        failure::format_err!(
-                            "Couldn't checkout {}: {}",
-                            include.remote.ref_.red(),
-                            err
-                        )
-                    })?;
+    /// Build the arguments for a command that's handed to a `shell` as a single string
+    ///
+    /// Pre-split argument lists don't need a shell, so they're run directly like always.
+    fn build_shell_args(
+        &self,
+        command: &Command,
+        shell: &[String],
+        vars: &VarMap,
+        work_dir: Option<&Path>,
+    ) -> Result<Vec<String>, Error> {
+        let line = match command {
+            Command::Shell(command) => self.expand(command, vars).into_owned(),
+            Command::Raw(command) => command.clone(),
+            Command::Any(alternatives) => match self.choose(alternatives, vars, work_dir)? {
+                Some((idx, _)) => self.expand(&alternatives[idx], vars).into_owned(),
+                None => return self.build_args(command, vars, work_dir),
+            },
+            Command::Timed(command, _) => {
+                return self.build_shell_args(command, shell, vars, work_dir)
             }
+            Command::Args(_) => return self.build_args(command, vars, work_dir),
+        };
 
-            let path = include.remote.path(&self.mold_dir);
-            self.remotes.push(include.remote.clone());
-            let filepath = Self::discover(&path, include.remote.file)?;
-            self.open(&filepath, &include.prefix)?;
+        // a command that expands to nothing is skipped, just like without a shell
+        if line.trim().is_empty() {
+            return Ok(vec![]);
         }
 
-        self.vars.extend(data.vars);
+        let mut args = shell.to_vec();
+        args.push(line);
+        Ok(args)
+    }
 
-        // if this file has a `dir` stmt, it overrides any other dir that was set
-        if let Some(rel_path) = data.dir {
-            self.work_dir = Some(rel_path);
+    /// Pick the first alternative of a `run any` whose program can be found
+    ///
+    /// Programs are looked up on the $PATH that the command would run with, and relative paths
+    /// from its working directory. The index of the alternative is returned along with its
+    /// arguments.
+    fn choose(
+        &self,
+        alternatives: &[String],
+        vars: &VarMap,
+        work_dir: Option<&Path>,
+    ) -> Result<Option<(usize, Vec<String>)>, Error> {
+        let path_var = vars.get("PATH").map(String::as_str);
+        for (idx, alternative) in alternatives.iter().enumerate() {
+            let args = self.build_args(&Command::Shell(alternative.clone()), vars, work_dir)?;
+            let found = args
+                .first()
+                .is_some_and(|x| util::resolve_program(x, path_var, work_dir).is_some());
+            if found {
+                return Ok(Some((idx, args)));
+            }
         }
+        Ok(None)
+    }
 
-        Ok(())
+    /// Make sure that every target recipe exists, reporting all of the missing ones at once
+    pub fn check_targets(&self, targets: &TargetSet) -> Result<(), Error> {
+        for name in targets {
+            if !self.recipes.contains_key(name) && self.broken_import(name).is_some() {
+                return Err(self.missing_recipe(name));
+            }
+        }
+
+        let missing: Vec<_> = targets
+            .iter()
+            .filter(|name| !self.recipes.contains_key(*name))
+            .map(|name| name.red().to_string())
+            .collect();
+
+        match missing.len() {
+            0 => Ok(()),
+            1 => Err(failure::format_err!("Couldn't find recipe {}", missing[0])),
+            _ => Err(failure::format_err!(
+                "Couldn't find recipes {}",
+                missing.join(", ")
+            )),
+        }
     }
 
-    /// Try to find a file by walking up the tree
+    /// Render the targets and all of their dependencies as a standalone script
     ///
-    /// Absolute paths will either be located or fail instantly. Relative paths
-    /// will walk the entire file tree up to root, looking for a file with the
-    /// given name.
-    fn discover_file(name: &Path) -> Result<PathBuf, Error> {
-        log::debug!("Discovering file {}", name.display());
+    /// Variables, working directories, and commands are resolved now, so the script only works
+    /// on hosts with the same layout. Anything that needs mold at run time is an error.
+    pub fn emit_script(&self, targets: &TargetSet, shell: script::Shell) -> Result<String, Error> {
+        self.check_targets(targets)?;
 
-        // if it's an absolute path, we don't need to walk up the tree.
-        if name.is_absolute() {
-            if name.is_file() {
-                return Ok(name.to_path_buf());
-            } else if name.exists() {
-                let name = format!("{}", name.display());
-                return Err(failure::format_err!(
-                    "{} exists, but is not a file",
-                    name.red()
+        let globals = self.expand_vars(&self.vars);
+        if let Some(key) = globals.keys().find(|x| !script::is_valid_name(x)) {
+            return Err(failure::format_err!(
+                "Variable {} can't be emitted as a script because it isn't a valid shell variable name",
+                key.red()
+            ));
+        }
+
+        let mut steps = vec![];
+        for name in self.find_all_dependencies(targets)? {
+            let recipe = self.recipe(&name)?;
+            let unsupported = |what: String| {
+                failure::format_err!(
+                    "Recipe {} can't be emitted as a script because {}",
+                    name.red(),
+                    what
+                )
+            };
+
+            if let Some((target, _)) = recipe.completed.first() {
+                return Err(unsupported(format!(
+                    "checking that {} completed needs mold",
+                    target.red()
+                )));
+            }
+
+            if recipe.clean_tree {
+                return Err(unsupported("checking for a clean tree needs mold".into()));
+            }
+
+            if recipe.script.is_some() {
+                return Err(unsupported("writing its inline script needs mold".into()));
+            }
+
+            if recipe.timeout.is_some() || recipe.commands.iter().any(|x| x.timeout().is_some()) {
+                return Err(unsupported("enforcing its timeout needs mold".into()));
+            }
+
+            if recipe.retries > 0 {
+                return Err(unsupported("retrying it needs mold".into()));
+            }
+
+            let any = |x: &Command| match x {
+                Command::Timed(inner, _) => matches!(**inner, Command::Any(_)),
+                _ => matches!(x, Command::Any(_)),
+            };
+            if recipe.commands.iter().any(any) {
+                return Err(unsupported(
+                    "choosing between `run any` alternatives needs mold".into(),
                 ));
-            } else {
-                let name = format!("{}", name.display());
-                return Err(failure::format_err!("{} does not exist", name.red()));
             }
-        }
 
-        // walk up the tree until we find the file or hit the root
-        let mut path = std::env::current_dir()
-            .map_err(|err| failure::format_err!("Couldn't identify working dir: {}", err))?;
+            let bindings = self.bindings(&name);
+            for param in &recipe.params {
+                let bound = bindings
+                    .as_ref()
+                    .is_some_and(|x| x.contains_key(&param.name));
+                if !bound && param.default.is_none() && std::env::var_os(&param.name).is_none() {
+                    return Err(unsupported(format!(
+                        "parameter {} would have to be prompted for",
+                        param.name.red()
+                    )));
+                }
+            }
 
-        log::debug!("Checking {}", path.join(name).display());
-        while !path.join(name).is_file() {
-            path.pop();
-            if path.parent().is_none() {
-                break;
+            let task = self.build_task(&name)?;
+            let vars: VarMap = task
+                .vars
+                .into_iter()
+                .filter(|(key, value)| globals.get(key) != Some(value))
+                .collect();
+
+            let mut names = vars.keys().chain(&task.unset);
+            if let Some(key) = names.find(|x| !script::is_valid_name(x)) {
+                return Err(unsupported(format!(
+                    "its variable {} isn't a valid shell variable name",
+                    key.red()
+                )));
             }
-            log::debug!("Checking {}", path.join(name).display());
+
+            steps.push(script::Step {
+                name,
+                dir: task.work_dir,
+                vars,
+                unset: task.unset,
+                umask: task.umask,
+                state_dir: task.state_dir,
+                commands: task.commands,
+            });
         }
 
-        path.push(name);
+        let header = format!(
+            "generated by mold {} from {}",
+            VERSION,
+            self.root_file.display()
+        );
+        script::render(shell, &header, &globals, &steps)
+    }
 
-        if path.is_file() {
-            Ok(path)
-        } else {
-            let name = format!("{}", name.display());
-            Err(failure::format_err!("Couldn't discover {}", name.red()))
+    /// Print the order that recipes would be executed in, one per line
+    pub fn plan(&self, targets: &TargetSet) -> Result<(), Error> {
+        self.check_targets(targets)?;
+        for name in self.find_all_dependencies(targets)? {
+            println!("{}", name);
         }
+
+        Ok(())
     }
 
-    /// Search a directory for default moldfile
-    fn discover_dir(name: &Path) -> Result<PathBuf, Error> {
-        log::debug!("Discovering directory {}", name.display());
-        let path = name.join("moldfile");
-        Self::discover_file(&path)
+    /// Collect the recipes in the graph of the targets, or of every recipe when there are none,
+    /// and what each requires
+    fn graph(&self, targets: &TargetSet) -> Result<Vec<(String, Vec<String>)>, Error> {
+        let names = if targets.is_empty() {
+            let all = self.recipe_names().into_iter().map(String::from).collect();
+            self.find_all_dependencies(&all)?
+        } else {
+            self.check_targets(targets)?;
+            self.find_all_dependencies(targets)?
+        };
+
+        names
+            .into_iter()
+            .map(|name| {
+                let requires = self.recipe(&name)?.requires.iter().cloned().collect();
+                Ok((name, requires))
+            })
+            .collect()
     }
 
-    /// Try to locate a file or a directory, opening it if found
-    pub fn discover(dir: &Path, file: Option<PathBuf>) -> Result<PathBuf, Error> {
-        // I think this should take Option<&Path> but I couldn't figure out how to
-        // please the compiler when I have an existing Option<PathBuf>, so... I'm
-        // just using .clone() on it.
-        match file {
-            Some(file) => Self::discover_file(&dir.join(file)),
-            None => Self::discover_dir(dir),
+    /// Render the dependency graph of the targets, or of every recipe, in Graphviz's DOT format
+    ///
+    /// Each recipe is a node labeled with its name and help, and each requirement is an edge from
+    /// the recipe to what it requires. Recipes that don't require anything are filled in a
+    /// different color.
+    pub fn graph_dot(&self, targets: &TargetSet) -> Result<String, Error> {
+        let graph = self.graph(targets)?;
+        let escape = |x: &str| x.replace('\\', "\\\\").replace('"', "\\\"");
+        let quote = |name: &str| format!("\"{}\"", escape(name));
+
+        let mut out = String::from("digraph mold {\n");
+        out.push_str("  node [shape=box, style=\"rounded,filled\"];\n");
+        for (name, requires) in &graph {
+            let color = if requires.is_empty() {
+                "palegreen"
+            } else {
+                "lightblue"
+            };
+
+            // DOT's own \n escape breaks the label into lines
+            let label = match &self.recipe(name)?.help {
+                Some(help) => format!("{}\\n{}", escape(name), escape(help).replace('\n', "\\n")),
+                None => escape(name),
+            };
+            out.push_str(&format!(
+                "  {} [label=\"{}\", fillcolor={}];\n",
+                quote(name),
+                label,
+                color
+            ));
         }
+        for (name, requires) in &graph {
+            for dep in requires {
+                out.push_str(&format!("  {} -> {};\n", quote(name), quote(dep)));
+            }
+        }
+        out.push_str("}\n");
+        Ok(out)
     }
 
-    /// Look up a recipe by name
-    fn recipe(&self, name: &str) -> Result<&Recipe, Error> {
-        self.recipes
-            .get(name)
-            .ok_or_else(|| failure::format_err!("Couldn't find recipe {}", name.red()))
+    /// Render the dependency graph of the targets, or of every recipe, as a Mermaid flowchart
+    pub fn graph_mermaid(&self, targets: &TargetSet) -> Result<String, Error> {
+        let graph = self.graph(targets)?;
+
+        // recipe names aren't valid Mermaid ids, so nodes are numbered and labeled instead
+        let ids: IndexMap<&str, String> = graph
+            .iter()
+            .enumerate()
+            .map(|(idx, (name, _))| (name.as_str(), format!("n{}", idx)))
+            .collect();
+
+        let mut out = String::from("graph TD\n");
+        for (name, _) in &graph {
+            let label = name.replace('"', "#quot;");
+            out.push_str(&format!("  {}[\"{}\"]\n", ids[name.as_str()], label));
+        }
+        for (name, requires) in &graph {
+            for dep in requires {
+                out.push_str(&format!(
+                    "  {} --> {}\n",
+                    ids[name.as_str()],
+                    ids[dep.as_str()]
+                ));
+            }
+        }
+
+        let leaves: Vec<_> = graph
+            .iter()
+            .filter(|(_, requires)| requires.is_empty())
+            .map(|(name, _)| ids[name.as_str()].as_str())
+            .collect();
+        if !leaves.is_empty() {
+            out.push_str("  classDef leaf fill:#cfc\n");
+            out.push_str(&format!("  class {} leaf\n", leaves.join(",")));
+        }
+        Ok(out)
     }
 
-    /// Construct a Task instance from a recipe name
-    fn build_task(&self, name: &str) -> Result<Task, Error> {
-        let recipe = self.recipe(name)?;
+    /// Find *all* dependencies for a given set of target recipes
+    ///
+    /// The result is in execution order; see `order::execution`.
+    pub fn find_all_dependencies(&self, targets: &TargetSet) -> Result<TargetSet, Error> {
+        order::execution(targets, |name| self.recipe(name).map(|x| &x.requires))
+    }
 
-        // expand all variables
-        let mut vars = VarMap::new();
-        for (name, value) in &self.vars {
-            vars.insert(name.clone(), self.expand(value, &vars).into());
+    /// List every recipe in display order; see `order::display`
+    pub fn recipe_names(&self) -> Vec<&str> {
+        order::display(&self.recipes, &self.recipe_files)
+    }
+
+    /// List the recipes that aren't private in display order, for help and completions
+    pub fn public_recipe_names(&self) -> Vec<&str> {
+        self.recipe_names()
+            .into_iter()
+            .filter(|name| !self.recipes[*name].is_private(name))
+            .collect()
+    }
+
+    /// Print every recipe that transitively requires the given recipe, grouped by moldfile
+    pub fn rdeps(&self, name: &str) -> Result<(), Error> {
+        if !self.recipes.contains_key(name) {
+            let hint = match util::suggest(name, self.recipes.keys().map(String::as_str)) {
+                Some(x) => format!("; did you mean {}?", x.green()),
+                None => String::new(),
+            };
+
+            return Err(failure::format_err!(
+                "Couldn't find recipe {}{}",
+                name.red(),
+                hint
+            ));
         }
 
-        // insert var for where this recipe's moldfile lives
-        if let Some(source) = self.sources.get(name) {
-            vars.insert("MOLD_SOURCE".into(), source.to_string_lossy().into());
-        } else {
+        // invert the dependency graph once so that each recipe knows who requires it
+        let mut required_by: IndexMap<&str, Vec<&str>> = IndexMap::new();
+        for (key, recipe) in &self.recipes {
+            for dep in &recipe.requires {
+                required_by
+                    .entry(self.base_name(dep))
+                    .or_default()
+                    .push(key);
+            }
+        }
+
+        let mut found = TargetSet::new();
+        let mut stack = vec![name];
+        while let Some(current) = stack.pop() {
+            for &parent in required_by.get(current).into_iter().flatten() {
+                if parent != name && found.insert(parent.to_string()) {
+                    stack.push(parent);
+                }
+            }
+        }
+
+        if found.is_empty() {
+            println!("nothing depends on {}", name);
+            return Ok(());
+        }
+
+        let mut groups: IndexMap<&Path, Vec<&str>> = IndexMap::new();
+        for key in self
+            .recipe_names()
+            .into_iter()
+            .filter(|key| found.contains(*key))
+        {
+            let file = self.recipe_files.get(key).map_or(Path::new("?"), |x| x);
+            groups.entry(file).or_default().push(key);
+        }
+
+        for (file, names) in groups {
+            println!("{}", file.display());
+            for name in names {
+                println!("  {}", name.cyan());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run every check, reporting which ones pass and fail
+    ///
+    /// Built-in checks run first, followed by the checks from every loaded moldfile.
+    pub fn doctor(&self) -> Result<(), Error> {
+        let mut failed = 0;
+        let mut report = |name: &str, result: Result<(), Error>, help: Option<&String>| match result
+        {
+            Ok(()) => println!("{:>12} {}", "Pass".green(), name),
+            Err(err) => {
+                println!("{:>12} {}: {}", "Fail".red(), name, util::error_chain(&err));
+                if let Some(help) = help {
+                    println!("{:>12} {}", "", help.yellow());
+                }
+                failed += 1;
+            }
+        };
+
+        // built-in checks
+        let path_var = std::env::var("PATH").ok();
+        let git = match util::resolve_program("git", path_var.as_deref(), None) {
+            Some(_) => Ok(()),
+            None => Err(failure::format_err!("git was not found on PATH")),
+        };
+        let git_help = "install git; it's used by --git and by many recipes".to_string();
+        report("git", git, Some(&git_help));
+
+        for (name, check) in &self.checks {
+            let result = self
+                .make_task(name, check, &self.check_sources[name], None)
+                .and_then(|mut task| {
+                    task.quiet = true;
+                    task.context = None;
+                    task.execute()
+                });
+            report(name, result, check.help.as_ref());
+        }
+
+        if failed > 0 {
             return Err(failure::format_err!(
-                "Couldn't find source repository for {}",
-                name.red()
+                "{} check(s) failed",
+                failed.to_string().red()
             ));
         }
 
-        // select the recipe's working dir if it's defined, otherwise select the Mold's working dir. in
-        // both cases, we want to expand the variables afterwards and join it with $MOLD_ROOT. if
-        // neither dir is defined, the command will default to the current working dir.
-        let work_dir = recipe
-            .dir
-            .clone()
-            .or_else(|| self.work_dir.clone())
-            .map(|raw_path| {
-                self.root_dir
-                    .join(self.expand(&raw_path, &vars).to_string())
-            });
+        Ok(())
+    }
 
-        // build the command strings to execute
-        let mut commands = vec![];
-        for command_str in &recipe.commands {
-            let args = self.build_args(command_str, &vars)?;
-            if args.is_empty() {
-                continue;
+    /// Look for problems in everything that was loaded without running anything
+    ///
+    /// Errors are things that would make running fail: requirements that don't exist and imports
+    /// that couldn't be loaded. Warnings are things that are probably mistakes: variables that
+    /// nothing defines, and recipes that another moldfile quietly redefines. Problems that stop a
+    /// moldfile from loading at all, like syntax errors, recipes that do nothing, or a version
+    /// requirement that this mold doesn't meet, are reported by loading instead.
+    pub fn validate(&self) -> Vec<ValidationDiagnostic> {
+        let mut diagnostics = vec![];
+        let location = |name: &str| {
+            let file = self.recipe_files.get(name)?;
+            Some(match self.recipes.get(name).and_then(|x| x.line) {
+                Some(line) => format!("{}:{}", file.display(), line),
+                None => file.display().to_string(),
+            })
+        };
+        let mut report = |severity, recipe: Option<&str>, message: String| {
+            diagnostics.push(ValidationDiagnostic {
+                severity,
+                message,
+                recipe: recipe.map(ToString::to_string),
+                location: recipe.and_then(location),
+            })
+        };
+
+        for (prefix, err) in &self.broken_imports {
+            report(
+                Severity::Error,
+                None,
+                format!("import {} couldn't be loaded: {}", prefix, err),
+            );
+        }
+
+        for (name, recipe) in &self.recipes {
+            let known = |x: &String| self.recipes.contains_key(x) || self.instances.contains_key(x);
+            for target in recipe.requires.iter().filter(|x| !known(x)) {
+                report(
+                    Severity::Error,
+                    Some(name),
+                    format!("recipe {} requires {}, which doesn't exist", name, target),
+                );
+            }
+            for (target, _) in recipe.completed.iter().filter(|(x, _)| !known(x)) {
+                report(
+                    Severity::Error,
+                    Some(name),
+                    format!(
+                        "recipe {} requires completed:{}, which doesn't exist",
+                        name, target
+                    ),
+                );
+            }
+
+            for var in self.undefined_vars(name, recipe) {
+                report(
+                    Severity::Warning,
+                    Some(name),
+                    format!(
+                        "recipe {} refers to ${}, which isn't defined anywhere",
+                        name, var
+                    ),
+                );
             }
-            commands.push(args);
         }
 
-        Ok(Task {
-            name: name.into(),
-            commands,
-            vars,
-            work_dir,
-        })
+        for (name, path) in &self.shadowed {
+            let kept = self
+                .recipe_files
+                .get(name)
+                .map(|x| x.display().to_string())
+                .unwrap_or_default();
+            report(
+                Severity::Warning,
+                Some(name),
+                format!(
+                    "recipe {} is defined again in {}, which is ignored in favor of {}",
+                    name,
+                    path.display(),
+                    kept
+                ),
+            );
+        }
+
+        diagnostics
     }
 
-    /// Construct and execute a Task from a recipe name
-    pub fn execute(&self, name: &str) -> Result<(), Error> {
-        let task = self.build_task(name)?;
-        task.execute()
+    /// Find the variables that a recipe refers to that nothing defines
+    ///
+    /// Names are found the same way that they're expanded. A name counts as defined if a
+    /// moldfile, the recipe's parameters, mold itself, or the current environment provides it.
+    fn undefined_vars(&self, name: &str, recipe: &Recipe) -> IndexSet<String> {
+        let referenced = std::cell::RefCell::new(IndexSet::new());
+        let record = |var: &str| -> Option<String> {
+            referenced.borrow_mut().insert(var.to_string());
+            Some(String::new())
+        };
+
+        let texts = recipe
+            .commands
+            .iter()
+            .flat_map(|command| match command {
+                Command::Timed(inner, _) => vec![inner.to_string()],
+                Command::Any(alternatives) => alternatives.clone(),
+                Command::Raw(_) => vec![],
+                _ => vec![command.to_string()],
+            })
+            .chain(recipe.dir.clone())
+            .chain(recipe.watch.iter().cloned())
+            .chain(recipe.inputs.iter().cloned())
+            .chain(recipe.outputs.iter().cloned());
+        for text in texts {
+            let text = text.replace("$$", "");
+            shellexpand::env_with_context_no_errors(&text, record);
+        }
+
+        let visible = self.visible_vars(name);
+        let bindings = self.bindings(name).unwrap_or_default();
+        referenced
+            .into_inner()
+            .into_iter()
+            .filter(|var| {
+                !(visible.contains_key(var)
+                    || bindings.contains_key(var)
+                    || self.unset.contains(var)
+                    || recipe.params.iter().any(|x| &x.name == var)
+                    || recipe.positionals.contains(var)
+                    || PATH_VARS.contains(&var.as_str())
+                    || var == "@"
+                    || var.parse::<usize>().is_ok()
+                    || std::env::var_os(var).is_some())
+            })
+            .collect()
     }
 
-    /// Perform variable expansion on a string
-    fn expand<'a>(&self, val: &'a str, vars: &VarMap) -> std::borrow::Cow<'a, str> {
-        shellexpand::env_with_context_no_errors(val, |name| {
-            vars.get(name)
-                .map(std::string::ToString::to_string)
-                .or_else(|| std::env::var(name).ok())
-                .or_else(|| Some("".into()))
+    /// Print a JSON document describing everything that was loaded, for editor integrations
+    ///
+    /// Commands are reported as written rather than expanded so that this stays fast on large
+    /// projects.
+    pub fn dump(&self) -> Result<(), Error> {
+        use serde_json::json;
+
+        let path_str = |path: &Path| path.display().to_string();
+
+        let recipes: Vec<_> = self
+            .recipe_names()
+            .into_iter()
+            .map(|name| self.describe_recipe(name))
+            .collect();
+
+        let instances: Vec<_> = self
+            .instances
+            .iter()
+            .map(|(name, (recipe, bindings))| {
+                json!({ "name": name, "recipe": recipe, "bindings": bindings })
+            })
+            .collect();
+
+        let checks: Vec<_> = self
+            .checks
+            .iter()
+            .map(|(name, check)| {
+                json!({
+                    "name": name,
+                    "help": check.help,
+                    "source": self.check_sources.get(name).map(|x| path_str(x)),
+                })
+            })
+            .collect();
+
+        let document = json!({
+            "schema": DUMP_SCHEMA,
+            "mold_version": VERSION,
+            "root": path_str(&self.root_file),
+            "environments": self.envs,
+            "imports": self.dump_imports(&self.root_file),
+            "recipes": recipes,
+            "instances": instances,
+            "checks": checks,
+        });
+
+        println!("{}", serde_json::to_string_pretty(&document)?);
+        Ok(())
+    }
+
+    /// Find the recipes that `--shell-init` defines shell functions for
+    ///
+    /// That's every recipe, unless the root moldfile lists the ones it wants with
+    /// `shell_functions`.
+    pub fn function_names(&self) -> Vec<&str> {
+        self.public_recipe_names()
+            .into_iter()
+            .filter(|name| match &self.shell_functions {
+                Some(allowed) => allowed.iter().any(|x| x == name),
+                None => true,
+            })
+            .collect()
+    }
+
+    /// Print every recipe that isn't private as a JSON array, for editor integrations and shell
+    /// completion
+    ///
+    /// Each entry has these fields, which tools can rely on:
+    ///
+    /// * `name`: the name to run the recipe by
+    /// * `help`: its description, or `null` if it doesn't have one
+    /// * `requires`: the recipes it requires, as written
+    /// * `dir`: its working directory relative to $MOLD_ROOT, or `null`
+    /// * `command_count`: how many commands it runs itself, which is 0 for aggregates
+    pub fn list_json(&self) -> Result<(), Error> {
+        let recipes: Vec<_> = self
+            .public_recipe_names()
+            .into_iter()
+            .map(|name| {
+                let recipe = &self.recipes[name];
+                serde_json::json!({
+                    "name": name,
+                    "help": recipe.help.as_ref().filter(|x| !x.is_empty()),
+                    "requires": recipe.requires,
+                    "dir": recipe.dir,
+                    "command_count": recipe.commands.len(),
+                })
+            })
+            .collect();
+
+        println!("{}", serde_json::to_string_pretty(&recipes)?);
+        Ok(())
+    }
+
+    /// Describe a single recipe as it appears in `--dump json`
+    fn describe_recipe(&self, name: &str) -> serde_json::Value {
+        use serde_json::json;
+
+        let path_str = |path: &Path| path.display().to_string();
+        let recipe = &self.recipes[name];
+        json!({
+            "name": name,
+            "help": recipe.help,
+            "file": self.recipe_files.get(name).map(|x| path_str(x)),
+            "line": recipe.line,
+            "source": self.sources.get(name).map(|x| path_str(x)),
+            "scope": self.scope_of(name),
+            "dir": recipe.dir,
+            "shell": recipe.shell,
+            "interpreter": recipe.interpreter,
+            "requires": recipe.requires,
+            "guards": recipe.guards,
+            "commands": recipe.commands.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "script": recipe.script,
+            "weight": recipe.weight,
+            "retries": recipe.retries,
+            "expected_secs": recipe.expected.map(|x| x.as_secs()),
+            "timeout_secs": recipe.timeout.map(|x| x.as_secs()),
+            "watch": recipe.watch,
+            "inputs": recipe.inputs,
+            "outputs": recipe.outputs,
+            "umask": recipe.umask.map(|x| format!("{:03o}", x)),
+            "requires_clean_tree": recipe.clean_tree,
+            "cache": recipe.cache,
+            "exclusive": recipe.exclusive,
+            "private": recipe.is_private(name),
+            "required_vars": recipe.required_vars.iter().map(|(name, help)| {
+                serde_json::json!({ "name": name, "help": help })
+            }).collect::<Vec<_>>(),
+            "positionals": recipe.positionals,
+            "unset": recipe.unset,
+            "params": recipe.params.iter().map(|param| {
+                serde_json::json!({
+                    "name": param.name,
+                    "default": match param.secret {
+                        true => param.default.as_ref().map(|_| "****"),
+                        false => param.default.as_deref(),
+                    },
+                    "secret": param.secret,
+                })
+            }).collect::<Vec<_>>(),
+            "requires_completed": recipe.completed.iter().map(|(target, max_age)| {
+                serde_json::json!({
+                    "recipe": target,
+                    "within_secs": max_age.map(|x| x.as_secs()),
+                })
+            }).collect::<Vec<_>>(),
         })
     }
 
-    /// Perform variable expansion on a string and return a list of arguments to
-    /// pass to std::process::Command
-    fn build_args(&self, command: &str, vars: &VarMap) -> Result<Vec<String>, Error> {
-        let expanded = self.expand(command, vars);
-        Ok(shell_words::split(&expanded).map_err(|err| {
-            failure::format_err!("Couldn't shell split string {}: {}", expanded.red(), err)
-        })?)
+    /// Describe the imports of a moldfile and everything they import in turn
+    fn dump_imports(&self, file: &Path) -> serde_json::Value {
+        let imports = self.imports.get(file).map(Vec::as_slice).unwrap_or(&[]);
+        imports
+            .iter()
+            .map(|(prefix, remote, path)| {
+                serde_json::json!({
+                    "url": remote.url,
+                    "ref": remote.ref_,
+                    "commit": remote.commit(&self.mold_dir),
+                    "version": self.versions.get(path),
+                    "prefix": prefix,
+                    "file": path.display().to_string(),
+                    "imports": self.dump_imports(path),
+                })
+            })
+            .collect()
     }
 
-    /// Find *all* dependencies for a given set of target recipes
-    pub fn find_all_dependencies(&self, targets: &TargetSet) -> Result<TargetSet, Error> {
-        let mut new_targets = TargetSet::new();
+    /// Print every import as a tree, with the commit it's at and the mold version it requires
+    pub fn print_imports(&self) {
+        self.print_imports_of(&self.root_file, 0);
+    }
 
-        // FIXME this might not break on weird infinite cycles
-        // ...but since those shouldn't happen in sanely written moldfiles...
-        for name in targets {
-            new_targets.extend(self.find_dependencies(name)?);
-            new_targets.insert(name.clone());
-        }
+    fn print_imports_of(&self, file: &Path, depth: usize) {
+        let imports = self.imports.get(file).map(Vec::as_slice).unwrap_or(&[]);
+        for (prefix, remote, path) in imports {
+            let prefix = if prefix.is_empty() { "-" } else { prefix };
+            let commit: String = match remote.commit(&self.mold_dir) {
+                Some(commit) => commit.chars().take(10).collect(),
+                None => "not cloned".into(),
+            };
+            let version = match self.versions.get(path) {
+                Some(version) => format!("(version {})", version),
+                None => "(not loaded)".into(),
+            };
 
-        Ok(new_targets)
+            println!(
+                "{}{} {} {} {}",
+                "  ".repeat(depth),
+                prefix.cyan(),
+                remote,
+                commit.yellow(),
+                version.white()
+            );
+            self.print_imports_of(path, depth + 1);
+        }
     }
 
-    /// Find all recipes for a *single* target recipe
-    fn find_dependencies(&self, name: &str) -> Result<TargetSet, Error> {
-        let recipe = self.recipe(name)?;
-        let deps = recipe.requires.iter().map(ToString::to_string).collect();
-        self.find_all_dependencies(&deps)
+    /// Update (ie: fetch + force checkout) all remotes to their refs and pin them in mold.lock
+    pub fn update_all(&mut self) -> Result<(), Error> {
+        let mut updated: Vec<&Remote> = vec![];
+        for remote in &self.remotes {
+            if remote.exists(&self.mold_dir) && !updated.iter().any(|x| x.same_clone(remote)) {
+                self.checkout(remote, None)?;
+                updated.push(remote);
+            }
+        }
+
+        if self.lock()? {
+            println!(
+                "{:>12} {}",
+                "Locked".green(),
+                Lockfile::path(&self.root_dir).display()
+            );
+        }
+
+        Ok(())
     }
 
-    /// Update (ie: fetch + force checkout) all remotes
-    pub fn update_all(&self) -> Result<(), Error> {
-        for remote in &self.remotes {
-            let path = remote.path(&self.mold_dir);
-            if path.is_dir() {
-                remote
-                    .checkout(&self.mold_dir, self.use_git)
-                    .map_err(|err| {
-                        failure::format_err!("Couldn't checkout {}: {}", remote.ref_.red(), err)
-                    })?;
-            }
+    /// Print a short description of all recipes in this moldfile
+    ///
+    /// Private recipes are only listed with `all`, and then they're dimmed.
+    pub fn help(&self, format: OutputFormat, all: bool) -> Result<(), Error> {
+        let names = match all {
+            true => self.recipe_names(),
+            false => self.public_recipe_names(),
+        };
+
+        if format == OutputFormat::Json {
+            let recipes: Vec<_> = names
+                .into_iter()
+                .map(|name| {
+                    let recipe = &self.recipes[name];
+                    serde_json::json!({
+                        "name": name,
+                        "help": recipe.help,
+                        "requires": recipe.requires,
+                        "commands": recipe.commands.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                        "private": recipe.is_private(name),
+                    })
+                })
+                .collect();
+
+            println!("{}", serde_json::to_string_pretty(&recipes)?);
+            return Ok(());
         }
 
-        Ok(())
-    }
-
-    /// Print a short description of all recipes in this moldfile
-    pub fn help(&self) -> Result<(), Error> {
-        for (name, recipe) in &self.recipes {
+        for name in names {
+            let recipe = &self.recipes[name];
             let help_str = match &recipe.help {
                 Some(x) => x,
                 None => "",
             };
-            println!("{:>12} {}", name.cyan(), help_str);
+            // aggregates stand out since running them only runs their requirements
+            if recipe.is_private(name) {
+                println!("{:>12} {}", name.dimmed(), help_str.dimmed());
+            } else if recipe.is_aggregate() {
+                println!("{:>12} {}", name.magenta(), help_str);
+            } else {
+                println!("{:>12} {}", name.cyan(), help_str);
+            }
 
             // print dependencies
             let deps: Vec<_> = recipe.requires.iter().map(|x| x.to_string()).collect();
@@ -500,11 +4046,31 @@ impl Mold {
     }
 
     /// Print a long description of a recipe
-    pub fn explain(&self, name: &str) -> Result<(), Error> {
+    pub fn explain(&self, name: &str, format: OutputFormat) -> Result<(), Error> {
+        if format == OutputFormat::Json {
+            let task = self.explain_json(name)?;
+            println!("{}", serde_json::to_string_pretty(&task)?);
+            return Ok(());
+        }
+
         // print recipe information
         let recipe = self.recipe(name)?;
 
         println!("{}", name.cyan());
+        if let Some((base, bindings)) = self.instances.get(name) {
+            let bindings: Vec<_> = bindings
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            println!(
+                "{} {} {} {}",
+                "instance of:".white(),
+                base.cyan(),
+                "with".white(),
+                bindings.join(", ")
+            );
+        }
+
         if let Some(help) = &recipe.help {
             if !help.is_empty() {
                 println!("{}", help);
@@ -516,14 +4082,186 @@ impl Mold {
             println!("{} {}", "depends on:".white(), deps.join(" ").cyan());
         }
 
+        // an aggregate doesn't run anything itself, so all there is to know is what it runs
+        if recipe.is_aggregate() {
+            println!("{}", "runs in order:".white());
+            for (idx, dep) in self
+                .find_all_dependencies(&recipe.requires)?
+                .iter()
+                .enumerate()
+            {
+                println!("  {}. {}", idx + 1, dep.cyan());
+            }
+            println!();
+            return Ok(());
+        }
+
         if let Some(dir) = &recipe.dir {
             println!("{} {}", "working dir:".white(), dir.cyan());
         }
 
+        if let Some(shell) = &recipe.shell {
+            println!("{} {}", "shell:".white(), shell.cyan());
+        }
+
+        if let Some(interpreter) = &recipe.interpreter {
+            println!("{} {}", "interpreter:".white(), interpreter.cyan());
+        }
+
+        for (var, help) in &recipe.required_vars {
+            match help {
+                Some(help) => println!("{} {} ({})", "requires var:".white(), var.cyan(), help),
+                None => println!("{} {}", "requires var:".white(), var.cyan()),
+            }
+        }
+
+        if recipe.weight != 1 {
+            println!("{} {}", "weight:".white(), recipe.weight.to_string().cyan());
+        }
+
+        if recipe.retries > 0 {
+            println!(
+                "{} {}",
+                "retries:".white(),
+                recipe.retries.to_string().cyan()
+            );
+        }
+
+        if let Some(expected) = recipe.expected {
+            println!(
+                "{} {}",
+                "expected:".white(),
+                util::format_duration(expected).cyan()
+            );
+        }
+
+        if let Some(timeout) = recipe.timeout {
+            println!(
+                "{} {}",
+                "timeout:".white(),
+                util::format_duration(timeout).cyan()
+            );
+        }
+
+        if !recipe.watch.is_empty() {
+            println!("{} {}", "watches:".white(), recipe.watch.join(" ").cyan());
+        }
+
+        if !recipe.inputs.is_empty() {
+            println!("{} {}", "inputs:".white(), recipe.inputs.join(" ").cyan());
+        }
+
+        if !recipe.outputs.is_empty() {
+            println!("{} {}", "outputs:".white(), recipe.outputs.join(" ").cyan());
+        }
+
+        if recipe.cache {
+            println!("{} {}", "cache:".white(), "skipped when unchanged".cyan());
+        }
+
+        if recipe.exclusive {
+            println!("{} {}", "lock:".white(), "exclusive".cyan());
+        }
+
+        if let Some(mask) = recipe.umask {
+            println!("{} {}", "umask:".white(), format!("{:03o}", mask).cyan());
+        }
+
+        if recipe.clean_tree {
+            let note = if self.allow_dirty {
+                " (overridden)"
+            } else {
+                ""
+            };
+            println!(
+                "{} {}{}",
+                "guard:".white(),
+                "requires clean tree".cyan(),
+                note
+            );
+        }
+
+        if !recipe.positionals.is_empty() {
+            println!(
+                "{} {}",
+                "usage:".white(),
+                format!("mold {} {}", name, recipe.positionals.join(" ")).cyan()
+            );
+        }
+
+        if !recipe.params.is_empty() {
+            println!("{}", "params:".white());
+            for param in &recipe.params {
+                let secret = if param.secret { " (secret)" } else { "" };
+                let default = match param.secret {
+                    true => param.default.as_ref().map(|_| "****".to_string()),
+                    false => param.default.clone(),
+                };
+                match default {
+                    Some(default) => println!(
+                        "  {} = {}{}",
+                        format!("${}", param.name).bright_cyan(),
+                        default,
+                        secret
+                    ),
+                    None => println!("  {}{}", format!("${}", param.name).bright_cyan(), secret),
+                }
+            }
+        }
+
+        for (target, max_age) in &recipe.completed {
+            let within = match max_age {
+                Some(age) => format!(" within {}", util::format_duration(*age)),
+                None => String::new(),
+            };
+            println!(
+                "{} {}{}",
+                "guard:".white(),
+                format!("completed {}", target).cyan(),
+                within
+            );
+        }
+
         if !recipe.commands.is_empty() {
+            // `run any` alternatives are looked up the same way they would be when running
+            let env = self.expand_vars(&self.visible_vars(name));
+            let dir = self.recipe_dir(recipe, &env);
+
             println!("{}", "commands:".white());
             for command in &recipe.commands {
-                println!("  {} {}", "$".white(), command);
+                let limit = match command.timeout() {
+                    Some(limit) => format!(" (timeout {})", util::format_duration(limit)),
+                    None => String::new(),
+                };
+                let raw = match command {
+                    Command::Timed(inner, _) => inner,
+                    _ => command,
+                };
+                match raw {
+                    Command::Raw(_) => {
+                        println!("  {} {} {}{}", "$".white(), "raw".yellow(), command, limit)
+                    }
+                    Command::Any(alternatives) => {
+                        let chosen = self.choose(alternatives, &env, dir.as_deref())?;
+                        println!("  {} {}{}", "$".white(), "any".yellow(), limit);
+                        for (idx, alternative) in alternatives.iter().enumerate() {
+                            match &chosen {
+                                Some((x, _)) if *x == idx => {
+                                    println!("    {} {}", alternative, "(chosen)".green())
+                                }
+                                _ => println!("    {}", alternative.white()),
+                            }
+                        }
+                    }
+                    _ => println!("  {} {}{}", "$".white(), command, limit),
+                }
+            }
+        }
+
+        if let Some(script) = &recipe.script {
+            println!("{}", "script:".white());
+            for line in script.lines() {
+                println!("  {}", line);
             }
         }
 
@@ -532,8 +4270,23 @@ impl Mold {
 
         if !task.vars.is_empty() {
             println!("{}", "variables:".white());
-            for (key, val) in &task.vars {
-                println!("  {} = {}", format!("${}", key).bright_cyan(), val);
+            let scope = self
+                .scope_of(name)
+                .map(|scope| (scope, &self.scopes[scope]));
+            for (key, val) in &task.masked_vars() {
+                match scope {
+                    Some((scope, vars))
+                        if vars.contains_key(key) && !self.overrides.contains_key(key) =>
+                    {
+                        println!(
+                            "  {} = {} {}",
+                            format!("${}", key).bright_cyan(),
+                            val,
+                            format!("(scope {})", scope).white()
+                        )
+                    }
+                    _ => println!("  {} = {}", format!("${}", key).bright_cyan(), val),
+                }
             }
         }
 
@@ -541,6 +4294,10 @@ impl Mold {
             println!("{}", "executes:".white());
             for args in &task.commands {
                 println!("  {} {}", "$".green(), shell_words::join(args));
+                match task.resolve(&args[0]) {
+                    Some(path) => println!("    ⮡ {}", path.display()),
+                    None => println!("    ⮡ {}", "not found".red()),
+                }
             }
         }
 
@@ -549,68 +4306,519 @@ impl Mold {
         Ok(())
     }
 
+    /// Describe a recipe as the task that running it would execute, for `--format json`
+    ///
+    /// Unlike `--dump json`, commands are fully expanded, along with the variables they see.
+    pub fn explain_json(&self, name: &str) -> Result<serde_json::Value, Error> {
+        let recipe = self.recipe(name)?;
+
+        // an aggregate doesn't run anything itself, so all there is to know is what it runs
+        if recipe.is_aggregate() {
+            return Ok(serde_json::json!({
+                "name": name,
+                "help": recipe.help,
+                "requires": recipe.requires,
+                "runs": self.find_all_dependencies(&recipe.requires)?,
+            }));
+        }
+
+        let task = self.build_task(name)?;
+        let commands: Vec<_> = task
+            .commands
+            .iter()
+            .zip(&task.timeouts)
+            .map(|(args, limit)| {
+                serde_json::json!({
+                    "args": args,
+                    "program": task.resolve(&args[0]).map(|x| x.display().to_string()),
+                    "timeout_secs": limit.map(|x| x.as_secs()),
+                })
+            })
+            .collect();
+
+        let vars = task.masked_vars();
+        Ok(serde_json::json!({
+            "name": name,
+            "help": recipe.help,
+            "requires": recipe.requires,
+            "dir": task.work_dir.map(|x| x.display().to_string()),
+            "commands": commands,
+            "script": task.script.map(|(path, _)| path.display().to_string()),
+            "vars": vars,
+            "unset": task.unset,
+            "timeout_secs": task.timeout.map(|x| x.as_secs()),
+            "retries": task.retries,
+        }))
+    }
+
+    /// Look up the fully expanded value of a single variable
+    ///
+    /// Variables that aren't defined by any moldfile fall back to the environment, just like they
+    /// do during expansion. A variable that is defined but empty is not an error.
+    pub fn get_var(&self, name: &str) -> Result<String, Error> {
+        self.expand_vars(&self.vars)
+            .swap_remove(name)
+            .or_else(|| std::env::var(name).ok())
+            .ok_or_else(|| failure::format_err!("Variable {} is not defined", name.red()))
+    }
+
     /// Print all variables in a shell format
     pub fn sh_vars(&self) -> Result<(), Error> {
-        // expand all variables
-        // expanded values are stored in this map so they can be used in later expansions
-        let mut vars = VarMap::new();
-        for (name, value) in &self.vars {
-            let expanded_value = self.expand(value, &vars);
-            println!("export {}={}", name, shell_words::quote(&expanded_value));
-            vars.insert(name.clone(), expanded_value.into());
+        for (name, value) in &self.expand_vars(&self.vars) {
+            println!("export {}={}", name, shell_words::quote(value));
+        }
+
+        for name in &self.unset {
+            println!("unset {}", name);
         }
 
         Ok(())
     }
 }
 
+/// Find the lowest version of mold that a simple version requirement like `0.8` or `>=0.8.2` allows
+///
+/// Requirements with more than one comparator, or that only set an upper bound, return None.
+fn minimum_version(req: &str) -> Option<String> {
+    let version = req.trim().trim_start_matches(['^', '~', '=', '>']).trim();
+    let parts: Vec<&str> = version.split('.').collect();
+    if parts.len() > 3 || parts.iter().any(|x| x.parse::<u64>().is_err()) {
+        return None;
+    }
+
+    let mut parts = parts;
+    parts.resize(3, "0");
+    Some(parts.join("."))
+}
+
+/// Whether a variable always expands to exactly one argument when a command is split
+///
+/// That covers the paths that mold sets itself and positional arguments like $1.
+fn is_protected(name: &str) -> bool {
+    PATH_VARS.contains(&name) || name.chars().all(|x| x.is_ascii_digit())
+}
+
+/// Name a recipe instance with a set of variable bindings, like `build{IMAGE=api}`
+///
+/// Bindings are sorted so that the same set always produces the same name.
+fn instance_key(name: &str, bindings: &VarMap) -> String {
+    let mut pairs: Vec<_> = bindings
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    pairs.sort();
+    format!("{}{{{}}}", name, pairs.join(","))
+}
+
+/// Canonicalize a path, resolving all symlinks
+fn canonicalize(path: &Path) -> Result<PathBuf, Error> {
+    fs::canonicalize(path).map_err(|err| {
+        failure::format_err!(
+            "Couldn't canonicalize {}: {}",
+            path.display().to_string().red(),
+            err
+        )
+    })
+}
+
+/// What happened to each recipe of a run that keeps going after failures
+#[derive(Default)]
+struct Summary {
+    succeeded: Vec<String>,
+    failed: Vec<String>,
+    skipped: Vec<String>,
+}
+
+impl Summary {
+    /// Whether a recipe can't run because something that it requires failed or was skipped
+    ///
+    /// Recipes run after their requirements, so only direct requirements need to be checked.
+    fn blocks(&self, recipe: &Recipe) -> bool {
+        recipe
+            .requires
+            .iter()
+            .any(|x| self.failed.contains(x) || self.skipped.contains(x))
+    }
+
+    /// Record a failure, describing it right away since the run carries on
+    fn fail(&mut self, name: &str, err: Error) {
+        eprintln!("{:>12} {}", "Failed".red(), util::error_chain(&err));
+        self.failed.push(name.into());
+    }
+
+    /// Print what happened to every recipe, and fail if any of them did
+    fn finish(self) -> Result<(), Error> {
+        let total = self.succeeded.len() + self.failed.len() + self.skipped.len();
+        println!(
+            "{:>12} {} succeeded, {} failed, {} skipped",
+            "Summary".white(),
+            self.succeeded.len(),
+            self.failed.len(),
+            self.skipped.len()
+        );
+
+        if !self.succeeded.is_empty() {
+            println!("{:>12} {}", "Succeeded".green(), self.succeeded.join(" "));
+        }
+        if !self.failed.is_empty() {
+            println!("{:>12} {}", "Failed".red(), self.failed.join(" "));
+        }
+        if !self.skipped.is_empty() {
+            println!("{:>12} {}", "Skipped".yellow(), self.skipped.join(" "));
+        }
+
+        if self.failed.is_empty() {
+            Ok(())
+        } else {
+            Err(failure::format_err!(
+                "{} of {} recipes failed",
+                self.failed.len().to_string().red(),
+                total
+            ))
+        }
+    }
+}
+
 /// An instantiation of a recipe ready for execution
 struct Task {
     name: String,
+
+    /// Paths that aren't valid UTF-8, which get their real bytes back in commands and variables
+    lossy_paths: Vec<PathBuf>,
+
     commands: Vec<Vec<String>>,
+
+    /// $MOLD_SCRIPT and the inline script to write there before running
+    script: Option<(PathBuf, String)>,
+
+    /// Program that the script is handed to, which has to be found before anything runs
+    interpreter: Option<String>,
+
     work_dir: Option<PathBuf>,
     vars: VarMap,
+
+    /// Parameters declared `secret`, whose values are masked like those of secret-looking names
+    secrets: Vec<String>,
+
+    /// Variables to remove from the environment that the commands inherit
+    unset: Vec<String>,
+
+    /// How long all of the commands together may run
+    timeout: Option<Duration>,
+
+    /// How long each command may run on its own, in the same order as `commands`
+    timeouts: Vec<Option<Duration>>,
+
+    /// How many more times every command is run after one exits non-zero
+    retries: u32,
+
+    /// The `.mold` whose project lock is held while running, and how long to wait for it
+    exclusive: Option<(PathBuf, Option<Duration>)>,
+
+    umask: Option<u32>,
+    label: Option<String>,
+    quiet: bool,
+
+    /// Variables that differ from the parent environment, if failures should print context
+    context: Option<Vec<(String, String)>>,
+
+    /// $MOLD_STATE, which is created before running
+    state_dir: PathBuf,
 }
 
+/// Maximum number of variables listed in a failure's context
+const CONTEXT_VARS: usize = 20;
+
+/// Maximum length of a variable's value in a failure's context
+const CONTEXT_WIDTH: usize = 80;
+
 impl Task {
+    /// Describe a failed command on stderr so that it can be reproduced by hand
+    fn print_context(&self, args: &[String]) {
+        let injected = match &self.context {
+            Some(injected) => injected,
+            None => return,
+        };
+
+        eprintln!("{:>12} recipe {}", "Context".red(), self.name);
+        eprintln!("{:>12} {}", "argv", shell_words::join(args));
+        self.print_environment(injected);
+    }
+
+    /// Describe the working directory and injected variables on stderr, with secrets masked
+    fn print_environment(&self, injected: &[(String, String)]) {
+        let dir = match &self.work_dir {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir().unwrap_or_default(),
+        };
+
+        eprintln!("{:>12} {}", "dir", dir.display());
+
+        for (idx, (key, value)) in injected.iter().take(CONTEXT_VARS).enumerate() {
+            let value = if self.is_secret(key) {
+                "****".to_string()
+            } else if value.chars().count() > CONTEXT_WIDTH {
+                let short: String = value.chars().take(CONTEXT_WIDTH).collect();
+                format!("{}...", short)
+            } else {
+                value.clone()
+            };
+
+            let label = if idx == 0 { "env" } else { "" };
+            eprintln!("{:>12} {}={}", label, key, value);
+        }
+
+        if injected.len() > CONTEXT_VARS {
+            eprintln!("{:>12} ...and {} more", "", injected.len() - CONTEXT_VARS);
+        }
+    }
+
+    /// Check whether a variable's value shouldn't be printed
+    fn is_secret(&self, key: &str) -> bool {
+        util::is_secret(key) || self.secrets.iter().any(|x| x == key)
+    }
+
+    /// The task's variables, with the values of secret ones masked
+    fn masked_vars(&self) -> VarMap {
+        self.vars
+            .iter()
+            .map(|(key, value)| match self.is_secret(key) {
+                true => (key.clone(), "****".to_string()),
+                false => (key.clone(), value.clone()),
+            })
+            .collect()
+    }
+
+    /// Locate the program that a command will execute using this task's environment
+    fn resolve(&self, program: &str) -> Option<PathBuf> {
+        let path_var = self.vars.get("PATH").map(String::as_str);
+        let program = util::restore_paths(program, &self.lossy_paths);
+        util::resolve_program(program, path_var, self.work_dir.as_deref())
+    }
+
+    /// Format the line that announces a command before it runs
+    fn echo_line(&self, args: &[String]) -> String {
+        let label = match &self.label {
+            Some(label) => format!(" [{}]", label),
+            None => String::new(),
+        };
+
+        format!(
+            "{}{} {} {} {}",
+            "mold".white(),
+            label.white(),
+            self.name.cyan(),
+            "$".green(),
+            shell_words::join(args),
+        )
+    }
+
+    /// Print the line that announces a command before it runs
+    fn echo(&self, args: &[String]) {
+        println!("{}", self.echo_line(args));
+    }
+
+    /// Print what `execute` would print for each command without running anything
+    ///
+    /// The working directory and variables that the commands would get are described on stderr,
+    /// so that stdout can be diffed against a real run.
+    fn dry_run(&self) -> Result<(), Error> {
+        self.print_environment(&injected_vars(&self.vars));
+
+        for args in &self.commands {
+            if !args.is_empty() {
+                self.echo(args);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Populate a std::process::Command and spawn it
     fn execute(self) -> Result<(), Error> {
-        for args in &self.commands {
+        self.run(None)
+    }
+
+    /// Run every command, sending their output to `forward` as task `idx` if it's given
+    ///
+    /// Forwarded output includes the echo lines, so that they stay in order with everything else.
+    fn run(&self, forward: Option<(usize, &Sender<Event>)>) -> Result<(), Error> {
+        #[cfg(not(unix))]
+        {
+            if self.umask.is_some() {
+                util::warn(&format!(
+                    "umask for recipe {} is ignored on this platform",
+                    self.name.yellow()
+                ));
+            }
+        }
+
+        // a missing interpreter is clearer up front than as a command that couldn't start
+        if let Some(program) = &self.interpreter {
+            if self.resolve(program).is_none() {
+                return Err(failure::format_err!(
+                    "{} not found on PATH, but recipe {} needs it to run its script",
+                    program.red(),
+                    self.name.red()
+                ));
+            }
+        }
+
+        fs::create_dir_all(&self.state_dir).map_err(|err| {
+            failure::format_err!(
+                "Couldn't create directory {}: {}",
+                self.state_dir.display().to_string().red(),
+                err
+            )
+        })?;
+
+        // other invocations wait until every command is done, however they finish
+        let _lock = match &self.exclusive {
+            Some((mold_dir, timeout)) => Some(exclusive::acquire(mold_dir, *timeout)?),
+            None => None,
+        };
+
+        if let Some((path, body)) = &self.script {
+            write_script(path, body)?;
+        }
+
+        // a failed attempt starts over from the first command
+        let attempts = self.retries + 1;
+        for attempt in 1..=attempts {
+            if attempt > 1 {
+                let line = format!(
+                    "{:>12} {} (attempt {}/{})",
+                    "Retrying".yellow(),
+                    self.name,
+                    attempt,
+                    attempts
+                );
+                match forward.filter(|_| !self.quiet) {
+                    Some((idx, tx)) => {
+                        let _ = tx.send(Event::Line(idx, line));
+                    }
+                    None => println!("{}", line),
+                }
+            }
+
+            if self.run_commands(forward)? {
+                return Ok(());
+            }
+        }
+
+        Err(failure::format_err!(
+            "Recipe {} returned non-zero exit status",
+            self.name.red()
+        ))
+    }
+
+    /// Run every command once, stopping at the first that exits non-zero
+    ///
+    /// Returns whether they all succeeded. Commands that can't be started or that time out are
+    /// errors, since running them again wouldn't help.
+    fn run_commands(&self, forward: Option<(usize, &Sender<Event>)>) -> Result<bool, Error> {
+        let started = Instant::now();
+        for (args, timeout) in self.commands.iter().zip(&self.timeouts) {
             if args.is_empty() {
                 continue;
             }
 
-            let mut command = process::Command::new(&args[0]);
-            command.args(&args[1..]);
-            command.envs(&self.vars);
+            // a command gets its own limit or whatever's left of the recipe's, whichever is less
+            let remaining = self.timeout.map(|x| x.saturating_sub(started.elapsed()));
+            let (limit, limit_of) = match (*timeout, remaining) {
+                (Some(own), Some(left)) if left < own => (Some(left), self.timeout),
+                (Some(own), _) => (Some(own), Some(own)),
+                (None, left) => (left, self.timeout),
+            };
+
+            let resolved = self.resolve(&args[0]);
+            let is_path = args[0].contains('/') || args[0].contains(std::path::MAIN_SEPARATOR);
+            if resolved.is_none() && is_path {
+                let dir = match &self.work_dir {
+                    Some(dir) => dir.clone(),
+                    None => std::env::current_dir().unwrap_or_default(),
+                };
+                util::warn(&format!(
+                    "{} does not exist in {}",
+                    args[0].yellow(),
+                    dir.display()
+                ));
+            }
+
+            // describes where the program was found for error messages
+            let location = match &resolved {
+                Some(path) => format!("(resolved to {})", path.display()),
+                None => "(not found on PATH)".into(),
+            };
+
+            let restore = |x: &String| util::restore_paths(x, &self.lossy_paths);
+            let mut command = process::Command::new(restore(&args[0]));
+            command.args(args[1..].iter().map(restore));
+            command.envs(self.vars.iter().map(|(key, value)| (key, restore(value))));
+            for name in &self.unset {
+                command.env_remove(name);
+            }
 
             if let Some(dir) = &self.work_dir {
                 command.current_dir(dir);
             }
 
-            println!(
-                "{} {} {} {}",
-                "mold".white(),
-                self.name.cyan(),
-                "$".green(),
-                shell_words::join(args),
-            );
+            #[cfg(unix)]
+            {
+                if let Some(mask) = self.umask {
+                    use std::os::unix::process::CommandExt;
+                    let mask = mask as libc::mode_t;
+
+                    // umask is async-signal-safe, so it's fine to call between fork and exec
+                    unsafe {
+                        command.pre_exec(move || {
+                            libc::umask(mask);
+                            Ok(())
+                        });
+                    }
+                }
+
+                // a timed command gets a process group of its own, so that running out of time
+                // stops everything it started rather than just the command itself
+                if limit.is_some() {
+                    use std::os::unix::process::CommandExt;
+                    command.process_group(0);
+                }
+            }
+
+            let forward = forward.filter(|_| !self.quiet);
+            if self.quiet {
+                command.stdout(process::Stdio::null());
+                command.stderr(process::Stdio::null());
+            } else if let Some((idx, tx)) = forward {
+                let _ = tx.send(Event::Line(idx, self.echo_line(args)));
+                command.stdout(process::Stdio::piped());
+                command.stderr(process::Stdio::piped());
+            } else {
+                self.echo(args);
+            }
 
             use std::io::ErrorKind;
             let exit_status = command
                 .spawn()
-                .and_then(|mut handle| handle.wait())
+                .and_then(|mut handle| match forward {
+                    Some((idx, tx)) => wait_forwarded(&mut handle, idx, tx, limit),
+                    None => wait_limited(&mut handle, limit),
+                })
+                .inspect_err(|_| self.print_context(args))
                 .map_err(|err| match err.kind() {
                     ErrorKind::NotFound => failure::format_err!(
-                        "Recipe {} failed because command {} was not found",
+                        "Recipe {} failed because command {} was not found {}",
                         self.name.red(),
-                        args[0].red()
+                        args[0].red(),
+                        location
                     ),
 
                     ErrorKind::PermissionDenied => failure::format_err!(
-                        "Recipe {} failed because you do not have permission to execute command {}",
+                        "Recipe {} failed because you do not have permission to execute command {} {}",
                         self.name.red(),
-                        args[0].red()
+                        args[0].red(),
+                        location
                     ),
 
                     _ => failure::format_err!(
@@ -620,14 +4828,141 @@ impl Task {
                     ),
                 })?;
 
+            let exit_status = match exit_status {
+                Some(status) => status,
+                None => {
+                    self.print_context(args);
+                    return Err(failure::format_err!(
+                        "Recipe {} timed out after {}",
+                        self.name.red(),
+                        util::format_duration(limit_of.unwrap_or_default())
+                    ));
+                }
+            };
+
             if !exit_status.success() {
-                return Err(failure::format_err!(
-                    "Recipe {} returned non-zero exit status",
-                    self.name.red()
-                ));
+                self.print_context(args);
+                return Ok(false);
             }
         }
 
-        Ok(())
+        Ok(true)
+    }
+}
+
+/// Where inline scripts go for a project whose `.mold` is on a filesystem that can't run them
+///
+/// That's the user's own runtime directory if there is one. Otherwise it's the shared temp
+/// directory, under a name that includes the user so that nobody else's directory gets picked.
+fn fallback_script_dir(root_dir: &Path) -> PathBuf {
+    let hash = util::hash_string(&root_dir.to_string_lossy());
+    if let Some(runtime) = std::env::var_os("XDG_RUNTIME_DIR").filter(|x| !x.is_empty()) {
+        return PathBuf::from(runtime).join(format!("mold-{}", hash));
+    }
+
+    #[cfg(unix)]
+    let name = format!("mold-{}-{}", unsafe { libc::geteuid() }, hash);
+    #[cfg(not(unix))]
+    let name = format!("mold-{}", hash);
+    std::env::temp_dir().join(name)
+}
+
+/// Write an executable script to `path` unless it's already there, creating its directory if needed
+///
+/// The script is staged under a name of its own and then moved into place, so that concurrent
+/// runs never see it half-written or not yet executable.
+fn write_script(path: &Path, body: &str) -> Result<(), Error> {
+    if fs::read_to_string(path).is_ok_and(|x| x == body) {
+        return Ok(());
+    }
+
+    let fail = |err: std::io::Error| {
+        failure::format_err!(
+            "Couldn't write script {}: {}",
+            path.display().to_string().red(),
+            err
+        )
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(fail)?;
+    }
+
+    let staged = path.with_extension(format!("{}.tmp", process::id()));
+    let written = fs::write(&staged, body).and_then(|_| {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+        }
+        fs::rename(&staged, path)
+    });
+    if let Err(err) = written {
+        let _ = fs::remove_file(&staged);
+        return Err(fail(err));
+    }
+
+    Ok(())
+}
+
+/// Find the variables whose values differ from the environment mold was started in
+fn injected_vars(vars: &VarMap) -> Vec<(String, String)> {
+    vars.iter()
+        .filter(|(key, value)| std::env::var(key).ok().as_ref() != Some(value))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Wait for a child whose stdout and stderr are piped, forwarding both as lines from task `idx`
+fn wait_forwarded(
+    child: &mut process::Child,
+    idx: usize,
+    tx: &Sender<Event>,
+    limit: Option<Duration>,
+) -> std::io::Result<Option<process::ExitStatus>> {
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    thread::scope(|scope| {
+        if let Some(stdout) = stdout {
+            scope.spawn(move || output::forward_lines(idx, stdout, tx));
+        }
+        if let Some(stderr) = stderr {
+            scope.spawn(move || output::forward_lines(idx, stderr, tx));
+        }
+        wait_limited(child, limit)
+    })
+}
+
+/// Wait for a child to exit, killing it if it's still running after `limit`
+///
+/// Returns None if the child had to be killed. On Unix, a child with a limit leads its own
+/// process group, and the whole group is killed.
+fn wait_limited(
+    child: &mut process::Child,
+    limit: Option<Duration>,
+) -> std::io::Result<Option<process::ExitStatus>> {
+    let deadline = match limit {
+        Some(limit) => Instant::now() + limit,
+        None => return child.wait().map(Some),
+    };
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            #[cfg(unix)]
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            #[cfg(not(unix))]
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(20)));
     }
 }
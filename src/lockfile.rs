@@ -0,0 +1,96 @@
+//! Commits that imports are pinned to, kept in `mold.lock` next to the root moldfile
+//!
+//! Imports name a branch or tag, which can move between one clone and the next. The lockfile
+//! records the commit that each import resolved to, so that everyone working on a project gets the
+//! same recipes until somebody runs `mold --update` and commits the new lockfile.
+
+use super::remote::Remote;
+use super::util;
+use colored::*;
+use failure::Error;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Name of the lockfile, which lives next to the root moldfile
+pub const LOCKFILE: &str = "mold.lock";
+
+/// What the lockfile knows about a single import
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockEntry {
+    /// Full SHA of the commit that the import is checked out at
+    pub commit: String,
+}
+
+/// Pinned commits keyed by each import's `url#ref[/file]`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Lockfile {
+    pub remotes: BTreeMap<String, LockEntry>,
+}
+
+impl Lockfile {
+    /// Path of the lockfile for a project rooted at `root_dir`
+    pub fn path(root_dir: &Path) -> PathBuf {
+        root_dir.join(LOCKFILE)
+    }
+
+    /// Load the lockfile for a project, if it has one
+    pub fn load(root_dir: &Path) -> Result<Option<Lockfile>, Error> {
+        let path = Self::path(root_dir);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let data = fs::read_to_string(&path)?;
+        let json: serde_json::Value = serde_json::from_str(&data).map_err(|err| {
+            failure::format_err!(
+                "Couldn't parse {}: {}",
+                path.display().to_string().red(),
+                err
+            )
+        })?;
+
+        let mut lockfile = Lockfile::default();
+        for (name, entry) in json["remotes"].as_object().into_iter().flatten() {
+            let commit = entry["commit"].as_str().unwrap_or_default().to_string();
+            lockfile.remotes.insert(name.clone(), LockEntry { commit });
+        }
+
+        Ok(Some(lockfile))
+    }
+
+    /// Save the lockfile for a project rooted at `root_dir`
+    pub fn save(&self, root_dir: &Path) -> Result<(), Error> {
+        let remotes: serde_json::Map<_, _> = self
+            .remotes
+            .iter()
+            .map(|(name, entry)| (name.clone(), serde_json::json!({ "commit": entry.commit })))
+            .collect();
+
+        let json = serde_json::json!({ "remotes": remotes });
+        let path = Self::path(root_dir);
+        let mut data = serde_json::to_string_pretty(&json)?;
+        data.push('\n');
+        util::write_atomic(&path, data.as_bytes()).map_err(|err| {
+            failure::format_err!(
+                "Couldn't write {}: {}",
+                path.display().to_string().red(),
+                err
+            )
+        })
+    }
+
+    /// Find the commit that a remote is pinned to
+    pub fn get(&self, remote: &Remote) -> Option<&str> {
+        self.remotes
+            .get(&remote.to_string())
+            .map(|entry| entry.commit.as_str())
+    }
+
+    /// Pin a remote to a commit
+    pub fn insert(&mut self, remote: &Remote, commit: String) {
+        self.remotes
+            .insert(remote.to_string(), LockEntry { commit });
+    }
+}
@@ -1,9 +1,15 @@
+mod completion;
+mod shell_init;
+
 use colored::*;
 use exitfailure::ExitFailure;
 use failure::Error;
+use mold::settings::Settings;
 use mold::Mold;
+use mold::OutputFormat;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 // there's no good way that I could find to group these into exclusive groups.
@@ -24,6 +30,56 @@ pub struct Args {
     #[structopt(long = "add", short = "a", number_of_values = 1)]
     pub add_envs: Vec<String>,
 
+    /// Don't activate the current OS and OS family as environments
+    #[structopt(long = "no-default-envs")]
+    pub no_default_envs: bool,
+
+    /// OS environment to activate instead of the current OS
+    #[structopt(long = "os")]
+    pub os: Option<String>,
+
+    /// OS family environment to activate instead of the current OS family
+    #[structopt(long = "family")]
+    pub family: Option<String>,
+
+    /// Run the targets once per comma-separated environment set, joining environments in a set
+    /// with `+`; the current OS and OS family aren't activated implicitly
+    #[structopt(long = "matrix-env", value_name = "SETS")]
+    pub matrix_env: Option<String>,
+
+    /// Print every import with the commit it's at and the mold version it requires
+    #[structopt(long = "imports", visible_alias = "remotes")]
+    pub print_imports: bool,
+
+    /// Print the list of active environments
+    #[structopt(long = "envs")]
+    pub print_envs: bool,
+
+    /// Print only the name of every recipe that isn't private, one per line
+    #[structopt(long = "list-names")]
+    pub list_names: bool,
+
+    /// Print every public recipe's name, help, requirements, directory, and command count as json
+    #[structopt(long = "list-json")]
+    pub list_json: bool,
+
+    /// Print the recipes that --shell-init defines functions for, one per line
+    #[structopt(long = "list-functions", hidden = true)]
+    pub list_functions: bool,
+
+    /// Print code for bash or zsh to eval that defines a function for every recipe of the current
+    /// project
+    #[structopt(long = "shell-init", value_name = "SHELL")]
+    pub shell_init: Option<String>,
+
+    /// Print a completion script for bash, zsh, fish, or powershell, with setup steps at the top
+    #[structopt(
+        long = "completion",
+        value_name = "SHELL",
+        visible_alias = "completions"
+    )]
+    pub completion: Option<String>,
+
     /// Add an import to the selected moldfile
     #[structopt(long = "import", short = "i")]
     pub import: Option<String>,
@@ -32,15 +88,68 @@ pub struct Args {
     #[structopt(long = "prefix", short = "p")]
     pub prefix: Option<String>,
 
-    /// Fetch new updates for all downloaded remote data
+    /// Fetch new updates for all downloaded remote data and pin them in mold.lock
     #[structopt(long = "update", short = "u")]
     pub update: bool,
 
-    /// Remove all downloaded remote data
+    /// Fail instead of writing mold.lock if it's missing or doesn't match the imports
+    #[structopt(long = "frozen", conflicts_with = "update")]
+    pub frozen: bool,
+
+    /// Remove all downloaded remote data, along with the scripts that recipes wrote out
     #[structopt(long = "clean")]
     pub clean: bool,
 
-    /// Download all remote data
+    /// Check the files that mold generated in .mold against their recorded checksums
+    #[structopt(long = "verify")]
+    pub verify: bool,
+
+    /// Add .mold/ to the project's .gitignore if it isn't already there
+    #[structopt(long = "update-gitignore")]
+    pub update_gitignore: bool,
+
+    /// Install a git hook that runs the given targets, or the ones the moldfile declares for it
+    #[structopt(long = "install-hook", value_name = "HOOK")]
+    pub install_hook: Option<String>,
+
+    /// Install every git hook that the moldfile declares
+    #[structopt(long = "install-hooks")]
+    pub install_hooks: bool,
+
+    /// Remove a git hook that mold installed
+    #[structopt(long = "uninstall-hook", value_name = "HOOK")]
+    pub uninstall_hook: Option<String>,
+
+    /// Run recipes even when their outputs are up to date, and replace or remove git hooks that
+    /// mold didn't write
+    #[structopt(long = "force")]
+    pub force: bool,
+
+    /// Run recipes marked `cache` even if nothing they depend on has changed
+    #[structopt(long = "no-cache")]
+    pub no_cache: bool,
+
+    /// Convert a legacy mold.yaml into a moldfile
+    #[structopt(long = "migrate")]
+    pub migrate: bool,
+
+    /// Print recipe listings and --explain as plain text or json
+    #[structopt(long = "format", value_name = "FORMAT", default_value = "plain")]
+    pub format: String,
+
+    /// Print a description of the whole project; the only format is `json`
+    #[structopt(long = "dump", value_name = "FORMAT")]
+    pub dump: Option<String>,
+
+    /// Load the project N times and print timing percentiles
+    #[structopt(long = "bench-load", value_name = "N", hidden = true)]
+    pub bench_load: Option<usize>,
+
+    /// Run every check from the loaded moldfiles instead of any recipes
+    #[structopt(long = "doctor")]
+    pub doctor: bool,
+
+    /// Download all remote data and check that every remote moldfile loads
     #[structopt(long = "clone")]
     pub clone: bool,
 
@@ -48,41 +157,248 @@ pub struct Args {
     #[structopt(long = "vars")]
     pub vars: bool,
 
-    /// Use external git binary rather than libgit2
+    /// Run a one-off command with the variables and working directory that recipes get
+    #[structopt(long = "exec", value_name = "COMMAND")]
+    pub exec: Option<String>,
+
+    /// Delete the $MOLD_STATE directory of the given recipe
+    #[structopt(long = "clean-state", value_name = "RECIPE")]
+    pub clean_state: Option<String>,
+
+    /// List every recipe that depends on the given recipe
+    #[structopt(long = "rdeps", value_name = "RECIPE")]
+    pub rdeps: Option<String>,
+
+    /// Print the fully expanded value of a single variable
+    #[structopt(long = "get-var", value_name = "NAME")]
+    pub get_var: Option<String>,
+
+    /// Use external git binary rather than libgit2, which clones shallowly unless $MOLD_FULL_CLONE
+    /// is set
     #[structopt(long = "git")]
     pub git: bool,
 
+    /// Show output from git when cloning and updating remotes
+    #[structopt(long = "verbose", short = "v", visible_alias = "git-verbose")]
+    pub verbose: bool,
+
+    /// Set a variable, replacing its value from every moldfile; may be given more than once
+    #[structopt(
+        long = "set",
+        short = "s",
+        number_of_values = 1,
+        value_name = "KEY=VALUE"
+    )]
+    pub set: Vec<String>,
+
+    /// When listing recipes, also list private ones, dimmed
+    #[structopt(long = "all")]
+    pub all: bool,
+
     /// Skip variable definitions in moldfiles
     #[structopt(long = "no-vars")]
     pub no_vars: bool,
 
+    /// Don't load variables from .env, or the env file named by the moldfile's env_file
+    #[structopt(long = "no-dotenv")]
+    pub no_dotenv: bool,
+
+    /// Print a standalone script that runs the targets and their dependencies
+    #[structopt(long = "emit-script")]
+    pub emit_script: bool,
+
+    /// Shell to write --emit-script scripts for: sh or powershell
+    #[structopt(long = "shell", value_name = "SHELL", default_value = "sh")]
+    pub shell: String,
+
+    /// Print the dependency graph of the targets, or of every recipe, instead of running anything
+    #[structopt(long = "graph")]
+    pub graph: bool,
+
+    /// Format for --graph: dot or mermaid
+    #[structopt(long = "graph-format", value_name = "FORMAT", default_value = "dot")]
+    pub graph_format: String,
+
+    /// Print the order that recipes would run in without running them
+    #[structopt(long = "plan")]
+    pub plan: bool,
+
+    /// Run up to this many recipes at once; recipe weights count against it
+    #[structopt(long = "jobs", short = "j", value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Keep running recipes that don't depend on a failed one, then summarize what happened
+    #[structopt(long = "keep-going", short = "k")]
+    pub keep_going: bool,
+
+    /// Label every line of output with the recipe that printed it
+    #[structopt(long = "prefix-output")]
+    pub prefix_output: bool,
+
+    /// Print the effective value of every setting and where it came from
+    #[structopt(long = "show-settings")]
+    pub show_settings: bool,
+
+    /// Print the commands that would run, in order, without running them
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Run the targets again whenever a file that they `watch` changes, until Ctrl-C
+    #[structopt(long = "watch", short = "w")]
+    pub watch: bool,
+
+    /// Hold the project lock for the whole run, so other invocations of mold wait for it
+    #[structopt(long = "exclusive")]
+    pub exclusive: bool,
+
+    /// Give up waiting for another invocation's project lock after this long, like 30s or 5m
+    #[structopt(long = "lock-timeout", value_name = "DURATION")]
+    pub lock_timeout: Option<String>,
+
+    /// Check everything that was loaded for problems instead of running anything
+    #[structopt(long = "validate")]
+    pub validate: bool,
+
+    /// Run recipes that require a clean working tree even if there are uncommitted changes
+    #[structopt(long = "allow-dirty")]
+    pub allow_dirty: bool,
+
+    /// Keep going when an import fails to load, leaving out its recipes
+    #[structopt(long = "skip-broken-imports")]
+    pub skip_broken_imports: bool,
+
+    /// Only run recipes named exactly, never by leaving off their import prefix
+    #[structopt(long = "no-suffix-match")]
+    pub no_suffix_match: bool,
+
+    /// Never prompt, draw spinners, or redraw progress, even on a TTY [env: MOLD_NON_INTERACTIVE]
+    #[structopt(long = "non-interactive", alias = "no-interactive")]
+    pub non_interactive: bool,
+
+    /// Don't describe the recipe, command, and environment when a command fails
+    #[structopt(long = "no-failure-context")]
+    pub no_failure_context: bool,
+
+    /// Never clone missing remotes
+    #[structopt(long = "offline")]
+    pub offline: bool,
+
     /// Explain commands to be run rather than executing them
     #[structopt(long = "explain", short = "x")]
     pub explain: bool,
 
     /// Which recipe(s) to run
     pub targets: Vec<String>,
+
+    /// Arguments for the targets, given after `--`; their commands see them as $1, $2, ... and
+    /// $@, which expand to nothing when they aren't given
+    #[structopt(last = true)]
+    pub args: Vec<String>,
 }
 
 /// Handle actual execution
 fn run(args: Args) -> Result<(), Error> {
+    if args.non_interactive {
+        mold::util::set_non_interactive(true);
+    }
+
+    // json is for tools, which don't want escape codes mixed into it
+    let format: OutputFormat = args.format.parse()?;
+    if format == OutputFormat::Json {
+        colored::control::set_override(false);
+    }
+
+    // early return if we passed a --completion, which doesn't need a moldfile at all
+    if let Some(shell) = &args.completion {
+        print!("{}", completion::script(Args::clap(), shell)?);
+        return Ok(());
+    }
+
+    // early return if we passed a --shell-init, which finds recipes itself whenever it needs them
+    if let Some(shell) = &args.shell_init {
+        print!("{}", shell_init::script(shell)?);
+        return Ok(());
+    }
+
     // load the moldfile
-    let mut envs = vec![];
-    envs.extend(args.env);
-    envs.extend(args.add_envs);
-    envs.push(std::env::consts::FAMILY.to_string());
-    envs.push(std::env::consts::OS.to_string());
+    let mut envs: Vec<String> = vec![];
+    if let Some(env) = &args.env {
+        envs.extend(env.split(',').filter(|x| !x.is_empty()).map(Into::into));
+    }
+    envs.extend(args.add_envs.iter().cloned());
 
-    let filepath = Mold::discover(&Path::new("."), args.file.clone())?;
+    // matrix runs only use the environments that were asked for explicitly
+    let mut matrix_envs = envs.clone();
+    matrix_envs.extend(args.family.clone());
+    matrix_envs.extend(args.os.clone());
+
+    // explicit --family / --os always apply, but the implicit ones can be turned off
+    let (default_family, default_os) = if args.no_default_envs {
+        (None, None)
+    } else {
+        (
+            Some(std::env::consts::FAMILY.to_string()),
+            Some(std::env::consts::OS.to_string()),
+        )
+    };
+    envs.extend(args.family.clone().or(default_family));
+    envs.extend(args.os.clone().or(default_os));
+
+    // early return if we only want to see the active environments
+    if args.print_envs {
+        for env in &envs {
+            println!("{}", env);
+        }
+        return Ok(());
+    }
+
+    // early return if we passed a --migrate, which needs to find the YAML file rather than a moldfile
+    if args.migrate {
+        let path = match &args.file {
+            Some(file) => Mold::discover(Path::new("."), Some(file.clone()))?,
+            None => Mold::discover(Path::new("."), Some("mold.yaml".into()))
+                .or_else(|_| Mold::discover(Path::new("."), Some("mold.yml".into())))?,
+        };
+        return Mold::migrate(&path);
+    }
+
+    let filepath = Mold::discover(Path::new("."), args.file.clone())?;
 
     // early return if we passed a --clean
     if args.clean {
         return Mold::clean_all(&filepath);
     }
 
-    if let Some(import) = args.import {
+    // early return if we passed a --verify
+    if args.verify {
+        return Mold::verify_all(&filepath);
+    }
+
+    // early return if we passed a --update-gitignore, which is the only thing that touches it
+    if args.update_gitignore {
+        return Mold::update_gitignore(&filepath);
+    }
+
+    // early return if we passed a --uninstall-hook, which doesn't need the moldfile to load
+    if let Some(hook) = &args.uninstall_hook {
+        return Mold::uninstall_hook(&filepath, hook, args.force);
+    }
+
+    // early return if we passed a --clone
+    if args.clone {
+        return Mold::clone_all(
+            &filepath,
+            envs,
+            args.git,
+            args.verbose,
+            !args.no_vars,
+            args.frozen,
+        );
+    }
+
+    if let Some(import) = &args.import {
         use std::io::prelude::*;
-        let line = if let Some(prefix) = args.prefix {
+        let line = if let Some(prefix) = &args.prefix {
             format!("import \"{}\" as {}\n", import, prefix)
         } else {
             format!("import \"{}\"\n", import)
@@ -102,7 +418,73 @@ fn run(args: Args) -> Result<(), Error> {
         return Ok(());
     }
 
-    let mold = Mold::init(&filepath, envs, args.git, !args.no_vars)?;
+    // time repeated loads so that users can report real-world numbers
+    if let Some(count) = args.bench_load {
+        return bench_load(&args, &filepath, &envs, count);
+    }
+
+    let mut mold = load(&args, &filepath, envs.clone())?;
+
+    // early return if we passed a --list-names, which shell completion uses for recipe names
+    if args.list_names {
+        for name in mold.public_recipe_names() {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    // early return if we passed a --list-functions, which --shell-init uses for recipe names
+    if args.list_functions {
+        for name in mold.function_names() {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    // early return if we passed a --list-json, for editors and other tools
+    if args.list_json {
+        return mold.list_json();
+    }
+
+    // early return if we passed an --imports
+    if args.print_imports {
+        mold.print_imports();
+        return Ok(());
+    }
+
+    // early return if we passed a --dump
+    if let Some(format) = &args.dump {
+        if format != "json" {
+            return Err(failure::format_err!("Unknown dump format {}", format.red()));
+        }
+        return mold.dump();
+    }
+
+    let settings = resolve_settings(&args, &mold)?;
+    if args.show_settings {
+        settings.print();
+        return Ok(());
+    }
+    mold.keep_going = settings.keep_going();
+
+    // install hooks after loading so that their targets can be checked
+    if let Some(hook) = &args.install_hook {
+        return mold.install_hook(hook, &args.targets, args.force);
+    }
+
+    if args.install_hooks {
+        return mold.install_hooks(args.force);
+    }
+
+    // early return if we passed a --doctor
+    if args.doctor {
+        return mold.doctor();
+    }
+
+    // report problems with the moldfile instead of running anything
+    if args.validate {
+        return validate(&mold);
+    }
 
     // early return if we passed a --update
     if args.update {
@@ -115,29 +497,456 @@ fn run(args: Args) -> Result<(), Error> {
         return Ok(());
     }
 
+    // print a single variable with no decoration so it can be captured by scripts
+    if let Some(name) = &args.get_var {
+        println!("{}", mold.get_var(name)?);
+        return Ok(());
+    }
+
+    // wipe a single recipe's state rather than all of .mold
+    if let Some(name) = &args.clean_state {
+        return mold.clean_state(&mold.resolve_target(name)?);
+    }
+
+    // print the reverse dependencies of a recipe instead of running anything
+    if let Some(name) = &args.rdeps {
+        return mold.rdeps(&mold.resolve_target(name)?);
+    }
+
+    // run a one-off command in the moldfile's environment instead of a recipe
+    if let Some(command) = &args.exec {
+        return mold.exec(command);
+    }
+
+    // run the targets once per environment set rather than once overall
+    if let Some(matrix) = &args.matrix_env {
+        return run_matrix(&args, &filepath, &matrix_envs, matrix);
+    }
+
     // early return and print help if we didn't pass any targets
-    if args.targets.is_empty() {
-        return mold.help();
+    let (requested_targets, named) = mold.parse_targets(&args.targets)?;
+
+    // print the dependency graph, of everything if nothing in particular was asked for
+    if args.graph {
+        let graph = match args.graph_format.as_str() {
+            "dot" => mold.graph_dot(&requested_targets)?,
+            "mermaid" => mold.graph_mermaid(&requested_targets)?,
+            format => {
+                return Err(failure::format_err!(
+                    "Unknown graph format {}; expected dot or mermaid",
+                    format.red()
+                ))
+            }
+        };
+        print!("{}", graph);
+        return Ok(());
     }
 
+    if requested_targets.is_empty() {
+        return mold.help(format, args.all);
+    }
+
+    // only the targets named on the command line see the arguments, not their dependencies
+    mold.bind_args(&requested_targets, &args.args, &named)?;
+
     // explain all of the given targets rather than executing them
     if args.explain {
-        for target_name in &args.targets {
-            mold.explain(target_name)?;
+        // several targets still make a single document
+        if format == OutputFormat::Json && requested_targets.len() > 1 {
+            let tasks = requested_targets
+                .iter()
+                .map(|x| mold.explain_json(x))
+                .collect::<Result<Vec<_>, _>>()?;
+            println!("{}", serde_json::to_string_pretty(&tasks)?);
+            return Ok(());
         }
 
+        for target_name in &requested_targets {
+            mold.explain(target_name, format)?;
+        }
+
+        return Ok(());
+    }
+
+    // print the execution order rather than executing anything
+    if args.plan {
+        return mold.plan(&requested_targets);
+    }
+
+    // print a script that does what running the targets would
+    if args.emit_script {
+        let shell = args.shell.parse()?;
+        print!("{}", mold.emit_script(&requested_targets, shell)?);
         return Ok(());
     }
 
-    let requested_targets = args
+    mold.check_targets(&requested_targets)?;
+
+    // every run is the same command line without --watch, so it can be killed as a whole
+    if args.watch {
+        let exe = std::env::current_exe()?;
+        let rest: Vec<_> = std::env::args_os()
+            .skip(1)
+            .filter(|x| x != "--watch" && x != "-w")
+            .collect();
+        let reload = || {
+            let mut mold = load(&args, &filepath, envs.clone())?;
+            mold.bind_args(&requested_targets, &args.args, &named)?;
+            Ok(mold)
+        };
+        return mold.watch(
+            &requested_targets,
+            Duration::from_millis(200),
+            reload,
+            || {
+                let mut command = std::process::Command::new(&exe);
+                command.args(&rest);
+                command
+            },
+        );
+    }
+
+    let all_targets = mold.find_all_dependencies(&requested_targets)?;
+    mold.check_all_required_vars(&all_targets)?;
+
+    // dry runs print exactly what a sequential run would, without running anything
+    if args.dry_run {
+        return mold.execute_all(&all_targets, true);
+    }
+
+    // held until the run is over, however it ends
+    let _lock = match args.exclusive || mold.exclusive {
+        true => Some(mold.exclusive_lock()?),
+        false => None,
+    };
+
+    if settings.jobs() > 1 || settings.prefix_output() {
+        mold.execute_parallel(&all_targets, settings.jobs())
+    } else {
+        mold.execute_all(&all_targets, false)
+    }
+}
+
+/// Combine the settings from the command line, the moldfile, and the user's own
+///
+/// Command-line options win over the moldfile's settings, which win over the user's.
+fn resolve_settings(args: &Args, mold: &Mold) -> Result<Settings, Error> {
+    Settings::resolve(
+        &[
+            ("jobs", args.jobs.map(|x| x.to_string())),
+            (
+                "keep_going",
+                Some("true".to_string()).filter(|_| args.keep_going),
+            ),
+            (
+                "prefix_output",
+                Some("true".to_string()).filter(|_| args.prefix_output),
+            ),
+        ],
+        (&mold.root_file, &mold.settings),
+        mold::settings::load_user()?,
+    )
+}
+
+/// Print every problem that `Mold::validate` finds, failing if any of them is an error
+fn validate(mold: &Mold) -> Result<(), Error> {
+    let diagnostics = mold.validate();
+    for diagnostic in &diagnostics {
+        println!("{}\n", diagnostic);
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|x| x.severity == mold::Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    if errors > 0 {
+        return Err(failure::format_err!(
+            "Found {} error(s) and {} warning(s) in {}",
+            errors.to_string().red(),
+            warnings,
+            mold.root_file.display()
+        ));
+    }
+
+    println!(
+        "{:>12} {} {}",
+        "Valid".green(),
+        mold.root_file.display(),
+        format!("({} warning(s))", warnings).white()
+    );
+    Ok(())
+}
+
+/// Load the moldfile with the given environments and the rest of the command line options
+fn load(args: &Args, filepath: &Path, envs: Vec<String>) -> Result<Mold, Error> {
+    let mut mold = Mold::new(
+        filepath,
+        envs,
+        args.git,
+        args.verbose,
+        args.offline,
+        !args.no_vars,
+    )?;
+
+    // listing recipes and updating remotes should still work when a shared import is broken,
+    // since updating is usually how it gets fixed
+    mold.overrides = parse_overrides(&args.set)?;
+    mold.frozen = args.frozen;
+    mold.use_dotenv = !args.no_dotenv;
+    mold.skip_broken_imports = args.skip_broken_imports || tolerates_broken_imports(args);
+    mold.load()?;
+
+    mold.allow_dirty = args.allow_dirty;
+    mold.failure_context = !args.no_failure_context;
+    mold.suffix_match = !args.no_suffix_match;
+    mold.force = args.force;
+    mold.use_cache = !args.no_cache;
+    mold.lock_timeout = match &args.lock_timeout {
+        Some(timeout) => Some(mold::util::parse_duration(timeout)?),
+        None => None,
+    };
+    Ok(mold)
+}
+
+/// Parse `--set KEY=VALUE` options into variables
+fn parse_overrides(values: &[String]) -> Result<mold::VarMap, Error> {
+    values
+        .iter()
+        .map(|value| match value.split_once('=') {
+            Some((key, value)) if mold::util::is_var_name(key) => {
+                Ok((key.to_string(), value.to_string()))
+            }
+            _ => Err(failure::format_err!(
+                "Invalid --set {}; expected KEY=VALUE, which wins over `var` and `:=` in every \
+                 moldfile",
+                value.red()
+            )),
+        })
+        .collect()
+}
+
+/// Whether the command line only lists recipes, updates remotes, or validates
+///
+/// Anything that runs or writes out commands needs every import, even without targets.
+fn tolerates_broken_imports(args: &Args) -> bool {
+    if args.update || args.validate {
+        return true;
+    }
+
+    args.targets.is_empty()
+        && args.dump.is_none()
+        && !args.doctor
+        && !args.vars
+        && args.get_var.is_none()
+        && args.clean_state.is_none()
+        && args.rdeps.is_none()
+        && args.exec.is_none()
+        && !args.install_hooks
+        && !args.emit_script
+}
+
+/// Load the project repeatedly and print a summary of how long it took
+fn bench_load(args: &Args, filepath: &Path, envs: &[String], count: usize) -> Result<(), Error> {
+    if count == 0 {
+        return Err(failure::format_err!(
+            "{} needs at least 1",
+            "--bench-load".red()
+        ));
+    }
+
+    // load once up front so that cloning remotes isn't part of the measurements
+    load(args, filepath, envs.to_vec())?;
+
+    let mut times = vec![];
+    for _ in 0..count {
+        let start = std::time::Instant::now();
+        load(args, filepath, envs.to_vec())?;
+        times.push(start.elapsed());
+    }
+    times.sort();
+
+    let percentile = |q: f64| times[((times.len() - 1) as f64 * q).round() as usize];
+    let ms = |time: std::time::Duration| format!("{:.3}ms", time.as_secs_f64() * 1000.0);
+
+    println!(
+        "{:>12} {} {} times",
+        "Loaded".green(),
+        filepath.display(),
+        count
+    );
+    println!("{:>12} {}", "min", ms(times[0]));
+    println!("{:>12} {}", "p50", ms(percentile(0.5)));
+    println!("{:>12} {}", "p90", ms(percentile(0.9)));
+    println!("{:>12} {}", "p99", ms(percentile(0.99)));
+    println!("{:>12} {}", "max", ms(times[times.len() - 1]));
+
+    Ok(())
+}
+
+/// Run the requested targets once per environment set and summarize the results
+///
+/// Each set is loaded separately, so conditions can pick different recipes, commands, and
+/// variables for every run. A failure stops the remaining targets for that set, but the other
+/// sets still run.
+fn run_matrix(args: &Args, filepath: &Path, base: &[String], matrix: &str) -> Result<(), Error> {
+    if args.targets.is_empty() {
+        return Err(failure::format_err!(
+            "{} requires at least one target",
+            "--matrix-env".red()
+        ));
+    }
+
+    // these describe a single run instead of running anything, so there's nothing to repeat
+    let modes = [
+        (args.explain, "--explain"),
+        (args.plan, "--plan"),
+        (args.emit_script, "--emit-script"),
+        (args.graph, "--graph"),
+        (args.watch, "--watch"),
+    ];
+    if let Some((_, flag)) = modes.iter().find(|(given, _)| *given) {
+        return Err(failure::format_err!(
+            "{} can't be combined with {}",
+            "--matrix-env".red(),
+            flag.red()
+        ));
+    }
+
+    let sets: Vec<Vec<String>> = matrix
+        .split(',')
+        .filter(|x| !x.is_empty())
+        .map(|set| set.split('+').map(Into::into).collect())
+        .collect();
+
+    // recipes can differ between sets, so each target is resolved, and can fail, on its own
+    let named: mold::VarMap = args
         .targets
         .iter()
-        .map(std::string::ToString::to_string)
+        .filter_map(|x| mold::util::split_assignment(x))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
         .collect();
-    let all_targets = mold.find_all_dependencies(&requested_targets)?;
+    let targets: Vec<&String> = args
+        .targets
+        .iter()
+        .filter(|x| mold::util::split_assignment(x).is_none())
+        .collect();
+
+    // one (environment set, target, status) row per run
+    let mut rows = vec![];
+    for set in sets {
+        let label = set.join("+");
+        let mut envs = base.to_vec();
+        envs.extend(set);
+
+        let prepared = load(args, filepath, envs).and_then(|mut mold| {
+            mold.label = Some(label.clone());
+            let settings = resolve_settings(args, &mold)?;
+            mold.keep_going = settings.keep_going();
+
+            let resolved = targets
+                .iter()
+                .filter_map(|x| mold.resolve_target(x).ok())
+                .collect();
+            mold.bind_args(&resolved, &args.args, &named)?;
+
+            // held until this set is done, however it ends
+            let lock = match !args.dry_run && (args.exclusive || mold.exclusive) {
+                true => Some(mold.exclusive_lock()?),
+                false => None,
+            };
+            Ok((mold, settings, lock))
+        });
+        let (mold, settings, _lock) = match prepared {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                eprintln!(
+                    "{:>12} [{}] {}",
+                    "Error".red(),
+                    label,
+                    mold::util::error_chain(&err)
+                );
+                for target in &targets {
+                    rows.push((label.clone(), target.to_string(), "failed"));
+                }
+                continue;
+            }
+        };
+
+        let mut done = mold::TargetSet::new();
+        let mut broken = false;
+        for target in &targets {
+            if broken {
+                rows.push((label.clone(), target.to_string(), "skipped"));
+                continue;
+            }
+
+            // dependencies that an earlier target already ran aren't run again
+            let result = mold
+                .resolve_target(target)
+                .and_then(|target| {
+                    let requested = std::iter::once(target).collect();
+                    mold.find_all_dependencies(&requested)
+                })
+                .and_then(|deps| {
+                    let deps: mold::TargetSet =
+                        deps.into_iter().filter(|x| !done.contains(x)).collect();
+                    mold.check_all_required_vars(&deps)?;
+                    if args.dry_run {
+                        mold.execute_all(&deps, true)?;
+                    } else if settings.jobs() > 1 || settings.prefix_output() {
+                        mold.execute_parallel(&deps, settings.jobs())?;
+                    } else {
+                        mold.execute_all(&deps, false)?;
+                    }
+                    done.extend(deps);
+                    Ok(())
+                });
+
+            if let Err(err) = result {
+                eprintln!(
+                    "{:>12} [{}] {}",
+                    "Error".red(),
+                    label,
+                    mold::util::error_chain(&err)
+                );
+                broken = true;
+            }
+
+            let status = if broken { "failed" } else { "ok" };
+            rows.push((label.clone(), target.to_string(), status));
+        }
+    }
+
+    let env_width = rows.iter().map(|x| x.0.len()).max().unwrap_or(0);
+    let target_width = rows.iter().map(|x| x.1.len()).max().unwrap_or(0);
+
+    println!();
+    println!("{:>12}", "Summary".white());
+    for (label, target, status) in &rows {
+        let status = match *status {
+            "ok" => status.green(),
+            "skipped" => status.yellow(),
+            _ => status.red(),
+        };
+        println!(
+            "{:>12} {:<env_width$}  {:<target_width$}  {}",
+            "",
+            label,
+            target,
+            status,
+            env_width = env_width,
+            target_width = target_width
+        );
+    }
 
-    for target_name in &all_targets {
-        mold.execute(target_name)?;
+    let failed = rows.iter().filter(|x| x.2 != "ok").count();
+    if failed > 0 {
+        return Err(failure::format_err!(
+            "{} of {} matrix runs didn't succeed",
+            failed.to_string().red(),
+            rows.len()
+        ));
     }
 
     Ok(())
@@ -0,0 +1,152 @@
+//! Checksums of the files that mold writes into `.mold`
+//!
+//! Every file that mold generates is written through `write`, which records a sha256 digest of
+//! its contents along with a hash of whatever it was generated from. `read` only returns files
+//! that still match both, so corrupted, tampered, or outdated files are treated as missing and get
+//! regenerated by their owner.
+
+use super::util;
+use colored::*;
+use failure::Error;
+use indexmap::IndexMap;
+use sha2::Digest;
+use sha2::Sha256;
+use std::fs;
+use std::path::Path;
+
+/// Name of the manifest file inside `.mold`
+const MANIFEST: &str = "manifest.json";
+
+/// What the manifest knows about a single file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// sha256 of the file's contents, as lowercase hex
+    pub digest: String,
+
+    /// Hash of the statement or data that the file was generated from
+    pub source: String,
+}
+
+/// A problem found by `verify`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// The file is in the manifest but doesn't exist
+    Missing(String),
+
+    /// The file's contents don't match its recorded digest
+    Modified(String),
+}
+
+/// Compute the sha256 of some data, as lowercase hex
+pub fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect()
+}
+
+/// Load the manifest of a `.mold` directory, or an empty one if there isn't one yet
+///
+/// A manifest that can't be parsed is treated like a missing one, so that it gets rebuilt as
+/// files are written again.
+pub fn load(mold_dir: &Path) -> Result<IndexMap<String, Entry>, Error> {
+    let path = mold_dir.join(MANIFEST);
+    if !path.is_file() {
+        return Ok(IndexMap::new());
+    }
+
+    let data = fs::read_to_string(&path)?;
+    let json: serde_json::Value = match serde_json::from_str(&data) {
+        Ok(json) => json,
+        Err(err) => {
+            util::warn(&format!(
+                "Ignoring {}, which couldn't be parsed: {}",
+                path.display().to_string().yellow(),
+                err
+            ));
+            return Ok(IndexMap::new());
+        }
+    };
+
+    let mut entries = IndexMap::new();
+    for (file, entry) in json["files"].as_object().into_iter().flatten() {
+        let field = |name: &str| entry[name].as_str().unwrap_or_default().to_string();
+        entries.insert(
+            file.clone(),
+            Entry {
+                digest: field("sha256"),
+                source: field("source"),
+            },
+        );
+    }
+
+    Ok(entries)
+}
+
+/// Save the manifest of a `.mold` directory
+fn save(mold_dir: &Path, entries: &IndexMap<String, Entry>) -> Result<(), Error> {
+    let files: serde_json::Map<_, _> = entries
+        .iter()
+        .map(|(file, entry)| {
+            let entry = serde_json::json!({
+                "sha256": entry.digest,
+                "source": entry.source,
+            });
+            (file.clone(), entry)
+        })
+        .collect();
+
+    let json = serde_json::json!({ "files": files });
+    let path = mold_dir.join(MANIFEST);
+    util::write_atomic(&path, serde_json::to_string_pretty(&json)?.as_bytes())
+}
+
+/// Write a file relative to `.mold` and record it in the manifest
+pub fn write(mold_dir: &Path, rel: &str, contents: &[u8], source: &str) -> Result<(), Error> {
+    let path = mold_dir.join(rel);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    util::write_atomic(&path, contents)?;
+
+    let mut entries = load(mold_dir)?;
+    entries.insert(
+        rel.to_string(),
+        Entry {
+            digest: sha256(contents),
+            source: source.to_string(),
+        },
+    );
+    save(mold_dir, &entries)
+}
+
+/// Read a file relative to `.mold`, but only if it matches the manifest and came from `source`
+pub fn read(mold_dir: &Path, rel: &str, source: &str) -> Result<Option<Vec<u8>>, Error> {
+    let entries = load(mold_dir)?;
+    let entry = match entries.get(rel) {
+        Some(entry) if entry.source == source => entry,
+        _ => return Ok(None),
+    };
+
+    match fs::read(mold_dir.join(rel)) {
+        Ok(contents) if sha256(&contents) == entry.digest => Ok(Some(contents)),
+        _ => Ok(None),
+    }
+}
+
+/// Check every file in the manifest against its recorded digest
+pub fn verify(mold_dir: &Path) -> Result<Vec<Discrepancy>, Error> {
+    let mut problems = vec![];
+    for (file, entry) in load(mold_dir)? {
+        match fs::read(mold_dir.join(&file)) {
+            Err(_) => problems.push(Discrepancy::Missing(file)),
+            Ok(contents) if sha256(&contents) != entry.digest => {
+                problems.push(Discrepancy::Modified(file))
+            }
+            Ok(_) => {}
+        }
+    }
+
+    Ok(problems)
+}
@@ -0,0 +1,131 @@
+//! The orders that recipes are listed and executed in
+//!
+//! Anything that shows or runs more than one recipe goes through here, so that help, `--plan`,
+//! `--rdeps`, `--dump`, and execution all agree with each other.
+
+use super::RecipeMap;
+use super::SourceMap;
+use super::TargetSet;
+use colored::*;
+use failure::Error;
+use indexmap::IndexMap;
+use std::path::Path;
+
+/// Order recipes for display
+///
+/// Recipes are grouped by the moldfile that defined them, with the groups in the order that the
+/// moldfiles were loaded, and sorted by name within each group. Recipes without a known moldfile
+/// come last.
+pub fn display<'a>(recipes: &'a RecipeMap, files: &SourceMap) -> Vec<&'a str> {
+    let mut groups: IndexMap<Option<&Path>, Vec<&str>> = IndexMap::new();
+
+    // seed the groups in load order so that the root moldfile comes first
+    for file in files.values() {
+        groups.entry(Some(file.as_path())).or_default();
+    }
+
+    for name in recipes.keys() {
+        let file = files.get(name).map(|x| x.as_path());
+        groups.entry(file).or_default().push(name);
+    }
+
+    if let Some(unknown) = groups.swap_remove(&None) {
+        groups.insert(None, unknown);
+    }
+
+    groups
+        .into_iter()
+        .flat_map(|(_, mut names)| {
+            names.sort_unstable();
+            names
+        })
+        .collect()
+}
+
+/// Order targets and all of their dependencies for execution
+///
+/// Every recipe comes after the recipes it requires. Otherwise, recipes keep the order that they
+/// were given in, both on the command line and in `require` statements.
+pub fn execution<'a, F>(targets: &TargetSet, requires: F) -> Result<TargetSet, Error>
+where
+    F: Fn(&str) -> Result<&'a TargetSet, Error>,
+{
+    let mut found = TargetSet::new();
+    let mut visiting = TargetSet::new();
+
+    for name in targets {
+        visit(name, &requires, &mut visiting, &mut found)?;
+    }
+
+    Ok(found)
+}
+
+/// Add a single recipe to `found`, after all of its dependencies
+///
+/// Recipes that are already in `found` are skipped, so shared dependencies are only walked once.
+/// `visiting` holds the chain of recipes currently being walked, so running into one of them again
+/// means that they require each other.
+fn visit<'a, F>(
+    name: &str,
+    requires: &F,
+    visiting: &mut TargetSet,
+    found: &mut TargetSet,
+) -> Result<(), Error>
+where
+    F: Fn(&str) -> Result<&'a TargetSet, Error>,
+{
+    if found.contains(name) {
+        return Ok(());
+    }
+
+    if let Some(start) = visiting.get_index_of(name) {
+        let cycle: Vec<&str> = visiting
+            .iter()
+            .skip(start)
+            .map(String::as_str)
+            .chain(std::iter::once(name))
+            .collect();
+        return Err(failure::format_err!(
+            "Dependency cycle detected: {}",
+            cycle.join(" -> ").red()
+        ));
+    }
+
+    visiting.insert(name.to_string());
+    for dep in requires(name)? {
+        visit(dep, requires, visiting, found)?;
+    }
+    visiting.pop();
+
+    found.insert(name.to_string());
+    Ok(())
+}
+
+/// Group recipes that are already in execution order into levels that can run concurrently
+///
+/// Every recipe goes in the level after the latest of its requirements, so each level only
+/// depends on earlier ones. Within a level, recipes keep their execution order.
+pub fn levels<'a, F>(ordered: &TargetSet, requires: F) -> Result<Vec<Vec<String>>, Error>
+where
+    F: Fn(&str) -> Result<&'a TargetSet, Error>,
+{
+    let mut depth: IndexMap<&str, usize> = IndexMap::new();
+    let mut levels: Vec<Vec<String>> = vec![];
+
+    for name in ordered {
+        let level = requires(name)?
+            .iter()
+            .filter_map(|dep| depth.get(dep.as_str()))
+            .map(|x| x + 1)
+            .max()
+            .unwrap_or(0);
+
+        depth.insert(name, level);
+        if levels.len() <= level {
+            levels.resize(level + 1, vec![]);
+        }
+        levels[level].push(name.clone());
+    }
+
+    Ok(levels)
+}
@@ -0,0 +1,157 @@
+//! Fair forwarding of output from tasks that run at the same time
+//!
+//! Each running task sends its lines to an `Interleaver` over a channel. Lines are buffered per
+//! task and written out round-robin, at most `per_turn` lines from each task per turn, so a chatty
+//! task can't hold the terminal while its siblings' output piles up. When a task finishes, whatever
+//! it still has buffered is written immediately instead of waiting behind long-running siblings.
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::TryRecvError;
+
+/// Something that happened to one of the tasks, identified by its index
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The task printed a line, without its trailing newline
+    Line(usize, String),
+
+    /// The task finished and won't print anything else
+    Done(usize),
+}
+
+/// Writes lines from several tasks to one output, taking turns between them
+pub struct Interleaver<W: Write> {
+    out: W,
+
+    /// Prefix for each task's lines, already padded to the same width
+    labels: Vec<String>,
+
+    /// Maximum number of lines written from one task before moving on to the next
+    per_turn: usize,
+
+    /// Lines that each task has printed but that haven't been written yet
+    queues: Vec<VecDeque<String>>,
+
+    /// Task that the next turn starts with
+    next: usize,
+}
+
+impl<W: Write> Interleaver<W> {
+    /// Create an interleaver for tasks labeled `labels`, indexed in the same order
+    pub fn new(out: W, labels: &[String], per_turn: usize) -> Self {
+        let width = labels.iter().map(|x| x.chars().count()).max().unwrap_or(0);
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|x| format!("{}{}", x, " ".repeat(width - x.chars().count())))
+            .collect();
+
+        Interleaver {
+            out,
+            queues: vec![VecDeque::new(); labels.len()],
+            labels,
+            per_turn: per_turn.max(1),
+            next: 0,
+        }
+    }
+
+    /// Forward events until every sender has hung up, then write whatever is left
+    ///
+    /// Returns the output so that callers can inspect what was written.
+    pub fn run(mut self, rx: Receiver<Event>) -> io::Result<W> {
+        let mut open = true;
+
+        while open || self.pending() {
+            // take everything that's already waiting so that each turn sees every active task
+            loop {
+                match rx.try_recv() {
+                    Ok(event) => self.push(event)?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        open = false;
+                        break;
+                    }
+                }
+            }
+
+            if self.pending() {
+                self.turn()?;
+            } else if open {
+                match rx.recv() {
+                    Ok(event) => self.push(event)?,
+                    Err(_) => open = false,
+                }
+            }
+        }
+
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Whether any task has lines waiting to be written
+    fn pending(&self) -> bool {
+        self.queues.iter().any(|x| !x.is_empty())
+    }
+
+    fn push(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Line(idx, line) => self.queues[idx].push_back(line),
+
+            // nothing else is coming from this task, so there's no reason to hold its output back
+            Event::Done(idx) => {
+                while let Some(line) = self.queues[idx].pop_front() {
+                    self.write_line(idx, &line)?;
+                }
+                self.out.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write up to `per_turn` lines from each task that has any, starting where the last turn
+    /// left off
+    fn turn(&mut self) -> io::Result<()> {
+        let count = self.queues.len();
+        for offset in 0..count {
+            let idx = (self.next + offset) % count;
+            for _ in 0..self.per_turn {
+                match self.queues[idx].pop_front() {
+                    Some(line) => self.write_line(idx, &line)?,
+                    None => break,
+                }
+            }
+        }
+
+        self.next = (self.next + 1) % count.max(1);
+        self.out.flush()
+    }
+
+    fn write_line(&mut self, idx: usize, line: &str) -> io::Result<()> {
+        writeln!(self.out, "{} | {}", self.labels[idx], line)
+    }
+}
+
+/// Send every line of `reader` as coming from task `idx`
+///
+/// This doesn't send `Event::Done`, since a task usually has more than one stream; that's up to
+/// whoever waits for the task.
+pub fn forward_lines<R: Read>(idx: usize, reader: R, tx: &Sender<Event>) {
+    for line in BufReader::new(reader).split(b'\n') {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\r').to_string();
+        if tx.send(Event::Line(idx, line)).is_err() {
+            break;
+        }
+    }
+}
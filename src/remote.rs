@@ -7,42 +7,148 @@ use git2::build::RepoBuilder;
 use git2::FetchOptions;
 use git2::RemoteCallbacks;
 use git2::Repository;
+#[cfg(feature = "cli")]
 use spinners::Spinner;
+#[cfg(feature = "cli")]
 use spinners::Spinners;
+use std::cell::Cell;
+use std::fmt;
+use std::io::BufRead;
+use std::io::BufReader;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::process::Stdio;
 use std::str::FromStr;
-use std::string::ToString;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 use url::Url;
 
-fn with_spinner<F>(label: String, f: F) -> Result<(), Error>
+/// How often to print progress updates when there's no spinner
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Rate-limited progress reporting for output that isn't a terminal
+struct Progress {
+    label: String,
+    enabled: bool,
+    last: Cell<Instant>,
+}
+
+impl Progress {
+    fn new(label: String, enabled: bool) -> Progress {
+        Progress {
+            label,
+            enabled,
+            last: Cell::new(Instant::now()),
+        }
+    }
+
+    /// Print a progress line if enough time has passed since the last one
+    fn update(&self, received: usize, total: usize) {
+        if !self.enabled || total == 0 || self.last.get().elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+
+        self.last.set(Instant::now());
+        println!(
+            "{}... {}% ({}/{} objects)",
+            self.label,
+            received * 100 / total,
+            received,
+            total
+        );
+    }
+}
+
+/// Whether a spinner can be drawn; they don't work on Windows or without a TTY
+fn use_spinner() -> bool {
+    util::is_interactive(util::Stream::Stdout) && std::env::consts::FAMILY != "windows"
+}
+
+fn with_spinner<F>(label: String, progress: Progress, f: F) -> Result<(), Error>
 where
-    F: FnOnce() -> Result<(), Error>,
+    F: FnOnce(&Progress) -> Result<(), Error>,
 {
-    // spinners don't work on Windows or without a TTY
-    if atty::is(atty::Stream::Stdout) && std::env::consts::FAMILY != "windows" {
-        let spinner = Spinner::new(Spinners::Dots, label);
-        let res = f();
-        spinner.stop();
-        println!();
-        res
-    } else {
-        // without a spinner, just print the line.
-        println!("{}", label);
-        f()
+    #[cfg(feature = "cli")]
+    {
+        if use_spinner() {
+            let spinner = Spinner::new(Spinners::Dots, label);
+            let res = f(&progress);
+            spinner.stop();
+            println!();
+            return res;
+        }
     }
+
+    // without a spinner, print the line along with periodic progress and the elapsed time
+    println!("{}", label);
+    let start = Instant::now();
+    let res = f(&progress);
+    if res.is_ok() {
+        println!(
+            "{}... done in {:.1}s",
+            progress.label,
+            start.elapsed().as_secs_f64()
+        );
+    }
+    res
 }
 
-fn new_cmd() -> Command {
+/// Pull an object count out of a line of `git --progress` output
+///
+/// eg: `Receiving objects:  45% (1234/2750), 1.20 MiB | 2.00 MiB/s`
+fn parse_git_progress(line: &str) -> Option<(usize, usize)> {
+    let rest = line.split("Receiving objects:").nth(1)?;
+    let start = rest.find('(')? + 1;
+    let end = start + rest[start..].find(')')?;
+    let mut counts = rest[start..end].split('/');
+    let received = counts.next()?.trim().parse().ok()?;
+    let total = counts.next()?.trim().parse().ok()?;
+    Some((received, total))
+}
+
+/// Create a git command, silencing its output unless `verbose` is set
+fn new_cmd(verbose: bool) -> Command {
     let mut cmd = Command::new("git");
-    cmd.stderr(Stdio::null()).stdout(Stdio::null());
+    cmd.stdout(Stdio::null());
+    if !verbose {
+        cmd.stderr(Stdio::null());
+    }
     cmd
 }
 
+/// Run a git command, failing if it exits unsuccessfully
+fn run_cmd(mut cmd: Command) -> Result<(), Error> {
+    let status = cmd.spawn().and_then(|mut handle| handle.wait())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(failure::format_err!("git exited with {}", status))
+    }
+}
+
+/// Whether remotes are cloned with their full history, because $MOLD_FULL_CLONE is set
+///
+/// Otherwise the git CLI only clones the latest commit, and fetches more when it's needed.
+fn full_clone() -> bool {
+    match std::env::var("MOLD_FULL_CLONE") {
+        Ok(value) => !value.is_empty() && value != "0",
+        Err(_) => false,
+    }
+}
+
+/// Whether a clone only has part of its history
+fn is_shallow(path: &Path) -> bool {
+    path.join(".git").join("shallow").is_file()
+}
+
 fn ref_exists(path: &Path, ref_: &str) -> Result<bool, Error> {
-    let exists = new_cmd()
+    // this is expected to fail for missing refs, so it's never verbose
+    let exists = new_cmd(false)
         .arg("rev-parse")
         .arg(ref_)
         .arg("--")
@@ -54,8 +160,86 @@ fn ref_exists(path: &Path, ref_: &str) -> Result<bool, Error> {
     Ok(exists)
 }
 
+/// Find the first of `targets` that a clone has
+fn find_ref(path: &Path, targets: &[String]) -> Result<Option<String>, Error> {
+    for target in targets {
+        if ref_exists(path, target)? {
+            return Ok(Some(target.clone()));
+        }
+    }
+    Ok(None)
+}
+
+/// Fetch what a shallow clone is missing to check out `wanted`, then find the first of `targets`
+///
+/// Only `wanted` itself is fetched at first, as a tag, a branch, or a commit id. The rest of the
+/// history is fetched when that doesn't turn it up, like for an abbreviated commit id.
+fn deepen(
+    path: &Path,
+    wanted: &str,
+    targets: &[String],
+    verbose: bool,
+    progress: &Progress,
+) -> Result<Option<String>, Error> {
+    let refspecs = [
+        format!("+refs/tags/{0}:refs/tags/{0}", wanted),
+        format!("+refs/heads/{0}:refs/remotes/origin/{0}", wanted),
+        wanted.to_string(),
+    ];
+    for refspec in &refspecs {
+        // only one of these is expected to exist, so failures are never verbose
+        let mut cmd = new_cmd(false);
+        cmd.args(["fetch", "--depth", "1", "origin"])
+            .arg(refspec)
+            .current_dir(path);
+        if run_cmd(cmd).is_ok() {
+            if let Some(target) = find_ref(path, targets)? {
+                return Ok(Some(target));
+            }
+        }
+    }
+
+    log::info!("cd {} && git fetch --unshallow --tags", path.display());
+    let mut cmd = new_cmd(verbose);
+    cmd.args(["fetch", "--unshallow", "--tags", "--progress", "origin"])
+        .current_dir(path);
+    run_cmd_progress(cmd, progress, verbose)?;
+    find_ref(path, targets)
+}
+
+/// Run a git command that reports `--progress` on stderr, forwarding the counts to `progress`
+///
+/// Other stderr output is only shown when `verbose` is set.
+fn run_cmd_progress(mut cmd: Command, progress: &Progress, verbose: bool) -> Result<(), Error> {
+    cmd.stderr(Stdio::piped());
+    let mut child = cmd.spawn()?;
+    let mut reader = BufReader::new(child.stderr.take().expect("stderr is piped"));
+
+    // git separates repeated progress updates with carriage returns rather than newlines
+    let mut buf = vec![];
+    while reader.read_until(b'\r', &mut buf)? > 0 {
+        for line in String::from_utf8_lossy(&buf).split(['\r', '\n']) {
+            match parse_git_progress(line) {
+                Some((received, total)) => progress.update(received, total),
+                None if verbose && !line.trim().is_empty() => eprintln!("{}", line),
+                None => {}
+            }
+        }
+        buf.clear();
+    }
+
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(failure::format_err!("git exited with {}", status))
+    }
+}
+
 /// Clone a git repository
-fn pull(url: &str, path: &Path) -> Result<(), Error> {
+///
+/// This version of libgit2 can't make shallow clones, so the whole history is always fetched.
+fn pull(url: &str, path: &Path, _verbose: bool, progress: &Progress) -> Result<(), Error> {
     let config = git2::Config::open_default()?;
 
     with_authentication(url, &config, |creds| {
@@ -63,6 +247,10 @@ fn pull(url: &str, path: &Path) -> Result<(), Error> {
         // prep callbacks
         let mut callbacks = RemoteCallbacks::new();
         callbacks.credentials(creds);
+        callbacks.transfer_progress(|stats| {
+            progress.update(stats.received_objects(), stats.total_objects());
+            true
+        });
         let mut fetch = FetchOptions::new();
         fetch.remote_callbacks(callbacks);
 
@@ -72,7 +260,13 @@ fn pull(url: &str, path: &Path) -> Result<(), Error> {
     })
 }
 
-fn checkout(path: &Path, ref_: &str) -> Result<(), Error> {
+fn checkout(
+    path: &Path,
+    ref_: &str,
+    commit: Option<&str>,
+    _verbose: bool,
+    progress: &Progress,
+) -> Result<(), Error> {
     let config = git2::Config::open_default()?;
 
     if !path.is_dir() {
@@ -89,19 +283,31 @@ fn checkout(path: &Path, ref_: &str) -> Result<(), Error> {
         // prep callbacks
         let mut callbacks = RemoteCallbacks::new();
         callbacks.credentials(creds);
+        callbacks.transfer_progress(|stats| {
+            progress.update(stats.received_objects(), stats.total_objects());
+            true
+        });
         let mut fetch = FetchOptions::new();
         fetch.remote_callbacks(callbacks);
 
         // fetch ref
         remote.fetch(&[ref_], Some(&mut fetch), None)?;
 
-        // checkout the appropriate ref
-        let tag_name = format!("tags/{}", ref_);
-        let branch_name = format!("origin/{}", ref_);
-        let object = repo
-            .revparse_single(&tag_name)
-            .or_else(|_| repo.revparse_single(&branch_name))
-            .map_err(|_| failure::format_err!("Unable to locate ref '{}'", ref_.red()))?;
+        // checkout the pinned commit, or else the appropriate ref: a tag, then a branch, then a
+        // commit id, which can be abbreviated
+        let object = match commit {
+            Some(commit) => repo
+                .revparse_single(commit)
+                .map_err(|_| failure::format_err!("Unable to locate commit '{}'", commit.red()))?,
+            None => {
+                let tag_name = format!("tags/{}", ref_);
+                let branch_name = format!("origin/{}", ref_);
+                repo.revparse_single(&tag_name)
+                    .or_else(|_| repo.revparse_single(&branch_name))
+                    .or_else(|_| repo.revparse_single(&format!("{}^{{commit}}", ref_)))
+                    .map_err(|_| failure::format_err!("Unable to locate ref '{}'", ref_.red()))?
+            }
+        };
         repo.set_head_detached(object.id())?;
 
         // force checkout
@@ -113,41 +319,79 @@ fn checkout(path: &Path, ref_: &str) -> Result<(), Error> {
     })
 }
 
-fn pull_git(url: &str, path: &Path) -> Result<(), Error> {
-    // start spinner
+fn pull_git(url: &str, path: &Path, verbose: bool, progress: &Progress) -> Result<(), Error> {
     log::info!("git clone {} {}", url, path.display());
-    let mut cmd = new_cmd();
-    cmd.arg("clone").arg(url).arg(path);
-    cmd.spawn().and_then(|mut handle| handle.wait())?;
-    Ok(())
+    let mut cmd = new_cmd(verbose);
+    cmd.arg("clone").arg("--progress");
+
+    // every branch's latest commit, since the ref to check out isn't known yet
+    if !full_clone() {
+        cmd.args(["--depth", "1", "--no-single-branch"]);
+    }
+
+    cmd.arg(url).arg(path);
+    run_cmd_progress(cmd, progress, verbose)
 }
 
-fn checkout_git(path: &Path, ref_: &str) -> Result<(), Error> {
+fn checkout_git(
+    path: &Path,
+    ref_: &str,
+    commit: Option<&str>,
+    verbose: bool,
+    progress: &Progress,
+) -> Result<(), Error> {
     log::info!(
         "cd {} && git fetch --all --prune && git checkout {}",
         path.display(),
-        ref_
+        commit.unwrap_or(ref_)
     );
 
     if !path.is_dir() {
         return Err(failure::format_err!("{} does not exist", path.display()));
     }
 
-    let mut cmd = new_cmd();
-    cmd.args(&["fetch", "--all", "--prune"]).current_dir(path);
-    cmd.spawn().and_then(|mut handle| handle.wait())?;
+    // shallow clones stay shallow, unless they've been asked not to be
+    let shallow = is_shallow(path);
+    let mut cmd = new_cmd(verbose);
+    cmd.args(["fetch", "--all", "--prune", "--progress"])
+        .current_dir(path);
+    match (shallow, full_clone()) {
+        (true, true) => cmd.arg("--unshallow"),
+        (true, false) => cmd.args(["--depth", "1"]),
+        _ => &mut cmd,
+    };
+    run_cmd_progress(cmd, progress, verbose)?;
+
+    // the pinned commit, or else a tag, then a branch, then a commit id, which can be abbreviated
+    let targets = match commit {
+        Some(commit) => vec![format!("{}^{{commit}}", commit)],
+        None => vec![
+            format!("tags/{}", ref_),
+            format!("origin/{}", ref_),
+            format!("{}^{{commit}}", ref_),
+        ],
+    };
+
+    let mut found = find_ref(path, &targets)?;
+    if found.is_none() && is_shallow(path) {
+        found = deepen(path, commit.unwrap_or(ref_), &targets, verbose, progress)?;
+    }
 
-    let refs = vec![format!("tags/{}", ref_), format!("origin/{}", ref_)];
-    for target in refs {
-        if ref_exists(path, &target)? {
-            let mut command = new_cmd();
+    match (found, commit) {
+        (Some(target), _) => {
+            let mut command = new_cmd(verbose);
             command.arg("checkout").arg(target).current_dir(path);
-            command.spawn().and_then(|mut handle| handle.wait())?;
-            break;
+            run_cmd(command)
         }
+        (None, Some(commit)) => Err(failure::format_err!(
+            "Unable to locate commit '{}'",
+            commit.red()
+        )),
+        (None, None) => Err(failure::format_err!(
+            "Unable to locate ref '{}'",
+            ref_.red()
+        )),
     }
-
-    Ok(())
 }
 
 #[derive(Debug, Clone)]
@@ -164,11 +408,13 @@ pub struct Remote {
 
 impl Remote {
     /// Return this module's folder name in the format hash(url@ref)
+    ///
+    /// The file isn't part of it, so every file imported from the same URL and ref shares a clone.
     fn folder_name(&self) -> String {
         // first attempt to parse with an implicit https://
         let url = Url::parse(&format!("https://{}", &self.url)).or_else(|_| Url::parse(&self.url));
         let last_path = match url {
-            Ok(ref url) => url.path_segments().map(|mut x| x.next_back()).flatten(),
+            Ok(ref url) => url.path_segments().and_then(|mut x| x.next_back()),
             _ => None,
         };
 
@@ -181,6 +427,11 @@ impl Remote {
         }
     }
 
+    /// Whether two remotes share a clone, which only depends on their URL and ref
+    pub fn same_clone(&self, other: &Remote) -> bool {
+        self.url == other.url && self.ref_ == other.ref_
+    }
+
     pub fn path(&self, mold_dir: &Path) -> PathBuf {
         mold_dir.join(self.folder_name())
     }
@@ -189,9 +440,15 @@ impl Remote {
         self.path(mold_dir).is_dir()
     }
 
-    pub fn pull(&self, mold_dir: &Path, use_git: bool) -> Result<(), Error> {
+    /// Find the commit that's checked out for this remote, if it's been cloned
+    pub fn commit(&self, mold_dir: &Path) -> Option<String> {
+        let repo = Repository::open(self.path(mold_dir)).ok()?;
+        let commit = repo.head().ok()?.peel_to_commit().ok()?;
+        Some(commit.id().to_string())
+    }
+
+    pub fn pull(&self, mold_dir: &Path, use_git: bool, verbose: bool) -> Result<(), Error> {
         let path = self.path(mold_dir);
-        let func = if use_git { pull_git } else { pull };
 
         let label = format!(
             "{} {} into {}...",
@@ -199,24 +456,152 @@ impl Remote {
             self.url.yellow(),
             path.display().to_string().yellow()
         );
+        let progress = Progress::new(
+            format!("{} {}", "Cloning".green(), self.url.yellow()),
+            !use_spinner(),
+        );
 
-        with_spinner(label, || {
-            // first attempt to pull with an implicit https://
-            func(&format!("https://{}", self.url), &path).or_else(|_| func(&self.url, &path))
+        with_spinner(label, progress, |progress| {
+            self.clone_into(&path, use_git, verbose, progress)
         })
     }
 
-    pub fn checkout(&self, mold_dir: &Path, use_git: bool) -> Result<(), Error> {
+    /// Clone this remote into `path` without saying anything about it
+    fn clone_into(
+        &self,
+        path: &Path,
+        use_git: bool,
+        verbose: bool,
+        progress: &Progress,
+    ) -> Result<(), Error> {
+        let func = if use_git { pull_git } else { pull };
+
+        // first attempt to pull with an implicit https://
+        func(&format!("https://{}", self.url), path, verbose, progress).or_else(|err| {
+            if verbose {
+                eprintln!(
+                    "Couldn't clone https://{}: {}",
+                    self.url,
+                    util::error_chain(&err)
+                );
+            }
+            func(&self.url, path, verbose, progress)
+        })
+    }
+
+    /// Fetch and force checkout this remote's ref, or the given commit if it's pinned to one
+    pub fn checkout(
+        &self,
+        mold_dir: &Path,
+        commit: Option<&str>,
+        use_git: bool,
+        verbose: bool,
+    ) -> Result<(), Error> {
         let path = self.path(mold_dir);
         let func = if use_git { checkout_git } else { checkout };
         let label = format!(
             "{} {} to {}...",
             "Updating".green(),
             path.display().to_string().yellow(),
-            self.ref_.yellow()
+            commit.unwrap_or(&self.ref_).yellow()
         );
 
-        with_spinner(label, || func(&path, &self.ref_))
+        let progress = Progress::new(
+            format!(
+                "{} {}",
+                "Updating".green(),
+                path.display().to_string().yellow()
+            ),
+            !use_spinner(),
+        );
+
+        with_spinner(label, progress, |progress| {
+            func(&path, &self.ref_, commit, verbose, progress)
+        })
+    }
+
+    /// Clone several remotes at once and check out their refs, or the commits they're pinned to
+    ///
+    /// Up to `jobs` of them are cloned at a time. Their progress would trample over each other, so
+    /// there's a single line for all of them instead. Results are in the same order as `remotes`.
+    pub fn clone_many(
+        remotes: &[(&Remote, Option<String>)],
+        mold_dir: &Path,
+        use_git: bool,
+        verbose: bool,
+        jobs: usize,
+    ) -> Vec<Result<(), Error>> {
+        let results: Mutex<Vec<Option<Result<(), Error>>>> =
+            Mutex::new(remotes.iter().map(|_| None).collect());
+        let next = AtomicUsize::new(0);
+
+        let clone = |remote: &Remote, commit: Option<&str>| {
+            let path = remote.path(mold_dir);
+            let quiet = Progress::new(String::new(), false);
+            remote
+                .clone_into(&path, use_git, verbose, &quiet)
+                .map_err(|err| {
+                    failure::format_err!(
+                        "Couldn't clone {}: {}",
+                        remote.url.red(),
+                        util::error_chain(&err)
+                    )
+                })?;
+
+            let func = if use_git { checkout_git } else { checkout };
+            func(&path, &remote.ref_, commit, verbose, &quiet).map_err(|err| {
+                failure::format_err!(
+                    "Couldn't checkout {}: {}",
+                    commit.unwrap_or(&remote.ref_).red(),
+                    util::error_chain(&err)
+                )
+            })
+        };
+
+        let label = format!(
+            "{} {} remotes into {}...",
+            "Cloning".green(),
+            remotes.len(),
+            mold_dir.display().to_string().yellow()
+        );
+        let progress = Progress::new(
+            format!("{} {} remotes", "Cloning".green(), remotes.len()),
+            false,
+        );
+        // every failure is reported on its own by the caller, so this is only about the summary
+        let _ = with_spinner(label, progress, |_| {
+            thread::scope(|scope| {
+                for _ in 0..jobs.clamp(1, remotes.len().max(1)) {
+                    scope.spawn(|| loop {
+                        let idx = next.fetch_add(1, Ordering::SeqCst);
+                        let (remote, commit) = match remotes.get(idx) {
+                            Some(entry) => entry,
+                            None => break,
+                        };
+                        let result = clone(remote, commit.as_deref());
+                        results.lock().unwrap_or_else(|x| x.into_inner())[idx] = Some(result);
+                    });
+                }
+            });
+
+            let results = results.lock().unwrap_or_else(|x| x.into_inner());
+            let failed = results.iter().filter(|x| matches!(x, Some(Err(_)))).count();
+            match failed {
+                0 => Ok(()),
+                _ => Err(failure::format_err!(
+                    "{} of {} remotes couldn't be cloned",
+                    failed,
+                    remotes.len()
+                )),
+            }
+        });
+
+        results
+            .into_inner()
+            .unwrap_or_else(|x| x.into_inner())
+            .into_iter()
+            .map(|x| x.expect("every remote gets a result"))
+            .collect()
     }
 
     /// Parse a string into an Remote
@@ -262,12 +647,12 @@ impl Remote {
     }
 }
 
-impl ToString for Remote {
-    fn to_string(&self) -> String {
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if let Some(file) = &self.file {
-            format!("{}#{}/{}", self.url, self.ref_, file.display())
+            write!(f, "{}#{}/{}", self.url, self.ref_, file.display())
         } else {
-            format!("{}#{}", self.url, self.ref_)
+            write!(f, "{}#{}", self.url, self.ref_)
         }
     }
 }
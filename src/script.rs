@@ -0,0 +1,193 @@
+//! Rendering of recipes as standalone shell scripts for `--emit-script`
+
+use failure::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A shell that scripts can be emitted for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Posix,
+    PowerShell,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sh" | "posix" => Ok(Shell::Posix),
+            "powershell" | "pwsh" => Ok(Shell::PowerShell),
+            _ => Err(failure::format_err!(
+                "Unknown script shell {}; expected sh or powershell",
+                s
+            )),
+        }
+    }
+}
+
+/// One fully expanded recipe
+pub struct Step {
+    /// Name of the recipe
+    pub name: String,
+
+    /// Directory to run the commands in, if any
+    pub dir: Option<PathBuf>,
+
+    /// Variables whose values differ from the script's global ones
+    pub vars: super::VarMap,
+
+    /// Variables to remove from the environment
+    pub unset: Vec<String>,
+
+    /// File mode creation mask for the commands
+    pub umask: Option<u32>,
+
+    /// $MOLD_STATE, which has to be created first
+    pub state_dir: PathBuf,
+
+    /// Commands to run, already split into arguments
+    pub commands: Vec<Vec<String>>,
+}
+
+/// Check whether both kinds of script can set a variable by this name
+///
+/// Moldfiles allow names like `my-var` that shells don't, so those can't be translated.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|x| x.is_ascii_alphabetic() || x == '_')
+        && chars.all(|x| x.is_ascii_alphanumeric() || x == '_')
+}
+
+/// Render a script that sets `globals` and then runs every step in order
+///
+/// `header` is written as a comment at the top of the script.
+pub fn render(
+    shell: Shell,
+    header: &str,
+    globals: &super::VarMap,
+    steps: &[Step],
+) -> Result<String, Error> {
+    match shell {
+        Shell::Posix => Ok(render_posix(header, globals, steps)),
+        Shell::PowerShell => render_powershell(header, globals, steps),
+    }
+}
+
+fn render_posix(header: &str, globals: &super::VarMap, steps: &[Step]) -> String {
+    let quote = |x: &str| shell_words::quote(x).into_owned();
+
+    let mut out = String::from("#!/bin/sh\n");
+    out.push_str(&format!("# {}\n", header));
+    out.push_str("set -eu\n");
+    out.push_str("if (set -o pipefail) 2>/dev/null; then set -o pipefail; fi\n\n");
+
+    for (key, value) in globals {
+        out.push_str(&format!("export {}={}\n", key, quote(value)));
+    }
+
+    for (idx, step) in steps.iter().enumerate() {
+        out.push_str(&format!("\n# {}/{}: {}\n", idx + 1, steps.len(), step.name));
+
+        // each step runs in a subshell so that its directory and variables don't leak
+        out.push_str("(\n");
+        for (key, value) in &step.vars {
+            out.push_str(&format!("  export {}={}\n", key, quote(value)));
+        }
+        for key in &step.unset {
+            out.push_str(&format!("  unset {}\n", key));
+        }
+        if let Some(mask) = step.umask {
+            out.push_str(&format!("  umask {:03o}\n", mask));
+        }
+        out.push_str(&format!(
+            "  mkdir -p {}\n",
+            quote(&step.state_dir.to_string_lossy())
+        ));
+        if let Some(dir) = &step.dir {
+            out.push_str(&format!("  cd {}\n", quote(&dir.to_string_lossy())));
+        }
+        for args in &step.commands {
+            let command = shell_words::join(args);
+            out.push_str(&format!(
+                "  echo {}\n",
+                quote(&format!("mold {} $ {}", step.name, command))
+            ));
+            out.push_str(&format!("  {}\n", command));
+        }
+        out.push_str(")\n");
+    }
+
+    out
+}
+
+fn render_powershell(
+    header: &str,
+    globals: &super::VarMap,
+    steps: &[Step],
+) -> Result<String, Error> {
+    let quote = |x: &str| format!("'{}'", x.replace('\'', "''"));
+
+    let mut out = format!("# {}\n", header);
+    out.push_str("$ErrorActionPreference = 'Stop'\n\n");
+
+    for (key, value) in globals {
+        out.push_str(&format!("$env:{} = {}\n", key, quote(value)));
+    }
+
+    for (idx, step) in steps.iter().enumerate() {
+        if step.umask.is_some() {
+            return Err(failure::format_err!(
+                "Recipe {} sets a umask, which PowerShell scripts can't express",
+                step.name
+            ));
+        }
+
+        out.push_str(&format!("\n# {}/{}: {}\n", idx + 1, steps.len(), step.name));
+        for (key, value) in &step.vars {
+            out.push_str(&format!("$env:{} = {}\n", key, quote(value)));
+        }
+        for key in &step.unset {
+            out.push_str(&format!("$unset_{} = $env:{}\n", key, key));
+            out.push_str(&format!(
+                "Remove-Item Env:{} -ErrorAction SilentlyContinue\n",
+                key
+            ));
+        }
+        out.push_str(&format!(
+            "New-Item -ItemType Directory -Force -Path {} | Out-Null\n",
+            quote(&step.state_dir.to_string_lossy())
+        ));
+
+        let dir = step.dir.as_ref().map(|x| x.to_string_lossy().into_owned());
+        out.push_str(&format!(
+            "Push-Location -LiteralPath {}\n",
+            quote(dir.as_deref().unwrap_or("."))
+        ));
+        for args in &step.commands {
+            let command: Vec<_> = args.iter().map(|x| quote(x)).collect();
+            out.push_str(&format!(
+                "Write-Output {}\n",
+                quote(&format!("mold {} $ {}", step.name, shell_words::join(args)))
+            ));
+            out.push_str(&format!("& {}\n", command.join(" ")));
+            out.push_str("if ($LASTEXITCODE -ne 0) { exit $LASTEXITCODE }\n");
+        }
+        out.push_str("Pop-Location\n");
+
+        // put back anything this step changed so that it doesn't leak into the next one
+        for key in step.vars.keys() {
+            match globals.get(key) {
+                Some(value) => out.push_str(&format!("$env:{} = {}\n", key, quote(value))),
+                None => out.push_str(&format!("Remove-Item Env:{}\n", key)),
+            }
+        }
+        for key in &step.unset {
+            out.push_str(&format!("$env:{} = $unset_{}\n", key, key));
+        }
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,178 @@
+//! Defaults for command-line options that come from `settings` blocks
+//!
+//! A setting can come from four places. In order of precedence, they are the command line, the
+//! root moldfile's `settings` block, the user's config file, and the built-in default.
+
+use super::lang;
+use colored::*;
+use failure::Error;
+use indexmap::IndexMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Every known setting along with its built-in default
+const DEFAULTS: &[(&str, &str)] = &[
+    ("jobs", "1"),
+    ("keep_going", "false"),
+    ("prefix_output", "false"),
+];
+
+/// Setting values by name, as written in a `settings` block
+pub type Values = IndexMap<String, String>;
+
+/// Where the effective value of a setting came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    User(PathBuf),
+    Project(PathBuf),
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::User(path) => write!(f, "user config {}", path.display()),
+            Source::Project(path) => write!(f, "moldfile {}", path.display()),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// Make sure that a setting exists and that its value makes sense for it
+pub fn check(key: &str, value: &str) -> Result<(), Error> {
+    let valid = match key {
+        "jobs" => value.parse::<usize>().is_ok_and(|x| x > 0),
+        "keep_going" | "prefix_output" => value == "true" || value == "false",
+        _ => {
+            let known: Vec<_> = DEFAULTS.iter().map(|(key, _)| *key).collect();
+            return Err(failure::format_err!(
+                "Unknown setting {}; expected one of {}",
+                key.red(),
+                known.join(", ")
+            ));
+        }
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(failure::format_err!(
+            "Invalid value {} for setting {}",
+            value.red(),
+            key.red()
+        ))
+    }
+}
+
+/// Find the user's config file: $MOLD_CONFIG, or `mold/config` in the user's config directory
+pub fn user_config_path() -> Option<PathBuf> {
+    match std::env::var_os("MOLD_CONFIG") {
+        Some(path) => Some(path.into()),
+        None => dirs_next::config_dir().map(|x| x.join("mold").join("config")),
+    }
+}
+
+/// Load the settings from the user's config file, if there is one
+pub fn load_user() -> Result<Option<(PathBuf, Values)>, Error> {
+    let path = match user_config_path() {
+        Some(path) if path.is_file() => path,
+        _ => return Ok(None),
+    };
+
+    let code = fs::read_to_string(&path)?;
+    let values = lang::parse_settings(&code)
+        .and_then(|values| {
+            for (key, value) in &values {
+                check(key, value)?;
+            }
+            Ok(values)
+        })
+        .map_err(|err| {
+            failure::format_err!(
+                "Couldn't load config {}: {}",
+                path.display().to_string().red(),
+                err
+            )
+        })?;
+
+    Ok(Some((path, values)))
+}
+
+/// The effective value of every setting
+pub struct Settings {
+    values: IndexMap<&'static str, (String, Source)>,
+}
+
+impl Settings {
+    /// Resolve every setting from the command line, the root moldfile, and the user's config
+    ///
+    /// Command-line values are `None` when the option wasn't given at all.
+    pub fn resolve(
+        cli: &[(&str, Option<String>)],
+        project: (&Path, &Values),
+        user: Option<(PathBuf, Values)>,
+    ) -> Result<Settings, Error> {
+        let mut values = IndexMap::new();
+
+        for (key, default) in DEFAULTS {
+            let from_cli = cli
+                .iter()
+                .find(|(name, _)| name == key)
+                .and_then(|(_, value)| value.clone());
+            let from_user = user
+                .as_ref()
+                .and_then(|(path, values)| Some((path, values.get(*key)?)));
+
+            let value = if let Some(value) = from_cli {
+                check(key, &value)?;
+                (value, Source::CommandLine)
+            } else if let Some(value) = project.1.get(*key) {
+                (value.clone(), Source::Project(project.0.to_path_buf()))
+            } else if let Some((path, value)) = from_user {
+                (value.clone(), Source::User(path.clone()))
+            } else {
+                (default.to_string(), Source::Default)
+            };
+
+            values.insert(*key, value);
+        }
+
+        Ok(Settings { values })
+    }
+
+    /// Look up the effective value of a setting
+    pub fn get(&self, key: &str) -> &str {
+        &self.values[key].0
+    }
+
+    /// How many recipes may run at once
+    pub fn jobs(&self) -> usize {
+        self.get("jobs").parse().unwrap_or(1)
+    }
+
+    /// Whether unrelated recipes keep running after one fails
+    pub fn keep_going(&self) -> bool {
+        self.get("keep_going") == "true"
+    }
+
+    /// Whether output is labeled with the recipe that printed it, even when running one at a time
+    pub fn prefix_output(&self) -> bool {
+        self.get("prefix_output") == "true"
+    }
+
+    /// Print every setting with its effective value and where that value came from
+    pub fn print(&self) {
+        for (key, (value, source)) in &self.values {
+            println!(
+                "{:>16} {} {}",
+                key.cyan(),
+                value,
+                format!("({})", source).white()
+            );
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Shell functions for recipes, for `--shell-init`
+//!
+//! The emitted code defines a function per recipe of whichever project the shell is in, so that
+//! `build` runs `mold build`. The list of recipes comes from `mold --list-functions` and is only
+//! refreshed when the working directory changes; the functions themselves call back into mold, so
+//! they always run the current moldfile.
+
+use colored::*;
+use failure::Error;
+
+const BASH: &str = r#"# Shell functions for mold recipes
+#
+# Load them in every new shell by adding this to ~/.bashrc:
+#   eval "$(mold --shell-init bash)"
+#
+# Inside a project, every recipe can be run by its name alone. Recipes named like an existing
+# command get a mold- prefix instead, and a moldfile can limit which recipes get functions at all
+# with `shell_functions ["build", "test"]`.
+
+_mold_functions=()
+_mold_functions_dir=
+
+_mold_refresh_functions() {
+    [[ "$PWD" == "$_mold_functions_dir" ]] && return
+    _mold_functions_dir="$PWD"
+
+    local name fn
+    for fn in "${_mold_functions[@]}"; do
+        unset -f "$fn"
+        complete -r "$fn" 2>/dev/null
+    done
+    _mold_functions=()
+
+    while IFS= read -r name; do
+        [[ -z "$name" ]] && continue
+        fn="$name"
+        if type -t -- "$fn" >/dev/null; then
+            fn="mold-$name"
+        fi
+        eval "$fn() { command mold $(printf '%q' "$name") \"\$@\"; }"
+        _mold_functions+=("$fn")
+
+        # the options are the same as mold's own, when its completion is loaded
+        if declare -F _mold >/dev/null; then
+            complete -F _mold "$fn"
+        fi
+    done < <(command mold --offline --list-functions 2>/dev/null)
+}
+
+PROMPT_COMMAND="_mold_refresh_functions${PROMPT_COMMAND:+; $PROMPT_COMMAND}"
+"#;
+
+const ZSH: &str = r#"# Shell functions for mold recipes
+#
+# Load them in every new shell by adding this to ~/.zshrc:
+#   eval "$(mold --shell-init zsh)"
+#
+# Inside a project, every recipe can be run by its name alone. Recipes named like an existing
+# command get a mold- prefix instead, and a moldfile can limit which recipes get functions at all
+# with `shell_functions ["build", "test"]`.
+
+typeset -ga _mold_functions
+typeset -g _mold_functions_dir=
+
+_mold_refresh_functions() {
+    [[ "$PWD" == "$_mold_functions_dir" ]] && return
+    _mold_functions_dir="$PWD"
+
+    local name fn
+    for fn in $_mold_functions; do
+        unfunction "$fn" 2>/dev/null
+    done
+    _mold_functions=()
+
+    for name in ${(f)"$(command mold --offline --list-functions 2>/dev/null)"}; do
+        fn="$name"
+        if whence -- "$fn" >/dev/null; then
+            fn="mold-$name"
+        fi
+        eval "function ${(q)fn} { command mold ${(q)name} \"\$@\" }"
+        _mold_functions+=("$fn")
+
+        # the options are the same as mold's own, when its completion is installed
+        if (( $+functions[compdef] && $+functions[_mold] )); then
+            compdef _mold "$fn"
+        fi
+    done
+}
+
+autoload -Uz add-zsh-hook
+add-zsh-hook precmd _mold_refresh_functions
+"#;
+
+/// Render the shell integration code for bash or zsh
+pub fn script(shell: &str) -> Result<&'static str, Error> {
+    match shell {
+        "bash" => Ok(BASH),
+        "zsh" => Ok(ZSH),
+        _ => Err(failure::format_err!(
+            "Unknown shell {}; expected bash or zsh",
+            shell.red()
+        )),
+    }
+}
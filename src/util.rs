@@ -1,6 +1,15 @@
+use colored::*;
+use failure::Error;
 use std::collections::hash_map::DefaultHasher;
+use std::ffi::OsStr;
+use std::ffi::OsString;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 pub fn hash_url_ref(url: &str, ref_: &str) -> String {
     hash_string(&format!("{}@{}", url, ref_))
@@ -11,3 +20,500 @@ pub fn hash_string(string: &str) -> String {
     string.hash(&mut hasher);
     format!("{:016x}", hasher.finish())
 }
+
+/// Rewrite Windows-style %VAR% references into ${VAR} references
+///
+/// A doubled `%%` is an escaped literal percent sign, and any `%` that doesn't start a complete
+/// reference is passed through untouched.
+pub fn convert_windows_vars(val: &str) -> String {
+    let mut new = String::with_capacity(val.len());
+    let mut rest = val;
+
+    while let Some(idx) = rest.find('%') {
+        new.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        if let Some(after) = rest.strip_prefix('%') {
+            new.push('%');
+            rest = after;
+            continue;
+        }
+
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+
+        if len > 0 && rest[len..].starts_with('%') {
+            new.push_str(&format!("${{{}}}", &rest[..len]));
+            rest = &rest[len + 1..];
+        } else {
+            new.push('%');
+        }
+    }
+
+    new.push_str(rest);
+    new
+}
+
+/// Locate the program that will be executed for a command's argv[0]
+///
+/// Programs containing a path separator are resolved relative to `work_dir`, while bare names are
+/// searched for on `path_var` (falling back to the current PATH), just like the OS would do when
+/// spawning them.
+pub fn resolve_program<S: AsRef<OsStr>>(
+    program: S,
+    path_var: Option<&str>,
+    work_dir: Option<&Path>,
+) -> Option<PathBuf> {
+    let program = program.as_ref();
+    let cwd = match work_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => std::env::current_dir().ok()?,
+    };
+
+    let name = program.to_string_lossy();
+    if name.contains('/') || name.contains(std::path::MAIN_SEPARATOR) {
+        let path = cwd.join(program);
+        return if path.is_file() { Some(path) } else { None };
+    }
+
+    let path_var = path_var
+        .map(ToString::to_string)
+        .or_else(|| std::env::var("PATH").ok())?;
+    which::which_in(program, Some(path_var), cwd).ok()
+}
+
+/// Find the paths that can't be held in a string without losing some of their bytes
+///
+/// Longer paths come first, so that a path inside another one is restored as a whole.
+pub fn lossy_paths<'a, I: IntoIterator<Item = &'a Path>>(paths: I) -> Vec<PathBuf> {
+    let mut lossy: Vec<PathBuf> = paths
+        .into_iter()
+        .filter(|x| x.to_str().is_none())
+        .map(Path::to_path_buf)
+        .collect();
+    lossy.sort_by_key(|x| std::cmp::Reverse(x.as_os_str().len()));
+    lossy.dedup();
+    lossy
+}
+
+/// Put back the real bytes of paths that were converted lossily on their way into a string
+///
+/// Wherever the lossy form of one of `paths` shows up in `value`, the path itself is used instead.
+/// Only Unix lets an OsString hold arbitrary bytes, so elsewhere `value` is used as it is.
+#[cfg(unix)]
+pub fn restore_paths(value: &str, paths: &[PathBuf]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::ffi::OsStringExt;
+
+    let mut bytes = value.as_bytes().to_vec();
+    for path in paths {
+        let lossy = path.to_string_lossy();
+        let (from, to) = (lossy.as_bytes(), path.as_os_str().as_bytes());
+        let mut restored = Vec::with_capacity(bytes.len());
+        let mut rest = &bytes[..];
+        while let Some(idx) = rest.windows(from.len()).position(|x| x == from) {
+            restored.extend_from_slice(&rest[..idx]);
+            restored.extend_from_slice(to);
+            rest = &rest[idx + from.len()..];
+        }
+        restored.extend_from_slice(rest);
+        bytes = restored;
+    }
+    OsString::from_vec(bytes)
+}
+
+/// Put back the real bytes of paths that were converted lossily on their way into a string
+#[cfg(not(unix))]
+pub fn restore_paths(value: &str, _paths: &[PathBuf]) -> OsString {
+    value.into()
+}
+
+/// Replace a file's contents by writing a temporary file next to it and renaming it into place
+///
+/// Anything reading the file sees either the old contents or the new ones, never a partial write.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    // the pid keeps concurrent writers from clobbering each other's temporary files
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.{}.mold-tmp", name, std::process::id()));
+
+    std::fs::write(&tmp, contents)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|err| {
+            let _ = std::fs::remove_file(&tmp);
+            failure::format_err!(
+                "Couldn't write {}: {}",
+                path.display().to_string().red(),
+                err
+            )
+        })
+}
+
+/// Whether a directory is inside a git work tree
+pub fn in_git_repo(dir: &Path) -> bool {
+    git2::Repository::discover(dir)
+        .map(|repo| !repo.is_bare())
+        .unwrap_or(false)
+}
+
+/// Format an error along with all of its underlying causes
+///
+/// This makes sure that the original message from something like libgit2 isn't hidden behind a
+/// more generic context message.
+pub fn error_chain(err: &Error) -> String {
+    err.iter_chain()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+/// Count the single-character edits needed to turn one string into another
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+
+    prev[b.len()]
+}
+
+/// Find the closest candidate to `name`, if any is close enough to be a likely typo
+pub fn suggest<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|x| (edit_distance(name, x), x))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, x)| x)
+}
+
+/// Check whether a string can be used as the name of a variable
+pub fn is_var_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_')
+}
+
+/// Split a `NAME=value` assignment from the command line into its name and value
+pub fn split_assignment(source: &str) -> Option<(&str, &str)> {
+    source.split_once('=').filter(|(name, _)| is_var_name(name))
+}
+
+/// Check whether a variable's name suggests that its value shouldn't be printed
+pub fn is_secret(name: &str) -> bool {
+    let name = name.to_uppercase();
+    [
+        "SECRET",
+        "TOKEN",
+        "PASSWORD",
+        "PASSWD",
+        "CREDENTIAL",
+        "AUTH",
+        "_KEY",
+        "APIKEY",
+    ]
+    .iter()
+    .any(|x| name.contains(x))
+}
+
+/// Set by `--non-interactive`; checked alongside $MOLD_NON_INTERACTIVE
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// A stream that interactive output or input would go through
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+/// Turn off every interactive feature for the rest of the process
+pub fn set_non_interactive(value: bool) {
+    NON_INTERACTIVE.store(value, Ordering::Relaxed);
+}
+
+/// Whether interactive behavior (spinners, prompts, redrawn progress) is allowed on a stream
+///
+/// This is the only place that should decide it: `--non-interactive` or a non-empty
+/// $MOLD_NON_INTERACTIVE other than `0` force plain line-oriented behavior, and otherwise the
+/// stream has to be a terminal.
+pub fn is_interactive(stream: Stream) -> bool {
+    if NON_INTERACTIVE.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    match std::env::var("MOLD_NON_INTERACTIVE") {
+        Ok(value) if !value.is_empty() && value != "0" => return false,
+        _ => {}
+    }
+
+    is_tty(stream)
+}
+
+#[cfg(feature = "cli")]
+fn is_tty(stream: Stream) -> bool {
+    atty::is(match stream {
+        Stream::Stdin => atty::Stream::Stdin,
+        Stream::Stdout => atty::Stream::Stdout,
+        Stream::Stderr => atty::Stream::Stderr,
+    })
+}
+
+/// Without the CLI there's nobody to interact with
+#[cfg(not(feature = "cli"))]
+fn is_tty(_stream: Stream) -> bool {
+    false
+}
+
+/// Print a prompt to stderr and read a line from stdin, optionally without echoing it
+pub fn prompt(message: &str, secret: bool) -> Result<String, Error> {
+    use std::io::Write;
+
+    eprint!("{}", message);
+    std::io::stderr().flush()?;
+
+    let echo = if secret { disable_echo() } else { None };
+    let mut line = String::new();
+    let res = std::io::stdin().read_line(&mut line);
+
+    // the terminal still echoes the newline, so there's no need to print one
+    if let Some(echo) = echo {
+        restore_echo(echo);
+    }
+
+    res?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Terminal settings from before echo was first turned off, for putting back on Ctrl-C
+#[cfg(unix)]
+static SAVED_TERM: std::sync::OnceLock<libc::termios> = std::sync::OnceLock::new();
+
+/// Put back the terminal settings and die of the signal like we would have without a handler
+#[cfg(unix)]
+extern "C" fn restore_and_reraise(signal: libc::c_int) {
+    unsafe {
+        if let Some(term) = SAVED_TERM.get() {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, term);
+        }
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+/// Stop the terminal on stdin from echoing input, returning its previous settings
+///
+/// Until `restore_echo`, Ctrl-C puts the old settings back before exiting.
+#[cfg(unix)]
+fn disable_echo() -> Option<(libc::termios, libc::sighandler_t)> {
+    unsafe {
+        let mut term = std::mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut term) != 0 {
+            return None;
+        }
+
+        let old = term;
+        let _ = SAVED_TERM.set(old);
+        let handler = libc::signal(
+            libc::SIGINT,
+            restore_and_reraise as *const () as libc::sighandler_t,
+        );
+
+        term.c_lflag &= !libc::ECHO;
+        term.c_lflag |= libc::ECHONL;
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) != 0 {
+            libc::signal(libc::SIGINT, handler);
+            return None;
+        }
+
+        Some((old, handler))
+    }
+}
+
+/// Put back the terminal settings and Ctrl-C handling from `disable_echo`
+#[cfg(unix)]
+fn restore_echo((term, handler): (libc::termios, libc::sighandler_t)) {
+    unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+/// Hiding input isn't supported here, so secrets are echoed like anything else
+#[cfg(not(unix))]
+fn disable_echo() -> Option<()> {
+    warn("input can't be hidden on this platform");
+    None
+}
+
+#[cfg(not(unix))]
+fn restore_echo(_: ()) {}
+
+/// Print a warning to stderr
+pub fn warn(message: &str) {
+    eprintln!("{:>12} {}", "Warning".yellow(), message);
+}
+
+/// Create a directory that only the current user can get into, or check that an existing one is
+///
+/// Whatever is in the directory gets run, so one that somebody else owns, or that anyone else can
+/// write to, is refused rather than used.
+#[cfg(unix)]
+pub fn private_dir(dir: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::DirBuilderExt;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let fail = |err: std::io::Error| {
+        failure::format_err!(
+            "Couldn't create directory {}: {}",
+            dir.display().to_string().red(),
+            err
+        )
+    };
+
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        // the umask could have taken bits away, but never added any
+        Ok(()) => {
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700)).map_err(fail)?
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(err) => return Err(fail(err)),
+    }
+
+    let meta = std::fs::symlink_metadata(dir).map_err(fail)?;
+    let owner = unsafe { libc::geteuid() };
+    if !meta.is_dir() || meta.uid() != owner || meta.mode() & 0o777 != 0o700 {
+        return Err(failure::format_err!(
+            "{} isn't a directory that only you can use, so nothing will be run from it",
+            dir.display().to_string().red()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Create a directory for the current user; there are no other users' files to worry about here
+#[cfg(not(unix))]
+pub fn private_dir(dir: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(dir).map_err(|err| {
+        failure::format_err!(
+            "Couldn't create directory {}: {}",
+            dir.display().to_string().red(),
+            err
+        )
+    })
+}
+
+/// Check whether executables written to `dir` can be run, which they can't on a `noexec` mount
+///
+/// This writes and runs a tiny script, creating `dir` if needed. Directories under
+/// $MOLD_ASSUME_NOEXEC always fail the check, so that tests can pretend to be on such a mount.
+#[cfg(unix)]
+pub fn can_exec(dir: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(noexec) = std::env::var_os("MOLD_ASSUME_NOEXEC") {
+        if dir.starts_with(noexec) {
+            return false;
+        }
+    }
+
+    let probe = dir.join(format!(".exec-probe-{}", std::process::id()));
+    let written = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(&probe, "#!/bin/sh\nexit 0\n"))
+        .and_then(|_| std::fs::set_permissions(&probe, std::fs::Permissions::from_mode(0o755)));
+
+    let ok = written.is_ok()
+        && std::process::Command::new(&probe)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|x| x.success());
+    let _ = std::fs::remove_file(&probe);
+    ok
+}
+
+/// Check whether executables written to `dir` can be run; only Unix has `noexec` mounts
+#[cfg(not(unix))]
+pub fn can_exec(_dir: &Path) -> bool {
+    true
+}
+
+/// List the paths with staged or unstaged changes in the repository containing a directory
+///
+/// Untracked and ignored files don't count as changes.
+pub fn dirty_paths(dir: &Path) -> Result<Vec<String>, Error> {
+    let repo = git2::Repository::discover(dir).map_err(|_| {
+        failure::format_err!(
+            "{} isn't inside a git repository",
+            dir.display().to_string().red()
+        )
+    })?;
+
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+
+    let statuses = repo.statuses(Some(&mut options))?;
+    Ok(statuses
+        .iter()
+        .filter(|x| x.status() != git2::Status::CURRENT)
+        .map(|x| String::from_utf8_lossy(x.path_bytes()).into_owned())
+        .collect())
+}
+
+/// Parse a duration like `90s`, `5m`, or `1h30m`
+pub fn parse_duration(source: &str) -> Result<Duration, Error> {
+    let invalid = || failure::format_err!("Invalid duration {}", source.red());
+
+    let mut total = 0;
+    let mut digits = String::new();
+    for ch in source.trim().chars() {
+        if ch.is_ascii_digit() {
+            digits.push(ch);
+            continue;
+        }
+
+        let scale = match ch {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        total = value
+            .checked_mul(scale)
+            .and_then(|x| x.checked_add(total))
+            .ok_or_else(invalid)?;
+        digits.clear();
+    }
+
+    // every number needs a unit, and there needs to be at least one
+    if !digits.is_empty() || source.trim().is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(Duration::from_secs(total))
+}
+
+/// Format a duration roughly, like `45s`, `4m`, or `1h5m`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let mins = (secs + 30) / 60;
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if mins < 60 {
+        format!("{}m", mins)
+    } else {
+        format!("{}h{}m", mins / 60, mins % 60)
+    }
+}
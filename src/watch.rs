@@ -0,0 +1,260 @@
+//! Noticing when the files that recipes `watch` change, for `--watch`
+//!
+//! The same snapshots tell whether a recipe's `outputs` are newer than its `inputs`.
+//!
+//! Changes are found by polling: every so often each pattern is walked again and the modification
+//! time and size of every matching file are compared with the last look. That's slower to notice
+//! than an OS-level watcher, but it behaves the same everywhere and needs nothing from the system.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+
+/// Directories that are never walked into, however broad a pattern is
+const SKIPPED_DIRS: &[&str] = &[".git", ".mold"];
+
+/// What's known about every watched file at one point in time
+pub type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Glob patterns to watch, each relative to its own base directory
+#[derive(Clone, Debug, Default)]
+pub struct Watcher {
+    patterns: Vec<(PathBuf, String)>,
+
+    /// Whether files that git ignores are left out, which keeps build output from re-triggering
+    skip_ignored: bool,
+}
+
+impl Watcher {
+    /// Watch the files under `base` that match `pattern`
+    pub fn add(&mut self, base: &Path, pattern: &str) {
+        let entry = (base.to_path_buf(), pattern.to_string());
+        if !self.patterns.contains(&entry) {
+            self.patterns.push(entry);
+        }
+    }
+
+    /// Leave out files that git ignores
+    pub fn skip_ignored(&mut self) {
+        self.skip_ignored = true;
+    }
+
+    /// Whether there's anything to watch at all
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Every pattern along with the directory it's relative to
+    pub fn patterns(&self) -> &[(PathBuf, String)] {
+        &self.patterns
+    }
+
+    /// Look at every watched file as it is right now
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        for (base, pattern) in &self.patterns {
+            // only walk from the deepest directory that the pattern names outright
+            let parts: Vec<&str> = pattern
+                .split('/')
+                .filter(|x| !x.is_empty() && *x != ".")
+                .collect();
+            let fixed = parts.iter().take_while(|x| !is_wildcard(x)).count();
+            let start = parts[..fixed]
+                .iter()
+                .fold(base.clone(), |dir, x| dir.join(x));
+
+            let repo = match self.skip_ignored {
+                true => git2::Repository::discover(base).ok(),
+                false => None,
+            };
+
+            if fixed == parts.len() {
+                record(&mut snapshot, &start);
+            } else {
+                walk(&start, &parts[fixed..], repo.as_ref(), &mut snapshot);
+            }
+        }
+        snapshot
+    }
+}
+
+/// Find the files that were added, removed, or modified between two snapshots
+pub fn changes(before: &Snapshot, after: &Snapshot) -> Vec<PathBuf> {
+    let mut changed: Vec<_> = after
+        .iter()
+        .filter(|(path, stat)| before.get(*path) != Some(stat))
+        .map(|(path, _)| path.clone())
+        .collect();
+    changed.extend(before.keys().filter(|x| !after.contains_key(*x)).cloned());
+    changed.sort();
+    changed
+}
+
+/// Record a single file in a snapshot, if it exists
+fn record(snapshot: &mut Snapshot, path: &Path) {
+    if let Ok(meta) = fs::metadata(path) {
+        if meta.is_file() {
+            snapshot.insert(path.to_path_buf(), (meta.modified().ok(), meta.len()));
+        }
+    }
+}
+
+/// Record every file under `dir` whose path relative to it matches the rest of a pattern
+///
+/// When there's a repository, whatever it ignores is skipped.
+fn walk(dir: &Path, pattern: &[&str], repo: Option<&git2::Repository>, snapshot: &mut Snapshot) {
+    let mut pending = vec![(dir.to_path_buf(), String::new())];
+    while let Some((dir, relative)) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if is_ignored(repo, &entry.path()) {
+                continue;
+            }
+
+            let relative = if relative.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", relative, name)
+            };
+
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => {
+                    if !SKIPPED_DIRS.contains(&name.as_str()) {
+                        pending.push((entry.path(), relative));
+                    }
+                }
+                Ok(_) => {
+                    let parts: Vec<&str> = relative.split('/').collect();
+                    if glob_parts(pattern, &parts) {
+                        record(snapshot, &entry.path());
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+/// Whether a repository ignores a path
+fn is_ignored(repo: Option<&git2::Repository>, path: &Path) -> bool {
+    let repo = match repo {
+        Some(repo) => repo,
+        None => return false,
+    };
+
+    // git wants paths relative to the top of the work tree
+    let relative = repo
+        .workdir()
+        .and_then(|top| fs::canonicalize(top).ok())
+        .and_then(|top| {
+            Some(
+                fs::canonicalize(path)
+                    .ok()?
+                    .strip_prefix(top)
+                    .ok()?
+                    .to_path_buf(),
+            )
+        });
+    match relative {
+        Some(relative) => repo.is_path_ignored(relative).unwrap_or(false),
+        None => false,
+    }
+}
+
+/// Whether a path component of a pattern has any wildcards in it
+fn is_wildcard(part: &str) -> bool {
+    part.contains(['*', '?'])
+}
+
+/// Check whether a relative path matches a glob pattern
+///
+/// `*` and `?` match within a single path component, and `**` matches any number of whole
+/// components, including none at all.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern
+        .split('/')
+        .filter(|x| !x.is_empty() && *x != ".")
+        .collect();
+    let path: Vec<&str> = path.split('/').filter(|x| !x.is_empty()).collect();
+    glob_parts(&pattern, &path)
+}
+
+fn glob_parts(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| glob_parts(rest, &path[i..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, tail)) => {
+                glob_component(first.as_bytes(), name.as_bytes()) && glob_parts(rest, tail)
+            }
+            None => false,
+        },
+    }
+}
+
+fn glob_component(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| glob_component(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && glob_component(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && glob_component(rest, &name[1..]),
+    }
+}
+
+/// Set once Ctrl-C is pressed while watching
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Turn Ctrl-C into a request to stop watching instead of an immediate exit
+///
+/// Runs are started in their own process group, so they don't see it; whoever's watching kills
+/// them on the way out instead.
+pub fn catch_interrupts() {
+    #[cfg(unix)]
+    unsafe {
+        extern "C" fn handle(_: libc::c_int) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        }
+        libc::signal(libc::SIGINT, handle as *const () as libc::sighandler_t);
+    }
+}
+
+/// Whether Ctrl-C has been pressed since `catch_interrupts`
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Start a run in its own process group, so it can be stopped along with everything it started
+pub fn spawn(mut command: process::Command) -> std::io::Result<process::Child> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    command.spawn()
+}
+
+/// Stop a run that's still going, along with everything it started
+pub fn stop(child: &mut process::Child) {
+    if let Ok(Some(_)) = child.try_wait() {
+        return;
+    }
+
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGTERM);
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+
+    let _ = child.wait();
+}
@@ -0,0 +1,178 @@
+//! Loader for the YAML moldfiles used before mold 0.7
+//!
+//! Legacy files are translated into the same statements that the parser produces for the new
+//! language, so everything after parsing behaves exactly like a regular moldfile.
+
+use super::lang::Expr;
+use super::lang::Statement;
+use super::remote::Remote;
+use failure::format_err;
+use failure::Error;
+use indexmap::IndexMap;
+use semver::VersionReq;
+use serde::Deserialize;
+
+/// Catch-all for keys that have no equivalent in the new language
+type Extra = IndexMap<String, serde_yaml::Value>;
+
+#[derive(Deserialize)]
+struct File {
+    version: String,
+
+    #[serde(default)]
+    includes: Vec<Include>,
+
+    #[serde(default)]
+    recipes: IndexMap<String, Recipe>,
+
+    #[serde(default)]
+    variables: IndexMap<String, String>,
+
+    /// Variable overrides applied when an environment is active
+    #[serde(default)]
+    environments: IndexMap<String, IndexMap<String, String>>,
+
+    #[serde(flatten)]
+    extra: Extra,
+}
+
+#[derive(Deserialize)]
+struct Include {
+    url: String,
+
+    #[serde(rename = "ref", default = "default_ref")]
+    ref_: String,
+
+    #[serde(default)]
+    prefix: String,
+
+    file: Option<String>,
+
+    #[serde(flatten)]
+    extra: Extra,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Command {
+    Shell(String),
+    Args(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct Recipe {
+    help: Option<String>,
+
+    command: Option<Command>,
+
+    script: Option<String>,
+
+    #[serde(default)]
+    deps: Vec<String>,
+
+    #[serde(alias = "work_dir")]
+    dir: Option<String>,
+
+    #[serde(flatten)]
+    extra: Extra,
+}
+
+fn default_ref() -> String {
+    "master".into()
+}
+
+/// A legacy moldfile translated into the new language
+pub struct Translation {
+    /// Statements equivalent to the original file
+    pub statements: Vec<Statement>,
+
+    /// Descriptions of everything that couldn't be translated
+    pub skipped: Vec<String>,
+}
+
+/// Given a &str of YAML, translate it into statements
+pub fn translate(code: &str) -> Result<Translation, Error> {
+    use Statement::*;
+
+    let file: File = serde_yaml::from_str(code)?;
+    let mut skipped = vec![];
+    let mut statements = vec![];
+
+    VersionReq::parse(&file.version)
+        .map_err(|err| format_err!("Couldn't parse version {}: {}", file.version, err))?;
+
+    // translated files are written in the current language, whatever version they came from
+    let current = semver::Version::parse(super::VERSION)?;
+    statements.push(Version(format!("{}.{}", current.major, current.minor)));
+    skip_extra(&mut skipped, "top-level key", &file.extra);
+
+    if !file.environments.is_empty() {
+        statements.push(Environments(file.environments.keys().cloned().collect()));
+    }
+
+    for include in file.includes {
+        skip_extra(
+            &mut skipped,
+            &format!("include {} key", include.url),
+            &include.extra,
+        );
+        let remote = Remote {
+            url: include.url,
+            ref_: include.ref_,
+            file: include.file.map(Into::into),
+        };
+        let prefix = Some(include.prefix).filter(|x| !x.is_empty());
+        statements.push(Import(remote.to_string(), prefix, false));
+    }
+
+    for (name, value) in file.variables {
+        statements.push(Var(name, value));
+    }
+
+    for (env, vars) in file.environments {
+        let body = vars
+            .into_iter()
+            .map(|(name, value)| Var(name, value))
+            .collect();
+        statements.push(IfBlock(vec![If(Expr::Atom(env), body)]));
+    }
+
+    for (name, recipe) in file.recipes {
+        skip_extra(&mut skipped, &format!("recipe {} key", name), &recipe.extra);
+
+        let mut body = vec![];
+        if let Some(help) = recipe.help {
+            body.push(Help(help));
+        }
+
+        if let Some(dir) = recipe.dir {
+            body.push(Dir(dir));
+        }
+
+        body.extend(recipe.deps.into_iter().map(|x| Require(x, vec![])));
+
+        match recipe.command {
+            Some(Command::Shell(command)) => body.push(Run(command)),
+            Some(Command::Args(args)) => body.push(RunList(args)),
+            None => {}
+        }
+
+        if let Some(script) = recipe.script {
+            body.push(RunList(vec!["sh".into(), "-c".into(), script]));
+        }
+
+        statements.push(Recipe(name, None, body));
+    }
+
+    Ok(Translation {
+        statements,
+        skipped,
+    })
+}
+
+/// Record every unrecognized key in a mapping
+fn skip_extra(skipped: &mut Vec<String>, what: &str, extra: &Extra) {
+    for key in extra.keys() {
+        skipped.push(format!("{} {}", what, key));
+    }
+}
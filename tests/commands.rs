@@ -0,0 +1,1033 @@
+mod common;
+
+use common::*;
+
+/// `printf` wraps each argument in brackets so argument boundaries are visible
+const MOLDFILE: &str = r#"
+version "0.7"
+var MESSAGE = "spaced \"and quoted\""
+
+recipe split {
+  $ "printf '<%s>' one 'two three' $MESSAGE"
+}
+
+recipe list {
+  run ["printf", "<%s>", "one", "two three", "$MESSAGE"]
+}
+
+recipe empty {
+  run []
+  run ["echo", "after"]
+}
+"#;
+
+#[test]
+fn string_commands_are_shell_split() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["split"]));
+    assert_eq!(
+        command_output(&out),
+        vec!["<one><two three><spaced><and quoted>"]
+    );
+}
+
+#[test]
+fn list_commands_keep_argument_boundaries() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["list"]));
+    assert_eq!(
+        command_output(&out),
+        vec!["<one><two three><spaced \"and quoted\">"]
+    );
+}
+
+#[test]
+fn empty_list_is_skipped() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["empty"]));
+    assert_eq!(command_output(&out), vec!["after"]);
+}
+
+#[test]
+fn explain_shows_list_commands() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["--explain", "list"]));
+    assert!(
+        out.contains("printf '<%s>' one 'two three' '$MESSAGE'"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn explain_shows_resolved_program() {
+    let project = Project::new();
+    project
+        .moldfile(
+            r#"
+            version "0.7"
+            recipe run {
+              dir "scripts"
+              $ "sh -c true"
+              $ "./build.sh"
+              $ "definitely-not-a-real-program"
+            }
+            "#,
+        )
+        .file("scripts/build.sh", "#!/bin/sh\n");
+
+    let out = success(project.run(&["--explain", "run"]));
+    let sh = which::which("sh").unwrap();
+    assert!(out.contains(&format!("⮡ {}", sh.display())), "{}", out);
+    assert!(
+        out.contains(&format!(
+            "⮡ {}",
+            project.path("scripts/./build.sh").display()
+        )),
+        "{}",
+        out
+    );
+    assert!(out.contains("⮡ not found"), "{}", out);
+}
+
+#[test]
+fn missing_relative_script_warns() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe run { $ "./missing.sh" }
+        "#,
+    );
+
+    let err = failure(project.run(&["run"]));
+    assert!(err.contains("./missing.sh does not exist in"), "{}", err);
+}
+
+#[test]
+fn missing_program_error_mentions_path() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe run { $ "definitely-not-a-real-program" }
+        "#,
+    );
+
+    let err = failure(project.run(&["run"]));
+    assert!(err.contains("was not found (not found on PATH)"), "{}", err);
+}
+
+#[test]
+fn clean_tree_guard() {
+    let project = Project::new();
+    let moldfile = "version \"0.7\"\nrecipe release { requires_clean_tree $ \"echo released\" }\n";
+    project.moldfile(moldfile);
+
+    let err = failure(project.run(&["release"]));
+    assert!(err.contains("isn't inside a git repository"), "{}", err);
+
+    git(&project.root(), &["init", "-q"]);
+    git(&project.root(), &["add", "moldfile"]);
+    git(&project.root(), &["commit", "-q", "-m", "init"]);
+
+    // untracked files don't make the tree dirty
+    project.file("notes.txt", "scratch");
+    let out = success(project.run(&["release"]));
+    assert_eq!(command_output(&out), vec!["released"]);
+
+    project.moldfile(&format!("{}# edited\n", moldfile));
+    let err = failure(project.run(&["release"]));
+    assert!(err.contains("uncommitted changes"), "{}", err);
+    assert!(err.contains("moldfile"), "{}", err);
+
+    let out = success(project.run(&["--explain", "release"]));
+    assert!(out.contains("requires clean tree"), "{}", out);
+
+    let out = success(project.run(&["--allow-dirty", "release"]));
+    assert_eq!(command_output(&out), vec!["released"]);
+}
+
+#[test]
+fn weights_are_explained() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe build { weight 4 $ "echo build" }
+        recipe fetch { weight 0 $ "echo fetch" }
+        recipe plain { $ "echo plain" }
+        "#,
+    );
+
+    let out = success(project.run(&["--explain", "build", "fetch", "plain"]));
+    assert!(out.contains("weight: 4"), "{}", out);
+    assert!(out.contains("weight: 0"), "{}", out);
+    assert_eq!(out.matches("weight:").count(), 2, "{}", out);
+
+    let out = success(project.run(&["build", "fetch"]));
+    assert_eq!(command_output(&out), vec!["build", "fetch"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn umask_applies_to_commands() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe strict { umask "077" $ "touch strict.txt" }
+        recipe open { umask "000" $ "touch open.txt" }
+        "#,
+    );
+
+    success(project.run(&["strict", "open"]));
+    let mode = |name| {
+        std::fs::metadata(project.path(name))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777
+    };
+    assert_eq!(mode("strict.txt"), 0o600);
+    assert_eq!(mode("open.txt"), 0o666);
+
+    let out = success(project.run(&["--explain", "strict"]));
+    assert!(out.contains("umask: 077"), "{}", out);
+
+    project.moldfile("version \"0.7\"\nrecipe bad { umask \"999\" $ \"true\" }\n");
+    let err = failure(project.run(&[]));
+    assert!(err.contains("Invalid umask"), "{}", err);
+}
+
+#[test]
+fn builtin_paths_with_spaces_stay_single_arguments() {
+    let project = Project::new();
+    project.file(
+        "My Projects/app/moldfile",
+        r#"
+        version "0.7"
+        recipe bare { $ "printf [%s] $MOLD_ROOT/scripts/build.sh" }
+        recipe quoted { $ "printf [%s] \"$MOLD_ROOT/a b\" x" }
+        recipe source { $ "printf [%s] ${MOLD_SOURCE}/x $MOLD_DIR" }
+        "#,
+    );
+
+    let root = project.path("My Projects/app").display().to_string();
+    let run = |recipe| {
+        let output = project
+            .mold_in("My Projects/app")
+            .arg(recipe)
+            .output()
+            .unwrap();
+        command_output(&success(output)).join("")
+    };
+
+    assert_eq!(run("bare"), format!("[{}/scripts/build.sh]", root));
+    assert_eq!(run("quoted"), format!("[{}/a b][x]", root));
+    assert_eq!(run("source"), format!("[{}/x][{}/.mold]", root, root));
+}
+
+#[test]
+fn failures_print_context() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+var GREETING = "hello"
+var API_TOKEN = "hunter2"
+
+recipe broken {
+  dir "sub"
+  $ "sh -c 'exit 3'"
+}
+"#,
+    );
+    std::fs::create_dir(project.path("sub")).unwrap();
+
+    let err = failure(project.run(&["broken"]));
+    assert!(err.contains("recipe broken"), "{}", err);
+    assert!(err.contains("sh -c 'exit 3'"), "{}", err);
+    assert!(err.contains("sub"), "{}", err);
+    assert!(err.contains("GREETING=hello"), "{}", err);
+    assert!(err.contains("API_TOKEN=****"), "{}", err);
+    assert!(!err.contains("hunter2"), "{}", err);
+
+    let err = failure(project.run(&["--no-failure-context", "broken"]));
+    assert!(!err.contains("Context"), "{}", err);
+    assert!(!err.contains("GREETING"), "{}", err);
+}
+
+#[test]
+fn recipes_get_their_own_state_dir() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+
+recipe first {
+  $ "sh -c 'echo one > $MOLD_STATE/marker'"
+}
+
+recipe second {
+  $ "sh -c 'cat $MOLD_STATE/marker 2>/dev/null || echo missing'"
+}
+
+recipe external {
+  $ "sh save.sh"
+}
+"#,
+    );
+    project.file("save.sh", "echo saved > \"$MOLD_STATE/marker\"\n");
+
+    success(project.run(&["first"]));
+    let out = success(project.run(&["second"]));
+    assert_eq!(command_output(&out), vec!["missing"]);
+
+    // scripts that the recipe runs can use it without the recipe mentioning it
+    success(project.run(&["external"]));
+    let state = project.path(".mold/state");
+    assert_eq!(std::fs::read_dir(&state).unwrap().count(), 3);
+
+    let out = success(project.run(&["--clean-state", "first"]));
+    assert!(out.contains("Deleted"), "{}", out);
+    assert_eq!(std::fs::read_dir(&state).unwrap().count(), 2);
+
+    let err = failure(project.run(&["--clean-state", "frist"]));
+    assert!(err.contains("frist"), "{}", err);
+}
+
+#[test]
+fn literal_dollars() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+var FIELD = "2"
+
+recipe escaped {
+  $ "awk 'BEGIN { split(\"a b c\", x); print x[$FIELD], \"$$\" }'"
+  run ["echo", "{{.Names}} $$1"]
+}
+
+recipe raw {
+  run raw "sh -c 'echo a b c | awk \"{ print \\$1, \\$NF }\"'"
+  run raw "echo '.k as $x | $x'"
+}
+"#,
+    );
+
+    let out = success(project.run(&["escaped"]));
+    assert_eq!(command_output(&out), vec!["b $", "{{.Names}} $1"]);
+
+    let out = success(project.run(&["raw"]));
+    assert_eq!(command_output(&out), vec!["a c", ".k as $x | $x"]);
+
+    let out = success(project.run(&["--explain", "raw"]));
+    assert_eq!(out.matches("$ raw").count(), 2, "{}", out);
+}
+
+#[test]
+fn short_form_recipes() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe clean = "echo cleaned"
+recipe tidy = "echo tidied" help "Tidy up"
+recipe all {
+  require clean
+  require tidy
+}
+"#,
+    );
+
+    let out = success(project.run(&["all"]));
+    assert_eq!(command_output(&out), vec!["cleaned", "tidied"]);
+
+    let out = success(project.run(&[]));
+    assert!(out.contains("tidy Tidy up"), "{}", out);
+
+    let out = success(project.run(&["--explain", "tidy"]));
+    assert!(out.contains("Tidy up"), "{}", out);
+    assert!(out.contains("$ echo tidied"), "{}", out);
+
+    // the short form is only for a lone command
+    project.moldfile("version \"0.7\"\nrecipe a = \"echo a\" $ \"echo b\"\n");
+    failure(project.run(&["a"]));
+}
+
+#[cfg(unix)]
+#[test]
+fn inline_scripts() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+var NAME = "world"
+
+recipe plain {
+  script """
+    for x in one two; do
+      echo "$x $NAME"
+    done
+  """
+}
+
+recipe shebang {
+  script """
+    #!/usr/bin/env sh
+    echo "from $0" | sed "s|$MOLD_DIR|DIR|"
+  """
+}
+
+recipe interpreted {
+  script """
+    echo "interpreted"
+  """
+  $ "sh $MOLD_SCRIPT"
+}
+
+recipe broken {
+  script """
+    exit 3
+  """
+}
+"#,
+    );
+
+    let out = success(project.run(&["plain"]));
+    assert_eq!(command_output(&out), vec!["one world", "two world"]);
+
+    let out = success(project.run(&["shebang"]));
+    let lines = command_output(&out);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].starts_with("from DIR/scripts/"), "{}", out);
+
+    let out = success(project.run(&["interpreted"]));
+    assert_eq!(command_output(&out), vec!["interpreted"]);
+
+    failure(project.run(&["broken"]));
+
+    // scripts are kept between runs, one per distinct body
+    let scripts = || {
+        std::fs::read_dir(project.path(".mold/scripts"))
+            .unwrap()
+            .count()
+    };
+    assert_eq!(scripts(), 4);
+    success(project.run(&["plain"]));
+    assert_eq!(scripts(), 4);
+
+    let out = success(project.run(&["--clean"]));
+    assert!(out.contains("Deleted"), "{}", out);
+    assert!(!project.path(".mold").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn changed_scripts_are_rewritten() {
+    let project = Project::new();
+    let moldfile = |word: &str| {
+        format!(
+            "version \"0.7\"\nrecipe a {{\n  script \"\"\"\n    echo {}\n  \"\"\"\n  $ \"sh $MOLD_SCRIPT\"\n  $ \"echo $MOLD_SCRIPT\"\n}}\n",
+            word
+        )
+    };
+
+    project.moldfile(&moldfile("one"));
+    let first = success(project.run(&["a"]));
+    let again = success(project.run(&["a"]));
+    let first = command_output(&first)[1];
+    assert_eq!(command_output(&again)[1], first);
+
+    project.moldfile(&moldfile("two"));
+    let out = success(project.run(&["a"]));
+    let lines = command_output(&out);
+    assert_eq!(lines[0], "two");
+    assert_ne!(lines[1], first);
+    assert!(lines[1].ends_with(".sh"), "{}", out);
+
+    // a script that was changed by hand is put back the way the moldfile has it
+    std::fs::write(lines[1], "echo tampered\n").unwrap();
+    let out = success(project.run(&["a"]));
+    assert_eq!(command_output(&out)[0], "two");
+}
+
+#[cfg(unix)]
+#[test]
+fn script_interpreters() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe counted {
+  interpreter "sh -e"
+  script """
+    echo "lines: $(wc -l < "$0" | tr -d ' ')"
+  """
+}
+
+recipe missing {
+  interpreter "no-such-interpreter"
+  script """
+    print("hi")
+  """
+}
+"#,
+    );
+
+    let out = success(project.run(&["counted"]));
+    assert_eq!(command_output(&out), vec!["lines: 1"]);
+
+    let out = success(project.run(&["--explain", "counted"]));
+    assert!(out.contains("interpreter: sh -e"), "{}", out);
+
+    let err = failure(project.run(&["missing"]));
+    assert!(
+        err.contains("no-such-interpreter not found on PATH"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn recipes_can_only_have_one_script() {
+    let project = Project::new();
+    project.moldfile(
+        "version \"0.7\"\nrecipe a {\n  script \"\"\"echo a\"\"\"\n  script \"\"\"echo b\"\"\"\n}\n",
+    );
+
+    let err = failure(project.run(&["a"]));
+    assert!(err.contains("Recipes can only have one script"), "{}", err);
+}
+
+#[cfg(unix)]
+#[test]
+fn scripts_move_off_noexec_filesystems() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe a {
+  script """
+    #!/bin/sh
+    echo "$0"
+  """
+}
+recipe b {
+  require a
+  script """
+    #!/bin/sh
+    echo "$0"
+  """
+}
+"#,
+    );
+
+    // there's no real noexec mount to test with, so pretend that .mold is on one
+    let runtime = project.path("runtime");
+    std::fs::create_dir(&runtime).unwrap();
+    let noexec = || {
+        let mut command = project.mold();
+        command
+            .env("MOLD_ASSUME_NOEXEC", project.path(".mold"))
+            .env("XDG_RUNTIME_DIR", &runtime);
+        command
+    };
+    let output = noexec().arg("b").output().unwrap();
+    let err = stderr(&output);
+    let out = success(output);
+
+    let lines = command_output(&out);
+    assert_eq!(lines.len(), 2, "{}", out);
+    assert!(
+        lines
+            .iter()
+            .all(|x| x.starts_with(&*runtime.to_string_lossy())),
+        "{}",
+        out
+    );
+    assert_eq!(err.matches("can't run scripts").count(), 1, "{}", err);
+
+    let out = success(project.run(&["a"]));
+    let mold_dir = std::fs::canonicalize(project.path(".mold")).unwrap();
+    assert!(
+        command_output(&out)[0].starts_with(&*mold_dir.to_string_lossy()),
+        "{}",
+        out
+    );
+
+    // the scripts left in the temp directory go along with .mold
+    let fallback = std::path::Path::new(&lines[0])
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    assert!(fallback.is_dir());
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(&fallback).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // a directory that others can write to isn't trusted with scripts
+        let open = std::fs::Permissions::from_mode(0o777);
+        std::fs::set_permissions(&fallback, open).unwrap();
+        let output = noexec().arg("a").output().unwrap();
+        let err = stderr(&output);
+        assert!(
+            err.contains("isn't a directory that only you can use"),
+            "{}",
+            err
+        );
+        let out = success(output);
+        assert!(!out.contains(&*fallback.to_string_lossy()), "{}", out);
+    }
+
+    success(
+        project
+            .mold()
+            .env("XDG_RUNTIME_DIR", &runtime)
+            .arg("--clean")
+            .output()
+            .unwrap(),
+    );
+    assert!(!fallback.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn timeouts_kill_slow_commands() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe whole {
+  timeout "1"
+  $ "sleep 0.6"
+  $ "sleep 0.6"
+  $ "touch whole.done"
+}
+recipe single {
+  timeout "1m" {
+    $ "sleep 0.1"
+  }
+  timeout "1s" {
+    $ "sleep 5"
+  }
+  $ "touch single.done"
+}
+"#,
+    );
+
+    // the recipe's limit covers all of its commands together
+    let err = failure(project.run(&["whole"]));
+    assert!(err.contains("Recipe whole timed out after 1s"), "{}", err);
+    assert!(!project.path("whole.done").exists());
+
+    let started = std::time::Instant::now();
+    let err = failure(project.run(&["single"]));
+    assert!(err.contains("Recipe single timed out after 1s"), "{}", err);
+    assert!(started.elapsed() < std::time::Duration::from_secs(4));
+    assert!(!project.path("single.done").exists());
+
+    let out = success(project.run(&["--explain", "single"]));
+    assert!(out.contains("sleep 5 (timeout 1s)"), "{}", out);
+}
+
+#[cfg(unix)]
+#[test]
+fn timeouts_kill_what_commands_started() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe spawner {
+  timeout "1s"
+  $ "sh -c '(sleep 2; touch $MARKER) & wait'"
+}
+recipe other { $ "true" }
+"#,
+    );
+
+    for (marker, args) in [
+        ("sequential", &["spawner"][..]),
+        ("parallel", &["-j", "2", "spawner", "other"]),
+    ] {
+        let started = std::time::Instant::now();
+        let output = project
+            .mold()
+            .env("MARKER", marker)
+            .args(args)
+            .output()
+            .unwrap();
+        let err = failure(output);
+        assert!(err.contains("Recipe spawner timed out after 1s"), "{}", err);
+        assert!(
+            started.elapsed() < std::time::Duration::from_millis(1800),
+            "{}: {:?}",
+            marker,
+            started.elapsed()
+        );
+
+        // the background process went along with the command that started it
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        assert!(!project.path(marker).exists(), "{}", marker);
+    }
+}
+
+#[test]
+fn run_any_picks_the_first_program_found() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe pick {
+  $ any ["mold-missing-tool --flag", "touch picked.txt", "touch other.txt"]
+}
+recipe none {
+  $ any ["mold-missing-tool", "mold-other-missing-tool"]
+}
+"#,
+    );
+
+    let out = success(project.run(&["pick"]));
+    assert!(
+        command_output(&out).is_empty() && out.contains("$ touch picked.txt"),
+        "{}",
+        out
+    );
+    assert!(project.path("picked.txt").exists());
+    assert!(!project.path("other.txt").exists());
+
+    let out = success(project.run(&["--explain", "pick"]));
+    assert!(out.contains("touch picked.txt (chosen)"), "{}", out);
+    assert!(!out.contains("touch other.txt (chosen)"), "{}", out);
+
+    let err = failure(project.run(&["none"]));
+    assert!(
+        err.contains("could be found: mold-missing-tool, mold-other-missing-tool"),
+        "{}",
+        err
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn up_to_date_outputs_skip_recipes() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe build {
+  inputs ["src/*.txt"]
+  outputs "out/app"
+  $ "sh -c 'mkdir -p out && cat src/*.txt > out/app && echo built >> builds.log'"
+}
+"#,
+    );
+    project.file("src/a.txt", "a\n");
+    let touch = |stamp: &str| {
+        let status = std::process::Command::new("touch")
+            .args(["-t", stamp, "src/a.txt"])
+            .current_dir(project.root())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    let builds = || {
+        std::fs::read_to_string(project.path("builds.log"))
+            .unwrap_or_default()
+            .lines()
+            .count()
+    };
+    touch("200001010000");
+
+    // missing outputs always run
+    success(project.run(&["build"]));
+    assert_eq!(builds(), 1);
+
+    let out = success(project.run(&["build"]));
+    assert!(out.contains("Skipped build (up to date)"), "{}", out);
+    assert_eq!(builds(), 1);
+
+    touch("209901010000");
+    success(project.run(&["build"]));
+    assert_eq!(builds(), 2);
+
+    touch("200001010000");
+    success(project.run(&["build"]));
+    assert_eq!(builds(), 2);
+    success(project.run(&["build", "--force"]));
+    assert_eq!(builds(), 3);
+}
+
+#[test]
+fn retries_rerun_failed_recipes() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe flaky {
+  retry 2
+  $ "sh -c 'echo try >> flaky.log; test $(wc -l < flaky.log) -ge 3'"
+  $ "touch flaky.done"
+}
+recipe once {
+  $ "sh -c 'echo try >> once.log; exit 1'"
+}
+recipe short {
+  retry 1
+  $ "sh -c 'echo try >> short.log; exit 1'"
+}
+"#,
+    );
+    let tries = |rel: &str| {
+        std::fs::read_to_string(project.path(rel))
+            .unwrap_or_default()
+            .lines()
+            .count()
+    };
+
+    let out = success(project.run(&["flaky"]));
+    assert!(out.contains("Retrying flaky (attempt 3/3)"), "{}", out);
+    assert_eq!(tries("flaky.log"), 3);
+    assert!(project.path("flaky.done").exists());
+
+    let err = failure(project.run(&["once"]));
+    assert!(err.contains("returned non-zero exit status"), "{}", err);
+    assert_eq!(tries("once.log"), 1);
+
+    failure(project.run(&["short"]));
+    assert_eq!(tries("short.log"), 2);
+}
+
+#[test]
+fn shells_run_command_strings() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe piped {
+  $ "echo hello | tr a-z A-Z > piped.txt"
+  $ ["sh", "-c", "echo listed > listed.txt"]
+}
+recipe own {
+  shell "env WHICH=recipe sh -c"
+  $ "echo $$WHICH > own.txt"
+}
+shell "sh -c"
+"#,
+    );
+
+    success(project.run(&["piped", "own"]));
+    let read = |rel: &str| std::fs::read_to_string(project.path(rel)).unwrap();
+    assert_eq!(read("piped.txt"), "HELLO\n");
+    assert_eq!(read("listed.txt"), "listed\n");
+    assert_eq!(read("own.txt"), "recipe\n");
+
+    let out = success(project.run(&["--explain", "own"]));
+    assert!(out.contains("shell: env WHICH=recipe sh -c"), "{}", out);
+}
+
+#[test]
+fn cached_recipes_skip_unchanged_runs() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe build {
+  cache
+  inputs "src/*.txt"
+  $ "sh -c 'echo $MESSAGE >> builds.log'"
+}
+"#,
+    );
+    project.file("src/a.txt", "a\n");
+    let builds = || {
+        std::fs::read_to_string(project.path("builds.log"))
+            .unwrap_or_default()
+            .lines()
+            .count()
+    };
+    let run = |args: &[&str], message: &str| {
+        project
+            .mold()
+            .args(args)
+            .env("MESSAGE", message)
+            .output()
+            .unwrap()
+    };
+
+    success(run(&["build"], "one"));
+    let out = success(run(&["build"], "one"));
+    assert!(out.contains("Cached build"), "{}", out);
+    assert_eq!(builds(), 1);
+
+    // input contents and the expanded commands are both part of the key
+    project.file("src/a.txt", "changed\n");
+    success(run(&["build"], "one"));
+    assert_eq!(builds(), 2);
+    success(run(&["build"], "two"));
+    assert_eq!(builds(), 3);
+
+    success(run(&["build", "--no-cache"], "two"));
+    assert_eq!(builds(), 4);
+
+    success(run(&["--clean"], "two"));
+    success(run(&["build"], "two"));
+    assert_eq!(builds(), 5);
+}
+
+#[cfg(unix)]
+#[test]
+fn exclusive_runs_wait_for_the_project_lock() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe migrate {
+  exclusive
+  $ "touch migrated"
+}
+recipe plain {
+  $ "touch plain"
+}
+"#,
+    );
+
+    // a lock left by a process that's gone is broken
+    let mut gone = std::process::Command::new("true").spawn().unwrap();
+    gone.wait().unwrap();
+    project.file(".mold/lock", &format!("{}\n", gone.id()));
+    let out = project.run(&["migrate"]);
+    let err = stderr(&out);
+    success(out);
+    assert!(err.contains("Breaking stale lock"), "{}", err);
+    assert!(project.path("migrated").exists());
+    assert!(!project.path(".mold/lock").exists());
+
+    // a lock held by a live process is waited on
+    let mut holder = std::process::Command::new("sleep")
+        .arg("30")
+        .spawn()
+        .unwrap();
+    project.file(".mold/lock", &format!("{}\n", holder.id()));
+    let out = project.run(&["--lock-timeout", "1s", "migrate"]);
+    let waiting = stdout(&out);
+    let err = failure(out);
+    assert!(
+        waiting.contains(&format!(
+            "Waiting for another mold invocation (pid {})",
+            holder.id()
+        )),
+        "{}",
+        waiting
+    );
+    assert!(err.contains("Timed out after 1s waiting for"), "{}", err);
+
+    // plain recipes ignore the lock unless the whole run is exclusive
+    success(project.run(&["plain"]));
+    failure(project.run(&["--exclusive", "--lock-timeout", "1s", "plain"]));
+
+    holder.kill().unwrap();
+    holder.wait().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn stale_locks_are_broken_only_once() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe migrate {
+  exclusive
+  $ "sh -c 'echo start >> runs.log; sleep 0.2; echo end >> runs.log'"
+}
+"#,
+    );
+
+    // every waiter sees the same dead owner, but only one of them may take its place
+    let mut gone = std::process::Command::new("true").spawn().unwrap();
+    gone.wait().unwrap();
+    project.file(".mold/lock", &format!("{}\n", gone.id()));
+    let runs: Vec<_> = (0..4)
+        .map(|_| {
+            project
+                .mold()
+                .arg("migrate")
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+
+    let mut broken = 0;
+    for run in runs {
+        let output = run.wait_with_output().unwrap();
+        broken += stderr(&output).matches("Breaking stale lock").count();
+        success(output);
+    }
+    assert_eq!(broken, 1);
+
+    let log = std::fs::read_to_string(project.path("runs.log")).unwrap();
+    assert_eq!(log, "start\nend\n".repeat(4));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn non_utf8_paths_and_output() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let project = Project::new();
+    let dir = project.root().join(OsStr::from_bytes(b"caf\xe9"));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("moldfile"),
+        r#"
+version "0.7"
+
+recipe root {
+  $ "sh -c 'cd \"$$MOLD_ROOT\" && test -f moldfile && echo found it'"
+}
+
+recipe bytes {
+  $ "printf '\\377 hi\\n'"
+}
+"#,
+    )
+    .unwrap();
+
+    let run = |args: &[&str]| {
+        let mut cmd = project.mold();
+        cmd.current_dir(&dir).args(args);
+        cmd.output().expect("couldn't run mold")
+    };
+
+    // MOLD_ROOT names the real directory, not a lossy copy of it
+    let out = success(run(&["root"]));
+    assert!(command_output(&out).contains(&"found it"), "{}", out);
+
+    // invalid UTF-8 in output is shown with replacement characters, however it's forwarded
+    for args in [
+        &["bytes"][..],
+        &["--prefix-output", "bytes"],
+        &["-j2", "bytes"],
+    ] {
+        let out = success(run(args));
+        assert!(out.contains("\u{fffd} hi"), "{}", out);
+    }
+}
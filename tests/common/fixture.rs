@@ -0,0 +1,96 @@
+//! Generator for synthetic moldfiles, shared by the tests and benchmarks
+
+/// Shape of a generated moldfile
+pub struct Fixture {
+    /// Number of recipes, named `r0`, `r1`, ...
+    pub recipes: usize,
+
+    /// How many of the preceding recipes each recipe requires
+    pub requires: usize,
+
+    /// Number of commands in each recipe
+    pub commands: usize,
+
+    /// Number of variables, named `V0`, `V1`, ..., each referring to the one before it
+    pub vars: usize,
+}
+
+impl Fixture {
+    pub fn small() -> Self {
+        Self {
+            recipes: 10,
+            requires: 1,
+            commands: 2,
+            vars: 5,
+        }
+    }
+
+    pub fn medium() -> Self {
+        Self {
+            recipes: 100,
+            requires: 2,
+            commands: 3,
+            vars: 20,
+        }
+    }
+
+    pub fn large() -> Self {
+        Self {
+            recipes: 1000,
+            requires: 3,
+            commands: 5,
+            vars: 100,
+        }
+    }
+
+    /// Render the fixture as moldfile source
+    ///
+    /// Every tenth recipe is wrapped in a condition so that conditional compilation is exercised
+    /// too; the `unix` branch and the `else` branch define the same recipe.
+    pub fn render(&self) -> String {
+        let mut out = String::from("version \"0.7\"\n\n");
+
+        for idx in 0..self.vars {
+            if idx == 0 {
+                out.push_str("var V0 = \"value\"\n");
+            } else {
+                out.push_str(&format!("var V{} = \"$V{}-{}\"\n", idx, idx - 1, idx));
+            }
+        }
+
+        for idx in 0..self.recipes {
+            let recipe = self.recipe(idx);
+            if idx % 10 == 9 {
+                out.push_str(&format!(
+                    "\nif unix {{\n{}}} else {{\n{}}}\n",
+                    recipe, recipe
+                ));
+            } else {
+                out.push('\n');
+                out.push_str(&recipe);
+            }
+        }
+
+        out
+    }
+
+    /// Render a single recipe
+    fn recipe(&self, idx: usize) -> String {
+        let mut out = format!("recipe r{} {{\n  help \"Recipe number {}\"\n", idx, idx);
+
+        for dep in idx.saturating_sub(self.requires)..idx {
+            out.push_str(&format!("  require r{}\n", dep));
+        }
+
+        for cmd in 0..self.commands {
+            let var = match self.vars {
+                0 => String::new(),
+                n => format!(" $V{}", (idx + cmd) % n),
+            };
+            out.push_str(&format!("  $ \"echo r{} {}{}\"\n", idx, cmd, var));
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
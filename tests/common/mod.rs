@@ -0,0 +1,209 @@
+//! Shared scaffolding for end-to-end tests
+//!
+//! Every test gets its own temporary project directory with moldfile fixtures written into it,
+//! runs the compiled `mold` binary against it, and inspects the output and filesystem afterwards.
+
+#![allow(dead_code)]
+
+pub mod fixture;
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use tempfile::TempDir;
+
+/// A temporary project directory that is deleted when dropped
+pub struct Project {
+    dir: TempDir,
+}
+
+impl Project {
+    /// Create a new, empty project directory
+    pub fn new() -> Self {
+        let dir = tempfile::Builder::new()
+            .prefix("mold-test-")
+            .tempdir()
+            .expect("couldn't create temp dir");
+        Self { dir }
+    }
+
+    /// Root of the project
+    ///
+    /// This is canonicalized so that it can be compared against the paths mold reports.
+    pub fn root(&self) -> PathBuf {
+        fs::canonicalize(self.dir.path()).unwrap()
+    }
+
+    /// Absolute path to a file or directory inside the project
+    pub fn path(&self, rel: &str) -> PathBuf {
+        self.root().join(rel)
+    }
+
+    /// Write a file relative to the project root, creating any parent directories
+    pub fn file(&self, rel: &str, contents: &str) -> &Self {
+        let path = self.path(rel);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, contents).unwrap();
+        self
+    }
+
+    /// Write the top-level moldfile
+    pub fn moldfile(&self, contents: &str) -> &Self {
+        self.file("moldfile", contents)
+    }
+
+    /// Build a mold command that runs from the project root
+    pub fn mold(&self) -> Command {
+        self.mold_in("")
+    }
+
+    /// Build a mold command that runs from a subdirectory of the project
+    pub fn mold_in(&self, rel: &str) -> Command {
+        let dir = self.path(rel);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_mold"));
+        cmd.current_dir(dir)
+            .env("CLICOLOR", "0")
+            .env("RUST_BACKTRACE", "0")
+            .env_remove("MOLDENV");
+        cmd
+    }
+
+    /// Run mold from the project root with the given arguments
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.mold().args(args).output().expect("couldn't run mold")
+    }
+}
+
+/// Captured stdout as a String
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// Captured stderr as a String
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Assert that mold exited successfully and return its stdout
+pub fn success(output: Output) -> String {
+    assert!(
+        output.status.success(),
+        "mold failed\nstdout:\n{}\nstderr:\n{}",
+        stdout(&output),
+        stderr(&output)
+    );
+    stdout(&output)
+}
+
+/// Assert that mold failed and return its stderr
+pub fn failure(output: Output) -> String {
+    assert!(
+        !output.status.success(),
+        "mold unexpectedly succeeded\nstdout:\n{}\nstderr:\n{}",
+        stdout(&output),
+        stderr(&output)
+    );
+    stderr(&output)
+}
+
+/// Lines that a recipe's commands printed, skipping mold's own `mold <name> $ ...` echoes
+pub fn command_output(stdout: &str) -> Vec<&str> {
+    stdout
+        .lines()
+        .filter(|line| !line.starts_with("mold "))
+        .collect()
+}
+
+/// Run git with a throwaway identity, panicking on failure
+pub fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=mold", "-c", "user.email=mold@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("couldn't run git");
+    assert!(
+        status.status.success(),
+        "git {:?} failed: {}",
+        args,
+        stderr(&status)
+    );
+}
+
+/// A local bare git repository that can be used as a mold remote without the network
+pub struct BareRepo {
+    /// Path to the bare repository
+    pub path: PathBuf,
+
+    /// Scratch clone used to create commits
+    work: PathBuf,
+}
+
+impl BareRepo {
+    /// Create an empty bare repository named `name` inside the project's scratch area
+    pub fn new(project: &Project, name: &str) -> Self {
+        let scratch = project.path("_remotes");
+        fs::create_dir_all(&scratch).unwrap();
+
+        let path = scratch.join(format!("{}.git", name));
+        let work = scratch.join(format!("{}-work", name));
+        git(&scratch, &["init", "--bare", "-q", path.to_str().unwrap()]);
+        git(&scratch, &["init", "-q", work.to_str().unwrap()]);
+        git(&work, &["checkout", "-q", "-b", "master"]);
+        git(&work, &["remote", "add", "origin", path.to_str().unwrap()]);
+
+        Self { path, work }
+    }
+
+    /// Commit the given files and push them to `branch`
+    pub fn commit(&self, branch: &str, files: &[(&str, &str)]) -> &Self {
+        for (rel, contents) in files {
+            let path = self.work.join(rel);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).unwrap();
+            }
+            fs::write(path, contents).unwrap();
+        }
+
+        git(&self.work, &["add", "-A"]);
+        git(&self.work, &["commit", "-q", "-m", "fixture"]);
+        git(
+            &self.work,
+            &[
+                "push",
+                "-q",
+                "origin",
+                &format!("HEAD:refs/heads/{}", branch),
+            ],
+        );
+        self
+    }
+
+    /// Tag the most recent commit and push the tag
+    pub fn tag(&self, name: &str) -> &Self {
+        git(&self.work, &["tag", name]);
+        git(&self.work, &["push", "-q", "origin", name]);
+        self
+    }
+
+    /// The full id of the last commit made with `commit`
+    pub fn head(&self) -> String {
+        let out = Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(&self.work)
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap().trim().to_string()
+    }
+
+    /// The URL to use in an `import` statement
+    pub fn url(&self) -> String {
+        self.path.display().to_string()
+    }
+}
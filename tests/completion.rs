@@ -0,0 +1,139 @@
+mod common;
+
+use common::*;
+use std::process::Command;
+
+#[test]
+fn list_names_prints_bare_recipe_names() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe build { help "Build it" $ "true" }
+recipe test { require build $ "true" }
+"#,
+    );
+
+    let out = success(project.run(&["--list-names"]));
+    assert_eq!(out, "build\ntest\n");
+}
+
+#[test]
+fn completion_scripts_explain_their_setup() {
+    let project = Project::new();
+    for shell in &["bash", "zsh", "fish", "powershell"] {
+        let out = success(project.run(&["--completion", shell]));
+        assert!(
+            out.contains(&format!("mold --completion {}", shell)),
+            "{}",
+            out
+        );
+        assert!(out.contains("mold --offline --list-names"), "{}", out);
+    }
+
+    let out = success(project.run(&["--completion", "zsh"]));
+    assert!(out.starts_with("#compdef mold\n"), "{}", out);
+    assert_eq!(success(project.run(&["--completions", "zsh"])), out);
+
+    let err = failure(project.run(&["--completion", "tcsh"]));
+    assert!(err.contains("Unknown shell tcsh"), "{}", err);
+}
+
+#[cfg(unix)]
+#[test]
+fn bash_completes_recipe_names() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe build { $ "true" }
+recipe bundle { $ "true" }
+recipe test { $ "true" }
+"#,
+    );
+    let script = success(project.run(&["--completion", "bash"]));
+    project.file("completion.bash", &script);
+
+    let mold = std::path::PathBuf::from(env!("CARGO_BIN_EXE_mold"));
+    let path = format!(
+        "{}:{}",
+        mold.parent().unwrap().display(),
+        std::env::var("PATH").unwrap()
+    );
+    let complete = |words: &str, cword: usize| {
+        let out = Command::new("bash")
+            .arg("-c")
+            .arg(format!(
+                "source completion.bash; COMP_WORDS=({}); COMP_CWORD={}; _mold_recipes; \
+                 printf '%s\\n' \"${{COMPREPLY[@]}}\"",
+                words, cword
+            ))
+            .current_dir(project.root())
+            .env("PATH", &path)
+            .output()
+            .unwrap();
+        success(out)
+    };
+
+    assert_eq!(complete("mold bu", 1), "build\nbundle\n");
+    assert_eq!(complete("mold build t", 2), "test\n");
+    assert!(complete("mold --of", 1).contains("--offline"));
+}
+
+#[cfg(unix)]
+#[test]
+fn shell_init_defines_recipe_functions() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe build { $ "echo built" }
+recipe test { $ "echo tested" }
+recipe hidden { $ "echo hidden" }
+"#,
+    );
+
+    let mold = std::path::PathBuf::from(env!("CARGO_BIN_EXE_mold"));
+    let path = format!(
+        "{}:{}",
+        mold.parent().unwrap().display(),
+        std::env::var("PATH").unwrap()
+    );
+    let init = success(project.run(&["--shell-init", "bash"]));
+    assert!(
+        init.contains("eval \"$(mold --shell-init bash)\""),
+        "{}",
+        init
+    );
+    project.file("init.bash", &init);
+
+    let shell = |script: &str| {
+        let out = Command::new("bash")
+            .arg("-c")
+            .arg(format!(
+                "source init.bash; _mold_refresh_functions; {}",
+                script
+            ))
+            .current_dir(project.root())
+            .env("PATH", &path)
+            .output()
+            .unwrap();
+        success(out)
+    };
+
+    // test is a shell builtin, so its recipe gets a prefix instead of replacing it
+    let out = shell("build; mold-test; type -t test; type -t hidden");
+    let lines = command_output(&out);
+    assert_eq!(lines, vec!["built", "tested", "builtin", "function"]);
+
+    project.moldfile(&format!(
+        "{}shell_functions [\"build\"]\n",
+        std::fs::read_to_string(project.path("moldfile")).unwrap()
+    ));
+    let out = success(project.run(&["--list-functions"]));
+    assert_eq!(out, "build\n");
+    assert_eq!(shell("type -t hidden || echo none"), "none\n");
+
+    let err = failure(project.run(&["--shell-init", "fish"]));
+    assert!(err.contains("Unknown shell fish"), "{}", err);
+}
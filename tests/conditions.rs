@@ -0,0 +1,135 @@
+mod common;
+
+use common::*;
+
+const MOLDFILE: &str = r#"
+version "0.7"
+
+recipe check {
+  if $PROFILE == "release" {
+    $ "echo release"
+  } elif $PROFILE == "" {
+    $ "echo empty"
+  } elif $PROFILE != "debug" {
+    $ "echo other"
+  } else {
+    $ "echo debug"
+  }
+}
+
+recipe quoted {
+  if $PROFILE == "say \"hi\"" {
+    $ "echo quoted"
+  }
+}
+
+recipe combined {
+  if ($PROFILE == "release" | $PROFILE == "bench") + ~ci {
+    $ "echo optimized"
+  }
+}
+
+recipe undefined {
+  if $NOPE_NOT_DEFINED == "" {
+    $ "echo eq"
+  }
+  if $NOPE_NOT_DEFINED != "" {
+    $ "echo ne"
+  }
+}
+"#;
+
+fn check(project: &Project, profile: Option<&str>, args: &[&str]) -> Vec<String> {
+    let mut cmd = project.mold();
+    cmd.env_remove("NOPE_NOT_DEFINED");
+    match profile {
+        Some(profile) => cmd.env("PROFILE", profile),
+        None => cmd.env_remove("PROFILE"),
+    };
+    let out = success(cmd.args(args).output().unwrap());
+    command_output(&out)
+        .into_iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[test]
+fn compares_environment_values() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    assert_eq!(
+        check(&project, Some("release"), &["check"]),
+        vec!["release"]
+    );
+    assert_eq!(check(&project, Some("debug"), &["check"]), vec!["debug"]);
+    assert_eq!(check(&project, Some("fast"), &["check"]), vec!["other"]);
+}
+
+#[test]
+fn empty_value_is_defined() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    assert_eq!(check(&project, Some(""), &["check"]), vec!["empty"]);
+}
+
+#[test]
+fn undefined_compares_unequal() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    // an undefined $PROFILE isn't equal to "" but is unequal to "debug"
+    assert_eq!(check(&project, None, &["check"]), vec!["other"]);
+    assert_eq!(check(&project, None, &["undefined"]), vec!["ne"]);
+}
+
+#[test]
+fn compares_escaped_strings() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    assert_eq!(
+        check(&project, Some("say \"hi\""), &["quoted"]),
+        vec!["quoted"]
+    );
+    assert!(check(&project, Some("say hi"), &["quoted"]).is_empty());
+}
+
+#[test]
+fn combines_with_env_operators() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    assert_eq!(
+        check(&project, Some("bench"), &["combined"]),
+        vec!["optimized"]
+    );
+    assert!(check(&project, Some("bench"), &["-e", "ci", "combined"]).is_empty());
+    assert!(check(&project, Some("debug"), &["combined"]).is_empty());
+}
+
+#[test]
+fn sees_earlier_vars_in_same_file() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        var BASE = "rel"
+        var PROFILE = "${BASE}ease"
+
+        if $PROFILE == "release" {
+          recipe build { $ "echo top-level" }
+        }
+
+        var PROFILE = "debug"
+
+        recipe check {
+          if $PROFILE == "debug" { $ "echo later-value" }
+        }
+        "#,
+    );
+
+    assert_eq!(check(&project, None, &["build"]), vec!["top-level"]);
+    assert_eq!(check(&project, None, &["check"]), vec!["later-value"]);
+}
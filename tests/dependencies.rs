@@ -0,0 +1,729 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn requirements_run_first() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe a { $ "echo a" }
+        recipe b { require a $ "echo b" }
+        recipe c { require b $ "echo c" }
+        "#,
+    );
+
+    let out = success(project.run(&["c"]));
+    assert_eq!(command_output(&out), vec!["a", "b", "c"]);
+}
+
+#[test]
+fn shared_requirements_run_once() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe base { $ "echo base" }
+        recipe left { require base $ "echo left" }
+        recipe right { require base $ "echo right" }
+        recipe top { require left require right $ "echo top" }
+        "#,
+    );
+
+    let out = success(project.run(&["top"]));
+    assert_eq!(command_output(&out), vec!["base", "left", "right", "top"]);
+}
+
+#[test]
+fn two_recipe_cycles_error() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe build { require test $ "echo build" }
+        recipe test { require build $ "echo test" }
+        "#,
+    );
+
+    let err = failure(project.run(&["build"]));
+    assert!(
+        err.contains("Dependency cycle detected: build -> test -> build"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn longer_cycles_name_only_their_recipes() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe top { require a $ "echo top" }
+        recipe a { require b $ "echo a" }
+        recipe b { require c $ "echo b" }
+        recipe c { require a $ "echo c" }
+        "#,
+    );
+
+    let err = failure(project.run(&["top"]));
+    assert!(
+        err.contains("Dependency cycle detected: a -> b -> c -> a"),
+        "{}",
+        err
+    );
+
+    let err = failure(project.run(&["--plan", "top"]));
+    assert!(err.contains("a -> b -> c -> a"), "{}", err);
+}
+
+#[test]
+fn recipes_requiring_themselves_error() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe a { require a $ "echo a" }
+        "#,
+    );
+
+    let err = failure(project.run(&["a"]));
+    assert!(err.contains("Dependency cycle detected: a -> a"), "{}", err);
+}
+
+#[test]
+fn diamonds_are_not_cycles() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe base { $ "echo base" }
+        recipe left { require base $ "echo left" }
+        recipe right { require base $ "echo right" }
+        recipe top { require left require right $ "echo top" }
+        recipe wide { require top require left require base $ "echo wide" }
+        "#,
+    );
+
+    let out = success(project.run(&["--plan", "wide", "top"]));
+    assert_eq!(
+        out.lines().collect::<Vec<_>>(),
+        vec!["base", "left", "right", "top", "wide"]
+    );
+}
+
+#[test]
+fn unknown_requirement_errors() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe a { require nope $ "echo a" }
+        "#,
+    );
+
+    let err = failure(project.run(&["a"]));
+    assert!(err.contains("Couldn't find recipe"), "{}", err);
+    assert!(err.contains("nope"), "{}", err);
+}
+
+#[test]
+fn failing_requirement_stops_run() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe bad { $ "false" }
+        recipe after { require bad $ "echo after" }
+        "#,
+    );
+
+    let output = project.run(&["after"]);
+    let out = stdout(&output);
+    let err = failure(output);
+    assert!(err.contains("non-zero exit status"), "{}", err);
+    assert!(!out.contains("after\n"), "{}", out);
+}
+
+fn workspace() -> Project {
+    let project = Project::new();
+    project
+        .moldfile(
+            r#"
+            version "0.7"
+            recipe all {
+              require "services/api#build"
+              require "services/web/moldfile#build"
+            }
+            "#,
+        )
+        .file(
+            "services/api/moldfile",
+            r#"
+            version "0.7"
+            recipe build { require "../shared#proto-gen" $ "echo api" }
+            "#,
+        )
+        .file(
+            "services/web/moldfile",
+            r#"
+            version "0.7"
+            recipe build { require "../shared#proto-gen" $ "echo web" }
+            "#,
+        )
+        .file(
+            "services/shared/moldfile",
+            r#"
+            version "0.7"
+            recipe proto-gen { $ "sh -c 'echo proto in $(basename $MOLD_SOURCE)'" }
+            "#,
+        );
+    project
+}
+
+#[test]
+fn requires_recipes_from_other_files() {
+    let project = workspace();
+
+    // shared is loaded once and its recipe only runs once
+    let out = success(project.run(&["all"]));
+    assert_eq!(command_output(&out), vec!["proto in shared", "api", "web"]);
+}
+
+#[test]
+fn referenced_recipes_are_registered_by_path() {
+    let project = workspace();
+
+    let out = success(project.run(&[]));
+    assert!(
+        out.contains("services/shared/moldfile#proto-gen"),
+        "{}",
+        out
+    );
+    let registered = out
+        .lines()
+        .filter(|line| {
+            line.trim_start()
+                .starts_with("services/shared/moldfile#proto-gen")
+        })
+        .count();
+    assert_eq!(registered, 1, "{}", out);
+}
+
+#[test]
+fn missing_referenced_file_errors() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe all { require "nowhere#build" }
+        "#,
+    );
+
+    let err = failure(project.run(&["all"]));
+    assert!(err.contains("Couldn't find moldfile"), "{}", err);
+    assert!(
+        err.contains(&project.path("nowhere").display().to_string()),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains(&project.path("moldfile").display().to_string()),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn missing_referenced_recipe_errors() {
+    let project = workspace();
+    project.file(
+        "services/web/moldfile",
+        r#"
+        version "0.7"
+        recipe build { require "../shared#nope" }
+        "#,
+    );
+
+    let err = failure(project.run(&["all"]));
+    assert!(err.contains("Couldn't find recipe nope"), "{}", err);
+    assert!(
+        err.contains(
+            &project
+                .path("services/shared/moldfile")
+                .display()
+                .to_string()
+        ),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains(&project.path("services/web/moldfile").display().to_string()),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn plan_prints_execution_order_only() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe a { $ "echo a" }
+        recipe b { require a $ "echo b" }
+        recipe c { require b $ "echo c" }
+        recipe d { $ "echo d" }
+        "#,
+    );
+
+    let out = success(project.run(&["--plan", "c", "d"]));
+    assert_eq!(out, "a\nb\nc\nd\n");
+}
+
+#[test]
+fn dry_run_prints_commands_without_running_them() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        var OUT = "made"
+        recipe a { $ "touch $OUT-a" }
+        recipe b { require a run ["touch", "$OUT b"] }
+        recipe broken { require nope $ "true" }
+        "#,
+    );
+
+    let output = project.run(&["--dry-run", "b"]);
+    let err = stderr(&output);
+    let dry = success(output);
+    assert_eq!(
+        dry,
+        "mold [1/2] a\nmold a $ touch made-a\nmold [2/2] b\nmold b $ touch 'made b'\n"
+    );
+    assert!(!project.path("made-a").exists());
+
+    // the directory and variables each recipe would get are described on stderr
+    let dir = format!("dir {}", project.root().display());
+    assert!(err.contains(&dir), "{}", err);
+    assert!(err.contains("OUT=made"), "{}", err);
+
+    // stdout matches a real run exactly, so the two can be diffed
+    let real = success(project.run(&["b"]));
+    assert_eq!(real, dry);
+    assert!(project.path("made b").exists());
+
+    let err = failure(project.run(&["--dry-run", "broken"]));
+    assert!(err.contains("nope"), "{}", err);
+}
+
+#[test]
+fn plan_reports_all_unknown_targets() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe a { $ "echo a" }
+        "#,
+    );
+
+    let output = project.run(&["--plan", "a", "nope", "other"]);
+    assert!(stdout(&output).is_empty());
+    let err = failure(output);
+    assert!(err.contains("Couldn't find recipes nope, other"), "{}", err);
+}
+
+#[test]
+fn rdeps_lists_transitive_dependents() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe login { $ "echo login" }
+        recipe push { require login }
+        recipe release { require push }
+        recipe unrelated { $ "echo nope" }
+        recipe deploy { require "infra#apply" }
+        "#,
+    );
+    project.file(
+        "infra/moldfile",
+        r#"
+        version "0.7"
+        recipe apply { require "..#release" }
+        "#,
+    );
+
+    let out = success(project.run(&["--rdeps", "login"]));
+    let lines: Vec<_> = out.lines().map(str::trim).collect();
+    assert!(lines.contains(&"push"), "{}", out);
+    assert!(lines.contains(&"release"), "{}", out);
+    assert!(lines.contains(&"deploy"), "{}", out);
+    assert!(lines.contains(&"infra/moldfile#apply"), "{}", out);
+    assert!(!out.contains("unrelated"), "{}", out);
+    assert!(out.contains(&project.path("infra/moldfile").display().to_string()));
+
+    let out = success(project.run(&["--rdeps", "release"]));
+    assert!(!out.contains("push"), "{}", out);
+
+    let out = success(project.run(&["--rdeps", "unrelated"]));
+    assert!(out.contains("nothing depends on unrelated"), "{}", out);
+
+    let err = failure(project.run(&["--rdeps", "lgin"]));
+    assert!(err.contains("did you mean login"), "{}", err);
+}
+
+#[test]
+fn requirements_with_bindings() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        var IMAGE = "default"
+        recipe docker-build { $ "echo building $IMAGE" }
+        recipe api { require docker-build with IMAGE="api" }
+        recipe web { require docker-build with IMAGE="web" }
+        recipe all {
+          require api
+          require web
+          require docker-build with IMAGE = "api"
+        }
+        "#,
+    );
+
+    let out = success(project.run(&["all"]));
+    assert_eq!(command_output(&out), vec!["building api", "building web"]);
+    assert!(out.contains("docker-build{IMAGE=api}"), "{}", out);
+
+    let out = success(project.run(&["--plan", "all"]));
+    assert_eq!(
+        out.lines().collect::<Vec<_>>(),
+        vec![
+            "docker-build{IMAGE=api}",
+            "api",
+            "docker-build{IMAGE=web}",
+            "web",
+            "all"
+        ]
+    );
+
+    let out = success(project.run(&["--explain", "api"]));
+    assert!(
+        out.contains("depends on: docker-build{IMAGE=api}"),
+        "{}",
+        out
+    );
+
+    let out = success(project.run(&["--rdeps", "docker-build"]));
+    assert!(out.contains("api") && out.contains("web"), "{}", out);
+
+    let out = success(project.run(&["docker-build"]));
+    assert_eq!(command_output(&out), vec!["building default"]);
+}
+
+#[test]
+fn aggregates_only_group_requirements() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe lint { $ "echo lint" }
+        recipe test { require lint $ "echo test" }
+        recipe ci { help "Everything CI runs" require test require lint }
+        recipe windows-only { if windows { $ "echo hi" } }
+        "#,
+    );
+
+    let out = success(project.run(&["ci"]));
+    assert_eq!(command_output(&out), vec!["lint", "test"]);
+
+    // explaining an aggregate just shows what it runs, in order
+    let out = success(project.run(&["--explain", "ci"]));
+    assert!(
+        out.contains("runs in order:\n  1. lint\n  2. test\n"),
+        "{}",
+        out
+    );
+    assert!(!out.contains("variables:"), "{}", out);
+
+    // a recipe with nothing in it at all is a mistake, but one that's empty on this platform isn't
+    project.moldfile("version \"0.7\"\nrecipe empty { help \"nothing\" }\n");
+    let err = failure(project.run(&[]));
+    assert!(
+        err.contains("Recipe empty has no commands and no requirements"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn progress_uses_expected_durations() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe fetch { expected "90s" $ "echo fetch" }
+        recipe lint { $ "echo lint" }
+        recipe build { require fetch require lint expected "1m30s" $ "echo build" }
+        recipe docs { $ "echo docs" }
+        "#,
+    );
+
+    let out = success(project.run(&["build", "docs"]));
+    let progress: Vec<_> = out.lines().filter(|x| x.contains("/4")).collect();
+    assert_eq!(
+        progress,
+        vec![
+            "mold [1/4, ~3m remaining] fetch",
+            "mold [2/4, ~2m remaining] lint",
+            "mold [3/4, ~2m remaining] build",
+            "mold [4/4] docs",
+        ]
+    );
+
+    // a single recipe doesn't need a progress indicator
+    let out = success(project.run(&["docs"]));
+    assert!(!out.contains("[1/1"), "{}", out);
+
+    let out = success(project.run(&["--explain", "fetch"]));
+    assert!(out.contains("expected: 2m"), "{}", out);
+
+    project.moldfile("version \"0.7\"\nrecipe x { expected \"soon\" $ \"true\" }\n");
+    let err = failure(project.run(&[]));
+    assert!(err.contains("Invalid duration"), "{}", err);
+
+    // durations too long to count are just as invalid
+    project.moldfile("version \"0.7\"\nrecipe x { expected \"99999999999999999h\" $ \"true\" }\n");
+    let err = failure(project.run(&[]));
+    assert!(err.contains("Invalid duration"), "{}", err);
+}
+
+#[test]
+fn large_generated_graph() {
+    let project = Project::new();
+    let fixture = fixture::Fixture {
+        recipes: 200,
+        requires: 3,
+        commands: 1,
+        vars: 0,
+    };
+    project.moldfile(&fixture.render());
+
+    let out = success(project.run(&["--plan", "r199"]));
+    let order: Vec<_> = out.lines().collect();
+    assert_eq!(order.len(), 200);
+    assert_eq!(order[0], "r0");
+    assert_eq!(order[199], "r199");
+}
+
+#[test]
+fn completed_requirements_check_markers() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+
+recipe build {
+  $ "echo building"
+}
+
+recipe deploy {
+  require completed:build within "1h"
+  $ "echo deploying"
+}
+"#,
+    );
+
+    // deploy doesn't run build for us
+    let err = failure(project.run(&["deploy"]));
+    assert!(err.contains("run 'mold build' first"), "{}", err);
+
+    let out = success(project.run(&["build"]));
+    assert_eq!(command_output(&out), vec!["building"]);
+
+    let out = success(project.run(&["deploy"]));
+    assert_eq!(command_output(&out), vec!["deploying"]);
+
+    // editing the moldfile makes the marker stale
+    let contents = std::fs::read_to_string(project.path("moldfile")).unwrap();
+    project.moldfile(&format!("{}\n// changed\n", contents));
+    let err = failure(project.run(&["deploy"]));
+    assert!(err.contains("has changed"), "{}", err);
+
+    success(project.run(&["build"]));
+    success(project.run(&["deploy"]));
+
+    // markers live in .mold and go away with --clean
+    success(project.run(&["--clean"]));
+    let err = failure(project.run(&["deploy"]));
+    assert!(err.contains("hasn't succeeded yet"), "{}", err);
+}
+
+#[test]
+fn tampered_markers_are_rejected() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe build { $ "echo building" }
+recipe deploy {
+  require completed:build
+  $ "echo deploying"
+}
+"#,
+    );
+
+    success(project.run(&["build"]));
+    let out = success(project.run(&["--verify"]));
+    assert!(out.contains("Verified 1 files"), "{}", out);
+
+    // editing a marker by hand doesn't count as a successful run
+    let dir = project.path(".mold/completed");
+    let marker = std::fs::read_dir(&dir)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let contents = std::fs::read_to_string(&marker).unwrap();
+    std::fs::write(&marker, contents.replace('\n', "\n\n")).unwrap();
+
+    let err = failure(project.run(&["--verify"]));
+    assert!(err.contains("1 files"), "{}", err);
+    let err = failure(project.run(&["deploy"]));
+    assert!(err.contains("hasn't succeeded yet"), "{}", err);
+
+    // running the recipe again regenerates it
+    success(project.run(&["build"]));
+    success(project.run(&["--verify"]));
+    success(project.run(&["deploy"]));
+
+    std::fs::remove_file(&marker).unwrap();
+    let out = project.run(&["--verify"]);
+    assert!(stdout(&out).contains("Missing"), "{}", stdout(&out));
+    failure(out);
+}
+
+#[test]
+fn corrupted_manifests_are_rebuilt() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe build { $ "echo building" }
+recipe deploy {
+  require completed:build
+  $ "echo deploying"
+}
+"#,
+    );
+
+    success(project.run(&["build"]));
+
+    // like what a write cut short would leave behind
+    let manifest = project.path(".mold/manifest.json");
+    let contents = std::fs::read_to_string(&manifest).unwrap();
+    std::fs::write(&manifest, &contents[..contents.len() / 2]).unwrap();
+
+    // what it vouched for can't be trusted anymore, but that doesn't fail recipes that succeed
+    let err = failure(project.run(&["deploy"]));
+    assert!(err.contains("hasn't succeeded yet"), "{}", err);
+    let output = project.run(&["build"]);
+    assert!(
+        stderr(&output).contains("couldn't be parsed"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(command_output(&success(output)), vec!["building"]);
+
+    let out = success(project.run(&["deploy"]));
+    assert_eq!(command_output(&out), vec!["deploying"]);
+    let out = success(project.run(&["--verify"]));
+    assert!(out.contains("Verified 2 files"), "{}", out);
+}
+
+#[test]
+fn graph_prints_dot_and_mermaid() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe c { require b $ "true" }
+recipe b { help "builds \"b\"" require a $ "true" }
+recipe a { $ "true" }
+recipe other { require gen with TARGET="x" }
+recipe gen { $ "echo $TARGET" }
+"#,
+    );
+
+    let out = success(project.run(&["--graph", "c"]));
+    assert_eq!(
+        out,
+        r#"digraph mold {
+  node [shape=box, style="rounded,filled"];
+  "a" [label="a", fillcolor=palegreen];
+  "b" [label="b\nbuilds \"b\"", fillcolor=lightblue];
+  "c" [label="c", fillcolor=lightblue];
+  "b" -> "a";
+  "c" -> "b";
+}
+"#
+    );
+
+    // without targets, every recipe is in the graph
+    let out = success(project.run(&["--graph"]));
+    assert!(out.contains("\"other\" -> \"gen{TARGET=x}\";"), "{}", out);
+    assert!(
+        out.contains("\"gen{TARGET=x}\" [label=\"gen{TARGET=x}\", fillcolor=palegreen];"),
+        "{}",
+        out
+    );
+
+    let out = success(project.run(&["--graph", "--graph-format", "mermaid", "c"]));
+    assert_eq!(
+        out,
+        "graph TD\n  n0[\"a\"]\n  n1[\"b\"]\n  n2[\"c\"]\n  n1 --> n0\n  n2 --> n1\n  classDef leaf fill:#cfc\n  class n0 leaf\n"
+    );
+
+    let err = failure(project.run(&["--graph", "--graph-format", "svg"]));
+    assert!(err.contains("Unknown graph format"), "{}", err);
+}
+
+#[test]
+fn private_recipes_are_hidden_but_still_run() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe _compile { help "Internal step" $ "echo compiling" }
+recipe stage { private $ "echo staging" }
+recipe build { help "Build it" require _compile require stage $ "echo building" }
+"#,
+    );
+
+    // they still show up as what other recipes require, just not with entries of their own
+    let out = success(project.run(&[]));
+    let listed: Vec<_> = out
+        .lines()
+        .filter_map(|x| x.split_whitespace().next())
+        .filter(|x| *x != "⮡")
+        .collect();
+    assert_eq!(listed, vec!["build"], "{}", out);
+    assert_eq!(success(project.run(&["--list-names"])), "build\n");
+
+    let out = success(project.run(&["--all"]));
+    assert!(out.contains("_compile Internal step"), "{}", out);
+    assert!(out.contains("stage"), "{}", out);
+
+    let out = success(project.run(&["build"]));
+    assert_eq!(
+        command_output(&out),
+        vec!["compiling", "staging", "building"]
+    );
+
+    let out = success(project.run(&["--explain", "_compile"]));
+    assert!(out.contains("Internal step"), "{}", out);
+    let out = success(project.run(&["stage"]));
+    assert_eq!(command_output(&out), vec!["staging"]);
+}
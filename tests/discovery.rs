@@ -0,0 +1,134 @@
+mod common;
+
+use common::*;
+use std::process::Command;
+
+#[test]
+fn walks_up_to_find_moldfile() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe where { $ "echo $MOLD_ROOT" }
+        "#,
+    );
+
+    let output = project
+        .mold_in("deeply/nested/dir")
+        .arg("where")
+        .output()
+        .unwrap();
+    let out = success(output);
+
+    assert_eq!(
+        command_output(&out),
+        vec![project.root().display().to_string()]
+    );
+    assert!(project.path(".mold").is_dir());
+}
+
+#[test]
+fn explicit_file_is_used() {
+    let project = Project::new();
+    project.file(
+        "other/custom.mold",
+        r#"
+        version "0.7"
+        recipe hello { $ "echo custom" }
+        "#,
+    );
+
+    let path = project.path("other/custom.mold");
+    let out = success(project.run(&["--file", path.to_str().unwrap(), "hello"]));
+
+    assert_eq!(command_output(&out), vec!["custom"]);
+    assert!(project.path("other/.mold").is_dir());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_moldfiles_resolve_to_the_real_directory() {
+    let project = Project::new();
+    project.file(
+        "real/moldfile",
+        r#"
+        version "0.7"
+        recipe where { $ "echo [$MOLD_SOURCE] [$MOLD_ROOT]" }
+        "#,
+    );
+    std::fs::create_dir(project.path("link")).unwrap();
+    std::os::unix::fs::symlink("../real/moldfile", project.path("link/moldfile")).unwrap();
+
+    let out = success(project.mold_in("link").arg("where").output().unwrap());
+    let real = project.path("real").display().to_string();
+    assert_eq!(command_output(&out), vec![format!("[{}] [{}]", real, real)]);
+    assert!(project.path("real/.mold").is_dir());
+    assert!(!project.path("link/.mold").exists());
+}
+
+#[test]
+fn missing_moldfile_errors() {
+    let project = Project::new();
+    let path = project.path("nope");
+    let err = failure(project.run(&["--file", path.to_str().unwrap()]));
+
+    assert!(err.contains("does not exist"), "{}", err);
+}
+
+#[test]
+fn bench_load_prints_percentiles() {
+    let project = Project::new();
+    project.moldfile(&fixture::Fixture::medium().render());
+
+    let out = success(project.run(&["--bench-load", "5"]));
+    assert!(out.contains("5 times"), "{}", out);
+    for label in &["min", "p50", "p90", "p99", "max"] {
+        assert!(
+            out.lines()
+                .any(|x| x.trim().starts_with(label) && x.ends_with("ms")),
+            "{}",
+            out
+        );
+    }
+
+    // the hidden mode stays out of --help
+    let out = success(project.run(&["--help"]));
+    assert!(!out.contains("bench-load"), "{}", out);
+}
+
+#[test]
+fn mold_dir_is_ignored_inside_git_repos() {
+    let project = Project::new();
+    project.moldfile("version \"0.7\"\nrecipe a {\n  $ \"true\"\n}\n");
+
+    // outside of a repository nothing is written
+    success(project.run(&["a"]));
+    assert!(!project.path(".mold/.gitignore").exists());
+    success(project.run(&["--update-gitignore"]));
+    assert!(!project.path(".gitignore").exists());
+
+    std::fs::remove_dir_all(project.path(".mold")).unwrap();
+    git(&project.root(), &["init", "-q"]);
+    success(project.run(&["a"]));
+    assert_eq!(
+        std::fs::read_to_string(project.path(".mold/.gitignore")).unwrap(),
+        "*\n"
+    );
+
+    // .mold ignores itself, so the user's .gitignore is left alone until they ask
+    assert!(!project.path(".gitignore").exists());
+    let out = Command::new("git")
+        .args(["status", "--porcelain"])
+        .current_dir(project.root())
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&out), "?? moldfile\n");
+
+    project.file(".gitignore", "target");
+    success(project.run(&["--update-gitignore"]));
+    success(project.run(&["--update-gitignore"]));
+    assert_eq!(
+        std::fs::read_to_string(project.path(".gitignore")).unwrap(),
+        "target\n.mold/\n"
+    );
+}
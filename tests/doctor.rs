@@ -0,0 +1,56 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn doctor_runs_checks() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "platform");
+    repo.commit(
+        "master",
+        &[(
+            "moldfile",
+            r#"
+            version "0.7"
+            check "shared" { $ "test -f $MOLD_SOURCE/moldfile" }
+            "#,
+        )],
+    );
+
+    project.moldfile(&format!(
+        r#"
+        version "0.7"
+        import "{}" as p/
+        check "passes" {{ $ "true" }}
+        check "fails" {{
+          help "install the thing"
+          $ "echo noisy"
+          $ "false"
+        }}
+        if windows {{
+          check "windows only" {{ $ "false" }}
+        }}
+        recipe build {{ $ "echo never" }}
+        "#,
+        repo.url()
+    ));
+    success(project.run(&[]));
+
+    let output = project.run(&["--doctor"]);
+    assert!(!output.status.success());
+
+    let out = stdout(&output);
+    let line = |name: &str| {
+        out.lines()
+            .find(|x| x.ends_with(name) || x.contains(&format!("{}:", name)))
+            .map(str::trim)
+            .unwrap_or_else(|| panic!("no {} in {}", name, out))
+    };
+    assert!(line("passes").starts_with("Pass"), "{}", out);
+    assert!(line("p/shared").starts_with("Pass"), "{}", out);
+    assert!(line("fails").starts_with("Fail"), "{}", out);
+    assert!(out.contains("install the thing"), "{}", out);
+    assert!(!out.contains("windows only"), "{}", out);
+    assert!(!out.contains("noisy") && !out.contains("never"), "{}", out);
+    assert!(stderr(&output).contains("1 check(s) failed"));
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use common::*;
+use serde_json::Value;
+
+fn dump(project: &Project) -> Value {
+    let out = success(project.run(&["--dump", "json"]));
+    serde_json::from_str(&out).expect("dump isn't valid JSON")
+}
+
+#[test]
+fn dump_describes_project() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[(
+            "moldfile",
+            "version \"0.7\"\nrecipe lint { help \"Lint it\" $ \"echo lint\" }\n",
+        )],
+    );
+
+    project.moldfile(&format!(
+        r#"version "0.7"
+import "{}" as s/
+environments ci
+
+if ci {{
+  recipe deploy {{ $ "echo deploy" }}
+}} elif linux {{
+  recipe build {{
+    help "Build it"
+    require s/lint
+    require gen with TARGET="x"
+    weight 2
+    $ "cargo build $FLAGS"
+  }}
+}}
+
+recipe gen {{ $ "echo $TARGET" }}
+check "docker" {{ $ "true" }}
+"#,
+        repo.url()
+    ));
+    success(project.run(&[]));
+
+    let doc = dump(&project);
+    assert_eq!(doc["schema"], 1);
+    assert_eq!(doc["root"], project.path("moldfile").display().to_string());
+
+    let recipes = doc["recipes"].as_array().unwrap();
+    let build = recipes.iter().find(|x| x["name"] == "build").unwrap();
+    assert_eq!(build["help"], "Build it");
+    assert_eq!(build["line"], 8);
+    assert_eq!(build["guards"], serde_json::json!(["~(ci) + linux"]));
+    assert_eq!(
+        build["requires"],
+        serde_json::json!(["s/lint", "gen{TARGET=x}"])
+    );
+    assert_eq!(build["commands"], serde_json::json!(["cargo build $FLAGS"]));
+    assert_eq!(build["weight"], 2);
+    assert!(recipes.iter().all(|x| x["name"] != "deploy"));
+
+    let lint = recipes.iter().find(|x| x["name"] == "s/lint").unwrap();
+    let imports = doc["imports"].as_array().unwrap();
+    assert_eq!(imports.len(), 1);
+    assert_eq!(imports[0]["prefix"], "s/");
+    assert_eq!(imports[0]["file"], lint["file"]);
+
+    assert_eq!(doc["instances"][0]["recipe"], "gen");
+    assert_eq!(doc["instances"][0]["bindings"]["TARGET"], "x");
+    assert_eq!(doc["checks"][0]["name"], "docker");
+}
+
+#[test]
+fn dump_handles_large_projects() {
+    let project = Project::new();
+    let mut moldfile = String::from("version \"0.7\"\n");
+    for idx in 0..1000 {
+        moldfile.push_str(&format!(
+            "recipe r{} {{ help \"recipe {}\" require base $ \"echo {}\" }}\n",
+            idx, idx, idx
+        ));
+    }
+    moldfile.push_str("recipe base { $ \"true\" }\n");
+    project.moldfile(&moldfile);
+
+    let start = std::time::Instant::now();
+    let doc = dump(&project);
+    let elapsed = start.elapsed();
+    assert_eq!(doc["recipes"].as_array().unwrap().len(), 1001);
+    assert!(elapsed.as_secs() < 5, "took {:?}", elapsed);
+}
+
+#[test]
+fn help_and_explain_as_json() {
+    let project = Project::new();
+    project.moldfile(
+        r#"version "0.7"
+var GREETING = "hello"
+recipe all { help "Everything" require greet }
+recipe greet {
+  dir "sub"
+  $ "echo $GREETING world"
+}
+"#,
+    );
+
+    let out = success(project.run(&["--format", "json"]));
+    assert!(!out.contains('\u{1b}'), "{}", out);
+    let recipes: Value = serde_json::from_str(&out).expect("help isn't valid JSON");
+    assert_eq!(recipes[0]["name"], "all");
+    assert_eq!(recipes[0]["help"], "Everything");
+    assert_eq!(recipes[0]["requires"], serde_json::json!(["greet"]));
+    assert_eq!(recipes[1]["help"], Value::Null);
+    assert_eq!(
+        recipes[1]["commands"],
+        serde_json::json!(["echo $GREETING world"])
+    );
+
+    let out = success(project.run(&["--format", "json", "--explain", "greet"]));
+    let task: Value = serde_json::from_str(&out).expect("explain isn't valid JSON");
+    assert_eq!(
+        task["commands"][0]["args"],
+        serde_json::json!(["echo", "hello", "world"])
+    );
+    assert_eq!(task["vars"]["GREETING"], "hello");
+    let root = std::fs::canonicalize(project.root()).unwrap();
+    assert_eq!(task["dir"], root.join("sub").display().to_string());
+
+    let out = success(project.run(&["--format", "json", "-x", "all", "greet"]));
+    let tasks: Value = serde_json::from_str(&out).expect("explain isn't valid JSON");
+    assert_eq!(tasks[0]["runs"], serde_json::json!(["greet"]));
+    assert_eq!(tasks[1]["name"], "greet");
+
+    let err = failure(project.run(&["--format", "yaml"]));
+    assert!(err.contains("Unknown output format"), "{}", err);
+}
+
+#[test]
+fn list_json_describes_recipes() {
+    let project = Project::new();
+    project.moldfile(
+        r#"version "0.7"
+recipe all { help "Everything" require build require test }
+recipe build {
+  dir "src"
+  $ "echo one"
+  $ "echo two"
+}
+recipe test { help "" $ "echo test" }
+"#,
+    );
+
+    let out = success(project.run(&["--list-json"]));
+    let recipes: Value = serde_json::from_str(&out).expect("listing isn't valid JSON");
+    assert_eq!(
+        recipes,
+        serde_json::json!([
+            {"name": "all", "help": "Everything", "requires": ["build", "test"], "dir": null, "command_count": 0},
+            {"name": "build", "help": null, "requires": [], "dir": "src", "command_count": 2},
+            {"name": "test", "help": null, "requires": [], "dir": null, "command_count": 1},
+        ])
+    );
+}
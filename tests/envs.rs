@@ -0,0 +1,239 @@
+mod common;
+
+use common::*;
+
+const MOLDFILE: &str = r#"
+version "0.7"
+
+if foo {
+  var WHICH = "foo"
+} elif bar + ~baz {
+  var WHICH = "bar"
+} else {
+  var WHICH = "neither"
+}
+
+recipe which {
+  if foo | bar {
+    $ "echo foo-or-bar"
+  }
+  $ "echo $WHICH"
+}
+"#;
+
+#[test]
+fn no_envs_takes_else_branch() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["which"]));
+    assert_eq!(command_output(&out), vec!["neither"]);
+}
+
+#[test]
+fn env_flag_activates_branch() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["-e", "foo", "which"]));
+    assert_eq!(command_output(&out), vec!["foo-or-bar", "foo"]);
+}
+
+#[test]
+fn negated_env_disables_branch() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["-a", "bar", "which"]));
+    assert_eq!(command_output(&out), vec!["foo-or-bar", "bar"]);
+
+    let out = success(project.run(&["-a", "bar", "-a", "baz", "which"]));
+    assert_eq!(command_output(&out), vec!["foo-or-bar", "neither"]);
+}
+
+#[test]
+fn moldenv_variable_is_respected() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let output = project
+        .mold()
+        .env("MOLDENV", "foo")
+        .arg("which")
+        .output()
+        .unwrap();
+    let out = success(output);
+    assert_eq!(command_output(&out), vec!["foo-or-bar", "foo"]);
+}
+
+#[test]
+fn envs_lists_active_environments() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["-e", "foo,bar", "-a", "baz", "--envs"]));
+    assert_eq!(
+        out,
+        format!(
+            "foo\nbar\nbaz\n{}\n{}\n",
+            std::env::consts::FAMILY,
+            std::env::consts::OS
+        )
+    );
+}
+
+#[test]
+fn default_envs_can_be_disabled_or_overridden() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["--no-default-envs", "--envs"]));
+    assert_eq!(out, "");
+
+    let out = success(project.run(&["--os", "plan9", "--envs"]));
+    assert_eq!(out, format!("{}\nplan9\n", std::env::consts::FAMILY));
+
+    let out = success(project.run(&["--no-default-envs", "--family", "windows", "--envs"]));
+    assert_eq!(out, "windows\n");
+}
+
+#[test]
+fn os_override_changes_conditions() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        recipe which {
+          if linux { $ "echo linux" }
+          if macos { $ "echo macos" }
+        }
+        "#,
+    );
+
+    let out = success(project.run(&["--os", "macos", "which"]));
+    assert_eq!(command_output(&out), vec!["macos"]);
+
+    let out = success(project.run(&["--no-default-envs", "which"]));
+    assert!(command_output(&out).is_empty());
+}
+
+#[test]
+fn warns_about_unknown_environments() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        environments ci, staging
+
+        if widows { var X = "1" }
+        if ci + ~staging { var Y = "1" }
+        recipe which {
+          if linx | macos { $ "echo unix-ish" }
+          if mystery { $ "echo mystery" }
+        }
+        "#,
+    );
+
+    let err = stderr(&project.run(&["which"]));
+    assert!(err.contains("widows"), "{}", err);
+    assert!(err.contains("did you mean windows?"), "{}", err);
+    assert!(err.contains("did you mean linux?"), "{}", err);
+    assert!(err.contains("mystery"), "{}", err);
+    assert!(!err.contains("environment ci "), "{}", err);
+    assert!(!err.contains("environment staging "), "{}", err);
+    assert!(!err.contains("macos"), "{}", err);
+
+    // explicitly activating an environment makes it known
+    let err = stderr(&project.run(&["-e", "mystery", "which"]));
+    assert!(!err.contains("mystery"), "{}", err);
+}
+
+#[test]
+fn matrix_runs_each_environment_set() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        environments ci
+        if linux { var OS = "linux" }
+        if macos { var OS = "macos" }
+        if ci { var MODE = "ci" } else { var MODE = "dev" }
+        recipe prep { $ "echo prep $OS" }
+        recipe build { require prep $ "echo build $OS $MODE" }
+        if linux { recipe package { $ "echo package" } }
+        "#,
+    );
+
+    let output = project.run(&["--matrix-env", "linux,macos+ci", "build", "package"]);
+    assert!(!output.status.success());
+
+    let out = stdout(&output);
+    assert_eq!(
+        command_output(&out)[..4],
+        ["prep linux", "build linux dev", "package", "prep macos"]
+    );
+    assert!(out.contains("mold [macos+ci] build $"), "{}", out);
+    assert!(out.contains("build macos ci"), "{}", out);
+
+    let summary: Vec<Vec<_>> = out
+        .lines()
+        .skip_while(|line| !line.contains("Summary"))
+        .skip(1)
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            vec!["linux", "build", "ok"],
+            vec!["linux", "package", "ok"],
+            vec!["macos+ci", "build", "ok"],
+            vec!["macos+ci", "package", "failed"],
+        ]
+    );
+    assert!(stderr(&output).contains("1 of 4 matrix runs"));
+}
+
+#[test]
+fn matrix_runs_honor_the_rest_of_the_command_line() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        environments x, y
+        recipe a {
+          $ "touch ran-a"
+          $ "echo a [$1]"
+        }
+        recipe b { $ "echo b" }
+        "#,
+    );
+
+    // describing what would run never runs it, once or once per set
+    for flag in ["--plan", "--explain", "--emit-script", "--graph"] {
+        let err = failure(project.run(&["--matrix-env", "x", flag, "a"]));
+        assert!(err.contains("can't be combined with"), "{}: {}", flag, err);
+        assert!(!project.path("ran-a").exists(), "{}", flag);
+    }
+
+    let out = success(project.run(&["--matrix-env", "x,y", "-j", "2", "a", "b", "--", "hi"]));
+    assert!(out.contains("mold [x] a $ echo a '[hi]'"), "{}", out);
+    assert!(out.contains("mold [y] b $ echo b"), "{}", out);
+    assert!(project.path("ran-a").exists());
+}
+
+#[test]
+fn matrix_dry_runs_run_nothing() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        environments x, y
+        recipe a { $ "touch ran-a" }
+        "#,
+    );
+
+    let out = success(project.run(&["--matrix-env", "x,y", "--dry-run", "a"]));
+    assert!(out.contains("mold [x] a $ touch ran-a"), "{}", out);
+    assert!(out.contains("mold [y] a $ touch ran-a"), "{}", out);
+    assert!(!project.path("ran-a").exists());
+}
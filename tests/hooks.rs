@@ -0,0 +1,150 @@
+mod common;
+
+use common::*;
+use std::process::Command;
+
+const MOLDFILE: &str = r#"
+version "0.7"
+hook pre-commit = "fmt lint"
+
+recipe fmt { $ "touch fmt.ran" }
+recipe lint { $ "touch lint.ran" }
+"#;
+
+fn project() -> Project {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+    git(&project.root(), &["init", "-q"]);
+    git(
+        &project.root(),
+        &["config", "user.email", "mold@example.com"],
+    );
+    git(&project.root(), &["config", "user.name", "mold"]);
+    project
+}
+
+#[cfg(unix)]
+#[test]
+fn installed_hooks_run_recipes() {
+    let project = project();
+    success(project.run(&["--install-hook", "pre-commit", "fmt lint"]));
+
+    // the hook finds the mold under test the same way it would find an installed one
+    let mold = std::path::PathBuf::from(env!("CARGO_BIN_EXE_mold"));
+    let path = format!(
+        "{}:{}",
+        mold.parent().unwrap().display(),
+        std::env::var("PATH").unwrap()
+    );
+    let out = Command::new("git")
+        .args(["commit", "-q", "--allow-empty", "-m", "test"])
+        .current_dir(project.root())
+        .env("PATH", path)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(project.path("fmt.ran").exists());
+    assert!(project.path("lint.ran").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn hooks_pass_their_arguments_to_recipes() {
+    let project = project();
+    project.moldfile(&format!(
+        "{}recipe msg {{ $ \"cp $1 msg.txt\" }}\n",
+        MOLDFILE
+    ));
+    success(project.run(&["--install-hook", "commit-msg", "msg"]));
+
+    let mold = std::path::PathBuf::from(env!("CARGO_BIN_EXE_mold"));
+    let path = format!(
+        "{}:{}",
+        mold.parent().unwrap().display(),
+        std::env::var("PATH").unwrap()
+    );
+    let out = Command::new("git")
+        .args(["commit", "-q", "--allow-empty", "-m", "from git"])
+        .current_dir(project.root())
+        .env("PATH", path)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    // commit-msg gets the path of the file holding the message
+    let msg = std::fs::read_to_string(project.path("msg.txt")).unwrap();
+    assert_eq!(msg.trim(), "from git");
+}
+
+#[cfg(unix)]
+#[test]
+fn hooks_explain_a_missing_mold() {
+    let project = project();
+    success(project.run(&["--install-hook", "pre-commit", "fmt"]));
+
+    let out = Command::new(project.path(".git/hooks/pre-commit"))
+        .current_dir(project.root())
+        .env("PATH", "/usr/bin:/bin")
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    assert!(
+        stderr(&out).contains("mold isn't on PATH"),
+        "{}",
+        stderr(&out)
+    );
+}
+
+#[test]
+fn declared_hooks_are_installed_together() {
+    let project = project();
+    let out = success(project.run(&["--install-hooks"]));
+    assert!(out.contains("Installed"), "{}", out);
+
+    let hook = std::fs::read_to_string(project.path(".git/hooks/pre-commit")).unwrap();
+    assert!(
+        hook.contains("exec mold --non-interactive --file moldfile fmt lint -- \"$@\""),
+        "{}",
+        hook
+    );
+
+    let out = success(project.run(&["--install-hooks"]));
+    assert!(out.contains("Unchanged"), "{}", out);
+}
+
+#[test]
+fn foreign_hooks_are_left_alone() {
+    let project = project();
+    project.file(".git/hooks/pre-commit", "#!/bin/sh\nexit 0\n");
+
+    let err = failure(project.run(&["--install-hook", "pre-commit", "fmt"]));
+    assert!(err.contains("wasn't written by mold"), "{}", err);
+    let err = failure(project.run(&["--uninstall-hook", "pre-commit"]));
+    assert!(err.contains("wasn't written by mold"), "{}", err);
+
+    success(project.run(&["--install-hook", "pre-commit", "fmt", "--force"]));
+    success(project.run(&["--uninstall-hook", "pre-commit"]));
+    assert!(!project.path(".git/hooks/pre-commit").exists());
+
+    let out = success(project.run(&["--uninstall-hook", "pre-commit"]));
+    assert!(out.contains("isn't installed"), "{}", out);
+}
+
+#[test]
+fn hook_names_and_targets_are_checked() {
+    let project = project();
+
+    let err = failure(project.run(&["--install-hook", "pre-comit", "fmt"]));
+    assert!(err.contains("Unknown git hook pre-comit"), "{}", err);
+
+    let err = failure(project.run(&["--install-hook", "pre-push", "fmtt"]));
+    assert!(err.contains("fmtt"), "{}", err);
+    assert!(!project.path(".git/hooks/pre-push").exists());
+
+    let err = failure(project.run(&["--install-hook", "pre-push"]));
+    assert!(
+        err.contains("No targets given for hook pre-push"),
+        "{}",
+        err
+    );
+}
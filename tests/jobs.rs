@@ -0,0 +1,94 @@
+mod common;
+
+use common::*;
+
+/// Wait up to 10 seconds for the file named by $0 to appear, then fail
+const WAIT: &str =
+    "i=0; while [ ! -e $$0 ]; do i=$$((i+1)); [ $$i -gt 100 ] && exit 1; sleep 0.1; done";
+
+#[cfg(unix)]
+#[test]
+fn independent_recipes_run_concurrently() {
+    let project = Project::new();
+    project.moldfile(&format!(
+        r#"
+version "0.7"
+recipe a {{ $ "touch a.started" run ["sh", "-c", "{wait}", "b.started"] $ "echo a done" }}
+recipe b {{ $ "touch b.started" run ["sh", "-c", "{wait}", "a.started"] $ "echo b done" }}
+recipe all {{ require a require b $ "echo all done" }}
+"#,
+        wait = WAIT
+    ));
+
+    // each of a and b waits for the other to start, so they can only finish together
+    let out = success(project.run(&["-j", "2", "all"]));
+    let lines: Vec<_> = out.lines().collect();
+    assert!(lines.contains(&"a   | a done"), "{}", out);
+    assert!(lines.contains(&"b   | b done"), "{}", out);
+    assert_eq!(lines.last(), Some(&"all | all done"), "{}", out);
+}
+
+#[cfg(unix)]
+#[test]
+fn failures_stop_the_pool() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe fail { $ "false" }
+recipe slow { $ "sleep 0.5" $ "touch slow.done" }
+recipe third { $ "touch third.done" }
+recipe after { require fail require slow require third $ "touch after.done" }
+"#,
+    );
+
+    let err = failure(project.run(&["-j", "2", "after"]));
+    assert!(
+        err.contains("Recipe fail returned non-zero exit status"),
+        "{}",
+        err
+    );
+
+    // running recipes finish, but nothing new starts
+    assert!(project.path("slow.done").exists());
+    assert!(!project.path("third.done").exists());
+    assert!(!project.path("after.done").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn weights_limit_what_runs_together() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe a { weight 2 $ "touch a.running" $ "sleep 0.3" $ "test ! -e b.running" $ "rm a.running" }
+recipe b { weight 2 $ "touch b.running" $ "sleep 0.3" $ "test ! -e a.running" $ "rm b.running" }
+recipe all { require a require b }
+"#,
+    );
+
+    success(project.run(&["-j", "2", "all"]));
+}
+
+#[cfg(unix)]
+#[test]
+fn weightless_recipes_dont_wait_for_a_slot() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe a { $ "sleep 1" }
+recipe b { $ "sleep 1" }
+recipe fetch { weight 0 $ "sleep 1" }
+recipe poll { weight 0 $ "sleep 1" }
+recipe all { require a require b require fetch require poll }
+"#,
+    );
+
+    // all four fit in the time of one, since only a and b need one of the two slots
+    let started = std::time::Instant::now();
+    success(project.run(&["-j", "2", "all"]));
+    let elapsed = started.elapsed();
+    assert!(elapsed.as_millis() < 1800, "{:?}", elapsed);
+}
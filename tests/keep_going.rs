@@ -0,0 +1,71 @@
+mod common;
+
+use common::*;
+
+const MOLDFILE: &str = r#"
+version "0.7"
+recipe lint { $ "false" }
+recipe test { $ "touch test.ran" }
+recipe build { require lint $ "touch build.ran" }
+recipe package { require build $ "touch package.ran" }
+recipe all { require lint require test require package }
+"#;
+
+#[test]
+fn failures_only_skip_their_dependents() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    // without -k, the first failure stops everything
+    failure(project.run(&["lint", "test"]));
+    assert!(!project.path("test.ran").exists());
+
+    let output = project.run(&["-k", "all"]);
+    let out = stdout(&output);
+    let err = failure(output);
+    assert!(err.contains("1 of 5 recipes failed"), "{}", err);
+    assert!(
+        err.contains("Recipe lint returned non-zero exit status"),
+        "{}",
+        err
+    );
+
+    assert!(project.path("test.ran").exists());
+    assert!(!project.path("build.ran").exists());
+    assert!(!project.path("package.ran").exists());
+
+    assert!(
+        out.contains("Summary 1 succeeded, 1 failed, 3 skipped"),
+        "{}",
+        out
+    );
+    assert!(out.contains("Succeeded test"), "{}", out);
+    assert!(out.contains("Failed lint"), "{}", out);
+    assert!(out.contains("Skipped build package all"), "{}", out);
+}
+
+#[test]
+fn keep_going_works_with_jobs_and_settings() {
+    let project = Project::new();
+    project.moldfile(&format!("settings {{ keep_going true }}\n{}", MOLDFILE));
+
+    let output = project.run(&["-j", "2", "all"]);
+    let out = stdout(&output);
+    failure(output);
+    assert!(project.path("test.ran").exists());
+    assert!(!project.path("build.ran").exists());
+    assert!(
+        out.contains("Summary 1 succeeded, 1 failed, 3 skipped"),
+        "{}",
+        out
+    );
+
+    // everything succeeding still prints the summary, and exits cleanly
+    project.moldfile(&MOLDFILE.replace("\"false\"", "\"true\""));
+    let out = success(project.run(&["-k", "all"]));
+    assert!(
+        out.contains("Summary 5 succeeded, 0 failed, 0 skipped"),
+        "{}",
+        out
+    );
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::*;
+
+/// Recipes spread over two moldfiles, defined out of alphabetical order
+fn project() -> Project {
+    let project = Project::new();
+    project.file(
+        "lib/moldfile",
+        r#"
+version "0.7"
+recipe zeta { $ "echo zeta" }
+recipe alpha { $ "echo alpha" }
+"#,
+    );
+    project.moldfile(
+        r#"
+version "0.7"
+
+recipe test {
+  require build
+  $ "echo test"
+}
+
+recipe build {
+  require "lib#zeta"
+  require "lib#alpha"
+  $ "echo build"
+}
+
+recipe all {
+  require test
+  require build
+}
+"#,
+    );
+    project
+}
+
+/// Recipe names from `mold` with no arguments
+fn help_order(project: &Project) -> Vec<String> {
+    let out = success(project.run(&[]));
+    out.lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty() && !x.starts_with('⮡'))
+        .map(|x| x.split_whitespace().next().unwrap().to_string())
+        .collect()
+}
+
+/// Recipe names from `--dump json`
+fn dump_order(project: &Project) -> Vec<String> {
+    let out = success(project.run(&["--dump", "json"]));
+    let dump: serde_json::Value = serde_json::from_str(&out).unwrap();
+    dump["recipes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["name"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn listings_share_one_display_order() {
+    let project = project();
+
+    let help = help_order(&project);
+    assert_eq!(help, dump_order(&project));
+
+    // the root moldfile's recipes come first, alphabetically, then the other file's
+    assert_eq!(&help[..3], &["all", "build", "test"]);
+    assert_eq!(help.len(), 5);
+    assert!(help[3].ends_with("alpha"), "{:?}", help);
+    assert!(help[4].ends_with("zeta"), "{:?}", help);
+
+    // --rdeps lists a subset of the same order
+    let out = success(project.run(&["--rdeps", &help[4]]));
+    let rdeps: Vec<_> = out
+        .lines()
+        .filter(|x| x.starts_with("  "))
+        .map(str::trim)
+        .collect();
+    assert_eq!(rdeps, vec!["all", "build", "test"]);
+}
+
+#[test]
+fn plan_matches_execution_order() {
+    let project = project();
+
+    let plan = success(project.run(&["--plan", "all"]));
+    let plan: Vec<_> = plan.lines().collect();
+    assert!(plan[0].ends_with("zeta"), "{:?}", plan);
+    assert!(plan[1].ends_with("alpha"), "{:?}", plan);
+    assert_eq!(&plan[2..], &["build", "test", "all"]);
+
+    let out = success(project.run(&["all"]));
+    assert_eq!(command_output(&out), vec!["zeta", "alpha", "build", "test"]);
+
+    // command line order breaks ties between unrelated targets
+    let plan = success(project.run(&["--plan", "test", "build"]));
+    let plan: Vec<_> = plan.lines().skip(2).collect();
+    assert_eq!(plan, vec!["build", "test"]);
+}
+
+#[test]
+fn requirements_always_come_first() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe z { require "y" $ "echo z" }
+recipe y { require "a" require "x" $ "echo y" }
+recipe x { require "a" $ "echo x" }
+recipe a { $ "echo a" }
+"#,
+    );
+
+    // whichever targets are given, and in whatever order, every recipe follows its requirements
+    for targets in &[
+        vec!["z"],
+        vec!["a", "z"],
+        vec!["z", "a"],
+        vec!["x", "z", "y"],
+        vec!["y", "x"],
+    ] {
+        let mut args = vec!["--plan"];
+        args.extend(targets);
+        let plan = success(project.run(&args));
+        let plan: Vec<_> = plan.lines().collect();
+        let position = |name| plan.iter().position(|x| *x == name);
+
+        for (name, deps) in &[("z", vec!["y"]), ("y", vec!["a", "x"]), ("x", vec!["a"])] {
+            if let Some(idx) = position(*name) {
+                for dep in deps {
+                    assert!(position(*dep).unwrap() < idx, "{:?}", plan);
+                }
+            }
+        }
+
+        // and the order is the same every time
+        assert_eq!(success(project.run(&args)), plan.join("\n") + "\n");
+    }
+
+    let out = success(project.run(&["z"]));
+    assert_eq!(command_output(&out), vec!["a", "x", "y", "z"]);
+}
@@ -0,0 +1,154 @@
+use mold::output::forward_lines;
+use mold::output::Event;
+use mold::output::Interleaver;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::thread;
+
+fn labels(names: &[&str]) -> Vec<String> {
+    names.iter().map(|x| x.to_string()).collect()
+}
+
+/// Split interleaved output back into (label, line) pairs
+fn parse(out: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(out)
+        .lines()
+        .map(|x| {
+            let mut parts = x.splitn(2, " | ");
+            let label = parts.next().unwrap().trim().to_string();
+            (label, parts.next().unwrap_or_default().to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn chatty_tasks_take_turns() {
+    let (tx, rx) = mpsc::channel();
+
+    // everything is already buffered by the time forwarding starts, which is the worst case
+    for idx in 0..1000 {
+        tx.send(Event::Line(0, format!("chatty {}", idx))).unwrap();
+    }
+    for idx in 0..5 {
+        tx.send(Event::Line(1, format!("quiet {}", idx))).unwrap();
+        tx.send(Event::Line(2, format!("quieter {}", idx))).unwrap();
+    }
+    drop(tx);
+
+    let out = Interleaver::new(vec![], &labels(&["chatty", "quiet", "q"]), 4)
+        .run(rx)
+        .unwrap();
+    let lines = parse(&out);
+    assert_eq!(lines.len(), 1010);
+
+    // every quiet line is out within two turns, so at most 4 chatty lines between each pair
+    let last_quiet = lines
+        .iter()
+        .rposition(|(label, _)| label != "chatty")
+        .unwrap();
+    assert!(
+        last_quiet < 2 * (4 + 4 + 4),
+        "{:?}",
+        &lines[..last_quiet + 1]
+    );
+
+    let mut run = 0;
+    for (label, _) in &lines[..last_quiet] {
+        run = if label == "chatty" { run + 1 } else { 0 };
+        assert!(run <= 4, "{:?}", &lines[..last_quiet + 1]);
+    }
+
+    // each task's lines stay in order
+    let chatty: Vec<_> = lines
+        .iter()
+        .filter(|(label, _)| label == "chatty")
+        .collect();
+    for (idx, (_, line)) in chatty.iter().enumerate() {
+        assert_eq!(line, &format!("chatty {}", idx));
+    }
+}
+
+#[test]
+fn finished_tasks_are_flushed_immediately() {
+    let (tx, rx) = mpsc::channel();
+    for idx in 0..100 {
+        tx.send(Event::Line(0, format!("{}", idx))).unwrap();
+    }
+    for idx in 0..10 {
+        tx.send(Event::Line(1, format!("{}", idx))).unwrap();
+    }
+    tx.send(Event::Done(1)).unwrap();
+    drop(tx);
+
+    let out = Interleaver::new(vec![], &labels(&["long", "short"]), 1)
+        .run(rx)
+        .unwrap();
+    let lines = parse(&out);
+
+    // the finished task doesn't wait for ten turns behind its sibling
+    let first: Vec<_> = lines[..10]
+        .iter()
+        .map(|(label, _)| label.as_str())
+        .collect();
+    assert_eq!(first, vec!["short"; 10]);
+    assert_eq!(lines.len(), 110);
+}
+
+#[cfg(unix)]
+#[test]
+fn fast_printing_commands_are_not_starved() {
+    let scripts = [
+        // chatty enough to still be running long after the others are done
+        "i=0; while [ $i -lt 20000 ]; do echo $i; i=$((i+1)); done",
+        "for i in 0 1 2 3 4; do echo $i; done",
+        "for i in 0 1 2 3 4; do echo $i; done",
+        "for i in 0 1 2 3 4; do echo $i; done",
+    ];
+
+    let (tx, rx) = mpsc::channel();
+    let names = labels(&["chatty", "a", "b", "c"]);
+    let forwarder = thread::spawn(move || Interleaver::new(vec![], &names, 8).run(rx));
+
+    let handles: Vec<_> = scripts
+        .iter()
+        .enumerate()
+        .map(|(idx, script)| {
+            let mut child = Command::new("sh")
+                .args(["-c", script])
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let stdout = child.stdout.take().unwrap();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                forward_lines(idx, stdout, &tx);
+                child.wait().unwrap();
+                tx.send(Event::Done(idx)).unwrap();
+            })
+        })
+        .collect();
+
+    drop(tx);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let lines = parse(&forwarder.join().unwrap().unwrap());
+
+    for label in &["chatty", "a", "b", "c"] {
+        let theirs: Vec<_> = lines
+            .iter()
+            .filter(|(x, _)| x == label)
+            .map(|(_, line)| line.parse::<usize>().unwrap())
+            .collect();
+        let count = if *label == "chatty" { 20000 } else { 5 };
+        assert_eq!(theirs, (0..count).collect::<Vec<_>>(), "{}", label);
+    }
+
+    // the short tasks finish long before the chatty one, so their output can't be stuck at the end
+    let last_short = lines
+        .iter()
+        .rposition(|(label, _)| label != "chatty")
+        .unwrap();
+    assert!(last_short < 20000, "{}", last_short);
+}
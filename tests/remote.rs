@@ -0,0 +1,660 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn import_round_trip() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[(
+            "moldfile",
+            r#"
+            version "0.7"
+            recipe greet { $ "echo hello from shared" }
+            recipe where { $ "echo $MOLD_SOURCE" }
+            "#,
+        )],
+    );
+
+    project.moldfile(&format!(
+        r#"
+        version "0.7"
+        import "{}" as s/
+        recipe local {{ require s/greet $ "echo local" }}
+        "#,
+        repo.url()
+    ));
+
+    // the first load clones the remote into .mold
+    let out = success(project.run(&[]));
+    assert!(out.contains("Cloning"), "{}", out);
+    assert!(out.contains("s/greet"), "{}", out);
+
+    let out = success(project.run(&["local"]));
+    assert_eq!(command_output(&out), vec!["hello from shared", "local"]);
+
+    // the clone lives in .mold and recipes from it see it as their source
+    let out = success(project.run(&["s/where"]));
+    let source = command_output(&out)[0].to_string();
+    assert!(source.starts_with(&project.path(".mold").display().to_string()));
+    assert!(std::path::Path::new(&source).join("moldfile").is_file());
+}
+
+#[test]
+fn import_branch_and_file() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit("master", &[("README", "nothing to see here")]);
+    repo.commit(
+        "dev",
+        &[(
+            "ci/tasks.mold",
+            r#"
+            version "0.7"
+            recipe lint { $ "echo dev lint" }
+            "#,
+        )],
+    );
+
+    project.moldfile(&format!(
+        r#"
+        version "0.7"
+        import "{}#dev/ci/tasks.mold" as ci/
+        "#,
+        repo.url()
+    ));
+
+    success(project.run(&[]));
+    let out = success(project.run(&["ci/lint"]));
+    assert_eq!(command_output(&out), vec!["dev lint"]);
+}
+
+#[test]
+fn update_fetches_new_commits() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[("moldfile", "version \"0.7\"\nrecipe v { $ \"echo one\" }\n")],
+    );
+
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}\" as s/\n",
+        repo.url()
+    ));
+
+    success(project.run(&[]));
+    let out = success(project.run(&["s/v"]));
+    assert_eq!(command_output(&out), vec!["one"]);
+
+    repo.commit(
+        "master",
+        &[("moldfile", "version \"0.7\"\nrecipe v { $ \"echo two\" }\n")],
+    );
+
+    // without an update, the existing clone is reused
+    let out = success(project.run(&["s/v"]));
+    assert_eq!(command_output(&out), vec!["one"]);
+
+    let out = success(project.run(&["--update"]));
+    assert!(out.contains("Updating"), "{}", out);
+    let out = success(project.run(&["s/v"]));
+    assert_eq!(command_output(&out), vec!["two"]);
+}
+
+#[test]
+fn verbose_shows_git_errors() {
+    let project = Project::new();
+    let missing = project.path("_remotes/missing.git");
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}\" as m/\n",
+        missing.display()
+    ));
+
+    let err = failure(project.run(&["--git"]));
+    assert!(err.contains("Couldn't clone"), "{}", err);
+    assert!(!err.contains("fatal:"), "{}", err);
+
+    let err = failure(project.run(&["--git", "--verbose"]));
+    assert!(err.contains("fatal:"), "{}", err);
+    assert!(err.contains("does not exist"), "{}", err);
+}
+
+#[test]
+fn import_pinned_to_a_commit() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[(
+            "moldfile",
+            "version \"0.7\"\nrecipe greet { $ \"echo first\" }\n",
+        )],
+    );
+    let first = repo.head();
+    repo.commit(
+        "master",
+        &[(
+            "moldfile",
+            "version \"0.7\"\nrecipe greet { $ \"echo second\" }\n",
+        )],
+    );
+
+    // full and abbreviated ids both work, with libgit2 and with the git CLI
+    for (id, mut args) in [(&first[..], vec![]), (&first[..7], vec!["--git"])] {
+        project.moldfile(&format!(
+            "version \"0.7\"\nimport \"{}#{}\" as s/\n",
+            repo.url(),
+            id
+        ));
+        args.push("s/greet");
+        let out = success(project.run(&args));
+        assert_eq!(command_output(&out).last(), Some(&"first"), "{}", out);
+    }
+}
+
+#[test]
+fn unknown_ref_errors() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit("master", &[("moldfile", "version \"0.7\"\n")]);
+
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}#nope\" as s/\n",
+        repo.url()
+    ));
+    let err = failure(project.run(&[]));
+    assert!(err.contains("Unable to locate ref"), "{}", err);
+
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}#nope-either\" as s/\n",
+        repo.url()
+    ));
+    let err = failure(project.run(&["--git"]));
+    assert!(err.contains("Unable to locate ref"), "{}", err);
+}
+
+#[test]
+fn offline_uses_existing_clones_only() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[("moldfile", "version \"0.7\"\nrecipe v { $ \"echo v\" }\n")],
+    );
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}\" as s/\n",
+        repo.url()
+    ));
+
+    let err = failure(project.run(&["--offline", "--plan", "s/v"]));
+    assert!(err.contains("while offline"), "{}", err);
+
+    success(project.run(&[]));
+
+    // break the remote so that any network access would fail
+    std::fs::remove_dir_all(&repo.path).unwrap();
+    let out = success(project.run(&["--offline", "--plan", "s/v"]));
+    assert_eq!(out, "s/v\n");
+}
+
+#[test]
+fn clone_verifies_every_remote() {
+    let project = Project::new();
+    let good = BareRepo::new(&project, "good");
+    good.commit("master", &[("moldfile", "version \"0.7\"\n")]);
+    let empty = BareRepo::new(&project, "empty");
+    empty.commit("master", &[("README", "no moldfile here")]);
+    let broken = BareRepo::new(&project, "broken");
+    broken.commit("master", &[("moldfile", "version \"0.7\"\nrecipe {\n")]);
+
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}\" as e/\nimport \"{}\" as b/\nimport \"{}\" as g/\n",
+        empty.url(),
+        broken.url(),
+        good.url()
+    ));
+
+    let output = project.run(&["--clone"]);
+    assert!(!output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("moldfile missing at"), "{}", out);
+    assert!(out.contains("parse error:"), "{}", out);
+
+    // the good remote is still cloned and verified after the failures
+    let ok = out.lines().find(|line| line.contains("Ok")).unwrap();
+    assert!(ok.contains("good"), "{}", out);
+    assert!(stderr(&output).contains("2 of 3 remotes failed"));
+}
+
+#[test]
+fn piped_clone_reports_completion() {
+    for flags in &[vec![], vec!["--git"]] {
+        let project = Project::new();
+        let repo = BareRepo::new(&project, "shared");
+        repo.commit("master", &[("moldfile", "version \"0.7\"\n")]);
+        project.moldfile(&format!(
+            "version \"0.7\"\nimport \"{}\" as s/\n",
+            repo.url()
+        ));
+
+        let out = success(project.run(flags));
+        let done = out.lines().find(|line| line.contains("done in"));
+        assert!(done.is_some(), "{}", out);
+        assert!(done.unwrap().contains("Cloning"), "{}", out);
+        assert!(done.unwrap().ends_with('s'), "{}", out);
+    }
+}
+
+#[test]
+fn broken_imports_can_be_skipped() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[("moldfile", "version \"0.7\"\nrecipe v { $ \"echo one\" }\n")],
+    );
+
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}\" as s/\nrecipe local {{ $ \"echo local\" }}\n",
+        repo.url()
+    ));
+    success(project.run(&[]));
+
+    repo.commit("master", &[("moldfile", "recipe v { oops }\n")]);
+    success(project.run(&["--update"]));
+
+    // without the flag, anything that does more than list recipes fails
+    let err = failure(project.run(&["local"]));
+    assert!(err.contains("Couldn't compile"), "{}", err);
+    for flags in [
+        &["--exec", "echo hi"][..],
+        &["--install-hooks"],
+        &["--emit-script"],
+    ] {
+        let err = failure(project.run(flags));
+        assert!(err.contains("Couldn't compile"), "{:?}: {}", flags, err);
+    }
+
+    let out = project.run(&[]);
+    assert!(stderr(&out).contains("s/"), "{}", stderr(&out));
+    let out = success(out);
+    assert!(out.contains("local"), "{}", out);
+    assert!(!out.contains("s/v"), "{}", out);
+
+    let out = success(project.run(&["--skip-broken-imports", "local"]));
+    assert_eq!(command_output(&out), vec!["local"]);
+
+    let err = failure(project.run(&["--skip-broken-imports", "s/v"]));
+    assert!(err.contains("Couldn't load import"), "{}", err);
+    assert!(err.contains("Couldn't compile"), "{}", err);
+
+    // updating is how a broken remote gets fixed, so it has to keep working
+    repo.commit(
+        "master",
+        &[("moldfile", "version \"0.7\"\nrecipe v { $ \"echo two\" }\n")],
+    );
+    let out = project.run(&["--update"]);
+    let err = stderr(&out);
+    assert!(err.contains("skipped a broken import"), "{}", err);
+    success(out);
+
+    let out = success(project.run(&["s/v"]));
+    assert_eq!(command_output(&out), vec!["two"]);
+}
+
+#[test]
+fn imported_recipes_match_by_suffix() {
+    let project = Project::new();
+    let ci = BareRepo::new(&project, "ci");
+    ci.commit(
+        "master",
+        &[(
+            "moldfile",
+            "version \"0.7\"\nrecipe docker-build { $ \"echo ci build\" }\nrecipe lint { $ \"echo ci lint\" }\n",
+        )],
+    );
+    let web = BareRepo::new(&project, "web");
+    web.commit(
+        "master",
+        &[(
+            "moldfile",
+            "version \"0.7\"\nrecipe lint { $ \"echo web lint\" }\n",
+        )],
+    );
+
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}\" as ci/\nimport \"{}\" as web/\n",
+        ci.url(),
+        web.url()
+    ));
+    success(project.run(&[]));
+
+    let out = project.run(&["docker-build"]);
+    assert!(stderr(&out).contains("ci/docker-build"), "{}", stderr(&out));
+    assert_eq!(command_output(&success(out)), vec!["ci build"]);
+
+    let err = failure(project.run(&["lint"]));
+    assert!(err.contains("ambiguous"), "{}", err);
+    assert!(err.contains("ci/lint, web/lint"), "{}", err);
+
+    let err = failure(project.run(&["--no-suffix-match", "docker-build"]));
+    assert!(err.contains("Couldn't find recipe"), "{}", err);
+}
+
+#[test]
+fn import_versions_are_surfaced() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[("moldfile", "version \"0.7\"\nrecipe v { $ \"echo one\" }\n")],
+    );
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}\" as s/\n",
+        repo.url()
+    ));
+
+    let out = success(project.run(&["--imports"]));
+    let line = out.lines().find(|x| x.starts_with("s/ ")).unwrap();
+    assert!(line.contains(&format!("{}#master", repo.url())), "{}", out);
+    assert!(line.ends_with("(version 0.7)"), "{}", out);
+
+    // a newer requirement upstream can be fixed either by upgrading or by pinning an older ref
+    repo.commit(
+        "master",
+        &[(
+            "moldfile",
+            "version \"99.1\"\nrecipe v { $ \"echo two\" }\n",
+        )],
+    );
+    success(project.run(&["--update"]));
+
+    let err = failure(project.run(&["s/v"]));
+    assert!(err.contains("at ref master (commit "), "{}", err);
+    assert!(err.contains("requires version ^99.1"), "{}", err);
+    assert!(err.contains("upgrade mold to 99.1.0"), "{}", err);
+    assert!(err.contains("pin the import to an older ref"), "{}", err);
+
+    let out = success(project.run(&["--imports"]));
+    assert!(out.contains("(version 99.1)"), "{}", out);
+}
+
+#[test]
+fn lockfile_pins_imports() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[("moldfile", "version \"0.7\"\nrecipe v { $ \"echo one\" }\n")],
+    );
+
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}\" as s/\n",
+        repo.url()
+    ));
+
+    success(project.run(&[]));
+    let lock = std::fs::read_to_string(project.path("mold.lock")).unwrap();
+    assert!(lock.contains(&format!("{}#master", repo.url())), "{}", lock);
+
+    repo.commit(
+        "master",
+        &[("moldfile", "version \"0.7\"\nrecipe v { $ \"echo two\" }\n")],
+    );
+
+    // fresh clones check out the pinned commit rather than wherever the branch is now
+    for git in &[false, true] {
+        success(project.run(&["--clean"]));
+        let mut args = vec!["s/v"];
+        if *git {
+            args.push("--git");
+        }
+        let out = success(project.run(&args));
+        assert_eq!(command_output(&out).last(), Some(&"one"), "{}", out);
+    }
+    success(project.run(&["--frozen", "s/v"]));
+
+    let out = success(project.run(&["--update"]));
+    assert!(out.contains("Locked"), "{}", out);
+    assert_ne!(
+        std::fs::read_to_string(project.path("mold.lock")).unwrap(),
+        lock
+    );
+    let out = success(project.run(&["--frozen", "s/v"]));
+    assert_eq!(command_output(&out), vec!["two"]);
+
+    // an older lockfile moves the existing clone back to its commit
+    std::fs::write(project.path("mold.lock"), &lock).unwrap();
+    let out = success(project.run(&["s/v"]));
+    assert_eq!(command_output(&out).last(), Some(&"one"), "{}", out);
+
+    std::fs::remove_file(project.path("mold.lock")).unwrap();
+    let err = failure(project.run(&["--frozen", "s/v"]));
+    assert!(err.contains("mold.lock doesn't exist"), "{}", err);
+}
+
+#[test]
+fn scoped_imports_keep_their_variables() {
+    let project = Project::new();
+    let mut urls = vec![];
+    for name in &["api", "web"] {
+        let repo = BareRepo::new(&project, name);
+        repo.commit(
+            "master",
+            &[(
+                "moldfile",
+                &format!(
+                    "version \"0.7\"\nvar IMAGE = \"{}:latest\"\nrecipe show {{ $ \"echo $IMAGE $REGISTRY\" }}\n",
+                    name
+                ),
+            )],
+        );
+        urls.push(repo.url());
+    }
+
+    project.moldfile(&format!(
+        r#"
+        version "0.7"
+        requires_features scoped
+        var REGISTRY = "registry.local"
+        import "{}" as api/ scoped
+        import "{}" as web/ scoped
+        recipe show {{ $ "echo [$IMAGE]" }}
+        "#,
+        urls[0], urls[1],
+    ));
+
+    let out = success(project.run(&["api/show", "web/show", "show"]));
+    let lines = command_output(&out);
+    assert_eq!(
+        &lines[lines.len() - 3..],
+        &[
+            "api:latest registry.local",
+            "web:latest registry.local",
+            "[]"
+        ]
+    );
+
+    let out = success(project.run(&["--explain", "web/show"]));
+    assert!(out.contains("$IMAGE = web:latest (scope web/)"), "{}", out);
+
+    // the command line still wins
+    let out = success(project.run(&["-s", "IMAGE=mine", "api/show"]));
+    assert_eq!(command_output(&out).last(), Some(&"mine registry.local"));
+}
+
+#[test]
+fn git_cli_clones_shallowly() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    let greet = |x: &str| format!("version \"0.7\"\nrecipe greet {{ $ \"echo {}\" }}\n", x);
+    repo.commit("master", &[("moldfile", &greet("first"))]);
+    let first = repo.head();
+    repo.commit("master", &[("moldfile", &greet("second"))]);
+    repo.commit("master", &[("moldfile", &greet("third"))]);
+
+    // git ignores --depth for plain paths, so the import needs a file:// URL
+    let url = format!("file://{}", repo.url());
+    let import = |ref_: &str, env: Option<&str>| {
+        project.moldfile(&format!(
+            "version \"0.7\"\nimport \"{}#{}\" as s/\n",
+            url, ref_
+        ));
+        std::fs::remove_dir_all(project.path(".mold")).ok();
+        let mut cmd = project.mold();
+        if let Some(env) = env {
+            cmd.env("MOLD_FULL_CLONE", env);
+        }
+        let out = success(cmd.args(["--git", "s/greet"]).output().unwrap());
+        let clone = std::fs::read_dir(project.path(".mold"))
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .find(|x| x.join(".git").is_dir())
+            .expect("no clone in .mold");
+        let shallow = clone.join(".git/shallow").is_file();
+        (command_output(&out).last().unwrap().to_string(), shallow)
+    };
+
+    assert_eq!(import("master", None), ("third".into(), true));
+
+    // an older commit is fetched on its own
+    assert_eq!(import(&first, None), ("first".into(), true));
+
+    // an abbreviated commit id can't be, so the whole history is fetched for it
+    assert_eq!(import(&first[..7], None), ("first".into(), false));
+
+    assert_eq!(import("master", Some("1")), ("third".into(), false));
+}
+
+#[test]
+fn named_remotes_share_one_clone() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "tools");
+    repo.commit(
+        "master",
+        &[
+            (
+                "ci.mold",
+                "version \"0.7\"\nrecipe lint { $ \"echo linting\" }\n",
+            ),
+            (
+                "release.mold",
+                "version \"0.7\"\nrecipe ship { $ \"echo shipping\" }\n",
+            ),
+        ],
+    );
+
+    project.moldfile(&format!(
+        r#"
+version "0.7"
+remote tools = "{}#master"
+import tools "ci.mold" as ci/
+import tools "release.mold" as rel/
+"#,
+        repo.url()
+    ));
+
+    // each of these is printed once when starting, and again along with how long it took
+    let count = |out: &str, label: &str| out.lines().filter(|x| x.starts_with(label)).count() / 2;
+
+    let out = success(project.run(&["ci/lint", "rel/ship"]));
+    assert_eq!(count(&out, "Cloning"), 1, "{}", out);
+    assert!(
+        out.ends_with("linting\nmold [2/2] rel/ship\nmold rel/ship $ echo shipping\nshipping\n"),
+        "{}",
+        out
+    );
+
+    let clones = std::fs::read_dir(project.path(".mold"))
+        .unwrap()
+        .filter(|x| x.as_ref().unwrap().path().join(".git").is_dir())
+        .count();
+    assert_eq!(clones, 1);
+
+    let out = success(project.run(&["--update"]));
+    assert_eq!(count(&out, "Updating"), 1, "{}", out);
+
+    project.moldfile("version \"0.7\"\nimport tools \"ci.mold\"\n");
+    let err = failure(project.run(&[]));
+    assert!(err.contains("Unknown remote tools"), "{}", err);
+
+    project.moldfile("version \"0.7\"\nremote tools = \"example.com/x.git#master/ci.mold\"\n");
+    let err = failure(project.run(&[]));
+    assert!(err.contains("Remote tools names a file"), "{}", err);
+}
+
+#[test]
+fn missing_imports_are_cloned_together() {
+    let project = Project::new();
+    let nested = BareRepo::new(&project, "nested");
+    nested.commit(
+        "master",
+        &[(
+            "moldfile",
+            "version \"0.7\"\nrecipe deep { $ \"echo deep\" }\n",
+        )],
+    );
+
+    let repos: Vec<_> = ["one", "two", "three"]
+        .iter()
+        .map(|name| {
+            let repo = BareRepo::new(&project, name);
+            let import = match *name {
+                "one" => format!("import \"{}\" as nested/\n", nested.url()),
+                _ => String::new(),
+            };
+            repo.commit(
+                "master",
+                &[(
+                    "moldfile",
+                    &format!(
+                        "version \"0.7\"\n{}recipe hi {{ $ \"echo {}\" }}\n",
+                        import, name
+                    ),
+                )],
+            );
+            repo
+        })
+        .collect();
+
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}\" as one/\nimport \"{}\" as two/\nimport \"{}\" as three/\n",
+        repos[0].url(),
+        repos[1].url(),
+        repos[2].url()
+    ));
+
+    // the three direct imports in one go, then the one that the first of them imports
+    let out = success(project.run(&["one/hi", "two/hi", "three/hi", "nested/deep"]));
+    assert!(out.contains("Cloning 3 remotes into"), "{}", out);
+    assert!(
+        out.contains(&format!("Cloning {} into", nested.url())),
+        "{}",
+        out
+    );
+    let ran: Vec<_> = command_output(&out)
+        .into_iter()
+        .filter(|x| !x.starts_with("Cloning") && !x.starts_with("Updating"))
+        .collect();
+    assert_eq!(ran, vec!["one", "two", "three", "deep"]);
+
+    // a broken one fails the load, naming the remote that couldn't be cloned
+    let project = Project::new();
+    project.moldfile(&format!(
+        "version \"0.7\"\nimport \"{}\" as one/\nimport \"/nonexistent/repo.git\" as bad/\n",
+        repos[0].url()
+    ));
+    let output = project.run(&[]);
+    let out = stdout(&output);
+    let err = failure(output);
+    assert!(
+        err.contains("Couldn't clone /nonexistent/repo.git"),
+        "{}",
+        err
+    );
+    assert!(out.contains("Cloning 2 remotes into"), "{}", out);
+    assert!(!out.contains("done in"), "{}", out);
+}
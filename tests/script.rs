@@ -0,0 +1,110 @@
+mod common;
+
+use common::*;
+
+const MOLDFILE: &str = r#"
+version "0.7"
+var GREETING = "hello there"
+
+recipe prep {
+  dir "sub"
+  $ "sh -c 'echo prep in ${PWD##*/}'"
+}
+
+recipe release {
+  require prep
+  require greet with NAME="release"
+  $ "echo $GREETING"
+}
+
+recipe greet {
+  param NAME = "world"
+  run ["echo", "hi $NAME"]
+}
+
+recipe guarded {
+  requires_clean_tree
+  $ "echo never"
+}
+"#;
+
+#[test]
+fn emitted_script_matches_a_real_run() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+    std::fs::create_dir(project.path("sub")).unwrap();
+
+    let script = success(project.run(&["--emit-script", "release"]));
+    assert!(script.starts_with("#!/bin/sh"), "{}", script);
+    assert!(script.contains("set -o pipefail"), "{}", script);
+    project.file("release.sh", &script);
+
+    let expected = success(project.run(&["release"]));
+    let out = std::process::Command::new("sh")
+        .arg(project.path("release.sh"))
+        .current_dir("/")
+        .output()
+        .unwrap();
+    let out = success(out);
+    assert_eq!(command_output(&out), command_output(&expected));
+}
+
+#[test]
+fn emitted_powershell_script() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let script = success(project.run(&["--emit-script", "--shell", "powershell", "greet"]));
+    assert!(
+        script.contains("$ErrorActionPreference = 'Stop'"),
+        "{}",
+        script
+    );
+    assert!(
+        script.contains("$env:GREETING = 'hello there'"),
+        "{}",
+        script
+    );
+    assert!(script.contains("& 'echo' 'hi world'"), "{}", script);
+
+    let err = failure(project.run(&["--emit-script", "--shell", "fish", "greet"]));
+    assert!(err.contains("fish"), "{}", err);
+}
+
+#[test]
+fn untranslatable_recipes_abort_emission() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let err = failure(project.run(&["--emit-script", "guarded"]));
+    assert!(err.contains("clean tree"), "{}", err);
+
+    // shells only take letters, digits, and underscores in variable names
+    project.moldfile(
+        r#"
+version "0.7"
+var my-var = "x"
+recipe greet { $ "echo hi" }
+"#,
+    );
+    let err = failure(project.run(&["--emit-script", "greet"]));
+    assert!(err.contains("Variable my-var can't be emitted"), "{}", err);
+    let err = failure(project.run(&["--emit-script", "--shell", "powershell", "greet"]));
+    assert!(err.contains("Variable my-var can't be emitted"), "{}", err);
+
+    project.moldfile(
+        r#"
+version "0.7"
+recipe deploy {
+  param target:env = "prod"
+  $ "echo hi"
+}
+"#,
+    );
+    let err = failure(project.run(&["--emit-script", "deploy"]));
+    assert!(
+        err.contains("Recipe deploy can't be emitted as a script because its variable target:env"),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::*;
+
+/// Show the effective settings as (name, value, source) triples
+fn show(project: &Project, args: &[&str]) -> Vec<(String, String, String)> {
+    let output = project
+        .mold()
+        .env("MOLD_CONFIG", project.path("user-config"))
+        .arg("--show-settings")
+        .args(args)
+        .output()
+        .unwrap();
+
+    success(output)
+        .lines()
+        .map(|line| {
+            let mut parts = line.trim().splitn(3, ' ');
+            let name = parts.next().unwrap().to_string();
+            let value = parts.next().unwrap().to_string();
+            let source = parts.next().unwrap().trim_matches(&['(', ')'][..]);
+            (name, value, source.to_string())
+        })
+        .collect()
+}
+
+fn setting(settings: &[(String, String, String)], name: &str) -> (String, String) {
+    let (_, value, source) = settings.iter().find(|(x, _, _)| x == name).unwrap();
+    (value.clone(), source.clone())
+}
+
+#[test]
+fn settings_follow_precedence() {
+    let project = Project::new();
+    project.moldfile("version \"0.7\"\nrecipe a { $ \"echo a\" }\n");
+
+    let settings = show(&project, &[]);
+    assert_eq!(setting(&settings, "jobs"), ("1".into(), "default".into()));
+
+    project.file(
+        "user-config",
+        "settings {\n  jobs 2\n  prefix_output true\n}\n",
+    );
+    let settings = show(&project, &[]);
+    let (value, source) = setting(&settings, "jobs");
+    assert_eq!(value, "2");
+    assert!(source.starts_with("user config"), "{}", source);
+
+    project.moldfile("version \"0.7\"\nsettings { jobs 4 }\nrecipe a { $ \"echo a\" }\n");
+    let settings = show(&project, &[]);
+    let (value, source) = setting(&settings, "jobs");
+    assert_eq!(value, "4");
+    assert!(source.starts_with("moldfile"), "{}", source);
+
+    // the user config still provides anything the moldfile doesn't mention
+    let (value, source) = setting(&settings, "prefix_output");
+    assert_eq!(value, "true");
+    assert!(source.starts_with("user config"), "{}", source);
+
+    let settings = show(&project, &["-j", "3"]);
+    assert_eq!(
+        setting(&settings, "jobs"),
+        ("3".into(), "command line".into())
+    );
+}
+
+#[test]
+fn settings_seed_options() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+settings {
+  prefix_output true
+}
+recipe a { $ "echo a" }
+"#,
+    );
+
+    let out = success(project.run(&["a"]));
+    assert!(out.lines().any(|x| x == "a | a"), "{}", out);
+}
+
+#[test]
+fn unknown_settings_are_errors() {
+    let project = Project::new();
+    project.moldfile("version \"0.7\"\nsettings { colour always }\nrecipe a { $ \"true\" }\n");
+    let err = failure(project.run(&[]));
+    assert!(err.contains("Unknown setting colour"), "{}", err);
+
+    project.moldfile("version \"0.7\"\nsettings { jobs many }\nrecipe a { $ \"true\" }\n");
+    let err = failure(project.run(&[]));
+    assert!(
+        err.contains("Invalid value many for setting jobs"),
+        "{}",
+        err
+    );
+}
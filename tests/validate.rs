@@ -0,0 +1,73 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn validation_reports_errors_and_warnings() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[(
+            "moldfile",
+            "version \"0.7\"\nrecipe shared { $ \"echo lib\" }\n",
+        )],
+    );
+    project.moldfile(&format!(
+        r#"
+version "0.7"
+import "{}"
+var NAME = "mold"
+
+recipe build {{
+  require lint
+  $ "echo $NAME $MOLD_VALIDATE_UNDEFINED"
+}}
+
+recipe shared {{
+  $ "echo root"
+}}
+"#,
+        repo.url()
+    ));
+
+    let out = project.run(&["--validate"]);
+    let report = stdout(&out);
+    let err = failure(out);
+    assert!(err.contains("Found 1 error(s) and 2 warning(s)"), "{}", err);
+    assert!(
+        report.contains("error: recipe build requires lint, which doesn't exist"),
+        "{}",
+        report
+    );
+    assert!(report.contains("--> "), "{}", report);
+    assert!(report.contains("moldfile:6"), "{}", report);
+    assert!(
+        report.contains("warning: recipe build refers to $MOLD_VALIDATE_UNDEFINED"),
+        "{}",
+        report
+    );
+    assert!(!report.contains("$NAME"), "{}", report);
+    assert!(
+        report.contains("recipe shared is defined again"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn warnings_alone_are_valid() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe greet {
+  $ "echo $MOLD_VALIDATE_UNDEFINED"
+}
+"#,
+    );
+
+    let out = success(project.run(&["--validate"]));
+    assert!(out.contains("Valid"), "{}", out);
+    assert!(out.contains("(1 warning(s))"), "{}", out);
+}
@@ -0,0 +1,690 @@
+mod common;
+
+use common::*;
+use mold::util::convert_windows_vars;
+
+const MOLDFILE: &str = r#"
+version "0.7"
+var NAME = "world"
+var GREETING = "hello $NAME"
+var EMPTY = ""
+"#;
+
+#[test]
+fn get_var_prints_expanded_value() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["--get-var", "GREETING"]));
+    assert_eq!(out, "hello world\n");
+
+    let out = success(project.run(&["--get-var", "MOLD_ROOT"]));
+    assert_eq!(out, format!("{}\n", project.root().display()));
+}
+
+#[test]
+fn get_var_prints_empty_value() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["--get-var", "EMPTY"]));
+    assert_eq!(out, "\n");
+}
+
+#[test]
+fn get_var_errors_when_undefined() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let output = project
+        .mold()
+        .env_remove("NOT_DEFINED_ANYWHERE")
+        .args(["--get-var", "NOT_DEFINED_ANYWHERE"])
+        .output()
+        .unwrap();
+    assert!(stdout(&output).is_empty());
+    let err = failure(output);
+    assert!(err.contains("is not defined"), "{}", err);
+}
+
+#[test]
+fn get_var_falls_back_to_environment() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let output = project
+        .mold()
+        .env("FROM_THE_ENV", "yes")
+        .args(["--get-var", "FROM_THE_ENV"])
+        .output()
+        .unwrap();
+    assert_eq!(success(output), "yes\n");
+}
+
+#[test]
+fn params_are_bound_from_defaults_environment_and_requires() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+
+recipe deploy {
+  param IMAGE = "api"
+  param secret TOKEN
+  $ "echo deploying $IMAGE"
+}
+
+recipe all {
+  require deploy with IMAGE="web", TOKEN="abc"
+}
+"#,
+    );
+
+    // without a TTY, missing parameters are an error rather than a prompt
+    let err = failure(project.run(&["deploy"]));
+    assert!(err.contains("TOKEN"), "{}", err);
+    assert!(!err.contains("Enter value"), "{}", err);
+
+    // and forcing non-interactive behavior is the same
+    let err = failure(project.run(&["--non-interactive", "deploy"]));
+    assert!(err.contains("TOKEN"), "{}", err);
+    let err = failure(
+        project
+            .mold()
+            .env("MOLD_NON_INTERACTIVE", "1")
+            .arg("deploy")
+            .output()
+            .unwrap(),
+    );
+    assert!(err.contains("TOKEN"), "{}", err);
+
+    let out = success(
+        project
+            .mold()
+            .env("TOKEN", "abc")
+            .arg("deploy")
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(command_output(&out), vec!["deploying api"]);
+
+    let out = success(project.run(&["all"]));
+    assert_eq!(command_output(&out), vec!["deploying web"]);
+
+    let out = success(project.run(&["--no-interactive", "--explain", "deploy"]));
+    assert!(out.contains("$IMAGE = api"), "{}", out);
+    assert!(out.contains("$TOKEN (secret)"), "{}", out);
+}
+
+#[test]
+fn secret_params_are_masked() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+
+recipe deploy {
+  param secret DB_PASS = "hunter2"
+  $ "sh -c 'exit 3'"
+}
+"#,
+    );
+
+    let err = failure(project.run(&["deploy", "DB_PASS=swordfish"]));
+    assert!(err.contains("DB_PASS=****"), "{}", err);
+    assert!(!err.contains("swordfish"), "{}", err);
+
+    let output = project.run(&["--dry-run", "deploy"]);
+    let err = stderr(&output);
+    assert!(err.contains("DB_PASS=****"), "{}", err);
+    assert!(!err.contains("hunter2"), "{}", err);
+
+    let out = success(project.run(&["--explain", "deploy"]));
+    assert!(out.contains("$DB_PASS = **** (secret)"), "{}", out);
+    assert!(!out.contains("hunter2"), "{}", out);
+
+    let out = success(project.run(&["--format", "json", "--explain", "deploy"]));
+    assert!(out.contains("\"DB_PASS\": \"****\""), "{}", out);
+    assert!(!out.contains("hunter2"), "{}", out);
+
+    let out = success(project.run(&["--dump", "json"]));
+    assert!(out.contains("\"default\": \"****\""), "{}", out);
+    assert!(!out.contains("hunter2"), "{}", out);
+}
+
+#[test]
+fn params_can_start_with_secret() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+
+recipe show {
+  param secret_key = "abc"
+  param secretary = "jo"
+  $ "echo [$secret_key] [$secretary]"
+}
+"#,
+    );
+
+    let out = success(project.run(&["show"]));
+    assert_eq!(command_output(&out), vec!["[abc] [jo]"]);
+
+    let out = success(project.run(&["--explain", "show"]));
+    assert!(out.contains("$secret_key = abc\n"), "{}", out);
+    assert!(out.contains("$secretary = jo\n"), "{}", out);
+    assert!(!out.contains("(secret)"), "{}", out);
+}
+
+#[test]
+fn exec_sees_what_recipes_see() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+var NAME = "world"
+var GREETING = "hello $NAME"
+dir "sub"
+
+recipe show {
+  $ "sh -c 'pwd; echo $GREETING; echo $MOLD_ROOT'"
+}
+"#,
+    );
+    project.file("sub/.keep", "");
+
+    let recipe = success(project.run(&["show"]));
+    let exec = success(project.run(&["--exec", "sh -c 'pwd; echo $GREETING; echo $MOLD_ROOT'"]));
+    assert_eq!(command_output(&exec), command_output(&recipe));
+    assert_eq!(
+        command_output(&exec),
+        vec![
+            project.path("sub").display().to_string(),
+            "hello world".to_string(),
+            project.root().display().to_string(),
+        ]
+    );
+
+    // the command is echoed and its exit status is checked like a recipe's
+    assert!(exec.contains("mold exec $ sh -c"), "{}", exec);
+    let err = failure(project.run(&["--exec", "false"]));
+    assert!(err.contains("non-zero exit status"), "{}", err);
+}
+
+#[test]
+fn arguments_after_dashes_are_positional() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe dep { $ "printf '<%s>\\n' dep $1 $@" }
+recipe deploy {
+  require dep
+  $ "printf '<%s>\\n' $1 $2"
+  $ "printf '<%s>\\n' $@"
+  run ["printf", "<%s>\\n", "$@"]
+}
+"#,
+    );
+
+    let out = success(project.run(&["deploy", "--", "staging", "two words"]));
+    assert_eq!(
+        command_output(&out),
+        vec![
+            "<dep>",
+            "<staging>",
+            "<two words>",
+            "<staging>",
+            "<two words>",
+            "<staging>",
+            "<two words>",
+        ]
+    );
+
+    // without any arguments, they expand to nothing like any other unset variable
+    let out = success(project.run(&["deploy"]));
+    assert_eq!(command_output(&out), vec!["<dep>", "<>", "<>", "<>"]);
+
+    // dependencies don't get the arguments, but $@ still expands to nothing for them
+    let out = success(project.run(&["dep"]));
+    assert_eq!(command_output(&out), vec!["<dep>"]);
+}
+
+#[test]
+fn params_are_bound_from_the_command_line() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe deploy {
+  param ENV_NAME
+  param REGION = "us"
+  $ "echo deploying to $ENV_NAME in $REGION"
+}
+"#,
+    );
+
+    let out = success(project.run(&["deploy", "--", "staging"]));
+    assert_eq!(command_output(&out), vec!["deploying to staging in us"]);
+
+    let out = success(project.run(&["deploy", "--", "staging", "eu"]));
+    assert_eq!(command_output(&out), vec!["deploying to staging in eu"]);
+
+    let out = success(project.run(&["deploy", "REGION=ap", "ENV_NAME=prod"]));
+    assert_eq!(command_output(&out), vec!["deploying to prod in ap"]);
+
+    // named values win over positional ones
+    let out = success(project.run(&["deploy", "ENV_NAME=prod", "--", "staging"]));
+    assert_eq!(command_output(&out), vec!["deploying to prod in us"]);
+
+    let err = failure(project.run(&["deploy"]));
+    assert!(
+        err.contains("Recipe deploy needs a value for parameter ENV_NAME"),
+        "{}",
+        err
+    );
+
+    let err = failure(project.run(&["deploy", "ENV=prod"]));
+    assert!(
+        err.contains("None of the targets have a parameter named ENV"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn set_overrides_moldfile_vars() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+var VERSION = "0.1.0"
+var CHANNEL := "stable"
+var TAG = "v$VERSION"
+if $CHANNEL == "beta" {
+  var SUFFIX = "-beta"
+}
+recipe show { $ "echo $TAG $CHANNEL$SUFFIX" }
+"#,
+    );
+
+    let out = success(project.run(&["show"]));
+    assert_eq!(command_output(&out), vec!["v0.1.0 stable"]);
+
+    // later uses and conditions see the new values too
+    let out = success(project.run(&["-s", "VERSION=1.2.3", "--set", "CHANNEL=beta", "show"]));
+    assert_eq!(command_output(&out), vec!["v1.2.3 beta-beta"]);
+
+    let err = failure(project.run(&["--set", "VERSION", "show"]));
+    assert!(
+        err.contains("Invalid --set VERSION; expected KEY=VALUE"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn positionals_follow_their_recipe() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+recipe render {
+  positional IN OUT
+  $ "echo $IN -> $OUT"
+}
+recipe done { $ "echo done" }
+"#,
+    );
+
+    // positional values are taken as-is, even when they look like a recipe
+    let out = success(project.run(&["render", "a.md", "done", "done"]));
+    assert_eq!(command_output(&out), vec!["a.md -> done", "done"]);
+
+    let err = failure(project.run(&["render", "a.md"]));
+    assert!(
+        err.contains("Recipe render takes 2 arguments, but got 1; usage: mold render IN OUT"),
+        "{}",
+        err
+    );
+
+    let err = failure(project.run(&["render", "a.md", "b.html", "c.html"]));
+    assert!(
+        err.contains("but c.html was given too; usage: mold render IN OUT"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn unset_removes_variables() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+var SHARED = "shared"
+unset INHERITED
+recipe show { $ "sh -c 'echo [$$INHERITED-$${INHERITED-none}] [$${SHARED-none}]'" }
+recipe local {
+  unset SHARED
+  $ "sh -c 'echo [$SHARED] [$${SHARED-none}]'"
+}
+recipe after { require local $ "echo $SHARED" }
+"#,
+    );
+
+    let out = success(
+        project
+            .mold()
+            .env("INHERITED", "x")
+            .arg("show")
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(command_output(&out), vec!["[-none] [shared]"]);
+
+    // unsetting in a recipe doesn't affect the recipes around it
+    let out = success(project.run(&["after"]));
+    assert_eq!(command_output(&out), vec!["[] [none]", "shared"]);
+}
+
+#[test]
+fn vars_from_command_output() {
+    let project = Project::new();
+    project.file("VERSION", "  1.2.3\n");
+    project.moldfile(
+        r#"
+version "0.7"
+var FILE = "VERSION"
+var RELEASE = shell "cat $FILE"
+var PRICE = shell "printf '$$5\n'"
+var TAGGED = "v$RELEASE"
+"#,
+    );
+
+    // expanded with the variables before it, run from the root, and trimmed
+    let out = success(
+        project
+            .mold_in("sub")
+            .args(["--get-var", "TAGGED"])
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(out, "v1.2.3\n");
+
+    // what it prints isn't expanded again
+    let out = success(project.run(&["--get-var", "PRICE"]));
+    assert_eq!(out, "$5\n");
+
+    project.moldfile("version \"0.7\"\nvar BROKEN = shell \"sh -c 'exit 3'\"\n");
+    let err = failure(project.run(&["--get-var", "BROKEN"]));
+    assert!(
+        err.contains("Command sh -c 'exit 3' for variable BROKEN exited with exit status: 3"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn dotenv_files_provide_defaults() {
+    let project = Project::new();
+    project.file(
+        ".env",
+        r#"
+# comments and blank lines are skipped
+BARE=plain value # trailing comment
+export EXPORTED=yes
+DOUBLE="two\nlines $HOME"
+SINGLE='kept # as is'
+FROM_SHELL=from dotenv
+REPLACED=from dotenv
+DEFAULTED=from dotenv
+"#,
+    );
+    project.moldfile(
+        r#"
+version "0.7"
+var REPLACED = "from moldfile"
+var DEFAULTED := "from moldfile"
+recipe show { $ "echo [$BARE] [$EXPORTED] [$FROM_SHELL] [$REPLACED] [$DEFAULTED]" }
+"#,
+    );
+
+    let out = success(
+        project
+            .mold()
+            .env("FROM_SHELL", "from shell")
+            .arg("show")
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(
+        command_output(&out),
+        vec!["[plain value] [yes] [from shell] [from moldfile] [from dotenv]"]
+    );
+
+    // values are literal, with escapes only in double quotes
+    let out = success(project.run(&["--get-var", "DOUBLE"]));
+    assert_eq!(out, "two\nlines $HOME\n");
+    let out = success(project.run(&["--get-var", "SINGLE"]));
+    assert_eq!(out, "kept # as is\n");
+
+    let out = success(project.run(&["--no-dotenv", "--get-var", "DEFAULTED"]));
+    assert_eq!(out, "from moldfile\n");
+}
+
+#[test]
+fn env_file_names_another_file() {
+    let project = Project::new();
+    project.file(".env", "WHICH=default\n");
+    project.file("config/local.env", "WHICH=named\n");
+    project.moldfile("version \"0.7\"\nenv_file \"config/local.env\"\n");
+
+    let out = success(
+        project
+            .mold_in("config")
+            .args(["--get-var", "WHICH"])
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(out, "named\n");
+
+    // unlike .env, a file that's named has to be there
+    project.moldfile("version \"0.7\"\nenv_file \"missing.env\"\n");
+    let err = failure(project.run(&["--get-var", "WHICH"]));
+    assert!(err.contains("Couldn't read"), "{}", err);
+
+    project.file(".env", "not a variable\n");
+    project.moldfile("version \"0.7\"\n");
+    let err = failure(project.run(&["--get-var", "WHICH"]));
+    assert!(err.contains("line 1 has no '='"), "{}", err);
+}
+
+#[test]
+fn required_vars_are_checked_up_front() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+require_var REGION
+
+recipe setup {
+  run "touch setup-ran"
+}
+
+recipe deploy {
+  require_var TOKEN "an API token from the dashboard"
+  require setup
+  run "echo [$REGION $TOKEN]"
+}
+"#,
+    );
+
+    // nothing runs when something that's about to run is missing a variable
+    let err = failure(project.run(&["--set", "REGION=eu", "deploy"]));
+    assert!(
+        err.contains("Recipe deploy requires variable TOKEN (an API token from the dashboard)"),
+        "{}",
+        err
+    );
+    assert!(!project.path("setup-ran").exists());
+
+    // the moldfile-wide one applies to every recipe
+    let err = failure(project.run(&["setup"]));
+    assert!(
+        err.contains("Recipe setup requires variable REGION"),
+        "{}",
+        err
+    );
+
+    // either the environment or --set will do
+    let out = success(
+        project
+            .mold()
+            .env("TOKEN", "secret")
+            .args(["--set", "REGION=eu", "deploy"])
+            .output()
+            .unwrap(),
+    );
+    assert_eq!(command_output(&out), vec!["[eu secret]"]);
+    assert!(project.path("setup-ran").exists());
+}
+
+#[test]
+fn append_and_prepend_compose_in_load_order() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[
+            (
+                "a.mold",
+                "version \"0.7\"\nvar append PATHS = \":a\"\nvar prepend FLAGS = \"a \"\nvar SHARED = \"a\"\n",
+            ),
+            (
+                "b.mold",
+                "version \"0.7\"\nvar append PATHS = \":b\"\nvar prepend FLAGS = \"b \"\nvar SHARED = \"b\"\n",
+            ),
+        ],
+    );
+
+    project.moldfile(&format!(
+        r#"
+version "0.7"
+remote shared = "{}#master"
+import shared "a.mold" as a/
+import shared "b.mold" as b/
+var append PATHS = ":root"
+var prepend FLAGS = "root "
+"#,
+        repo.url()
+    ));
+    success(project.run(&[]));
+
+    // imports come first, in the order they're imported, and the root moldfile last
+    let get = |name: &str| {
+        let output = project
+            .mold()
+            .env("PATHS", "env")
+            .env("FLAGS", "env")
+            .args(["--get-var", name])
+            .output()
+            .unwrap();
+        (stderr(&output), success(output))
+    };
+    let (err, out) = get("PATHS");
+    assert_eq!(out, "env:a:b:root\n");
+    assert_eq!(get("FLAGS").1, "root b a env\n");
+
+    // two imports setting the same variable is most likely a mistake
+    assert_eq!(get("SHARED").1, "b\n");
+    assert!(err.contains("$SHARED from"), "{}", err);
+    assert!(err.contains("b.mold replaces value set by"), "{}", err);
+    assert!(err.contains("a.mold"), "{}", err);
+
+    // but the root moldfile is expected to override its imports
+    let contents = std::fs::read_to_string(project.path("moldfile")).unwrap();
+    project.moldfile(&format!("{}var SHARED = \"root\"\n", contents));
+    let output = project.run(&["--get-var", "SHARED"]);
+    let err = stderr(&output);
+    assert_eq!(success(output), "root\n");
+    assert_eq!(err.matches("replaces value set by").count(), 1, "{}", err);
+}
+
+#[test]
+fn windows_vars_are_converted() {
+    let cases = [
+        ("%HOME%/bin:$PATH", "${HOME}/bin:$PATH"),
+        ("${X}%Y%$Z", "${X}${Y}$Z"),
+        ("%A%%B%", "${A}${B}"),
+        ("50%%", "50%"),
+        ("50%%%NAME%", "50%${NAME}"),
+        ("%%PATH%%", "%PATH%"),
+        ("100% of %NAME%", "100% of ${NAME}"),
+        ("%unterminated", "%unterminated"),
+        ("%not a var%", "%not a var%"),
+    ];
+    for (windows, converted) in cases {
+        assert_eq!(convert_windows_vars(windows), converted, "{}", windows);
+    }
+
+    let project = Project::new();
+    project.moldfile(
+        r#"
+version "0.7"
+compat windows_vars
+var NAME = "world"
+var BOTH = "%NAME% $NAME"
+
+recipe show {
+  $ "echo [50%%] [%NAME%%NAME%] [$BOTH] [%%NAME%%]"
+}
+"#,
+    );
+    let out = success(project.run(&["show"]));
+    assert_eq!(
+        command_output(&out),
+        vec!["[50%] [worldworld] [world world] [%NAME%]"]
+    );
+
+    // without it, percent signs are just text
+    project.moldfile(
+        "version \"0.7\"\nvar NAME = \"world\"\nrecipe show { $ \"echo [%NAME%] [50%%]\" }\n",
+    );
+    let out = success(project.run(&["show"]));
+    assert_eq!(command_output(&out), vec!["[%NAME%] [50%%]"]);
+}
+
+#[test]
+fn windows_vars_only_apply_to_their_moldfile() {
+    let project = Project::new();
+    let repo = BareRepo::new(&project, "shared");
+    repo.commit(
+        "master",
+        &[(
+            "moldfile",
+            r#"
+            version "0.7"
+            compat windows_vars
+            var WHO = "shared"
+            recipe show { $ "echo [%WHO%] [50%%]" }
+            "#,
+        )],
+    );
+    project.moldfile(&format!(
+        r#"
+        version "0.7"
+        import "{}" as s/
+        var NAME = "world"
+        recipe show {{ require s/show $ "echo [%NAME%] [50%%]" }}
+        "#,
+        repo.url()
+    ));
+
+    success(project.run(&[]));
+    let out = success(project.run(&["show"]));
+    assert_eq!(
+        command_output(&out),
+        vec!["[shared] [50%]", "[%NAME%] [50%%]"]
+    );
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn matching_version_loads() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version ">=0.1"
+        recipe ok { $ "echo ok" }
+        "#,
+    );
+
+    let out = success(project.run(&["ok"]));
+    assert_eq!(command_output(&out), vec!["ok"]);
+}
+
+#[test]
+fn version_mismatch_errors() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "99.0"
+        recipe ok { $ "echo ok" }
+        "#,
+    );
+
+    let err = failure(project.run(&["ok"]));
+    assert!(err.contains("requires version"), "{}", err);
+}
+
+#[test]
+fn missing_version_errors() {
+    let project = Project::new();
+    project.moldfile(r#"recipe ok { $ "echo ok" }"#);
+
+    let err = failure(project.run(&["ok"]));
+    assert!(err.contains("File version must be specified"), "{}", err);
+}
+
+#[test]
+fn required_features_are_checked() {
+    let project = Project::new();
+    project.moldfile(
+        r#"
+        version "0.7"
+        requires_features check umask
+        check "ok" { $ "true" }
+        recipe a { umask "022" $ "echo a" }
+        "#,
+    );
+    let out = success(project.run(&["a"]));
+    assert_eq!(command_output(&out), vec!["a"]);
+
+    // an unknown feature is reported even though the rest of the file doesn't parse
+    project.moldfile(
+        r#"
+        version "0.7"
+        requires_features umask container
+        container "alpine" { $ "echo hi" }
+        "#,
+    );
+    let err = failure(project.run(&[]));
+    assert!(
+        err.contains("this moldfile needs the 'container' feature"),
+        "{}",
+        err
+    );
+
+    // the list ends at the end of the line
+    project
+        .moldfile("version \"0.7\"\nrequires_features weight\nrecipe b { weight 2 $ \"true\" }\n");
+    success(project.run(&["b"]));
+
+    project.moldfile(
+        r#"
+        version "0.7"
+        requires_features compat append prepend
+        compat windows_vars
+        var PATH_LIST = "b"
+        var append PATH_LIST = ":c"
+        var prepend PATH_LIST = "a:"
+        recipe c { $ "echo %PATH_LIST%" }
+        "#,
+    );
+    let out = success(project.run(&["c"]));
+    assert_eq!(command_output(&out), vec!["a:b:c"]);
+}
+
+#[test]
+fn features_are_sorted() {
+    let mut sorted = mold::lang::FEATURES.to_vec();
+    sorted.sort_unstable();
+    assert_eq!(mold::lang::FEATURES, sorted.as_slice());
+}
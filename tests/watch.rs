@@ -0,0 +1,158 @@
+mod common;
+
+use common::*;
+use std::process::Child;
+use std::process::Output;
+use std::process::Stdio;
+use std::time::Duration;
+use std::time::Instant;
+
+const MOLDFILE: &str = r#"
+version "0.7"
+
+recipe build {
+  watch ["src/**/*.txt"]
+  $ "sh -c 'cat src/input.txt >> runs.log'"
+}
+
+recipe copy {
+  $ "sh -c 'mkdir -p out && cat input.txt >> out/runs.log'"
+}
+"#;
+
+/// Wait up to a few seconds for a file to have the given number of lines
+fn wait_for_lines(project: &Project, rel: &str, count: usize) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(10) {
+        let contents = std::fs::read_to_string(project.path(rel)).unwrap_or_default();
+        if contents.lines().count() >= count {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+fn watch(project: &Project, args: &[&str]) -> Child {
+    project
+        .mold()
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+/// Press Ctrl-C and collect everything that was printed
+fn interrupt(child: Child) -> Output {
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn watched_files_rerun_recipes() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+    project.file("src/input.txt", "first\n");
+
+    let child = watch(&project, &["--watch", "build"]);
+    let ran_once = wait_for_lines(&project, "runs.log", 1);
+    if ran_once {
+        // make sure the change lands in a later modification time than the first look
+        std::thread::sleep(Duration::from_millis(1100));
+        project.file("src/nested/other.txt", "ignored\n");
+        project.file("src/input.txt", "second\n");
+    }
+    let ran_twice = ran_once && wait_for_lines(&project, "runs.log", 2);
+
+    // Ctrl-C stops watching without an error
+    let out = interrupt(child);
+    assert!(ran_twice, "{}{}", stdout(&out), stderr(&out));
+    assert!(out.status.success(), "{}", stderr(&out));
+
+    let log = std::fs::read_to_string(project.path("runs.log")).unwrap();
+    assert!(log.starts_with("first\nsecond\n"), "{}", log);
+    let out = stdout(&out);
+    assert!(out.contains("re-running build"), "{}", out);
+    assert!(out.contains("Stopped"), "{}", out);
+}
+
+#[cfg(unix)]
+#[test]
+fn unwatched_recipes_watch_their_source() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+    project.file("input.txt", "first\n");
+    project.file(".gitignore", "out/\n");
+    git(&project.root(), &["init", "-q"]);
+
+    let child = watch(&project, &["-w", "copy"]);
+    let ran_once = wait_for_lines(&project, "out/runs.log", 1);
+    if ran_once {
+        std::thread::sleep(Duration::from_millis(1100));
+        project.file("input.txt", "second\n");
+    }
+    let ran_twice = ran_once && wait_for_lines(&project, "out/runs.log", 2);
+
+    // what the recipe writes is ignored, so it doesn't set off another run
+    std::thread::sleep(Duration::from_millis(1000));
+    let out = interrupt(child);
+    assert!(ran_twice, "{}{}", stdout(&out), stderr(&out));
+
+    let log = std::fs::read_to_string(project.path("out/runs.log")).unwrap();
+    assert_eq!(log, "first\nsecond\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn moldfile_changes_reload_recipes() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+    project.file("src/input.txt", "first\n");
+
+    let child = watch(&project, &["--watch", "build"]);
+    let mut ran = wait_for_lines(&project, "runs.log", 1);
+
+    // a moldfile that doesn't parse is reported and doesn't re-run anything
+    if ran {
+        std::thread::sleep(Duration::from_millis(1100));
+        project.moldfile("version \"0.7\"\nrecipe build {\n");
+        std::thread::sleep(Duration::from_millis(1500));
+        ran = std::fs::read_to_string(project.path("runs.log")).unwrap() == "first\n";
+    }
+
+    if ran {
+        let fixed = MOLDFILE
+            .replace("cat src/input.txt", "cat src/input.txt src/input.txt")
+            .replace("recipe copy", "recipe paste");
+        project.moldfile(&fixed);
+        ran = wait_for_lines(&project, "runs.log", 3);
+    }
+
+    let out = interrupt(child);
+    let (err, out) = (stderr(&out), stdout(&out));
+    assert!(ran, "{}{}", out, err);
+    assert!(err.contains("Error"), "{}", err);
+    assert!(out.contains("Keeping"), "{}", out);
+    assert!(out.contains("Changed build"), "{}", out);
+    assert!(out.contains("Added paste"), "{}", out);
+    assert!(out.contains("Removed copy"), "{}", out);
+    assert!(
+        out.contains("moldfile changed, re-running build"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn watch_patterns_are_explained() {
+    let project = Project::new();
+    project.moldfile(MOLDFILE);
+
+    let out = success(project.run(&["--explain", "build"]));
+    assert!(out.contains("watches: src/**/*.txt"), "{}", out);
+}
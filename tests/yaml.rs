@@ -0,0 +1,179 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn legacy_yaml_moldfile_loads() {
+    let project = Project::new();
+    project.file(
+        "mold.yaml",
+        r#"
+version: "0.6"
+
+variables:
+  GREETING: "hello"
+
+environments:
+  loud:
+    GREETING: "HELLO"
+
+recipes:
+  greet:
+    help: "Say hello"
+    command: "echo $GREETING"
+  list:
+    command: ["printf", "[%s]", "a b"]
+  both:
+    deps: ["greet"]
+    script: "echo one && echo two"
+  odd:
+    runtime: "python"
+"#,
+    );
+
+    let output = project.run(&[]);
+    let err = stderr(&output);
+    assert!(err.contains("deprecated YAML format"), "{}", err);
+    assert!(err.contains("mold --migrate"), "{}", err);
+    assert!(err.contains("recipe odd key runtime"), "{}", err);
+    assert!(stdout(&output).contains("Say hello"));
+
+    let out = success(project.run(&["greet"]));
+    assert_eq!(command_output(&out), vec!["hello"]);
+
+    let out = success(project.run(&["--env", "loud", "greet"]));
+    assert_eq!(command_output(&out), vec!["HELLO"]);
+
+    let out = success(project.run(&["list"]));
+    assert_eq!(command_output(&out), vec!["[a b]"]);
+
+    let out = success(project.run(&["both"]));
+    assert_eq!(command_output(&out), vec!["hello", "one", "two"]);
+}
+
+#[test]
+fn moldfile_wins_over_yaml() {
+    let project = Project::new();
+    project.file(
+        "mold.yaml",
+        "version: \"0.6\"\nrecipes:\n  old:\n    command: \"echo old\"\n",
+    );
+    project.moldfile("version \"0.7\"\nrecipe new { $ \"echo new\" }\n");
+
+    let out = success(project.run(&["new"]));
+    assert_eq!(command_output(&out), vec!["new"]);
+}
+
+#[test]
+fn migrate_round_trip() {
+    let project = Project::new();
+    project.file(
+        "mold.yaml",
+        r#"
+version: "0.6"
+
+includes:
+  - url: "github.com/example/shared"
+    ref: "dev"
+    prefix: "s/"
+    private: true
+
+variables:
+  NAME: "tab\tand \\ backslash"
+
+environments:
+  ci:
+    NAME: "ci"
+
+recipes:
+  greet:
+    help: "Say \"hello\""
+    dir: "sub"
+    command: "echo $NAME"
+  list:
+    deps: ["greet"]
+    command: ["printf", "[%s]", "a\tb"]
+  script:
+    script: "echo one\necho two"
+  tidy:
+    help: "Tidy up"
+    command: "echo tidy"
+"#,
+    );
+
+    let out = success(project.run(&["--migrate"]));
+    assert!(out.contains("Migrated"), "{}", out);
+    assert!(
+        out.contains("include github.com/example/shared key private"),
+        "{}",
+        out
+    );
+    assert!(project.path("mold.yaml").is_file());
+
+    let moldfile = std::fs::read_to_string(project.path("moldfile")).unwrap();
+    assert!(moldfile.contains("import \"github.com/example/shared#dev\" as s/"));
+    assert!(moldfile.contains("if ci {"), "{}", moldfile);
+    assert!(moldfile.contains("require greet"), "{}", moldfile);
+    assert!(
+        moldfile.contains("recipe tidy = \"echo tidy\" help \"Tidy up\""),
+        "{}",
+        moldfile
+    );
+
+    // the import can't be cloned here, so compare the recipes with it removed
+    let strip = |text: &str| {
+        text.lines()
+            .filter(|line| {
+                !line.contains("import")
+                    && !line.contains("github.com")
+                    && !line.contains("ref:")
+                    && !line.contains("prefix:")
+                    && !line.contains("private:")
+                    && !line.contains("includes:")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let yaml = std::fs::read_to_string(project.path("mold.yaml")).unwrap();
+    project.file("mold.yaml", &strip(&yaml));
+    project.moldfile(&strip(&moldfile));
+
+    for env in &["", "ci"] {
+        let explain = |file: &str| {
+            let out = success(project.run(&[
+                "--file",
+                file,
+                "--env",
+                env,
+                "--explain",
+                "greet",
+                "list",
+                "script",
+                "tidy",
+            ]));
+            out.replace(&project.root().display().to_string(), "ROOT")
+        };
+        assert_eq!(explain("mold.yaml"), explain("moldfile"));
+    }
+
+    // an existing moldfile is never overwritten
+    let err = failure(project.run(&["--migrate"]));
+    assert!(err.contains("already exists"), "{}", err);
+}
+
+#[test]
+fn yml_extension_is_discovered() {
+    let project = Project::new();
+    project.file(
+        "mold.yml",
+        "version: \"0.6\"\nrecipes:\n  old:\n    command: \"echo old\"\n",
+    );
+
+    let out = success(project.run(&["old"]));
+    assert_eq!(command_output(&out), vec!["old"]);
+
+    success(project.run(&["--migrate"]));
+    let out = success(project.run(&["old"]));
+    assert_eq!(command_output(&out), vec!["old"]);
+    assert!(project.path("moldfile").exists());
+}